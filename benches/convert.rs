@@ -0,0 +1,44 @@
+//! `cargo bench --features bench`: this crate's conversions against mlua's `LuaSerdeExt`.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use rlua_json::mlua::{Lua, LuaSerdeExt};
+use rlua_json::{ConversionOptions, ShapePlan, json_to_lua, json_to_lua_with_plan, lua_to_json, testdata};
+use serde_json::Value as JsonValue;
+
+fn to_lua(c: &mut Criterion) {
+    let lua = Lua::new();
+    let options = ConversionOptions::default();
+    let mut group = c.benchmark_group("to_lua");
+    for (name, fixture) in testdata::fixtures() {
+        group.bench_with_input(BenchmarkId::new("rlua_json", name), &fixture, |b, fixture| {
+            b.iter(|| json_to_lua(&lua, fixture, &options).expect("convert"))
+        });
+        let plan = ShapePlan::compile(&lua, &fixture).expect("plan");
+        group.bench_with_input(BenchmarkId::new("rlua_json_plan", name), &fixture, |b, fixture| {
+            b.iter(|| json_to_lua_with_plan(&lua, fixture, &plan, &options).expect("convert"))
+        });
+        group.bench_with_input(BenchmarkId::new("LuaSerdeExt", name), &fixture, |b, fixture| {
+            b.iter(|| lua.to_value(fixture).expect("convert"))
+        });
+    }
+    group.finish();
+}
+
+fn from_lua(c: &mut Criterion) {
+    let lua = Lua::new();
+    let options = ConversionOptions::default();
+    let mut group = c.benchmark_group("from_lua");
+    for (name, fixture) in testdata::fixtures() {
+        let value = json_to_lua(&lua, &fixture, &options).expect("convert");
+        group.bench_with_input(BenchmarkId::new("rlua_json", name), &value, |b, value| {
+            b.iter(|| lua_to_json(&lua, value.clone(), &options).expect("convert"))
+        });
+        group.bench_with_input(BenchmarkId::new("LuaSerdeExt", name), &value, |b, value| {
+            b.iter(|| lua.from_value::<JsonValue>(value.clone()).expect("convert"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, to_lua, from_lua);
+criterion_main!(benches);
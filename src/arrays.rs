@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::path::unescape;
+use crate::{ConversionOptions, JsonWrapperValue, lua_to_json};
+
+/// jq's total order: null < false < true < numbers < strings < arrays < objects.
+pub(crate) fn compare(a: &JsonValue, b: &JsonValue) -> Ordering {
+    let rank = |v: &JsonValue| match v {
+        JsonValue::Null => 0,
+        JsonValue::Bool(false) => 1,
+        JsonValue::Bool(true) => 2,
+        JsonValue::Number(_) => 3,
+        JsonValue::String(_) => 4,
+        JsonValue::Array(_) => 5,
+        JsonValue::Object(_) => 6,
+    };
+    match (a, b) {
+        (JsonValue::Number(x), JsonValue::Number(y)) =>
+            x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal),
+        (JsonValue::String(x), JsonValue::String(y)) => x.cmp(y),
+        (JsonValue::Array(x), JsonValue::Array(y)) => x.iter().zip(y)
+            .map(|(x, y)| compare(x, y))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| x.len().cmp(&y.len())),
+        (JsonValue::Object(x), JsonValue::Object(y)) => {
+            let mut xk: Vec<_> = x.keys().collect();
+            let mut yk: Vec<_> = y.keys().collect();
+            xk.sort();
+            yk.sort();
+            xk.cmp(&yk).then_with(|| xk.iter()
+                .map(|k| compare(&x[k.as_str()], &y[k.as_str()]))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal))
+        },
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// The object key a group is stored under: strings as they are, other values as JSON text.
+fn group_key(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn not_an_array(value: &JsonValue) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("expected an array, got {}", match value {
+        JsonValue::Object(_) => "an object",
+        _ => "a scalar",
+    }))
+}
+
+impl JsonWrapperValue {
+    /// Stably sorts an array by the value at `pointer` in each element, in jq order;
+    /// elements without it sort first.
+    pub fn sort_by_path(&mut self, pointer: &str) -> rlua::Result<()> {
+        let JsonValue::Array(a) = &mut self.0 else { return Err(not_an_array(&self.0)) };
+        let null = JsonValue::Null;
+        a.sort_by(|x, y| compare(x.pointer(pointer).unwrap_or(&null), y.pointer(pointer).unwrap_or(&null)));
+        Ok(())
+    }
+
+    /// Groups the elements of an array by the value at `pointer`, into an object of arrays.
+    /// Non-string keys are written as JSON text (`"12"`, `"null"`).
+    pub fn group_by_path(&self, pointer: &str) -> rlua::Result<JsonWrapperValue> {
+        let JsonValue::Array(a) = &self.0 else { return Err(not_an_array(&self.0)) };
+        let mut groups = Map::new();
+        for item in a {
+            let key = group_key(item.pointer(pointer).unwrap_or(&JsonValue::Null));
+            if let JsonValue::Array(group) = groups.entry(key).or_insert_with(|| JsonValue::Array(Vec::new())) {
+                group.push(item.clone());
+            }
+        }
+        Ok(JsonWrapperValue(JsonValue::Object(groups)))
+    }
+}
+
+/// The Lua value at a JSON Pointer inside `value`; numeric segments index arrays (0-based).
+fn lua_pointer<'lua>(value: rlua::Value<'lua>, pointer: &str) -> rlua::Result<rlua::Value<'lua>> {
+    let mut current = value;
+    for segment in pointer.split('/').skip(1).map(unescape) {
+        let rlua::Value::Table(table) = current else { return Ok(rlua::Value::Nil) };
+        current = match segment.parse::<i64>() {
+            Ok(i) if table.contains_key(i + 1)? => table.get(i + 1)?,
+            _ => table.get(segment)?,
+        };
+    }
+    Ok(current)
+}
+
+/// The elements of a Lua array with their sort keys.
+fn keyed<'lua>(
+    lua: &'lua Lua, array: &rlua::Table<'lua>, pointer: &str, options: &ConversionOptions,
+) -> rlua::Result<Vec<(JsonValue, rlua::Value<'lua>)>> {
+    array.clone().sequence_values::<rlua::Value>()
+        .map(|item| {
+            let item = item?;
+            Ok((lua_to_json(lua, lua_pointer(item.clone(), pointer)?, options)?, item))
+        })
+        .collect()
+}
+
+/// `json.sort_by(array, pointer)`: sorts a Lua array in place like [`JsonWrapperValue::sort_by_path`].
+pub(crate) fn lua_sort_by<'lua>(
+    lua: &'lua Lua, array: rlua::Table<'lua>, pointer: &str, options: &ConversionOptions,
+) -> rlua::Result<rlua::Table<'lua>> {
+    let mut items = keyed(lua, &array, pointer, options)?;
+    items.sort_by(|(x, _), (y, _)| compare(x, y));
+    for (i, (_, item)) in items.into_iter().enumerate() {
+        array.raw_set(i + 1, item)?;
+    }
+    Ok(array)
+}
+
+/// `json.group_by(array, pointer)`: a table of arrays of the original elements by key.
+pub(crate) fn lua_group_by<'lua>(
+    lua: &'lua Lua, array: rlua::Table<'lua>, pointer: &str, options: &ConversionOptions,
+) -> rlua::Result<rlua::Table<'lua>> {
+    let mut groups: BTreeMap<String, Vec<rlua::Value>> = BTreeMap::new();
+    for (key, item) in keyed(lua, &array, pointer, options)? {
+        groups.entry(group_key(&key)).or_default().push(item);
+    }
+    let table = lua.create_table_with_capacity(0, groups.len())?;
+    for (key, items) in groups {
+        table.raw_set(key, lua.create_sequence_from(items)?)?;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, JsonWrapperValue, register};
+
+    #[test]
+    fn sort_and_group() {
+        let mut items = JsonWrapperValue::new(json!([
+            {"name": "a", "price": 12, "category": "x"},
+            {"name": "b", "category": "y"},
+            {"name": "c", "price": 3, "category": "x"},
+        ]));
+        items.sort_by_path("/price").expect("sort");
+        let names: Vec<_> = match serde_json::Value::from(items.clone()) {
+            serde_json::Value::Array(a) => a.into_iter().map(|item| item["name"].clone()).collect(),
+            _ => Vec::new(),
+        };
+        assert_eq!(names, vec![json!("b"), json!("c"), json!("a")]);
+        let groups = serde_json::Value::from(items.group_by_path("/category").expect("group"));
+        assert_eq!(groups["x"].as_array().map(Vec::len), Some(2));
+        assert!(JsonWrapperValue::new(json!({})).sort_by_path("/a").is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        let (sorted, same, groups): (String, bool, i64) = lua.load(r#"
+            local first = { name = "z", stats = { 5 } }
+            local items = { first, { name = "y", stats = { 1 } } }
+            json.sort_by(items, "/stats/0")
+            local names = items[1].name .. items[2].name
+            local groups = json.group_by(items, "/name")
+            return names, groups.z[1] == first, #groups.y
+        "#).eval().expect("eval");
+        assert_eq!((sorted.as_str(), same, groups), ("yz", true, 1));
+    }
+}
@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use std::path::Path;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, RecordBatch};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::{ArrowError, DataType};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua};
+
+fn arrow_error(error: ArrowError) -> rlua::Error {
+    rlua::Error::external(error)
+}
+
+fn data(bytes: &[u8]) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("$type".to_string(), JsonValue::from("data"));
+    o.insert("value".to_string(), JsonValue::String(base64::encode(bytes)));
+    JsonValue::Object(o)
+}
+
+fn values<T, F: Fn(usize) -> T>(array: &dyn Array, value: F) -> Vec<JsonValue> where JsonValue: From<T> {
+    (0..array.len()).map(|i| match array.is_null(i) {
+        true => JsonValue::Null,
+        false => JsonValue::from(value(i)),
+    }).collect()
+}
+
+fn float(n: f64) -> JsonValue {
+    Number::from_f64(n).map_or(JsonValue::Null, JsonValue::Number)
+}
+
+/// One value per row of `array`: numbers, booleans and strings as they are, binary as tagged
+/// data, lists as arrays and structs as objects; other types, such as dates, timestamps and
+/// decimals, as the text Arrow displays them with (`2024-05-01`, `2024-05-01T12:00:00`).
+fn column(array: &dyn Array) -> rlua::Result<Vec<JsonValue>> {
+    Ok(match array.data_type() {
+        DataType::Null => vec![JsonValue::Null; array.len()],
+        DataType::Boolean => values(array, |i| array.as_boolean().value(i)),
+        DataType::Int8 => values(array, |i| array.as_primitive::<Int8Type>().value(i)),
+        DataType::Int16 => values(array, |i| array.as_primitive::<Int16Type>().value(i)),
+        DataType::Int32 => values(array, |i| array.as_primitive::<Int32Type>().value(i)),
+        DataType::Int64 => values(array, |i| array.as_primitive::<Int64Type>().value(i)),
+        DataType::UInt8 => values(array, |i| array.as_primitive::<UInt8Type>().value(i)),
+        DataType::UInt16 => values(array, |i| array.as_primitive::<UInt16Type>().value(i)),
+        DataType::UInt32 => values(array, |i| array.as_primitive::<UInt32Type>().value(i)),
+        DataType::UInt64 => values(array, |i| array.as_primitive::<UInt64Type>().value(i)),
+        DataType::Float32 => values(array, |i| float(f64::from(array.as_primitive::<Float32Type>().value(i)))),
+        DataType::Float64 => values(array, |i| float(array.as_primitive::<Float64Type>().value(i))),
+        DataType::Utf8 => values(array, |i| array.as_string::<i32>().value(i)),
+        DataType::LargeUtf8 => values(array, |i| array.as_string::<i64>().value(i)),
+        DataType::Utf8View => values(array, |i| array.as_string_view().value(i)),
+        DataType::Binary => values(array, |i| data(array.as_binary::<i32>().value(i))),
+        DataType::LargeBinary => values(array, |i| data(array.as_binary::<i64>().value(i))),
+        DataType::BinaryView => values(array, |i| data(array.as_binary_view().value(i))),
+        DataType::FixedSizeBinary(_) => values(array, |i| data(array.as_fixed_size_binary().value(i))),
+        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _) => (0..array.len()).map(|i| {
+            let items = match array.data_type() {
+                DataType::List(_) => array.as_list::<i32>().value(i),
+                DataType::LargeList(_) => array.as_list::<i64>().value(i),
+                _ => array.as_fixed_size_list().value(i),
+            };
+            Ok(match array.is_null(i) {
+                true => JsonValue::Null,
+                false => JsonValue::Array(column(&items)?),
+            })
+        }).collect::<rlua::Result<_>>()?,
+        DataType::Struct(fields) => {
+            let children = array.as_struct().columns().iter().map(|c| column(c)).collect::<rlua::Result<Vec<_>>>()?;
+            (0..array.len()).map(|i| match array.is_null(i) {
+                true => JsonValue::Null,
+                false => JsonValue::Object(fields.iter().zip(&children)
+                    .map(|(field, values)| (field.name().clone(), values.get(i).cloned().unwrap_or_default()))
+                    .collect()),
+            }).collect()
+        },
+        _ => {
+            let formatter = ArrayFormatter::try_new(array, &FormatOptions::default()).map_err(arrow_error)?;
+            values(array, |i| formatter.value(i).to_string())
+        },
+    })
+}
+
+/// A batch as columns: an object of one array per column, keyed by column name, with `null`
+/// for null values.
+pub fn record_batch_to_json(batch: &RecordBatch) -> rlua::Result<JsonValue> {
+    let schema = batch.schema();
+    Ok(JsonValue::Object(schema.fields().iter().zip(batch.columns())
+        .map(|(field, array)| Ok((field.name().clone(), JsonValue::Array(column(array)?))))
+        .collect::<rlua::Result<_>>()?))
+}
+
+/// [`record_batch_to_json`], converted into a Lua table of columns with `options`.
+pub fn record_batch_to_lua<'lua>(lua: &'lua Lua, batch: &RecordBatch, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &record_batch_to_json(batch)?, options)
+}
+
+/// A Lua iterator over `batches`, for `for columns, rows in batches do ... end`: each call
+/// converts the next batch with [`record_batch_to_lua`] and returns it with its row count, so
+/// only one batch is in the Lua heap at a time.
+pub fn record_batches_to_lua_iterator<'lua, I>(lua: &'lua Lua, batches: I, options: &ConversionOptions) -> rlua::Result<rlua::Function<'lua>>
+where
+    I: Iterator<Item = Result<RecordBatch, ArrowError>> + Send + 'static,
+{
+    let batches = RefCell::new(batches);
+    let options = options.clone();
+    lua.create_function(move |lua, ()| {
+        let next = batches.try_borrow_mut().map_err(rlua::Error::external)?.next();
+        match next.transpose().map_err(arrow_error)? {
+            Some(batch) => Ok((record_batch_to_lua(lua, &batch, &options)?, Some(batch.num_rows()))),
+            None => Ok((rlua::Value::Nil, None)),
+        }
+    })
+}
+
+/// [`record_batches_to_lua_iterator`] over a Parquet file, `batch_size` rows at a time.
+pub fn parquet_to_lua_iterator<'lua>(
+    lua: &'lua Lua, path: impl AsRef<Path>, batch_size: usize, options: &ConversionOptions,
+) -> rlua::Result<rlua::Function<'lua>> {
+    let file = std::fs::File::open(path).map_err(rlua::Error::external)?;
+    let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.with_batch_size(batch_size.max(1)).build())
+        .map_err(rlua::Error::external)?;
+    record_batches_to_lua_iterator(lua, reader, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use arrow_array::builder::{Int32Builder, ListBuilder};
+    use arrow_array::{ArrayRef, BinaryArray, BooleanArray, Date32Array, Float64Array, RecordBatch, StringArray};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, parquet_to_lua_iterator, record_batch_to_json};
+
+    fn batch() -> RecordBatch {
+        let mut scores = ListBuilder::new(Int32Builder::new());
+        scores.values().append_slice(&[1, 2]);
+        scores.append(true);
+        scores.append(false);
+        scores.values().append_value(3);
+        scores.append(true);
+        RecordBatch::try_from_iter([
+            ("name", Arc::new(StringArray::from(vec![Some("a"), None, Some("c")])) as ArrayRef),
+            ("price", Arc::new(Float64Array::from(vec![1.5, 2.0, 0.25])) as ArrayRef),
+            ("ok", Arc::new(BooleanArray::from(vec![true, false, true])) as ArrayRef),
+            ("day", Arc::new(Date32Array::from(vec![19844, 0, 1])) as ArrayRef),
+            ("blob", Arc::new(BinaryArray::from(vec![&b"\x00\x01"[..], b"", b"z"])) as ArrayRef),
+            ("scores", Arc::new(scores.finish()) as ArrayRef),
+        ]).expect("batch")
+    }
+
+    #[test]
+    fn record_batches() {
+        assert_eq!(record_batch_to_json(&batch()).expect("json"), json!({
+            "name": ["a", null, "c"], "price": [1.5, 2.0, 0.25], "ok": [true, false, true],
+            "day": ["2024-05-01", "1970-01-01", "1970-01-02"],
+            "blob": [{"$type": "data", "value": "AAE="}, {"$type": "data", "value": ""}, {"$type": "data", "value": "eg=="}],
+            "scores": [[1, 2], null, [3]],
+        }));
+
+        let dir = std::env::temp_dir().join(format!("rlua_json_arrow_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let path = dir.join("items.parquet");
+        let batch = batch();
+        let mut writer = parquet::arrow::ArrowWriter::try_new(std::fs::File::create(&path).expect("create"), batch.schema(), None).expect("writer");
+        writer.write(&batch).expect("write");
+        writer.close().expect("close");
+
+        let lua = Lua::new();
+        let batches = parquet_to_lua_iterator(&lua, &path, 2, &ConversionOptions::default()).expect("reader");
+        lua.globals().set("batches", batches).expect("set");
+        lua.load(r#"
+            local sizes, total = {}, 0
+            for columns, rows in batches do
+                sizes[#sizes + 1] = rows
+                for i = 1, rows do total = total + columns.price[i] end
+            end
+            assert(#sizes == 2 and sizes[1] == 2 and sizes[2] == 1 and total == 3.75)
+        "#).exec().expect("lua");
+        assert!(parquet_to_lua_iterator(&lua, dir.join("missing.parquet"), 2, &ConversionOptions::default()).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
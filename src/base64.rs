@@ -0,0 +1,60 @@
+//! Standard, padded base64, for bytes that travel inside JSON strings, and the url-safe
+//! alphabet JWTs are decoded with.
+
+pub(crate) const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+pub(crate) const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        for i in 0..4 {
+            out.push(match i <= chunk.len() {
+                true => STANDARD.get((n >> (18 - 6 * i) & 63) as usize).map_or('=', |b| *b as char),
+                false => '=',
+            });
+        }
+    }
+    out
+}
+
+#[cfg_attr(not(any(feature = "bytecode", feature = "plist", feature = "sqlite", feature = "ion", feature = "ubjson", feature = "luau")), allow(dead_code))]
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    decode_with(text, STANDARD)
+}
+
+/// Decodes `text` in `alphabet`; padding is optional.
+pub(crate) fn decode_with(text: &str, alphabet: &[u8; 64]) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            n |= (alphabet.iter().position(|a| a == c)? as u32) << (18 - 6 * i);
+        }
+        if chunk.len() == 1 {
+            return None;
+        }
+        for i in 0..chunk.len() - 1 {
+            out.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{URL_SAFE, decode, decode_with, encode};
+
+    #[test]
+    fn base64_round_trip() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"\x1bLua\xff\x00"] {
+            assert_eq!(decode(&encode(data)).as_deref(), Some(data));
+        }
+        assert_eq!(encode(b"foob"), "Zm9vYg==");
+        assert_eq!(decode_with("TWE", URL_SAFE), Some(b"Ma".to_vec()));
+        assert_eq!(decode_with("_-8", URL_SAFE), Some(b"\xff\xef".to_vec()));
+        assert_eq!(decode_with("T", URL_SAFE), None);
+        assert_eq!(decode_with("T+==", URL_SAFE), None);
+    }
+}
@@ -0,0 +1,308 @@
+//! `lua-json`: converts documents the way `rlua_json` does, for checking its semantics by hand.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use rlua_json::mlua::{Lua, Table, Value};
+use rlua_json::{ConversionOptions, Validator, json_to_lua_with_report, json_to_lua_with_stats, lua_to_json, to_string};
+use serde_json::Value as JsonValue;
+
+const USAGE: &str = "\
+usage: lua-json <command> [options] [FILE]
+
+commands:
+  convert [--from FORMAT] [--to FORMAT]   convert FILE (or stdin); `--to lua` shows the Lua value
+  inspect [--from FORMAT]                 the Lua value, conversion counters and diagnostics
+  validate --schema SCHEMA [--from FORMAT]
+                                          check the document against a JSON Schema file
+  eval EXPRESSION [--from FORMAT]         evaluate Lua with the document in `doc`, print as JSON
+
+options:
+  --from FORMAT    json, json5, yaml, toml or lua (a table literal); by default from the
+                   file extension, else json
+  --to FORMAT      json (default), yaml, toml or lua
+  --pretty         indent JSON output
+  --preset NAME    default, cjson or dkjson conversion options";
+
+type CliResult<T> = Result<T, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Json5,
+    Yaml,
+    Toml,
+    Lua,
+}
+
+impl Format {
+    fn parse(name: &str) -> CliResult<Self> {
+        match name {
+            "json" => Ok(Format::Json),
+            "json5" => Ok(Format::Json5),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "lua" => Ok(Format::Lua),
+            _ => Err(format!("unknown format {:?}", name)),
+        }
+    }
+
+    fn from_path(path: &str) -> Self {
+        path.rsplit_once('.').and_then(|(_, extension)| Format::parse(extension).ok()).unwrap_or(Format::Json)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    expression: Option<String>,
+    schema: Option<String>,
+    from: Option<Format>,
+    to: Option<Format>,
+    pretty: bool,
+    preset: Option<String>,
+    file: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> CliResult<Args> {
+    let mut parsed = Args { command: args.next().ok_or(USAGE)?, ..Default::default() };
+    let value = |args: &mut dyn Iterator<Item = String>, flag: &str| args.next().ok_or(format!("{} needs a value", flag));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => parsed.from = Some(Format::parse(&value(&mut args, &arg)?)?),
+            "--to" => parsed.to = Some(Format::parse(&value(&mut args, &arg)?)?),
+            "--schema" => parsed.schema = Some(value(&mut args, &arg)?),
+            "--preset" => parsed.preset = Some(value(&mut args, &arg)?),
+            "--pretty" => parsed.pretty = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+            _ if parsed.command == "eval" && parsed.expression.is_none() => parsed.expression = Some(arg),
+            _ if parsed.file.is_none() => parsed.file = Some(arg),
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn options(args: &Args) -> CliResult<ConversionOptions> {
+    let options = match args.preset.as_deref() {
+        None | Some("default") => ConversionOptions::default(),
+        Some("cjson") => ConversionOptions::cjson(),
+        Some("dkjson") => ConversionOptions::dkjson(),
+        Some(name) => return Err(format!("unknown preset {:?}", name)),
+    };
+    Ok(ConversionOptions { indent: args.pretty, ..options })
+}
+
+fn read_input(file: Option<&str>) -> CliResult<String> {
+    let mut text = String::new();
+    match file {
+        Some("-") | None => io::stdin().read_to_string(&mut text).map(|_| ()),
+        Some(path) => std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut text).map(|_| ())),
+    }.map_err(|e| format!("{}: {}", file.unwrap_or("stdin"), e))?;
+    Ok(text)
+}
+
+/// Parses `text`; Lua table literals are evaluated in an empty environment.
+fn parse(lua: &Lua, text: &str, format: Format, options: &ConversionOptions) -> CliResult<JsonValue> {
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        Format::Json5 => json5::from_str(text).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+        Format::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        Format::Lua => {
+            let value: Value = lua.load(text)
+                .set_name("input")
+                .set_environment(lua.create_table().map_err(|e| e.to_string())?)
+                .eval()
+                .map_err(|e| e.to_string())?;
+            lua_to_json(lua, value, options).map_err(|e| e.to_string())
+        },
+    }
+}
+
+fn write(lua: &Lua, value: &JsonValue, format: Format, options: &ConversionOptions) -> CliResult<String> {
+    match format {
+        Format::Json | Format::Json5 => to_string(value, options).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+        Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Lua => {
+            let converted = rlua_json::json_to_lua(lua, value, options).map_err(|e| e.to_string())?;
+            lua_literal(&converted).map_err(|e| e.to_string())
+        },
+    }
+}
+
+fn run(args: Args) -> CliResult<String> {
+    let lua = Lua::new();
+    let options = options(&args)?;
+    let from = args.from.unwrap_or_else(|| args.file.as_deref().map_or(Format::Json, Format::from_path));
+    let document = parse(&lua, &read_input(args.file.as_deref())?, from, &options)?;
+
+    match args.command.as_str() {
+        "convert" => write(&lua, &document, args.to.unwrap_or(Format::Json), &options),
+        "inspect" => {
+            let (_, stats) = json_to_lua_with_stats(&lua, &document, &options).map_err(|e| e.to_string())?;
+            let (value, report) = json_to_lua_with_report(&lua, &document, &options).map_err(|e| e.to_string())?;
+            let mut out = lua_literal(&value).map_err(|e| e.to_string())?;
+            let _ = write!(out, "\n-- {} values, depth {}, {} strings ({} bytes), {} bytes of Lua heap",
+                stats.nodes, stats.max_depth, stats.strings, stats.string_bytes, stats.output_bytes);
+            for diagnostic in &report.diagnostics {
+                let _ = write!(out, "\n-- {}", diagnostic);
+            }
+            Ok(out)
+        },
+        "validate" => {
+            let path = args.schema.as_deref().ok_or("validate needs --schema")?;
+            let schema = parse(&lua, &read_input(Some(path))?, Format::from_path(path), &options)?;
+            Validator::schema(schema).check(&document)?;
+            Ok("valid".to_string())
+        },
+        "eval" => {
+            let expression = args.expression.as_deref().ok_or("eval needs an expression")?;
+            let converted = rlua_json::json_to_lua(&lua, &document, &options).map_err(|e| e.to_string())?;
+            lua.globals().set("doc", converted).map_err(|e| e.to_string())?;
+            let result: Value = lua.load(expression).set_name("expression").eval().map_err(|e| e.to_string())?;
+            let result = lua_to_json(&lua, result, &options).map_err(|e| e.to_string())?;
+            to_string(&result, &options).map_err(|e| e.to_string())
+        },
+        command => Err(format!("unknown command {:?}\n\n{}", command, USAGE)),
+    }
+}
+
+/// Renders a Lua value as source: the sequence part first, then the other keys sorted, one per line.
+fn lua_literal(value: &Value) -> rlua_json::mlua::Result<String> {
+    let mut out = String::new();
+    write_lua(&mut out, value, 0)?;
+    Ok(out)
+}
+
+fn write_lua(out: &mut String, value: &Value, indent: usize) -> rlua_json::mlua::Result<()> {
+    match value {
+        Value::Nil => out.push_str("nil"),
+        Value::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Integer(i) => {
+            let _ = write!(out, "{}", i);
+        },
+        Value::Number(n) if n.is_finite() => {
+            let _ = write!(out, "{:?}", n);
+        },
+        Value::Number(n) => out.push_str(if n.is_nan() { "0/0" } else if *n > 0.0 { "1/0" } else { "-1/0" }),
+        Value::String(s) => lua_string(out, s.as_bytes()),
+        Value::Table(t) if indent > 64 => {
+            let _ = write!(out, "--[[ {:?} ]]", t.to_pointer());
+        },
+        Value::Table(t) => write_table(out, t, indent)?,
+        other => {
+            let _ = write!(out, "--[[ {} ]] nil", other.type_name());
+        },
+    }
+    Ok(())
+}
+
+fn write_table(out: &mut String, table: &Table, indent: usize) -> rlua_json::mlua::Result<()> {
+    let pairs = table.clone().pairs::<Value, Value>().collect::<rlua_json::mlua::Result<Vec<_>>>()?;
+    let len = table.raw_len();
+    let in_sequence = |key: &Value| matches!(key, Value::Integer(i) if *i >= 1 && *i as usize <= len);
+    // With holes, every key is written out so positions stay visible.
+    let dense = pairs.iter().filter(|(key, _)| in_sequence(key)).count() == len;
+
+    let mut entries = Vec::new();
+    for (key, value) in pairs {
+        let mut line = String::new();
+        let order = match &key {
+            Value::Integer(i) if dense && in_sequence(&key) => (0, *i, String::new()),
+            Value::Integer(i) => (1, *i, String::new()),
+            Value::String(s) => (2, 0, String::from_utf8_lossy(s.as_bytes()).into_owned()),
+            _ => (3, 0, String::new()),
+        };
+        match &key {
+            _ if order.0 == 0 => {},
+            Value::String(s) if is_identifier(s.as_bytes()) => line.push_str(s.to_str()?),
+            key => {
+                line.push('[');
+                write_lua(&mut line, key, indent + 1)?;
+                line.push(']');
+            },
+        }
+        if order.0 != 0 {
+            line.push_str(" = ");
+        }
+        let order = if order.0 == 3 { (3, 0, line.clone()) } else { order };
+        write_lua(&mut line, &value, indent + 1)?;
+        entries.push((order, line));
+    }
+    entries.sort();
+    if entries.is_empty() {
+        out.push_str("{}");
+        return Ok(());
+    }
+    out.push_str("{\n");
+    for (_, line) in entries {
+        let _ = writeln!(out, "{}{},", "  ".repeat(indent + 1), line);
+    }
+    let _ = write!(out, "{}}}", "  ".repeat(indent));
+    Ok(())
+}
+
+fn is_identifier(s: &[u8]) -> bool {
+    const KEYWORDS: &[&[u8]] = &[b"and", b"break", b"do", b"else", b"elseif", b"end", b"false", b"for", b"function",
+        b"goto", b"if", b"in", b"local", b"nil", b"not", b"or", b"repeat", b"return", b"then", b"true", b"until", b"while"];
+    s.first().is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+        && s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+        && !KEYWORDS.contains(&s)
+}
+
+fn lua_string(out: &mut String, s: &[u8]) {
+    out.push('"');
+    for &b in s {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            },
+            b'\n' => out.push_str("\\n"),
+            b' '..=b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", b);
+            },
+        }
+    }
+    out.push('"');
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(run) {
+        Ok(output) => {
+            let _ = writeln!(io::stdout(), "{}", output.trim_end());
+            ExitCode::SUCCESS
+        },
+        Err(message) => {
+            let _ = writeln!(io::stderr(), "{}", message);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua_json::mlua::Lua;
+    use serde_json::json;
+    use rlua_json::ConversionOptions;
+    use super::{Format, lua_literal, parse, write};
+
+    #[test]
+    fn formats() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let document = json!({"name": "x", "list": [1, 2.5], "end": true});
+        for format in [Format::Json, Format::Json5, Format::Yaml, Format::Toml, Format::Lua] {
+            let text = write(&lua, &document, format, &options).expect("write");
+            assert_eq!(parse(&lua, &text, format, &options).expect("parse"), document, "{:?}", format);
+        }
+
+        let value = rlua_json::json_to_lua(&lua, &json!({"b": [1, "two"], "a": {}, "end": 1.0}), &options).expect("lua");
+        assert_eq!(lua_literal(&value).expect("literal"),
+            "{\n  a = {},\n  b = {\n    1,\n    \"two\",\n  },\n  [\"end\"] = 1.0,\n}");
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use rlua::Lua;
+#[cfg(feature = "luajit")]
+use rlua::TableExt;
+
+/// Instructions run between checks of the budget.
+const CHECK_INTERVAL: u32 = 1000;
+
+/// Marks a Lua state whose hook is counting a budget, so nested conversions share it.
+struct ActiveBudget;
+
+/// Instructions left for the script hooks of one conversion.
+#[derive(Debug, Clone)]
+pub(crate) struct InstructionBudget(Arc<AtomicI64>);
+
+impl InstructionBudget {
+    pub fn new(instructions: Option<u32>) -> Option<Self> {
+        instructions.map(|n| InstructionBudget(Arc::new(AtomicI64::new(n.into()))))
+    }
+
+    /// Whether `budget` has run out.
+    pub fn exhausted(budget: Option<&Self>) -> bool {
+        budget.is_some_and(|budget| budget.0.load(Ordering::Relaxed) <= 0)
+    }
+
+    /// Runs `f`, which calls into Lua, with a hook that fails it once the budget is spent.
+    pub fn run<R>(budget: Option<&Self>, lua: &Lua, f: impl FnOnce() -> rlua::Result<R>) -> rlua::Result<R> {
+        let Some(budget) = budget else { return f() };
+        if lua.app_data_ref::<ActiveBudget>().is_some() {
+            return f();
+        }
+
+        let remaining = budget.0.clone();
+        let step = remaining.load(Ordering::Relaxed).clamp(1, CHECK_INTERVAL.into()) as u32;
+        let spend = move || match remaining.fetch_sub(step.into(), Ordering::Relaxed) <= step.into() {
+            true => Err(rlua::Error::RuntimeError("instruction budget of conversion hooks exceeded".to_string())),
+            false => Ok(()),
+        };
+        lua.set_app_data(ActiveBudget);
+        // LuaJIT never calls count hooks from compiled code, so budgeted code runs interpreted.
+        #[cfg(feature = "luajit")]
+        let jit_was_on = match interpreted(lua) {
+            Ok(was_on) => was_on,
+            Err(error) => {
+                lua.remove_app_data::<ActiveBudget>();
+                return Err(error);
+            },
+        };
+        #[cfg(not(feature = "luau"))]
+        lua.set_hook(rlua::HookTriggers::new().every_nth_instruction(step), move |_, _| spend());
+        // Luau has no instruction hooks; each interrupt, at calls and loop iterations, is charged `step`.
+        #[cfg(feature = "luau")]
+        lua.set_interrupt(move |_| spend().map(|()| rlua::VmState::Continue));
+        let result = f();
+        #[cfg(not(feature = "luau"))]
+        lua.remove_hook();
+        #[cfg(feature = "luau")]
+        lua.remove_interrupt();
+        #[cfg(feature = "luajit")]
+        let result = match jit_was_on {
+            true => jit(lua).and_then(|jit| jit.call_function::<_, ()>("on", ())).and(result),
+            false => result,
+        };
+        lua.remove_app_data::<ActiveBudget>();
+        result
+    }
+}
+
+/// LuaJIT's `jit` library, from the loaded modules so that removing the `jit` global from a
+/// sandbox does not hide it.
+#[cfg(feature = "luajit")]
+fn jit(lua: &Lua) -> rlua::Result<rlua::Table<'_>> {
+    lua.named_registry_value::<rlua::Table>("_LOADED")?.get("jit")
+}
+
+/// Turns the JIT compiler off and discards compiled code; whether it was on.
+#[cfg(feature = "luajit")]
+fn interpreted(lua: &Lua) -> rlua::Result<bool> {
+    let jit = jit(lua)?;
+    let was_on = jit.call_function("status", ())?;
+    jit.call_function::<_, ()>("off", ())?;
+    jit.call_function::<_, ()>("flush", ())?;
+    Ok(was_on)
+}
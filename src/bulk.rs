@@ -0,0 +1,85 @@
+#[cfg(not(feature = "rayon"))]
+use std::num::NonZeroUsize;
+#[cfg(not(feature = "rayon"))]
+use std::thread;
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, json_to_lua, parse_json};
+
+/// Parses many JSON texts on scoped worker threads, one per available core; on the calling
+/// thread if there is only one. With the `rayon` feature, texts are parsed on rayon's global
+/// pool instead, which balances texts of uneven size across threads.
+///
+/// Results keep the input order; the first failing text (in input order) fails the whole call.
+pub fn bulk_parse(
+    texts: impl IntoIterator<Item = String>, options: &ConversionOptions,
+) -> rlua::Result<Vec<JsonValue>> {
+    let texts: Vec<String> = texts.into_iter().collect();
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        let parsed: Vec<rlua::Result<JsonValue>> = texts.par_iter().map(|text| parse_json(text.as_bytes(), options)).collect();
+        parsed.into_iter().collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    scoped(&texts, options)
+}
+
+#[cfg(not(feature = "rayon"))]
+fn scoped(texts: &[String], options: &ConversionOptions) -> rlua::Result<Vec<JsonValue>> {
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    if workers == 1 {
+        // Also where threads are unavailable, as on WASM.
+        return texts.iter().map(|text| parse_json(text.as_bytes(), options)).collect();
+    }
+    let chunk_size = texts.len().div_ceil(workers).max(1);
+
+    let parsed: Vec<rlua::Result<Vec<JsonValue>>> = thread::scope(|scope| {
+        let handles: Vec<_> = texts.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                chunk.iter().map(|text| parse_json(text.as_bytes(), options)).collect()
+            }))
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(rlua::Error::external("parser thread panicked"))))
+            .collect()
+    });
+
+    let mut result = Vec::with_capacity(texts.len());
+    for chunk in parsed {
+        result.extend(chunk?);
+    }
+    Ok(result)
+}
+
+/// Converts documents produced by [`bulk_parse`] on the thread owning `lua`.
+pub fn bulk_into_lua<'lua>(
+    lua: &'lua Lua, documents: &[JsonValue], options: &ConversionOptions,
+) -> rlua::Result<Vec<rlua::Value<'lua>>> {
+    documents.iter().map(|document| json_to_lua(lua, document, options)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, bulk_into_lua, bulk_parse};
+
+    #[test]
+    fn parses_in_order() {
+        let options = ConversionOptions::default();
+        let texts: Vec<String> = (0..100).map(|i| format!(r#"{{"id": {}}}"#, i)).collect();
+
+        let documents = bulk_parse(texts, &options).expect("parse");
+        assert_eq!(documents.len(), 100);
+        assert_eq!(documents[42], json!({"id": 42}));
+
+        let lua = Lua::new();
+        let values = bulk_into_lua(&lua, &documents, &options).expect("convert");
+        let table = values[99].as_table().expect("table");
+        assert_eq!(table.get::<_, i64>("id").expect("id"), 99);
+
+        let error = bulk_parse(vec!["[1]".to_string(), "{".to_string(), "[".to_string()], &options).expect_err("invalid");
+        assert!(error.to_string().contains("EOF while parsing an object"), "{}", error);
+    }
+}
@@ -0,0 +1,19 @@
+use rlua::Lua;
+use crate::base64;
+
+/// Base64 of the function's `string.dump` bytecode.
+pub(crate) fn dump(f: &rlua::Function) -> rlua::Result<String> {
+    let bytecode = f.dump(false);
+    if bytecode.is_empty() {
+        return Err(rlua::Error::FromLuaConversionError {
+            from: "Function", to: "bytecode", message: Some("only Lua functions can be dumped".to_string()) });
+    }
+    Ok(base64::encode(&bytecode))
+}
+
+/// Loads a function written by [`dump`]. Its upvalues start as `nil`, except `_ENV`.
+pub(crate) fn restore<'lua>(lua: &'lua Lua, text: &str) -> rlua::Result<rlua::Function<'lua>> {
+    let bytecode = base64::decode(text).ok_or_else(|| rlua::Error::ToLuaConversionError {
+        from: "JsonValue::String", to: "Function", message: Some("invalid base64 bytecode".to_string()) })?;
+    lua.load(bytecode).set_mode(rlua::ChunkMode::Binary).into_function()
+}
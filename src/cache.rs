@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use rlua::{Lua, RegistryKey};
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, json_to_lua, parse_json};
+
+struct Entry {
+    /// Compared on lookup, so a hash collision is a miss rather than the wrong document.
+    text: Box<[u8]>,
+    value: Arc<JsonValue>,
+    table: Option<RegistryKey>,
+    inserted: Option<Instant>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<u64, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl State {
+    /// The live entry for `input`, dropping it if expired.
+    fn get(&mut self, hash: u64, input: &[u8], ttl: Option<Duration>) -> Option<&mut Entry> {
+        let expired = self.entries.get(&hash)
+            .is_some_and(|e| e.inserted.zip(ttl).is_some_and(|(inserted, ttl)| inserted.elapsed() > ttl));
+        if expired {
+            self.remove(hash);
+        }
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(&hash).filter(|e| *e.text == *input)?;
+        entry.last_used = clock;
+        Some(entry)
+    }
+
+    fn remove(&mut self, hash: u64) {
+        if let Some(entry) = self.entries.remove(&hash) {
+            self.bytes -= entry.text.len();
+        }
+    }
+
+    /// Drops expired entries, then the least recently used ones until `bytes` fit `max_bytes`.
+    fn evict(&mut self, max_bytes: usize, ttl: Option<Duration>) {
+        if let Some(ttl) = ttl {
+            let expired: Vec<u64> = self.entries.iter()
+                .filter(|(_, e)| e.inserted.is_some_and(|inserted| inserted.elapsed() > ttl))
+                .map(|(hash, _)| *hash)
+                .collect();
+            expired.into_iter().for_each(|hash| self.remove(hash));
+        }
+        while self.bytes > max_bytes {
+            let Some(hash) = self.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(hash, _)| *hash) else { break };
+            self.remove(hash);
+        }
+    }
+}
+
+/// Decoded documents by their JSON text, so scripts rerun over the same static data files skip
+/// parsing it again. Entries are evicted least recently used first once their texts exceed
+/// `max_bytes`, and after the time to live if one is set.
+pub struct DecodeCache {
+    state: Mutex<State>,
+    max_bytes: usize,
+    ttl: Option<Duration>,
+    lua_tables: bool,
+}
+
+fn content_hash(input: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl DecodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        DecodeCache { state: Mutex::default(), max_bytes, ttl: None, lua_tables: false }
+    }
+
+    /// Expires entries this long after they were cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Also keeps the Lua value of each document in the registry of the state that converted
+    /// it, so [`parse_into_lua`](Self::parse_into_lua) returns the same table again: scripts must
+    /// treat it as read-only (e.g. with `read_only`), and one cache must be used with one set of
+    /// options.
+    pub fn with_lua_tables(mut self) -> Self {
+        self.lua_tables = true;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// [`parse_json`] through the cache.
+    pub fn parse(&self, input: &[u8], options: &ConversionOptions) -> rlua::Result<Arc<JsonValue>> {
+        let hash = content_hash(input);
+        if let Some(entry) = self.lock().get(hash, input, self.ttl) {
+            return Ok(entry.value.clone());
+        }
+        let value = Arc::new(parse_json(input, options)?);
+        if input.len() <= self.max_bytes {
+            let mut state = self.lock();
+            state.remove(hash);
+            state.bytes += input.len();
+            let inserted = self.ttl.map(|_| Instant::now());
+            let entry = Entry { text: input.into(), value: value.clone(), table: None, inserted, last_used: state.clock };
+            state.entries.insert(hash, entry);
+            state.evict(self.max_bytes, self.ttl);
+        }
+        Ok(value)
+    }
+
+    /// [`parse_into_lua`](crate::parse_into_lua) through the cache.
+    pub fn parse_into_lua<'lua>(
+        &self, lua: &'lua Lua, input: &[u8], options: &ConversionOptions,
+    ) -> rlua::Result<rlua::Value<'lua>> {
+        let hash = content_hash(input);
+        if self.lua_tables {
+            let mut state = self.lock();
+            let table = state.get(hash, input, self.ttl).and_then(|e| e.table.as_ref()).filter(|key| lua.owns_registry_value(key));
+            if let Some(key) = table {
+                return lua.registry_value(key);
+            }
+        }
+        let value = json_to_lua(lua, &*self.parse(input, options)?, options)?;
+        if self.lua_tables {
+            if let Some(entry) = self.lock().get(hash, input, self.ttl) {
+                entry.table = Some(lua.create_registry_value(value.clone())?);
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.entries.clear();
+        state.bytes = 0;
+    }
+}
+
+impl std::fmt::Debug for DecodeCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("DecodeCache")
+            .field("entries", &state.entries.len())
+            .field("bytes", &state.bytes)
+            .field("max_bytes", &self.max_bytes)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use rlua::Lua;
+    use crate::{ConversionOptions, DecodeCache};
+
+    #[test]
+    fn decode_cache() {
+        let options = ConversionOptions::default();
+        let cache = DecodeCache::new(20);
+        let first = cache.parse(br#"{"a": [1, 2]}"#, &options).expect("parse");
+        assert!(Arc::ptr_eq(&first, &cache.parse(br#"{"a": [1, 2]}"#, &options).expect("hit")));
+        assert_eq!(cache.len(), 1);
+
+        // 13 + 6 bytes fit; the next text evicts the least recently used.
+        cache.parse(b"[true]", &options).expect("parse");
+        cache.parse(br#"{"a": [1, 2]}"#, &options).expect("hit");
+        cache.parse(b"[false]", &options).expect("parse");
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&first, &cache.parse(br#"{"a": [1, 2]}"#, &options).expect("still cached")));
+        cache.parse(&[b' '; 21], &options).expect_err("not JSON");
+        assert_eq!(cache.len(), 2);
+
+        let cache = DecodeCache::new(1024).with_ttl(Duration::ZERO);
+        let first = cache.parse(b"[1]", &options).expect("parse");
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(!Arc::ptr_eq(&first, &cache.parse(b"[1]", &options).expect("expired")));
+
+        let lua = Lua::new();
+        let cache = DecodeCache::new(1024).with_lua_tables();
+        let table = cache.parse_into_lua(&lua, b"[1, 2]", &options).expect("decode");
+        assert_eq!(table, cache.parse_into_lua(&lua, b"[1, 2]", &options).expect("same table"));
+        let other = Lua::new();
+        cache.parse_into_lua(&other, b"[1, 2]", &options).expect("other state");
+        assert_ne!(table, cache.parse_into_lua(&lua, b"[1, 2]", &options).expect("converted again"));
+    }
+}
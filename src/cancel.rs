@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Values converted between checks of the token.
+const CHECK_INTERVAL: u32 = 256;
+
+/// Lets a host abort a conversion in progress, e.g. when the user cancels loading. Clones share
+/// the flag, so one can be handed to another thread or to a Lua function.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes conversions using this token fail with [`Cancelled`] at their next check.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CancellationToken").field(&self.is_cancelled()).finish()
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The error of a cancelled conversion, found with `rlua::Error::downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("conversion cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Checks a token every [`CHECK_INTERVAL`] values of one conversion.
+pub(crate) struct CancelCheck {
+    token: Option<CancellationToken>,
+    count: u32,
+}
+
+impl CancelCheck {
+    pub fn new(token: Option<&CancellationToken>) -> Self {
+        CancelCheck { token: token.cloned(), count: 0 }
+    }
+
+    /// Called once per converted value.
+    pub fn tick(&mut self) -> rlua::Result<()> {
+        self.advance(1)
+    }
+
+    /// Counts `n` values converted at once, e.g. the elements of an array of numbers, checking
+    /// the token once if a check falls among them.
+    pub fn advance(&mut self, n: usize) -> rlua::Result<()> {
+        let Some(token) = &self.token else { return Ok(()) };
+        let (before, interval) = (self.count as usize, CHECK_INTERVAL as usize);
+        self.count = ((before + n) % interval) as u32;
+        match n > 0 && (before == 0 || before + n > interval) && token.is_cancelled() {
+            true => Err(rlua::Error::external(Cancelled)),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{CancellationToken, Cancelled, ConversionOptions, json_to_lua, lua_to_json};
+
+    #[test]
+    fn cancellation() {
+        let lua = Lua::new();
+        let token = CancellationToken::new();
+        let options = ConversionOptions { cancellation: Some(token.clone()), tojson_metamethod: true, ..Default::default() };
+        let doc = json!((0..1000).collect::<Vec<_>>());
+        let value = json_to_lua(&lua, &doc, &options).expect("not cancelled");
+
+        // A script cancels the conversion it runs in; it stops within one check interval.
+        let cancel = lua.create_function(move |_, ()| { token.cancel(); Ok(0) }).expect("function");
+        let table: rlua::Table = lua.load("function(cancel, t) t[10] = setmetatable({}, { __tojson = cancel }) return t end")
+            .eval::<rlua::Function>().and_then(|f| f.call((cancel, value))).expect("table");
+        let error = lua_to_json(&lua, rlua::Value::Table(table), &options).expect_err("cancelled");
+        assert_eq!(error.downcast_ref::<Cancelled>(), Some(&Cancelled));
+
+        let error = json_to_lua(&lua, &doc, &options).expect_err("still cancelled");
+        assert_eq!(error.downcast_ref::<Cancelled>(), Some(&Cancelled));
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, Path};
+
+/// How an API writes booleans, for [`ConversionOptions::bool_encoding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolEncoding {
+    /// `1` and `0`.
+    Integer,
+    /// `"true"` and `"false"`.
+    String,
+}
+
+impl BoolEncoding {
+    /// The boolean `value` encodes, if it is in this encoding.
+    pub(crate) fn decode(self, value: &JsonValue) -> Option<bool> {
+        match (self, value) {
+            (BoolEncoding::Integer, JsonValue::Number(n)) => match n.as_u64() {
+                Some(0) => Some(false),
+                Some(1) => Some(true),
+                _ => None,
+            },
+            (BoolEncoding::String, JsonValue::String(s)) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(self, b: bool) -> JsonValue {
+        match self {
+            BoolEncoding::Integer => JsonValue::from(u8::from(b)),
+            BoolEncoding::String => JsonValue::from(b.to_string()),
+        }
+    }
+}
+
+/// Names of an enumeration and the integers scripts see for them, for
+/// [`ConversionOptions::enum_paths`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnumMapping {
+    values: BTreeMap<String, i64>,
+    names: BTreeMap<i64, String>,
+}
+
+impl EnumMapping {
+    /// `EnumMapping::new([("easy", 0), ("normal", 1), ("hard", 2)])`; a later pair for a name or
+    /// integer replaces the earlier one.
+    pub fn new<S: Into<String>>(pairs: impl IntoIterator<Item = (S, i64)>) -> Self {
+        let mut mapping = EnumMapping::default();
+        for (name, value) in pairs {
+            let name = name.into();
+            mapping.values.insert(name.clone(), value);
+            mapping.names.insert(value, name);
+        }
+        mapping
+    }
+
+    /// From an object such as `{"easy": 0, "normal": 1, "hard": 2}`; `None` if it is not an
+    /// object of integers.
+    pub fn from_json(mapping: &JsonValue) -> Option<Self> {
+        let pairs = mapping.as_object()?.iter()
+            .map(|(name, value)| value.as_i64().map(|value| (name.clone(), value)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(EnumMapping::new(pairs))
+    }
+
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.values.get(name).copied()
+    }
+
+    pub fn name(&self, value: i64) -> Option<&str> {
+        self.names.get(&value).map(String::as_str)
+    }
+}
+
+/// The enumeration of the first `enum_paths` entry matching `path`.
+pub(crate) fn enum_mapping<'o>(options: &'o ConversionOptions, path: &Path) -> Option<&'o EnumMapping> {
+    options.enum_paths.iter().find(|(pattern, _)| pattern.matches(path)).map(|(_, mapping)| mapping)
+}
+
+/// How numbers are written as strings, e.g. by spreadsheet exports, for
+/// [`ConversionOptions::number_format_paths`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    pub decimal_separator: char,
+    /// Thousands separator; accepted anywhere in the integer part when parsing.
+    pub group_separator: Option<char>,
+    /// Text around the number, e.g. `"$"` or `" kg"`; optional when parsing.
+    pub prefix: String,
+    pub suffix: String,
+    /// What the written number is multiplied by, e.g. 100 for percentages.
+    pub scale: f64,
+    /// Fraction digits written; as many as needed if `None`.
+    pub decimals: Option<usize>,
+}
+
+impl Default for NumberFormat {
+    /// `1234.5`.
+    fn default() -> Self {
+        NumberFormat { decimal_separator: '.', group_separator: None, prefix: String::new(), suffix: String::new(), scale: 1.0, decimals: None }
+    }
+}
+
+impl NumberFormat {
+    /// `1,234.5`.
+    pub fn english() -> Self {
+        NumberFormat { group_separator: Some(','), ..Default::default() }
+    }
+
+    /// `1.234,5`, as in German and many other European locales.
+    pub fn european() -> Self {
+        NumberFormat { decimal_separator: ',', group_separator: Some('.'), ..Default::default() }
+    }
+
+    /// `12.5%` for 0.125.
+    pub fn percent() -> Self {
+        NumberFormat { suffix: "%".to_string(), scale: 100.0, ..Default::default() }
+    }
+
+    /// The number `text` denotes in this format: an integer if it has no fraction and no scale.
+    pub fn parse(&self, text: &str) -> Option<JsonValue> {
+        let text = text.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, text),
+        };
+        let text = text.strip_prefix(self.prefix.as_str()).unwrap_or(text).trim_start();
+        let text = text.strip_suffix(self.suffix.as_str()).unwrap_or(text).trim_end();
+        let (integral, fraction) = match text.split_once(self.decimal_separator) {
+            Some((integral, fraction)) => (integral, Some(fraction)),
+            None => (text, None),
+        };
+        let integral: String = integral.chars().filter(|c| Some(*c) != self.group_separator).collect();
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !digits(&integral) || fraction.is_some_and(|f| !digits(f)) {
+            return None;
+        }
+        let sign = if negative { "-" } else { "" };
+        if fraction.is_none() && self.scale == 1.0 {
+            if let Ok(i) = format!("{}{}", sign, integral).parse::<i64>() {
+                return Some(JsonValue::from(i));
+            }
+        }
+        let n: f64 = format!("{}{}.{}", sign, integral, fraction.unwrap_or("0")).parse().ok()?;
+        serde_json::Number::from_f64(n / self.scale).map(JsonValue::Number)
+    }
+
+    /// `n` written in this format.
+    pub fn format(&self, n: f64) -> String {
+        let scaled = n * self.scale;
+        let plain = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, scaled.abs()),
+            // Rounded to drop the noise of scaling, e.g. 0.07 * 100 = 7.000000000000001.
+            None => {
+                let rounded = format!("{:.12}", scaled.abs());
+                rounded.trim_end_matches('0').trim_end_matches('.').to_string()
+            },
+        };
+        self.decorate(scaled < 0.0, &plain)
+    }
+
+    /// `i` written in this format, digit for digit when there is no scale; a scaled integer
+    /// goes through [`format`](Self::format), exact only up to 2^53.
+    pub fn format_integer(&self, i: i64) -> String {
+        if self.scale != 1.0 {
+            return self.format(i as f64);
+        }
+        let mut plain = i.unsigned_abs().to_string();
+        if let Some(decimals) = self.decimals.filter(|&decimals| decimals > 0) {
+            plain.push('.');
+            plain.extend(std::iter::repeat_n('0', decimals));
+        }
+        self.decorate(i < 0, &plain)
+    }
+
+    /// `plain`, unsigned digits with `.` before any fraction, with the sign, separators, prefix
+    /// and suffix of this format.
+    fn decorate(&self, negative: bool, plain: &str) -> String {
+        let (integral, fraction) = plain.split_once('.').unwrap_or((plain, ""));
+        let mut text = String::new();
+        if negative && plain.bytes().any(|b| b != b'0' && b != b'.') {
+            text.push('-');
+        }
+        text.push_str(&self.prefix);
+        for (i, digit) in integral.chars().enumerate() {
+            if let Some(separator) = self.group_separator.filter(|_| i > 0 && (integral.len() - i).is_multiple_of(3)) {
+                text.push(separator);
+            }
+            text.push(digit);
+        }
+        if !fraction.is_empty() {
+            text.push(self.decimal_separator);
+            text.push_str(fraction);
+        }
+        text.push_str(&self.suffix);
+        text
+    }
+}
+
+/// The number format of the first `number_format_paths` entry matching `path`.
+pub(crate) fn number_format<'o>(options: &'o ConversionOptions, path: &Path) -> Option<&'o NumberFormat> {
+    options.number_format_paths.iter().find(|(pattern, _)| pattern.matches(path)).map(|(_, format)| format)
+}
+
+/// The boolean encoding at `path`: that of the first matching `bool_paths` entry, else
+/// `bool_encoding`.
+pub(crate) fn bool_encoding(options: &ConversionOptions, path: &Path) -> Option<BoolEncoding> {
+    options.bool_paths.iter()
+        .find(|(pattern, _)| pattern.matches(path))
+        .map(|(_, encoding)| *encoding)
+        .or(options.bool_encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{BoolEncoding, ConversionOptions, DiagnosticHandler, EnumMapping, NumberFormat, json_to_lua, lua_to_json};
+
+    #[test]
+    fn bool_coercion() {
+        let lua = Lua::new();
+        let options = ConversionOptions {
+            bool_encoding: Some(BoolEncoding::String),
+            bool_paths: vec![("/flags/*".into(), BoolEncoding::Integer)],
+            ..Default::default()
+        };
+        let doc = json!({"enabled": "true", "name": "truely", "flags": [1, 0, 2, true], "count": 1});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        let rlua::Value::Table(table) = &value else { panic!("table") };
+        assert_eq!(table.get::<_, rlua::Value>("enabled").expect("enabled"), rlua::Value::Boolean(true));
+        assert_eq!(table.get::<_, rlua::Table>("flags").expect("flags").sequence_values::<rlua::Value>()
+            .map(|v| v.expect("flag")).collect::<Vec<_>>(),
+            [rlua::Value::Boolean(true), rlua::Value::Boolean(false), rlua::Value::Integer(2), rlua::Value::Boolean(true)]);
+        assert_eq!(table.get::<_, i64>("count").expect("count"), 1);
+
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"),
+            json!({"enabled": "true", "name": "truely", "flags": [1, 0, 2, 1], "count": 1}));
+    }
+
+    #[test]
+    fn enum_mapping() {
+        let lua = Lua::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let difficulty = EnumMapping::from_json(&json!({"easy": 0, "normal": 1, "hard": 2})).expect("mapping");
+        assert_eq!(EnumMapping::from_json(&json!({"easy": "0"})), None);
+        let options = ConversionOptions {
+            enum_paths: vec![("/levels/*/difficulty".into(), difficulty)],
+            diagnostics: Some(DiagnosticHandler::new(move |d| sink.lock().unwrap().push(d.to_string()))),
+            ..Default::default()
+        };
+        let doc = json!({"levels": [{"difficulty": "hard"}, {"difficulty": "nightmare"}], "difficulty": "easy"});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        let (hard, unknown, outside): (rlua::Value, rlua::Value, rlua::Value) = lua
+            .load("local t = ...; return t.levels[1].difficulty, t.levels[2].difficulty, t.difficulty")
+            .call(value.clone()).expect("fields");
+        assert_eq!(hard, rlua::Value::Integer(2));
+        assert_eq!(unknown.as_str(), Some("nightmare"));
+        assert_eq!(outside.as_str(), Some("easy"));
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+
+        let value = lua.load("{ levels = { { difficulty = 1 }, { difficulty = 7 } } }").eval().expect("table");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"),
+            json!({"levels": [{"difficulty": "normal"}, {"difficulty": 7}]}));
+        assert_eq!(*seen.lock().unwrap(), [
+            "/levels/1/difficulty: unknown enumeration value \"nightmare\"",
+            "/levels/1/difficulty: unknown enumeration value \"7\"",
+        ]);
+    }
+
+    #[test]
+    fn number_formats() {
+        let english = NumberFormat::english();
+        assert_eq!(english.parse("1,234.5"), Some(json!(1234.5)));
+        assert_eq!(english.parse(" -1,234 "), Some(json!(-1234)));
+        assert_eq!(english.parse("1,2a"), None);
+        assert_eq!(english.format(1234567.25), "1,234,567.25");
+        assert_eq!(english.format(-999.0), "-999");
+        assert_eq!(english.format_integer(-9_007_199_254_740_993), "-9,007,199,254,740,993");
+        assert_eq!(NumberFormat { decimals: Some(2), ..Default::default() }.format_integer(i64::MAX), "9223372036854775807.00");
+        assert_eq!(NumberFormat::percent().format_integer(3), "300%");
+        assert_eq!(NumberFormat::european().parse("1.234,5"), Some(json!(1234.5)));
+        assert_eq!(NumberFormat::european().format(1234.5), "1.234,5");
+        let euros = NumberFormat { prefix: "€".to_string(), decimals: Some(2), ..NumberFormat::european() };
+        assert_eq!(euros.format(-1234.5), "-€1.234,50");
+        assert_eq!(euros.parse("-€1.234,50"), Some(json!(-1234.5)));
+
+        let lua = Lua::new();
+        let options = ConversionOptions {
+            number_format_paths: vec![("/rows/*/price".into(), NumberFormat::english()), ("/rows/*/tax".into(), NumberFormat::percent())],
+            ..Default::default()
+        };
+        let doc = json!({"rows": [{"price": "1,234.5", "tax": "7%"}, {"price": "n/a", "tax": "12.5%"}]});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        let (price, tax, missing): (f64, f64, String) = lua
+            .load("local t = ...; return t.rows[1].price, t.rows[1].tax, t.rows[2].price")
+            .call(value.clone()).expect("fields");
+        assert_eq!((price, tax, missing.as_str()), (1234.5, 0.07, "n/a"));
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+    }
+}
@@ -0,0 +1,96 @@
+//! Gzip and zstd compressed JSON, streamed through [`encode_lua_to_writer`] and
+//! [`decode_reader_into_lua`] without an intermediate JSON string.
+
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use crate::{ConversionOptions, decode_reader_into_lua, encode_lua_to_writer};
+use crate::module::format_table;
+
+/// Encodes a Lua value to gzip-compressed JSON, at the default compression level.
+#[cfg(feature = "gzip")]
+pub fn encode_gzip<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encode_lua_to_writer(lua, value, &mut encoder, options)?;
+    encoder.finish().map_err(rlua::Error::external)
+}
+
+/// Decodes gzip-compressed JSON into a Lua value.
+#[cfg(feature = "gzip")]
+pub fn decode_gzip<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    decode_reader_into_lua(lua, flate2::read::GzDecoder::new(bytes), options)
+}
+
+/// Encodes a Lua value to zstd-compressed JSON at `level` (1 to 22; 0 for zstd's default, 3).
+#[cfg(feature = "zstd")]
+pub fn encode_zstd<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, level: i32, options: &ConversionOptions) -> rlua::Result<Vec<u8>> {
+    let mut encoder = zstd::Encoder::new(Vec::new(), level).map_err(rlua::Error::external)?;
+    encode_lua_to_writer(lua, value, &mut encoder, options)?;
+    encoder.finish().map_err(rlua::Error::external)
+}
+
+/// Decodes zstd-compressed JSON into a Lua value.
+#[cfg(feature = "zstd")]
+pub fn decode_zstd<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    decode_reader_into_lua(lua, zstd::Decoder::new(bytes).map_err(rlua::Error::external)?, options)
+}
+
+/// The `json.gzip` table: `decode(bytes)` and `encode(value)`.
+#[cfg(feature = "gzip")]
+pub(crate) fn create_gzip_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options, decode_gzip, |lua, value, (), options| encode_gzip(lua, value, options))
+}
+
+/// The `json.zstd` table: `decode(bytes)` and `encode(value, level)`.
+#[cfg(feature = "zstd")]
+pub(crate) fn create_zstd_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options, decode_zstd, |lua, value, level: Option<i32>, options| encode_zstd(lua, value, level.unwrap_or(0), options))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, lua_to_json, register};
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip() {
+        use crate::{decode_gzip, encode_gzip};
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let value = lua.load("{ name = string.rep('abc', 1000), n = { 1, 2, 3 } }").eval::<rlua::Value>().expect("value");
+        let compressed = encode_gzip(&lua, value.clone(), &options).expect("encode");
+        assert!(compressed.starts_with(&[0x1f, 0x8b]) && compressed.len() < 200, "{} bytes", compressed.len());
+        let decoded = decode_gzip(&lua, &compressed, &options).expect("decode");
+        assert_eq!(lua_to_json(&lua, decoded, &options).expect("json"), lua_to_json(&lua, value, &options).expect("json"));
+        assert!(decode_gzip(&lua, b"{}", &options).is_err());
+
+        register(&lua, options).expect("register");
+        lua.load(r#"
+            local bytes = json.gzip.encode({ a = { 1, 2 } })
+            assert(bytes:sub(1, 2) == "\31\139" and json.gzip.decode(bytes).a[2] == 2)
+            assert(not pcall(json.gzip.decode, "not gzip"))
+        "#).exec().expect("lua");
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd() {
+        use crate::{decode_zstd, encode_zstd};
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let value = lua.load("{ name = string.rep('abc', 1000), n = { 1, 2, 3 } }").eval::<rlua::Value>().expect("value");
+        let compressed = encode_zstd(&lua, value.clone(), 19, &options).expect("encode");
+        assert!(compressed.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) && compressed.len() < 200, "{} bytes", compressed.len());
+        let decoded = decode_zstd(&lua, &compressed, &options).expect("decode");
+        assert_eq!(lua_to_json(&lua, decoded, &options).expect("json"), lua_to_json(&lua, value, &options).expect("json"));
+
+        register(&lua, options).expect("register");
+        lua.load(r#"
+            local bytes = json.zstd.encode({ a = { 1, 2 } }, 3)
+            assert(json.zstd.decode(bytes).a[2] == 2 and json.zstd.decode(json.zstd.encode("x")) == "x")
+            assert(not pcall(json.zstd.decode, "not zstd"))
+        "#).exec().expect("lua");
+    }
+}
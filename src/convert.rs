@@ -0,0 +1,1683 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use rlua::{Lua, IntoLua};
+use serde_json::value::RawValue;
+use serde_json::{Map, Value as JsonValue};
+use crate::{AliasPolicy, BigIntegerPolicy, ConversionError, ConversionOptions, ConversionReport, ConversionStats, FunctionPolicy, Diagnostic, DiagnosticKind, JsonType, LazyString, MixedTablePolicy, Path, PathSegment, SparseArrayPolicy, UnsupportedPolicy};
+use crate::budget::InstructionBudget;
+use crate::cancel::CancelCheck;
+use crate::coerce::{bool_encoding, enum_mapping, number_format};
+use crate::dedup::Subtrees;
+use crate::gc::GcPacer;
+use crate::memory::MemoryLimit;
+use crate::progress::ProgressReporter;
+use crate::shape::Shape;
+use crate::json_type::{json_type_metatable, table_json_type};
+use crate::keys::{is_numeric_object, mark_numeric_object, numeric_keys, ordered_pairs, sort_object};
+use crate::known_keys::diagnose_unknown_keys;
+use crate::raw::{RAW_KEY, RawJson};
+#[cfg(feature = "luau")]
+use crate::{BufferPolicy, VectorPolicy};
+use crate::readonly::{read_only_view, view_contents};
+
+/// Nesting beyond which conversions fail instead of overflowing the stack; serde_json
+/// does not parse deeper either.
+pub(crate) const MAX_DEPTH: usize = 128;
+
+fn too_deep(path: &Path) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}: nesting deeper than {} levels", path, MAX_DEPTH))
+}
+
+fn impossible(from: &'static str) -> rlua::Error {
+    rlua::Error::FromLuaConversionError {
+        from, to: "JsonValue", message: Some("Impossible to convert".to_string()) }
+}
+
+/// Converts a JSON document into a Lua value. Arrays become 1-based sequences.
+pub fn json_to_lua<'lua>(
+    lua: &'lua Lua, value: &JsonValue, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    #[cfg(feature = "serde-delegate")]
+    if crate::delegate::delegates_decode(options) {
+        return crate::delegate::json_to_lua(lua, value, options);
+    }
+    #[cfg(feature = "tracing")]
+    if let Some(span) = crate::stats::conversion_span("decode") {
+        let mut decoder = Decoder::new(lua, options);
+        let result = span.in_scope(|| decoder.decode_counted(value));
+        crate::stats::record_span(&span, &decoder.stats);
+        return result;
+    }
+    Decoder::new(lua, options).decode(value)
+}
+
+/// Converts a Lua value into a JSON document.
+///
+/// The null sentinel (`rlua::Value::NULL`) becomes JSON `null`.
+pub fn lua_to_json<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<JsonValue> {
+    #[cfg(feature = "serde-delegate")]
+    if crate::delegate::delegates_encode(options) && crate::delegate::plain_tables(&value)? {
+        return crate::delegate::lua_to_json(value, options);
+    }
+    #[cfg(feature = "tracing")]
+    if let Some(span) = crate::stats::conversion_span("encode") {
+        let mut encoder = Encoder::new(lua, options);
+        let result = span.in_scope(|| encoder.convert_counted(value));
+        crate::stats::record_span(&span, &encoder.stats);
+        return result;
+    }
+    Encoder::new(lua, options).convert(value)
+}
+
+/// Like [`json_to_lua`], also returning conversion counters.
+pub fn json_to_lua_with_stats<'lua>(
+    lua: &'lua Lua, value: &JsonValue, options: &ConversionOptions,
+) -> rlua::Result<(rlua::Value<'lua>, ConversionStats)> {
+    let mut decoder = Decoder::new(lua, options);
+    let result = decoder.decode_counted(value);
+    Ok((result?, decoder.stats))
+}
+
+/// Like [`lua_to_json`], also returning conversion counters.
+pub fn lua_to_json_with_stats<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<(JsonValue, ConversionStats)> {
+    let mut encoder = Encoder::new(lua, options);
+    let result = encoder.convert_counted(value);
+    Ok((result?, encoder.stats))
+}
+
+/// Like [`json_to_lua`], also returning the diagnostics of the conversion.
+pub fn json_to_lua_with_report<'lua>(
+    lua: &'lua Lua, value: &JsonValue, options: &ConversionOptions,
+) -> rlua::Result<(rlua::Value<'lua>, ConversionReport)> {
+    let (result, report) = ConversionReport::collect(options, |options| json_to_lua(lua, value, options));
+    Ok((result?, report))
+}
+
+/// Like [`lua_to_json`], also returning the diagnostics of the conversion, e.g. skipped
+/// functions and NaN written as `null`.
+pub fn lua_to_json_with_report<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<(JsonValue, ConversionReport)> {
+    let (result, report) = ConversionReport::collect(options, |options| lua_to_json(lua, value, options));
+    Ok((result?, report))
+}
+
+/// Like [`lua_to_json`], but instead of stopping at the first value that cannot be converted,
+/// walks the whole tree and returns every failure with its location.
+pub fn lua_to_json_all_errors<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> Result<JsonValue, Vec<ConversionError>> {
+    match lua_to_json_collecting(lua, value, options) {
+        (Some(value), errors) if errors.is_empty() => Ok(value),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Converts what it can, writing `null` for failed values; `None` if the root itself failed.
+pub(crate) fn lua_to_json_collecting<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> (Option<JsonValue>, Vec<ConversionError>) {
+    let mut encoder = Encoder::new(lua, options);
+    encoder.errors = Some(Vec::new());
+    let result = encoder.convert(value);
+    let mut errors = encoder.errors.take().unwrap_or_default();
+    match result {
+        Ok(value) => (Some(value), errors),
+        Err(error) => {
+            errors.push(ConversionError { path: Path::new(), error });
+            (None, errors)
+        },
+    }
+}
+
+/// JSON to Lua, tracking the current position in the document.
+pub(crate) struct Decoder<'a, 'lua> {
+    pub lua: &'lua Lua,
+    pub options: &'a ConversionOptions,
+    pub path: Path,
+    pub stats: ConversionStats,
+    /// With [`dedup_subtrees`](ConversionOptions::dedup_subtrees): the document's identical
+    /// containers, and the table already built for each class of them.
+    subtrees: Option<Subtrees>,
+    dedup: HashMap<usize, rlua::Table<'lua>>,
+    /// With [`AliasPolicy::Reference`]: tables by JSON Pointer, and `(table, key, pointer)`
+    /// slots to fill from them once the whole document is converted.
+    tables: HashMap<String, rlua::Table<'lua>>,
+    references: Vec<(rlua::Table<'lua>, rlua::Value<'lua>, String)>,
+    gc: GcPacer<'lua>,
+    cancel: CancelCheck,
+    progress: ProgressReporter,
+    /// The planned shape of the value being converted, see [`ShapePlan`](crate::ShapePlan).
+    pub shape: Option<&'a Shape<'lua>>,
+    memory: MemoryLimit<'lua>,
+}
+
+impl<'a, 'lua> Decoder<'a, 'lua> {
+    pub fn new(lua: &'lua Lua, options: &'a ConversionOptions) -> Self {
+        Decoder {
+            lua, options, path: Path::new(), stats: ConversionStats::default(),
+            subtrees: None, dedup: HashMap::new(), tables: HashMap::new(), references: Vec::new(),
+            gc: GcPacer::new(lua, options.gc_hint), cancel: CancelCheck::new(options.cancellation.as_ref()),
+            progress: ProgressReporter::new(options.progress.as_ref()), shape: None,
+            memory: MemoryLimit::new(lua, options.lua_memory_limit),
+        }
+    }
+
+    /// Converts a whole document, resolving `$ref` aliases at the end.
+    pub fn decode(&mut self, value: &JsonValue) -> rlua::Result<rlua::Value<'lua>> {
+        self.index_subtrees(value);
+        let result = self.convert(value)?;
+        self.resolve_references()?;
+        Ok(result)
+    }
+
+    /// [`decode`](Self::decode), also timing it and measuring the heap growth.
+    pub fn decode_counted(&mut self, value: &JsonValue) -> rlua::Result<rlua::Value<'lua>> {
+        let before = self.lua.used_memory();
+        let (result, elapsed) = ConversionStats::timed(|| self.decode(value));
+        self.stats.elapsed = elapsed;
+        self.stats.output_bytes = self.lua.used_memory().saturating_sub(before);
+        result
+    }
+
+    /// Fills the slots of `$ref` aliases met so far.
+    pub fn resolve_references(&mut self) -> rlua::Result<()> {
+        for (table, key, pointer) in std::mem::take(&mut self.references) {
+            let target = self.tables.get(&pointer).ok_or_else(|| rlua::Error::ToLuaConversionError {
+                from: "JsonValue::Object", to: "Table",
+                message: Some(format!("$ref to {:?}, which is not a converted array or object", pointer)) })?;
+            table.raw_set(key, target.clone())?;
+        }
+        Ok(())
+    }
+
+    /// `{"$ref": "#/pointer"}` written by [`AliasPolicy::Reference`].
+    fn reference<'v>(&self, value: &'v JsonValue) -> Option<&'v str> {
+        if self.options.aliases != AliasPolicy::Reference {
+            return None;
+        }
+        match value {
+            JsonValue::Object(o) if o.len() == 1 => o.get("$ref")?.as_str()?.strip_prefix('#'),
+            _ => None,
+        }
+    }
+
+    /// Whether arrays of numbers may be converted without visiting each element: no option
+    /// changes a number by its location.
+    fn numbers_in_bulk(&self) -> bool {
+        self.options.raw_paths.is_empty() && self.options.number_format_paths.is_empty()
+            && self.options.bool_paths.is_empty() && self.options.bool_encoding.is_none()
+    }
+
+    /// Counts the `n` elements of an array converted at once, one level down.
+    fn advance(&mut self, n: usize) -> rlua::Result<()> {
+        (0..n).for_each(|_| self.stats.node(self.path.len() + 1));
+        self.gc.advance(n)?;
+        self.cancel.advance(n).inspect_err(|_| self.diagnose(DiagnosticKind::Cancelled))?;
+        self.progress.advance(n as u64);
+        Ok(())
+    }
+
+    fn register_table(&mut self, table: &rlua::Table<'lua>) {
+        if self.options.aliases == AliasPolicy::Reference {
+            self.tables.insert(self.path.to_string(), table.clone());
+        }
+    }
+
+    /// Finds the identical containers of `root` for [`dedup_subtrees`](ConversionOptions::dedup_subtrees),
+    /// unless options make conversion depend on location.
+    pub fn index_subtrees(&mut self, root: &JsonValue) {
+        self.subtrees = match self.options.dedup_subtrees {
+            Some(limit) if self.options.set_paths.is_empty() && self.options.raw_paths.is_empty()
+                && self.options.columnar_paths.is_empty() => Some(Subtrees::new(root, limit)),
+            _ => None,
+        };
+    }
+
+    pub fn convert(&mut self, value: &JsonValue) -> rlua::Result<rlua::Value<'lua>> {
+        let Some(class) = self.subtrees.as_ref().and_then(|subtrees| subtrees.class(value)) else {
+            return self.convert_limited(value);
+        };
+        if let Some(table) = self.dedup.get(&class) {
+            self.stats.node(self.path.len());
+            return Ok(rlua::Value::Table(table.clone()));
+        }
+        let converted = self.convert_limited(value)?;
+        if let rlua::Value::Table(table) = &converted {
+            self.dedup.insert(class, table.clone());
+        }
+        Ok(converted)
+    }
+
+    fn convert_limited(&mut self, value: &JsonValue) -> rlua::Result<rlua::Value<'lua>> {
+        self.memory.tick()?;
+        self.convert_value(value).map_err(|e| {
+            if let (rlua::Error::MemoryError(_), Some(limit)) = (&e, self.options.lua_memory_limit) {
+                self.diagnose(DiagnosticKind::MemoryLimit { limit });
+            }
+            self.memory.exceeded(e, &self.path, self.stats.nodes)
+        })
+    }
+
+    fn convert_value(&mut self, value: &JsonValue) -> rlua::Result<rlua::Value<'lua>> {
+        let lua = self.lua;
+        if self.path.len() > MAX_DEPTH {
+            self.diagnose(DiagnosticKind::DepthLimit { max: MAX_DEPTH });
+            return Err(too_deep(&self.path));
+        }
+        self.stats.node(self.path.len());
+        self.gc.tick()?;
+        self.cancel.tick().inspect_err(|_| self.diagnose(DiagnosticKind::Cancelled))?;
+        self.progress.tick();
+        let shape = self.shape.take();
+        if let Some(b) = bool_encoding(self.options, &self.path).and_then(|encoding| encoding.decode(value)) {
+            return Ok(rlua::Value::Boolean(b));
+        }
+        if let (Some(mapping), JsonValue::String(name)) = (enum_mapping(self.options, &self.path), value) {
+            match mapping.value(name) {
+                Some(i) => return Ok(integer(i)),
+                None => self.diagnose(DiagnosticKind::UnknownEnumValue { value: name.clone() }),
+            }
+        }
+        let parsed = match (number_format(self.options, &self.path), value) {
+            (Some(format), JsonValue::String(text)) => format.parse(text),
+            _ => None,
+        };
+        let value = parsed.as_ref().unwrap_or(value);
+        let result = match value {
+            _ if self.options.raw_paths.iter().any(|p| p.matches(&self.path)) => {
+                let raw = serde_json::value::to_raw_value(value).map_err(rlua::Error::external)?;
+                rlua::Value::UserData(lua.create_userdata(RawJson(raw))?)
+            },
+            JsonValue::Null => rlua::Value::Nil,
+            JsonValue::String(s) if self.options.lazy_strings.is_some_and(|max| s.len() > max) => {
+                self.stats.string(s.len());
+                rlua::Value::UserData(lua.create_userdata(LazyString(s.clone()))?)
+            },
+            JsonValue::String(s) => {
+                self.stats.string(s.len());
+                s.as_str().into_lua(lua)?
+            },
+            JsonValue::Number(n) => {
+                match n.as_i64() {
+                    Some(ni) if fits_lua_integer(ni) => return Ok(integer(ni)),
+                    Some(_) => return self.big_integer(n),
+                    None if n.is_u64() => return self.big_integer(n),
+                    None => {},
+                }
+
+                rlua::Value::Number(
+                    n.as_f64().ok_or_else(|| rlua::Error::ToLuaConversionError {
+                        from: "JsonValue::Number",
+                        to: "Value::Number",
+                        message: None,
+                    })?
+                )
+            },
+            JsonValue::Bool(b) => rlua::Value::Boolean(*b),
+            JsonValue::Object(o) if self.options.functions == FunctionPolicy::Handle && o.len() == 1 && o.contains_key("$func") => {
+                let function = o["$func"].as_i64()
+                    .map(|handle| registry_table(lua, FUNCTIONS_KEY)?.raw_get::<_, Option<rlua::Function>>(handle))
+                    .transpose()?
+                    .flatten();
+                rlua::Value::Function(function.ok_or_else(|| rlua::Error::ToLuaConversionError {
+                    from: "JsonValue::Object", to: "Function",
+                    message: Some(format!("{}: unknown function handle {}", self.path, o["$func"])) })?)
+            },
+            #[cfg(feature = "bytecode")]
+            JsonValue::Object(o) if self.options.functions == FunctionPolicy::Bytecode && o.len() == 1 && o.contains_key("$bytecode") => {
+                match &o["$bytecode"] {
+                    JsonValue::String(text) => rlua::Value::Function(crate::bytecode::restore(lua, text)?),
+                    _ => return Err(rlua::Error::ToLuaConversionError {
+                        from: "JsonValue::Object", to: "Function", message: Some(format!("{}: $bytecode must be a string", self.path)) }),
+                }
+            },
+            #[cfg(feature = "luau")]
+            JsonValue::Object(o) if self.options.buffers == BufferPolicy::Tagged && tagged_data(o).is_some() => {
+                let bytes = tagged_data(o).and_then(crate::base64::decode).ok_or_else(|| rlua::Error::ToLuaConversionError {
+                    from: "JsonValue::Object", to: "buffer", message: Some(format!("{}: invalid base64 data", self.path)) })?;
+                rlua::Value::UserData(lua.create_buffer(bytes)?)
+            },
+            JsonValue::Object(o) => {
+                let table = lua.create_table_with_capacity(0, o.len())?;
+                let numeric = if self.options.numeric_keys { numeric_keys(o) } else { None };
+                for (i, (k, v)) in o.iter().enumerate() {
+                    self.stats.string(k.len());
+                    let planned = shape.and_then(|s| s.entry(i, k));
+                    let key = match (numeric.as_ref().and_then(|keys| keys.get(i)), planned) {
+                        (Some(n), _) => integer(*n),
+                        (None, Some((key, _))) => rlua::Value::String(key.clone()),
+                        (None, None) => k.as_str().into_lua(lua)?,
+                    };
+                    if let Some(pointer) = self.reference(v) {
+                        self.references.push((table.clone(), key, pointer.to_string()));
+                        continue;
+                    }
+                    self.path.push(PathSegment::Key(k.clone()));
+                    self.shape = planned.map(|(_, s)| s);
+                    table.raw_set(key, self.convert(v)?)?;
+                    self.path.pop();
+                }
+                if numeric.is_some() {
+                    mark_numeric_object(lua, &table)?;
+                }
+                #[cfg(feature = "preserve_order")]
+                if numeric.is_none() {
+                    crate::keys::record_key_order(lua, &table, o.keys().map(String::as_str))?;
+                }
+                self.mark(&table, JsonType::Object)?;
+                rlua::Value::Table(table)
+            },
+            JsonValue::Array(a) if self.options.set_paths.iter().any(|p| p.matches(&self.path)) => {
+                rlua::Value::Table(self.array_to_set(a)?)
+            },
+            JsonValue::Array(a) if self.options.columnar_paths.iter().any(|p| p.matches(&self.path)) && uniform_records(a).is_some() => {
+                rlua::Value::Table(self.array_to_columns(a)?)
+            },
+            #[cfg(feature = "luajit")]
+            JsonValue::Array(a) if self.options.ffi_number_arrays.is_some_and(|min| a.len() >= min.max(1))
+                && self.numbers_in_bulk() && is_number_array(a) => {
+                self.advance(a.len())?;
+                double_array(lua, a)?
+            },
+            // Arrays of numbers, e.g. matrices: no per-element paths, lookups or dispatch.
+            JsonValue::Array(a) if self.numbers_in_bulk() && is_number_array(a) => {
+                self.advance(a.len())?;
+                let table = lua.create_sequence_from(a.iter().map(|v| match v.as_i64() {
+                    Some(i) => integer(i),
+                    None => rlua::Value::Number(v.as_f64().unwrap_or_default()),
+                }))?;
+                self.mark(&table, JsonType::Array)?;
+                rlua::Value::Table(table)
+            },
+            JsonValue::Array(a) => {
+                let table = lua.create_table_with_capacity(a.len(), 0)?;
+                for (i, v) in a.iter().enumerate() {
+                    if let Some(pointer) = self.reference(v) {
+                        self.references.push((table.clone(), integer(i as i64 + 1), pointer.to_string()));
+                        continue;
+                    }
+                    self.path.push(PathSegment::Index(i));
+                    self.shape = shape.and_then(Shape::element);
+                    table.raw_set(i + 1, self.convert(v)?)?;
+                    self.path.pop();
+                }
+                self.mark(&table, JsonType::Array)?;
+                rlua::Value::Table(table)
+            },
+        };
+
+        let result = match result {
+            rlua::Value::Table(table) if self.options.read_only || self.is_shared(value) => {
+                rlua::Value::Table(read_only_view(lua, table)?)
+            },
+            result => result,
+        };
+        if let rlua::Value::Table(table) = &result {
+            self.register_table(table);
+        }
+        Ok(result)
+    }
+
+    /// An integer the backend cannot represent exactly, handled per [`BigIntegerPolicy`].
+    fn big_integer(&self, n: &serde_json::Number) -> rlua::Result<rlua::Value<'lua>> {
+        match self.options.big_integers {
+            BigIntegerPolicy::Float => {
+                let f = n.as_f64().unwrap_or(f64::NAN);
+                self.diagnose(DiagnosticKind::LossyNumber { original: n.to_string(), converted: f.to_string() });
+                Ok(rlua::Value::Number(f))
+            },
+            BigIntegerPolicy::String => n.to_string().into_lua(self.lua),
+            BigIntegerPolicy::Error => Err(rlua::Error::ToLuaConversionError {
+                from: "JsonValue::Number", to: "Value::Integer",
+                message: Some(format!("{}: {} does not fit a Lua integer", self.path, n)) }),
+            #[cfg(feature = "luajit")]
+            BigIntegerPolicy::Cdata => int64_cdata(self.lua, n),
+        }
+    }
+
+    fn diagnose(&self, kind: DiagnosticKind) {
+        diagnose(self.options, &self.path, kind);
+    }
+
+    /// Whether `value` becomes a table shared with identical subtrees, which is then frozen.
+    fn is_shared(&self, value: &JsonValue) -> bool {
+        self.subtrees.as_ref().is_some_and(|subtrees| subtrees.is_shared(value))
+    }
+
+    pub fn mark(&self, table: &rlua::Table<'lua>, json_type: JsonType) -> rlua::Result<()> {
+        if self.options.json_type_metatables {
+            table.set_metatable(Some(json_type_metatable(self.lua, json_type)?));
+        }
+        Ok(())
+    }
+
+    /// `["a", "b"]` becomes `{a = true, b = true}`.
+    fn array_to_set(&mut self, a: &[JsonValue]) -> rlua::Result<rlua::Table<'lua>> {
+        let table = self.lua.create_table_with_capacity(0, a.len())?;
+        for v in a {
+            match v {
+                JsonValue::String(_) | JsonValue::Number(_) => table.raw_set(self.convert(v)?, true)?,
+                _ => return Err(rlua::Error::ToLuaConversionError {
+                    from: "JsonValue::Array", to: "set table",
+                    message: Some(format!("{}: set elements must be strings or numbers", self.path)) }),
+            }
+        }
+        Ok(table)
+    }
+
+    /// `[{"id": 1, "name": "a"}, ...]` as `{id = {1, ...}, name = {"a", ...}}`.
+    fn array_to_columns(&mut self, a: &[JsonValue]) -> rlua::Result<rlua::Table<'lua>> {
+        let keys = uniform_records(a).unwrap_or_default();
+        let table = self.lua.create_table_with_capacity(0, keys.len())?;
+        for key in keys {
+            let column = self.lua.create_table_with_capacity(a.len(), 0)?;
+            for (i, record) in a.iter().enumerate() {
+                let Some(v) = record.get(key) else { continue };
+                self.path.push(PathSegment::Index(i));
+                self.path.push(PathSegment::Key(key.to_string()));
+                let value = self.convert(v);
+                self.path.pop();
+                self.path.pop();
+                column.raw_set(i + 1, value?)?;
+            }
+            self.mark(&column, JsonType::Array)?;
+            table.raw_set(key, column)?;
+        }
+        self.mark(&table, JsonType::Object)?;
+        Ok(table)
+    }
+}
+
+/// The keys shared by every element, if the array is non-empty and all elements are objects
+/// with the same keys.
+fn uniform_records(a: &[JsonValue]) -> Option<Vec<&str>> {
+    let first = a.first()?.as_object()?;
+    let same = |v: &JsonValue| v.as_object().is_some_and(|o| o.len() == first.len() && first.keys().all(|k| o.contains_key(k)));
+    a.iter().all(same).then(|| first.keys().map(String::as_str).collect())
+}
+
+/// The base64 text of a `{"$type": "data", "value": "..."}` object.
+#[cfg(feature = "luau")]
+fn tagged_data(o: &Map<String, JsonValue>) -> Option<&str> {
+    match (o.len(), o.get("$type").and_then(JsonValue::as_str), o.get("value")) {
+        (2, Some("data"), Some(JsonValue::String(text))) => Some(text),
+        _ => None,
+    }
+}
+
+/// Without native integers (LuaJIT, Luau) only integers up to 2^53 survive a trip through `lua_Number`.
+pub(crate) fn fits_lua_integer(i: i64) -> bool {
+    cfg!(not(any(feature = "luajit", feature = "luau"))) || i.unsigned_abs() <= 1 << 53
+}
+
+#[cfg(not(feature = "luau"))]
+pub(crate) fn integer<'lua>(i: i64) -> rlua::Value<'lua> {
+    rlua::Value::Integer(i)
+}
+
+/// mlua's Luau `Integer` is 32 bits wide; larger values are delivered as numbers.
+#[cfg(feature = "luau")]
+pub(crate) fn integer<'lua>(i: i64) -> rlua::Value<'lua> {
+    i32::try_from(i).map_or(rlua::Value::Number(i as f64), rlua::Value::Integer)
+}
+
+/// Whether every element is a number Lua represents without a [`BigIntegerPolicy`] decision.
+fn is_number_array(a: &[JsonValue]) -> bool {
+    !a.is_empty() && a.iter().all(|v| match v {
+        JsonValue::Number(n) => n.as_i64().map_or(n.is_f64(), fits_lua_integer),
+        _ => false,
+    })
+}
+
+/// Builds a zero-based `double[?]` cdata of the numbers through the `ffi` library.
+#[cfg(feature = "luajit")]
+fn double_array<'lua>(lua: &'lua Lua, a: &[JsonValue]) -> rlua::Result<rlua::Value<'lua>> {
+    const KEY: &str = "rlua_json.double_array_ctor";
+    let ctor = match lua.named_registry_value::<Option<rlua::Function>>(KEY)? {
+        Some(ctor) => ctor,
+        None => {
+            let ctor: rlua::Function = lua.load(r#"
+                local ffi = package.loaded.ffi or error("ffi_number_arrays needs the ffi library")
+                return function(bytes, n)
+                    local array = ffi.new("double[?]", n)
+                    ffi.copy(array, bytes, n * 8)
+                    return array
+                end
+            "#).eval()?;
+            lua.set_named_registry_value(KEY, ctor.clone())?;
+            ctor
+        },
+    };
+    let bytes: Vec<u8> = a.iter().flat_map(|v| v.as_f64().unwrap_or_default().to_ne_bytes()).collect();
+    ctor.call((lua.create_string(&bytes)?, a.len()))
+}
+
+/// Builds an `int64_t`/`uint64_t` cdata through the `ffi` library, which the host must have opened.
+#[cfg(feature = "luajit")]
+fn int64_cdata<'lua>(lua: &'lua Lua, n: &serde_json::Number) -> rlua::Result<rlua::Value<'lua>> {
+    const KEY: &str = "rlua_json.int64_ctor";
+    let ctor = match lua.named_registry_value::<Option<rlua::Function>>(KEY)? {
+        Some(ctor) => ctor,
+        None => {
+            let ctor: rlua::Function = lua.load(r#"
+                local ffi = package.loaded.ffi or error("BigIntegerPolicy::Cdata needs the ffi library")
+                return function(unsigned, hi, lo)
+                    return ffi.new(unsigned and "uint64_t" or "int64_t", hi) * 0x100000000 + lo
+                end
+            "#).eval()?;
+            lua.set_named_registry_value(KEY, ctor.clone())?;
+            ctor
+        },
+    };
+    let (unsigned, bits) = match n.as_i64() {
+        Some(i) => (false, i as u64),
+        None => (true, n.as_u64().unwrap_or_default()),
+    };
+    let hi = if unsigned { (bits >> 32) as f64 } else { ((bits as i64) >> 32) as f64 };
+    ctor.call((unsigned, hi, (bits & 0xffff_ffff) as f64))
+}
+
+/// Hands a diagnostic to the handler of `options`, and with the `tracing` feature emits it.
+pub(crate) fn diagnose(options: &ConversionOptions, path: &Path, kind: DiagnosticKind) {
+    if options.diagnostics.is_none() && cfg!(not(feature = "tracing")) {
+        return;
+    }
+    let diagnostic = Diagnostic { path: path.clone(), kind };
+    #[cfg(feature = "tracing")]
+    diagnostic.trace();
+    if let Some(handler) = &options.diagnostics {
+        (handler.0)(&diagnostic);
+    }
+}
+
+/// Key census of a table: positive integer keys (count and highest) and all other keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KeyShape {
+    pub integers: usize,
+    pub max: usize,
+    pub others: usize,
+}
+
+pub(crate) fn key_shape(table: &rlua::Table) -> rlua::Result<KeyShape> {
+    let mut shape = KeyShape::default();
+    for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+        match pair?.0 {
+            rlua::Value::Integer(i) if i >= 1 => {
+                shape.integers += 1;
+                shape.max = shape.max.max(i as usize);
+            },
+            _ => shape.others += 1,
+        }
+    }
+    Ok(shape)
+}
+
+/// Decides whether a table with positive integer keys `count`/`max` is encoded as an array.
+pub(crate) fn encode_as_array(count: usize, max: usize, options: &ConversionOptions) -> rlua::Result<bool> {
+    if count == max {
+        return Ok(true);
+    }
+    match options.sparse_arrays {
+        SparseArrayPolicy::Object => Ok(false),
+        SparseArrayPolicy::Pad { ratio, safe } => Ok(max <= safe || max <= count.saturating_mul(ratio)),
+        SparseArrayPolicy::Error => Err(rlua::Error::FromLuaConversionError {
+            from: "Table", to: "JsonValue",
+            message: Some(format!("sparse array: {} values, highest index {}", count, max)) }),
+    }
+}
+
+/// Returns the sorted keys of a non-empty table whose keys are all strings and values all `true`.
+pub(crate) fn set_keys(table: &rlua::Table) -> rlua::Result<Option<Vec<String>>> {
+    let mut keys = Vec::new();
+    for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+        match pair? {
+            (rlua::Value::String(k), rlua::Value::Boolean(true)) => keys.push(k.to_str()?.to_string()),
+            _ => return Ok(None),
+        }
+    }
+    keys.sort();
+    Ok(if keys.is_empty() { None } else { Some(keys) })
+}
+
+/// Whether a float has an integral value that fits `i64` exactly.
+pub(crate) fn is_i64(n: f64) -> bool {
+    n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64
+}
+
+/// `tostring` of an `int64_t`/`uint64_t` cdata is `123LL`/`123ULL`.
+#[cfg(feature = "luajit")]
+fn cdata_integer<'lua>(lua: &'lua Lua, ud: rlua::AnyUserData<'lua>) -> rlua::Result<Option<JsonValue>> {
+    let tostring: rlua::Function = lua.globals().get("tostring")?;
+    let s: String = tostring.call(ud)?;
+    let n = match s.strip_suffix("ULL") {
+        Some(digits) => digits.parse::<u64>().ok().map(JsonValue::from),
+        None => s.strip_suffix("LL").and_then(|digits| digits.parse::<i64>().ok()).map(JsonValue::from),
+    };
+    Ok(n)
+}
+
+/// Registry tables of the functions encoded under [`FunctionPolicy::Handle`], by handle and back.
+const FUNCTIONS_KEY: &str = "rlua_json.functions";
+const FUNCTION_HANDLES_KEY: &str = "rlua_json.function_handles";
+
+fn registry_table<'lua>(lua: &'lua Lua, key: &str) -> rlua::Result<rlua::Table<'lua>> {
+    if let Some(table) = lua.named_registry_value::<Option<rlua::Table>>(key)? {
+        return Ok(table);
+    }
+    let table = lua.create_table()?;
+    lua.set_named_registry_value(key, table.clone())?;
+    Ok(table)
+}
+
+/// The handle of `f`, registering it on first use. Functions stay registered for the
+/// lifetime of the Lua state.
+fn function_handle<'lua>(lua: &'lua Lua, f: rlua::Function<'lua>) -> rlua::Result<i64> {
+    let handles = registry_table(lua, FUNCTION_HANDLES_KEY)?;
+    if let Some(handle) = handles.raw_get::<_, Option<i64>>(f.clone())? {
+        return Ok(handle);
+    }
+    let functions = registry_table(lua, FUNCTIONS_KEY)?;
+    let handle = functions.raw_len() as i64 + 1;
+    functions.raw_set(handle, f.clone())?;
+    handles.raw_set(f, handle)?;
+    Ok(handle)
+}
+
+/// `{"$error": {"message": ..., "traceback": ...}}` for [`ConversionOptions::structured_errors`].
+fn structured_error(error: &rlua::Error) -> JsonValue {
+    let (mut cause, mut traceback) = (error, None);
+    while let rlua::Error::CallbackError { cause: inner, traceback: t } = cause {
+        traceback = Some(t);
+        cause = inner;
+    }
+    let mut details = Map::new();
+    details.insert("message".to_string(), JsonValue::from(cause.to_string()));
+    details.insert("traceback".to_string(), traceback.map_or(JsonValue::Null, |t| JsonValue::from(t.as_str())));
+    let mut o = Map::new();
+    o.insert("$error".to_string(), JsonValue::Object(details));
+    JsonValue::Object(o)
+}
+
+fn object_key<'lua>(lua: &'lua Lua, key: rlua::Value<'lua>) -> rlua::Result<String> {
+    let type_name = key.type_name();
+    match lua.coerce_string(key)? {
+        Some(s) => Ok(s.to_str()?.to_string()),
+        None => Err(rlua::Error::FromLuaConversionError {
+            from: type_name, to: "JsonValue", message: Some("object keys must be strings or numbers".to_string()) }),
+    }
+}
+
+/// Lua to JSON, tracking the current position in the document.
+pub(crate) struct Encoder<'a, 'lua> {
+    pub lua: &'lua Lua,
+    pub options: &'a ConversionOptions,
+    pub path: Path,
+    /// Collects [`RawJson`] fragments when encoding to text; without it they are parsed.
+    pub raws: Option<Vec<Box<RawValue>>>,
+    pub stats: ConversionStats,
+    /// Tables being converted, to detect cycles.
+    ancestors: HashSet<*const c_void>,
+    /// Where each table was first written, unless aliases are duplicated.
+    seen: HashMap<*const c_void, String>,
+    budget: Option<InstructionBudget>,
+    /// Set when the value just converted was skipped under [`UnsupportedPolicy::Skip`].
+    skipped: bool,
+    /// When collecting errors: every failure so far; failed values are written as `null`.
+    pub errors: Option<Vec<ConversionError>>,
+    gc: GcPacer<'lua>,
+    cancel: CancelCheck,
+    progress: ProgressReporter,
+}
+
+impl<'a, 'lua> Encoder<'a, 'lua> {
+    pub fn new(lua: &'lua Lua, options: &'a ConversionOptions) -> Self {
+        Encoder {
+            lua, options, path: Path::new(), raws: None, stats: ConversionStats::default(),
+            ancestors: HashSet::new(), seen: HashMap::new(),
+            budget: InstructionBudget::new(options.hook_instruction_budget),
+            skipped: false, errors: None, gc: GcPacer::new(lua, options.gc_hint),
+            cancel: CancelCheck::new(options.cancellation.as_ref()),
+            progress: ProgressReporter::new(options.progress.as_ref()),
+        }
+    }
+
+    /// Records a failure at the current path when collecting errors.
+    fn recover<T>(&mut self, result: rlua::Result<T>) -> rlua::Result<Option<T>> {
+        match (result, &mut self.errors) {
+            (Err(error), Some(errors)) => {
+                errors.push(ConversionError { path: self.path.clone(), error });
+                Ok(None)
+            },
+            (result, _) => result.map(Some),
+        }
+    }
+
+    /// [`convert`](Self::convert), also timing it and measuring the JSON text of the result.
+    pub fn convert_counted(&mut self, value: rlua::Value<'lua>) -> rlua::Result<JsonValue> {
+        let (result, elapsed) = ConversionStats::timed(|| self.convert(value));
+        self.stats.elapsed = elapsed;
+        self.stats.output_bytes = result.as_ref().map_or(0, ConversionStats::json_bytes);
+        result
+    }
+
+    pub fn convert(&mut self, value: rlua::Value<'lua>) -> rlua::Result<JsonValue> {
+        self.stats.node(self.path.len());
+        self.gc.tick()?;
+        self.cancel.tick().inspect_err(|_| self.diagnose(DiagnosticKind::Cancelled))?;
+        self.progress.tick();
+        self.skipped = false;
+        if let Some(mapping) = enum_mapping(self.options, &self.path) {
+            #[allow(clippy::useless_conversion)] // `rlua::Integer` is 32 bits wide on Luau
+            let number = match value {
+                rlua::Value::Integer(i) => Some(i64::from(i)),
+                rlua::Value::Number(n) if is_i64(n) => Some(n as i64),
+                _ => None,
+            };
+            if let Some(i) = number {
+                match mapping.name(i) {
+                    Some(name) => return Ok(JsonValue::from(name)),
+                    None => self.diagnose(DiagnosticKind::UnknownEnumValue { value: i.to_string() }),
+                }
+            }
+        }
+        if let Some(format) = number_format(self.options, &self.path) {
+            match value {
+                rlua::Value::Integer(i) => {
+                    #[allow(clippy::useless_conversion)] // `rlua::Integer` is 32 bits wide on Luau
+                    let i = i64::from(i);
+                    let text = format.format_integer(i);
+                    if format.scale != 1.0 && i as f64 as i64 != i {
+                        self.diagnose(DiagnosticKind::LossyNumber { original: i.to_string(), converted: text.clone() });
+                    }
+                    return Ok(JsonValue::from(text));
+                },
+                rlua::Value::Number(n) if n.is_finite() => return Ok(JsonValue::from(format.format(n))),
+                _ => {},
+            }
+        }
+        let result = match value {
+            rlua::Value::Nil => JsonValue::Null,
+            rlua::Value::Boolean(b) => match bool_encoding(self.options, &self.path) {
+                Some(encoding) => encoding.encode(b),
+                None => JsonValue::Bool(b),
+            },
+            rlua::Value::LightUserData(ud) if ud.0.is_null() => JsonValue::Null,
+            rlua::Value::LightUserData(_) => self.unsupported(value)?,
+            rlua::Value::Integer(i) => JsonValue::from(i),
+            rlua::Value::Number(n) if self.options.integral_floats_as_integers && is_i64(n) => {
+                JsonValue::from(n as i64)
+            },
+            rlua::Value::Number(n) if !n.is_finite() => {
+                self.diagnose(DiagnosticKind::LossyNumber { original: n.to_string(), converted: "null".to_string() });
+                JsonValue::Null
+            },
+            rlua::Value::Number(n) => JsonValue::from(n),
+            rlua::Value::String(s) => {
+                let s = s.to_str()?;
+                self.stats.string(s.len());
+                JsonValue::from(s)
+            },
+            rlua::Value::Table(t) => {
+                if self.options.tojson_metamethod {
+                    if let Some(encoded) = self.call_tojson(&t)? {
+                        return Ok(encoded);
+                    }
+                }
+
+                let result = self.table(t)?;
+                self.skipped = false;
+                result
+            }
+            rlua::Value::Error(e) if self.options.structured_errors => structured_error(&e),
+            rlua::Value::Function(f) if self.options.functions != FunctionPolicy::Unsupported => self.function(f)?,
+            rlua::Value::Function(_) | rlua::Value::Thread(_) | rlua::Value::Error(_) => self.unsupported(value)?,
+            #[cfg(feature = "luau")]
+            rlua::Value::UserData(_) if value.is_buffer() => self.buffer(value)?,
+            rlua::Value::UserData(ud) => self.userdata(ud)?,
+            #[cfg(feature = "luau")]
+            rlua::Value::Vector(v) => self.vector(v)?,
+        };
+
+        if self.path.is_empty() {
+            diagnose_unknown_keys(self.options, &result);
+        }
+        Ok(result)
+    }
+
+    fn userdata(&mut self, ud: rlua::AnyUserData<'lua>) -> rlua::Result<JsonValue> {
+        if let Ok(raw) = ud.borrow::<RawJson>() {
+            return match &mut self.raws {
+                Some(raws) => {
+                    raws.push(raw.0.clone());
+                    let mut marker = Map::new();
+                    marker.insert(RAW_KEY.to_string(), JsonValue::from(raws.len() - 1));
+                    Ok(JsonValue::Object(marker))
+                },
+                None => serde_json::from_str(raw.get()).map_err(rlua::Error::external),
+            };
+        }
+        if let Ok(s) = ud.borrow::<LazyString>() {
+            self.stats.string(s.0.len());
+            return Ok(JsonValue::from(s.get()));
+        }
+        #[cfg(feature = "luajit")]
+        if let Some(n) = cdata_integer(self.lua, ud.clone())? {
+            return Ok(n);
+        }
+        self.unsupported(rlua::Value::UserData(ud))
+    }
+
+    #[cfg(feature = "luau")]
+    fn buffer(&mut self, buffer: rlua::Value<'lua>) -> rlua::Result<JsonValue> {
+        let base64 = |buffer| -> rlua::Result<JsonValue> {
+            let library: rlua::Table = self.lua.globals().get("buffer")?;
+            let bytes: rlua::String = library.get::<_, rlua::Function>("tostring")?.call(buffer)?;
+            Ok(JsonValue::String(crate::base64::encode(bytes.as_bytes())))
+        };
+        match self.options.buffers {
+            BufferPolicy::Tagged => {
+                let mut o = Map::new();
+                o.insert("$type".to_string(), JsonValue::from("data"));
+                o.insert("value".to_string(), base64(buffer)?);
+                Ok(JsonValue::Object(o))
+            },
+            BufferPolicy::Base64 => base64(buffer),
+            BufferPolicy::Unsupported => self.unsupported(buffer),
+        }
+    }
+
+    #[cfg(feature = "luau")]
+    fn vector(&mut self, v: rlua::Vector) -> rlua::Result<JsonValue> {
+        match self.options.vectors {
+            VectorPolicy::Array => Ok(JsonValue::from(vec![v.x(), v.y(), v.z()])),
+            VectorPolicy::Object => {
+                let mut o = Map::new();
+                for (axis, n) in [("x", v.x()), ("y", v.y()), ("z", v.z())] {
+                    o.insert(axis.to_string(), JsonValue::from(n));
+                }
+                Ok(JsonValue::Object(o))
+            },
+            VectorPolicy::Unsupported => self.unsupported(rlua::Value::Vector(v)),
+        }
+    }
+
+    fn function(&mut self, f: rlua::Function<'lua>) -> rlua::Result<JsonValue> {
+        match &self.options.functions {
+            FunctionPolicy::Unsupported => self.unsupported(rlua::Value::Function(f)),
+            FunctionPolicy::Skip => Ok(self.skip("function")),
+            FunctionPolicy::Placeholder(placeholder) => Ok(JsonValue::from(placeholder.as_str())),
+            FunctionPolicy::Handle => {
+                let mut o = Map::new();
+                o.insert("$func".to_string(), JsonValue::from(function_handle(self.lua, f)?));
+                Ok(JsonValue::Object(o))
+            },
+            #[cfg(feature = "bytecode")]
+            FunctionPolicy::Bytecode => {
+                let mut o = Map::new();
+                o.insert("$bytecode".to_string(), JsonValue::from(crate::bytecode::dump(&f)?));
+                Ok(JsonValue::Object(o))
+            },
+        }
+    }
+
+    /// A value without a JSON equivalent, handled per [`UnsupportedPolicy`].
+    fn unsupported(&mut self, value: rlua::Value<'lua>) -> rlua::Result<JsonValue> {
+        let type_name = value.type_name();
+        let tostring = |value| -> rlua::Result<JsonValue> {
+            let tostring: rlua::Function = self.lua.globals().get("tostring")?;
+            Ok(JsonValue::String(tostring.call::<_, rlua::String>(value)?.to_str()?.to_string()))
+        };
+        match self.options.unsupported_values {
+            UnsupportedPolicy::Error => Err(impossible(type_name)),
+            UnsupportedPolicy::ToString => tostring(value),
+            UnsupportedPolicy::Tagged => {
+                let mut o = Map::new();
+                o.insert("$type".to_string(), JsonValue::from(type_name));
+                o.insert("value".to_string(), tostring(value)?);
+                Ok(JsonValue::Object(o))
+            },
+            UnsupportedPolicy::Skip => Ok(self.skip(type_name)),
+        }
+    }
+
+    fn skip(&mut self, type_name: &'static str) -> JsonValue {
+        self.diagnose(DiagnosticKind::Skipped { type_name });
+        self.skipped = true;
+        JsonValue::Null
+    }
+
+    fn diagnose(&self, kind: DiagnosticKind) {
+        diagnose(self.options, &self.path, kind);
+    }
+
+    fn insert(&self, o: &mut Map<String, JsonValue>, key: String, value: JsonValue) {
+        if (self.options.diagnostics.is_some() || cfg!(feature = "tracing")) && o.contains_key(&key) {
+            self.diagnose(DiagnosticKind::DuplicateKey { key: key.clone() });
+        }
+        o.insert(key, value);
+    }
+
+    fn object_key(&mut self, key: rlua::Value<'lua>) -> rlua::Result<String> {
+        let key = object_key(self.lua, key)?;
+        self.stats.string(key.len());
+        Ok(key)
+    }
+
+    fn convert_at(&mut self, segment: PathSegment, value: rlua::Value<'lua>) -> rlua::Result<JsonValue> {
+        self.path.push(segment);
+        let result = self.convert(value);
+        let result = self.recover(result);
+        self.path.pop();
+        Ok(result?.unwrap_or(JsonValue::Null))
+    }
+
+    /// An object member; `None` if it is skipped.
+    fn member(&mut self, segment: PathSegment, value: rlua::Value<'lua>) -> rlua::Result<Option<JsonValue>> {
+        let value = self.convert_at(segment, value)?;
+        Ok(if std::mem::take(&mut self.skipped) { None } else { Some(value) })
+    }
+
+    fn sequence(&mut self, table: &rlua::Table<'lua>, len: usize) -> rlua::Result<JsonValue> {
+        let mut a = Vec::with_capacity(len);
+        for i in 1..=len {
+            a.push(self.convert_at(PathSegment::Index(i - 1), table.raw_get(i)?)?);
+        }
+        Ok(JsonValue::Array(a))
+    }
+
+    fn alias_error(&self, message: String) -> rlua::Error {
+        rlua::Error::FromLuaConversionError { from: "Table", to: "JsonValue", message: Some(message) }
+    }
+
+    fn table(&mut self, table: rlua::Table<'lua>) -> rlua::Result<JsonValue> {
+        if self.path.len() > MAX_DEPTH {
+            self.diagnose(DiagnosticKind::DepthLimit { max: MAX_DEPTH });
+            return Err(too_deep(&self.path));
+        }
+        let table = view_contents(&table)?.unwrap_or(table);
+        let pointer = table.to_pointer();
+        let reference = |path: &str| {
+            let mut o = Map::new();
+            o.insert("$ref".to_string(), JsonValue::String(format!("#{}", path)));
+            JsonValue::Object(o)
+        };
+
+        if self.ancestors.contains(&pointer) {
+            return match (self.options.aliases, self.seen.get(&pointer)) {
+                (AliasPolicy::Reference, Some(path)) => Ok(reference(path)),
+                _ => Err(self.alias_error(format!("{}: table contains itself", self.path))),
+            };
+        }
+        match (self.options.aliases, self.seen.get(&pointer)) {
+            (AliasPolicy::Duplicate, _) | (_, None) => {},
+            (AliasPolicy::Error, Some(path)) =>
+                return Err(self.alias_error(format!("{}: same table as {}", self.path, path))),
+            (AliasPolicy::Reference, Some(path)) => return Ok(reference(path)),
+        }
+        if self.options.aliases != AliasPolicy::Duplicate {
+            self.seen.insert(pointer, self.path.to_string());
+        }
+
+        self.ancestors.insert(pointer);
+        let result = self.table_contents(table);
+        self.ancestors.remove(&pointer);
+        result
+    }
+
+    fn table_contents(&mut self, table: rlua::Table<'lua>) -> rlua::Result<JsonValue> {
+        let options = self.options;
+
+        if options.json_type_metatables {
+            match table_json_type(&table)? {
+                Some(JsonType::Array) => return self.tagged_array(&table),
+                Some(JsonType::Object) => return self.object(&table),
+                None => {},
+            }
+        }
+
+        if options.numeric_keys && is_numeric_object(self.lua, &table)? {
+            return self.object(&table);
+        }
+
+        if options.set_array_paths.iter().any(|p| p.matches(&self.path)) {
+            if let Some(keys) = set_keys(&table)? {
+                return Ok(JsonValue::Array(keys.into_iter().map(JsonValue::String).collect()));
+            }
+        }
+
+        let shape = key_shape(&table)?;
+
+        if shape.integers > 0 && shape.others == 0 && encode_as_array(shape.integers, shape.max, options)? {
+            return self.sequence(&table, shape.max);
+        }
+
+        let split_key = match &options.mixed_tables {
+            _ if shape.integers == 0 || shape.others == 0 => None,
+            MixedTablePolicy::ObjectWithNumericKeys => None,
+            MixedTablePolicy::Split { items_key } => Some(items_key.as_str()),
+            MixedTablePolicy::Error => return Err(rlua::Error::FromLuaConversionError {
+                from: "Table", to: "JsonValue",
+                message: Some(format!("mixed table: {} array values and {} other keys", shape.integers, shape.others)) }),
+        };
+
+        let mut o = Map::new();
+        let mut items = Vec::new();
+        for (key, value) in ordered_pairs(self.lua, &table)? {
+            match key {
+                rlua::Value::Integer(i) if i >= 1 && split_key.is_some() => items.push((i as usize, value)),
+                key => {
+                    let key = self.object_key(key);
+                    let Some(key) = self.recover(key)? else { continue };
+                    if let Some(value) = self.member(PathSegment::Key(key.clone()), value)? {
+                        self.insert(&mut o, key, value);
+                    }
+                },
+            }
+        }
+
+        if let Some(items_key) = split_key {
+            if o.contains_key(items_key) {
+                return Err(rlua::Error::FromLuaConversionError {
+                    from: "Table", to: "JsonValue",
+                    message: Some(format!("mixed table already has a \"{}\" key", items_key)) });
+            }
+            self.path.push(PathSegment::Key(items_key.to_string()));
+            let items = self.split_items(&table, shape, items);
+            self.path.pop();
+            o.insert(items_key.to_string(), items?);
+        }
+
+        Ok(JsonValue::Object(sort_object(o, self.options.sort_keys)))
+    }
+
+    /// A table tagged `__jsontype = "array"`: always an array, holes padded with `null`.
+    fn tagged_array(&mut self, table: &rlua::Table<'lua>) -> rlua::Result<JsonValue> {
+        let shape = key_shape(table)?;
+        if shape.others > 0 {
+            return Err(rlua::Error::FromLuaConversionError {
+                from: "Table", to: "JsonValue",
+                message: Some(format!("{}: table marked as array has non-index keys", self.path)) });
+        }
+        self.sequence(table, shape.max)
+    }
+
+    fn object(&mut self, table: &rlua::Table<'lua>) -> rlua::Result<JsonValue> {
+        let mut o = Map::new();
+        for (key, value) in ordered_pairs(self.lua, table)? {
+            let key = self.object_key(key);
+            let Some(key) = self.recover(key)? else { continue };
+            if let Some(value) = self.member(PathSegment::Key(key.clone()), value)? {
+                self.insert(&mut o, key, value);
+            }
+        }
+        Ok(JsonValue::Object(sort_object(o, self.options.sort_keys)))
+    }
+
+    /// The array part of a mixed table under [`MixedTablePolicy::Split`].
+    fn split_items(
+        &mut self, table: &rlua::Table<'lua>, shape: KeyShape, mut items: Vec<(usize, rlua::Value<'lua>)>,
+    ) -> rlua::Result<JsonValue> {
+        if encode_as_array(shape.integers, shape.max, self.options)? {
+            return self.sequence(table, shape.max);
+        }
+        items.sort_by_key(|(i, _)| *i);
+        let mut sparse = Map::new();
+        for (i, value) in items {
+            let value = self.convert_at(PathSegment::Key(i.to_string()), value)?;
+            sparse.insert(i.to_string(), value);
+        }
+        Ok(JsonValue::Object(sparse))
+    }
+
+    /// dkjson-style `__tojson(self, state)`: the metamethod returns an already encoded JSON string.
+    fn call_tojson(&mut self, table: &rlua::Table<'lua>) -> rlua::Result<Option<JsonValue>> {
+        let tojson = match table.get_metatable() {
+            Some(mt) => mt.raw_get::<_, Option<rlua::Function>>("__tojson")?,
+            None => None,
+        };
+        let Some(tojson) = tojson else { return Ok(None) };
+
+        let state = self.lua.create_table()?;
+        state.set("indent", self.options.indent)?;
+        let encoded: rlua::String = InstructionBudget::run(self.budget.as_ref(), self.lua, || tojson.call((table.clone(), state)))
+            .inspect_err(|_| if InstructionBudget::exhausted(self.budget.as_ref()) { self.diagnose(DiagnosticKind::BudgetExceeded) })?;
+        serde_json::from_slice(encoded.as_bytes())
+            .map(Some)
+            .map_err(|e| rlua::Error::FromLuaConversionError {
+                from: "__tojson", to: "JsonValue", message: Some(e.to_string()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{AliasPolicy, BigIntegerPolicy, ConversionOptions, FunctionPolicy, MixedTablePolicy, SparseArrayPolicy, UnsupportedPolicy, json_to_lua, lua_to_json};
+    use crate::{DiagnosticHandler, lua_to_json_all_errors, json_to_lua_with_report, json_to_lua_with_stats, lua_to_json_with_report, lua_to_json_with_stats};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn sparse_array_policies() {
+        let lua = Lua::new();
+        let sparse = || lua.load("{1, 2, nil, nil, 5}").eval::<rlua::Value>().expect("table");
+
+        let mut options = ConversionOptions::default();
+        assert_eq!(lua_to_json(&lua, sparse(), &options).expect("object"),
+                   json!({"1": 1, "2": 2, "5": 5}));
+
+        options.sparse_arrays = SparseArrayPolicy::Pad { ratio: 2, safe: 10 };
+        assert_eq!(lua_to_json(&lua, sparse(), &options).expect("padded"),
+                   json!([1, 2, null, null, 5]));
+
+        options.sparse_arrays = SparseArrayPolicy::Pad { ratio: 1, safe: 4 };
+        assert!(lua_to_json(&lua, sparse(), &options).expect("too sparse").is_object());
+
+        options.sparse_arrays = SparseArrayPolicy::Error;
+        assert!(lua_to_json(&lua, sparse(), &options).is_err());
+    }
+
+    #[test]
+    fn mixed_table_policies() {
+        let lua = Lua::new();
+        let mixed = || lua.load("{1, 2, 3, name = 'x'}").eval::<rlua::Value>().expect("table");
+
+        let mut options = ConversionOptions::default();
+        assert_eq!(lua_to_json(&lua, mixed(), &options).expect("object"),
+                   json!({"1": 1, "2": 2, "3": 3, "name": "x"}));
+
+        options.mixed_tables = MixedTablePolicy::Split { items_key: "items".to_string() };
+        assert_eq!(lua_to_json(&lua, mixed(), &options).expect("split"),
+                   json!({"items": [1, 2, 3], "name": "x"}));
+
+        options.mixed_tables = MixedTablePolicy::Error;
+        assert!(lua_to_json(&lua, mixed(), &options).is_err());
+    }
+
+    #[test]
+    fn set_tables() {
+        let lua = Lua::new();
+        let options = ConversionOptions {
+            set_array_paths: vec!["/users/*/tags".into()],
+            set_paths: vec!["/users/*/tags".into()],
+            ..Default::default()
+        };
+
+        let set = lua.load("{ users = { { tags = { b = true, a = true } } }, flags = { enabled = true, visible = true } }")
+            .eval::<rlua::Value>().expect("table");
+        assert_eq!(lua_to_json(&lua, set, &options).expect("set"), json!({
+            "users": [{"tags": ["a", "b"]}], "flags": {"enabled": true, "visible": true},
+        }));
+
+        let doc = json!({"users": [{"tags": ["admin", "dev"]}], "list": ["admin"]});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        lua.globals().set("doc", value).expect("set global");
+        let (is_set, is_list): (bool, bool) = lua.load(
+            "return doc.users[1].tags.admin == true and doc.users[1].tags.dev == true, doc.list[1] == 'admin'"
+        ).eval().expect("eval");
+        assert!(is_set && is_list);
+        let value = lua.globals().get::<_, rlua::Value>("doc").expect("get global");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "luajit", feature = "lua51", feature = "lua52", feature = "luau")))]
+    fn integer_preservation() {
+        let lua = Lua::new();
+        let options = ConversionOptions { integral_floats_as_integers: true, ..Default::default() };
+
+        let value = lua.load("{ a = 3.0, b = 2^53, c = 0.5 }").eval::<rlua::Value>().expect("table");
+        let encoded = lua_to_json(&lua, value, &options).expect("encode");
+        assert_eq!(encoded, json!({"a": 3, "b": 9007199254740992i64, "c": 0.5}));
+        assert!(encoded["a"].is_i64());
+
+        let doc = json!({"max": i64::MAX, "min": i64::MIN, "float": 1.5});
+        lua.globals().set("doc", json_to_lua(&lua, &doc, &options).expect("decode")).expect("set global");
+        let types: (String, String, String, bool) = lua.load(
+            "return math.type(doc.max), math.type(doc.min), math.type(doc.float), doc.max == math.maxinteger"
+        ).eval().expect("eval");
+        assert_eq!(types, ("integer".into(), "integer".into(), "float".into(), true));
+    }
+
+    #[test]
+    fn big_integers() {
+        let lua = Lua::new();
+        let mut options = ConversionOptions { big_integers: BigIntegerPolicy::String, ..Default::default() };
+        let doc = json!([u64::MAX]);
+
+        let value = json_to_lua(&lua, &doc, &options).expect("string");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), json!([u64::MAX.to_string()]));
+
+        options.big_integers = BigIntegerPolicy::Error;
+        assert!(json_to_lua(&lua, &doc, &options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "luajit")]
+    fn luajit_int64_cdata() {
+        use rlua::{LuaOptions, StdLib};
+
+        let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE | StdLib::FFI, LuaOptions::default()) };
+        let options = ConversionOptions { big_integers: BigIntegerPolicy::Cdata, ..Default::default() };
+        let doc = json!([i64::MIN, -(1i64 << 60) - 7, u64::MAX, 42]);
+
+        let value = json_to_lua(&lua, &doc, &options).expect("cdata");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+    }
+
+    #[test]
+    #[cfg(feature = "luajit")]
+    fn luajit_double_arrays() {
+        use rlua::{LuaOptions, StdLib};
+
+        let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE | StdLib::FFI, LuaOptions::default()) };
+        let options = ConversionOptions { ffi_number_arrays: Some(3), ..Default::default() };
+        let value = json_to_lua(&lua, &json!({"m": [0.5, 2, -3], "short": [1, 2]}), &options).expect("cdata");
+        lua.globals().set("doc", value).expect("set");
+        let (sum, short): (f64, i64) = lua.load("doc.m[0] + doc.m[1] + doc.m[2], #doc.short").eval().expect("eval");
+        assert_eq!((sum, short), (-0.5, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "luau")]
+    fn luau_buffers_and_vectors() {
+        use crate::{BufferPolicy, VectorPolicy};
+
+        let lua = Lua::new();
+        let value = lua.load("{ blob = buffer.fromstring('\\0\\1z'), at = vector(1, 2.5, -3) }").eval::<rlua::Value>().expect("value");
+        let options = ConversionOptions::default();
+        let encoded = lua_to_json(&lua, value.clone(), &options).expect("encode");
+        assert_eq!(encoded, json!({"blob": {"$type": "data", "value": "AAF6"}, "at": [1.0, 2.5, -3.0]}));
+        lua.globals().set("doc", json_to_lua(&lua, &encoded, &options).expect("decode")).expect("set");
+        assert!(lua.load("type(doc.blob) == 'buffer' and buffer.readu8(doc.blob, 2) == 122").eval::<bool>().expect("eval"));
+        assert!(json_to_lua(&lua, &json!({"$type": "data", "value": "not base64!"}), &options).is_err());
+
+        let options = ConversionOptions { buffers: BufferPolicy::Base64, vectors: VectorPolicy::Object, ..Default::default() };
+        assert_eq!(lua_to_json(&lua, value.clone(), &options).expect("encode"), json!({"blob": "AAF6", "at": {"x": 1.0, "y": 2.5, "z": -3.0}}));
+        assert!(json_to_lua(&lua, &json!({"$type": "data", "value": "AAF6"}), &options).expect("decode").is_table());
+        let options = ConversionOptions { buffers: BufferPolicy::Unsupported, vectors: VectorPolicy::Unsupported, ..Default::default() };
+        assert!(lua_to_json(&lua, value, &options).is_err());
+    }
+
+    #[test]
+    fn number_arrays() {
+        use crate::{BoolEncoding, CancellationToken, NumberFormat};
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let doc = json!({"matrix": [[1, 2.5], [-3, 0.25]], "mixed": [1, "two"]});
+        let (value, stats) = json_to_lua_with_stats(&lua, &doc, &options).expect("decode");
+        assert_eq!(stats.nodes, 11);
+        lua.globals().set("doc", value.clone()).expect("set");
+        assert!(lua.load("doc.matrix[1][1] == 1 and doc.matrix[2][2] == 0.25").eval::<bool>().expect("eval"));
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+
+        let options = ConversionOptions {
+            number_format_paths: vec![("/prices/*".into(), NumberFormat { decimals: Some(2), ..NumberFormat::english() })],
+            bool_paths: vec![("/flags/*".into(), BoolEncoding::Integer)],
+            ..Default::default()
+        };
+        let doc = json!({"prices": [1234.5, 2], "flags": [0, 1, 1], "matrix": [[1, 2]]});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        lua.globals().set("doc", value.clone()).expect("set");
+        assert!(lua.load("doc.prices[1] == 1234.5 and doc.flags[1] == false and doc.flags[3] == true").eval::<bool>().expect("eval"));
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), json!({
+            "prices": ["1,234.50", "2.00"], "flags": [0, 1, 1], "matrix": [[1, 2]],
+        }));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = ConversionOptions { cancellation: Some(token), ..Default::default() };
+        assert!(json_to_lua(&lua, &json!([[1, 2, 3]]), &options).is_err());
+    }
+
+    #[test]
+    fn columnar_arrays() {
+        let lua = Lua::new();
+        let options = ConversionOptions { columnar_paths: vec!["/rows".into(), "/ragged".into()], ..Default::default() };
+        let doc = json!({
+            "rows": [{"id": 1, "name": "a", "tags": ["x"]}, {"id": 2, "name": null, "tags": []}],
+            "ragged": [{"id": 1}, {"other": 2}],
+        });
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        lua.globals().set("doc", value.clone()).expect("set");
+        let (ids, name, tag, ragged): (i64, String, String, i64) = lua.load(
+            "#doc.rows.id, doc.rows.name[1] .. tostring(doc.rows.name[2]), doc.rows.tags[1][1], doc.ragged[2].other"
+        ).eval().expect("eval");
+        assert_eq!((ids, name.as_str(), tag.as_str(), ragged), (2, "anil", "x", 2));
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), json!({
+            "rows": {"id": [1, 2], "name": ["a"], "tags": [["x"], {}]},
+            "ragged": [{"id": 1}, {"other": 2}],
+        }));
+    }
+
+    #[test]
+    fn stats() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let doc = json!({"ab": [1, "xyz"]});
+
+        let (value, stats) = json_to_lua_with_stats(&lua, &doc, &options).expect("decode");
+        assert_eq!((stats.nodes, stats.max_depth, stats.strings, stats.string_bytes), (4, 2, 2, 5));
+        let (_, stats) = lua_to_json_with_stats(&lua, value, &options).expect("encode");
+        assert_eq!((stats.nodes, stats.max_depth, stats.strings, stats.string_bytes), (4, 2, 2, 5));
+        assert_eq!(stats.output_bytes, r#"{"ab":[1,"xyz"]}"#.len());
+        let lazy = ConversionOptions { lazy_strings: Some(2), ..Default::default() };
+        let (value, stats) = json_to_lua_with_stats(&lua, &doc, &lazy).expect("decode");
+        assert_eq!((stats.strings, stats.string_bytes), (2, 5));
+        let (_, stats) = lua_to_json_with_stats(&lua, value, &lazy).expect("encode");
+        assert_eq!((stats.strings, stats.string_bytes), (2, 5));
+
+        let rows = json!((0..1000).map(|i| format!("row {}", i)).collect::<Vec<_>>());
+        let (_, stats) = json_to_lua_with_stats(&lua, &rows, &options).expect("decode");
+        assert!(stats.output_bytes > 1000 * "row 000".len(), "{:?}", stats);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn conversion_spans() {
+        use crate::stats::capture::Capture;
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let capture = Capture::default();
+        let lines = capture.lines.clone();
+        tracing::subscriber::with_default(capture, || {
+            let value = json_to_lua(&lua, &json!({"ab": [1, "xyz"]}), &options).expect("decode");
+            lua_to_json(&lua, value, &options).expect("encode");
+        });
+        let lines = lines.lock().expect("lines").join("\n");
+        assert!(lines.contains("span conversion direction=\"decode\"") && lines.contains("span conversion direction=\"encode\""), "{}", lines);
+        for field in ["record nodes=4", "record max_depth=2", "record output_bytes=16", "record elapsed_us="] {
+            assert!(lines.contains(field), "{}: {}", field, lines);
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let deep = lua.load("local t = {} for _ = 1, 1000 do t = { t } end return t").eval().expect("table");
+        let error = lua_to_json(&lua, deep, &options).expect_err("too deep");
+        assert!(error.to_string().contains("nesting deeper than 128 levels"), "{}", error);
+
+        let deep = (0..5000).fold(json!(1), |inner, _| serde_json::Value::Array(vec![inner]));
+        assert!(json_to_lua(&lua, &deep, &options).is_err());
+        let mut deep = deep;
+        while let serde_json::Value::Array(mut a) = deep {
+            deep = a.pop().unwrap_or_default();
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let lua = Lua::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let options = ConversionOptions {
+            diagnostics: Some(DiagnosticHandler::new(move |d| sink.lock().unwrap().push(d.to_string()))),
+            ..Default::default()
+        };
+
+        let value = lua.load("{ [1] = 'a', ['1'] = 'b', nested = { nan = 0/0 } }").eval::<rlua::Value>().expect("table");
+        lua_to_json(&lua, value, &options).expect("encode");
+        json_to_lua(&lua, &json!([u64::MAX]), &options).expect("decode");
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(seen, [
+            "/0: number 18446744073709551615 converted to 18446744073709552000".to_string(),
+            "/: duplicate key \"1\"".to_string(),
+            "/nested/nan: number NaN converted to null".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn limit_diagnostics() {
+        use crate::{CancellationToken, DiagnosticKind};
+
+        let lua = Lua::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let handler = DiagnosticHandler::new(move |d| sink.lock().unwrap().push(d.kind.clone()));
+        let options = ConversionOptions { diagnostics: Some(handler), tojson_metamethod: true, hook_instruction_budget: Some(10_000), ..Default::default() };
+
+        let deep = lua.load("local t = {} for _ = 1, 200 do t = { t } end return t").eval::<rlua::Value>().expect("deep");
+        assert!(lua_to_json(&lua, deep, &options).is_err());
+        let slow = lua.load("setmetatable({}, { __tojson = function() while true do end end })").eval::<rlua::Value>().expect("slow");
+        assert!(lua_to_json(&lua, slow, &options).is_err());
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(json_to_lua(&lua, &json!([1]), &ConversionOptions { cancellation: Some(token), ..options.clone() }).is_err());
+        #[cfg(not(feature = "luajit"))]
+        {
+            let doc = json!((0..10_000).map(|i| format!("item {}", i)).collect::<Vec<_>>());
+            assert!(json_to_lua(&lua, &doc, &ConversionOptions { lua_memory_limit: Some(64 * 1024), ..options.clone() }).is_err());
+        }
+
+        let seen = seen.lock().unwrap().clone();
+        assert!(seen.iter().all(DiagnosticKind::is_limit), "{:?}", seen);
+        let mut expected = vec![DiagnosticKind::DepthLimit { max: 128 }, DiagnosticKind::BudgetExceeded, DiagnosticKind::Cancelled,
+            DiagnosticKind::MemoryLimit { limit: 64 * 1024 }];
+        if cfg!(feature = "luajit") {
+            expected.pop();
+        }
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn diagnostic_events() {
+        use crate::stats::capture::Capture;
+
+        let lua = Lua::new();
+        let capture = Capture::default();
+        let lines = capture.lines.clone();
+        tracing::subscriber::with_default(capture, || {
+            let value = lua.load("{ ok = 1, nested = { nan = 0/0 } }").eval::<rlua::Value>().expect("table");
+            lua_to_json(&lua, value, &ConversionOptions::default()).expect("encode");
+        });
+        let lines = lines.lock().expect("lines").clone();
+        assert!(lines.contains(&"event WARN message=/nested/nan: number NaN converted to null path=\"/nested/nan\" kind=\"lossy_number\" limit=false".to_string()), "{:?}", lines);
+    }
+
+    #[test]
+    fn conversion_reports() {
+        let lua = Lua::new();
+        let options = ConversionOptions { unsupported_values: UnsupportedPolicy::Skip, ..Default::default() };
+        let value = lua.load("{ print, ok = 1, ratio = 0/0 }").eval::<rlua::Value>().expect("table");
+        let (encoded, report) = lua_to_json_with_report(&lua, value, &options).expect("encode");
+        assert_eq!(encoded, json!({"ok": 1, "ratio": null}));
+        let mut lines: Vec<String> = report.diagnostics.iter().map(ToString::to_string).collect();
+        lines.sort();
+        assert_eq!(lines, ["/1: skipped function", "/ratio: number NaN converted to null"]);
+
+        let (_, report) = json_to_lua_with_report(&lua, &json!({"n": 1}), &options).expect("decode");
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn all_errors() {
+        let lua = Lua::new();
+        let value = lua.load("{ ok = 1, f = print, list = { 1, coroutine.create(function() end) }, [true] = 1 }")
+            .eval::<rlua::Value>().expect("table");
+        let errors = lua_to_json_all_errors(&lua, value.clone(), &ConversionOptions::default()).expect_err("errors");
+        let mut paths: Vec<String> = errors.iter().map(|e| e.path.to_string()).collect();
+        paths.sort();
+        assert_eq!(paths, ["", "/f", "/list/1"]);
+        assert!(lua_to_json(&lua, value, &ConversionOptions::default()).is_err());
+
+        let fine = lua.load("{ ok = { 1, 2 } }").eval::<rlua::Value>().expect("table");
+        assert_eq!(lua_to_json_all_errors(&lua, fine, &ConversionOptions::default()).expect("encode"), json!({"ok": [1, 2]}));
+    }
+
+    #[test]
+    fn dedup_subtrees() {
+        let lua = Lua::new();
+        let options = ConversionOptions { dedup_subtrees: Some(256), ..Default::default() };
+        let row = json!({"kind": "default", "tags": ["a"]});
+        let doc = json!([row, row, {"kind": "other"}, row, {"tags": ["a"], "kind": "default"}]);
+
+        lua.globals().set("rows", json_to_lua(&lua, &doc, &options).expect("decode")).expect("set global");
+        let (shared, distinct): (bool, bool) = lua.load("return rows[1] == rows[4] and rows[1] == rows[5], rows[1] ~= rows[3]")
+            .eval().expect("eval");
+        assert!(shared && distinct);
+        lua.load("rows[3].kind = 'changed'").exec().expect("unshared tables stay writable");
+        assert!(lua.load("rows[1].kind = 'changed'").exec().is_err());
+        assert!(lua.load("rows[1].tags[1] = 'b'").exec().is_err());
+        lua.load("rows[3].kind = 'other'").exec().expect("restore");
+
+        let rows = lua.globals().get::<_, rlua::Value>("rows").expect("rows");
+        assert_eq!(lua_to_json(&lua, rows, &options).expect("encode"), doc);
+    }
+
+    #[test]
+    fn alias_policies() {
+        let lua = Lua::new();
+        let shared = || lua.load("local t = { x = 1 }; return { a = t, b = { t } }").eval::<rlua::Value>().expect("table");
+
+        let mut options = ConversionOptions::default();
+        assert_eq!(lua_to_json(&lua, shared(), &options).expect("duplicate"),
+                   json!({"a": {"x": 1}, "b": [{"x": 1}]}));
+
+        options.aliases = AliasPolicy::Error;
+        assert!(lua_to_json(&lua, shared(), &options).is_err());
+
+        options.aliases = AliasPolicy::Reference;
+        let encoded = lua_to_json(&lua, shared(), &options).expect("reference");
+        let refs = [json!({"$ref": "#/a"}), json!({"$ref": "#/b/0"})];
+        assert!(refs.contains(&encoded["a"]) || refs.contains(&encoded["b"][0]));
+
+        lua.globals().set("doc", json_to_lua(&lua, &encoded, &options).expect("decode")).expect("set global");
+        assert!(lua.load("return doc.a == doc.b[1] and doc.a.x == 1").eval::<bool>().expect("eval"));
+
+        let cycle = lua.load("local t = {}; t.self = t; return t").eval::<rlua::Value>().expect("table");
+        assert!(lua_to_json(&lua, cycle.clone(), &ConversionOptions::default()).is_err());
+        assert_eq!(lua_to_json(&lua, cycle, &options).expect("cycle"), json!({"self": {"$ref": "#"}}));
+    }
+
+    #[test]
+    fn hook_instruction_budget() {
+        let lua = Lua::new();
+        let value = lua.load(r#"
+            local function slow(limit)
+                return setmetatable({}, { __tojson = function()
+                    for i = 1, limit do end
+                    return "1"
+                end })
+            end
+            return { slow(10), slow(1e9) }
+        "#).eval::<rlua::Value>().expect("table");
+        let options = ConversionOptions { tojson_metamethod: true, hook_instruction_budget: Some(100_000), ..Default::default() };
+        let error = lua_to_json(&lua, value, &options).expect_err("budget");
+        assert!(error.to_string().contains("instruction budget"), "{}", error);
+        #[cfg(feature = "luajit")]
+        assert!(lua.load("return jit.status()").eval::<bool>().expect("jit status"), "JIT left off");
+
+        let fast = lua.load("return setmetatable({}, { __tojson = function() return '2' end })").eval::<rlua::Value>().expect("table");
+        assert_eq!(lua_to_json(&lua, fast, &options).expect("fast"), json!(2));
+    }
+
+    #[test]
+    fn unsupported_values() {
+        let lua = Lua::new();
+        let value = || lua.load("return { f = function() end, list = { 1, coroutine.create(function() end) } }").eval::<rlua::Value>().expect("table");
+        let encode = |policy| {
+            let options = ConversionOptions { unsupported_values: policy, ..Default::default() };
+            lua_to_json(&lua, value(), &options)
+        };
+
+        assert!(encode(UnsupportedPolicy::Error).is_err());
+        assert_eq!(encode(UnsupportedPolicy::Skip).expect("skip"), json!({"list": [1, null]}));
+        let tagged = encode(UnsupportedPolicy::Tagged).expect("tagged");
+        assert_eq!(tagged["f"]["$type"], json!("function"));
+        assert!(tagged["list"][1]["value"].as_str().expect("string").starts_with("thread: "));
+        assert!(encode(UnsupportedPolicy::ToString).expect("tostring")["f"].as_str().expect("string").starts_with("function: "));
+    }
+
+    #[test]
+    fn structured_errors() {
+        let lua = Lua::new();
+        let fail = lua.create_function(|_, ()| -> rlua::Result<()> {
+            Err(rlua::Error::RuntimeError("out of fuel".to_string()))
+        }).expect("function");
+        lua.globals().set("fail", fail).expect("set global");
+        let value = lua.load("local ok, err = pcall(fail); return { ok = ok, err = err }").eval::<rlua::Value>().expect("eval");
+
+        let options = ConversionOptions { structured_errors: true, ..Default::default() };
+        let encoded = lua_to_json(&lua, value, &options).expect("encode");
+        assert_eq!(encoded["ok"], json!(false));
+        assert_eq!(encoded["err"]["$error"]["message"], json!("runtime error: out of fuel"));
+        assert!(encoded["err"]["$error"]["traceback"].as_str().expect("traceback").contains("stack traceback"));
+    }
+
+    #[test]
+    fn function_policies() {
+        let lua = Lua::new();
+        let layout = || lua.load("local f = function() return 42 end; return { on_click = f, buttons = { f } }").eval::<rlua::Value>().expect("table");
+        let encode = |functions| {
+            let options = ConversionOptions { functions, ..Default::default() };
+            lua_to_json(&lua, layout(), &options).expect("encode")
+        };
+
+        assert_eq!(encode(FunctionPolicy::Skip), json!({"buttons": [null]}));
+        assert_eq!(encode(FunctionPolicy::Placeholder("<fn>".to_string())), json!({"on_click": "<fn>", "buttons": ["<fn>"]}));
+
+        let options = ConversionOptions { functions: FunctionPolicy::Handle, ..Default::default() };
+        let encoded = lua_to_json(&lua, layout(), &options).expect("encode");
+        assert_eq!(encoded["on_click"], encoded["buttons"][0]);
+        lua.globals().set("layout", json_to_lua(&lua, &encoded, &options).expect("decode")).expect("set global");
+        assert_eq!(lua.load("return layout.on_click()").eval::<i64>().expect("call"), 42);
+        assert!(json_to_lua(&lua, &json!({"$func": 99}), &options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "bytecode")]
+    fn function_bytecode() {
+        let options = ConversionOptions { functions: FunctionPolicy::Bytecode, ..Default::default() };
+        let saved = {
+            let lua = Lua::new();
+            let behavior = lua.load("return { on_hit = function(damage) return math.max(0, damage - 2) end }").eval::<rlua::Value>().expect("table");
+            lua_to_json(&lua, behavior, &options).expect("encode")
+        };
+        assert!(saved["on_hit"]["$bytecode"].is_string());
+
+        let lua = Lua::new();
+        lua.globals().set("behavior", json_to_lua(&lua, &saved, &options).expect("decode")).expect("set global");
+        assert_eq!(lua.load("return behavior.on_hit(5)").eval::<i64>().expect("call"), 3);
+        let builtin = lua.load("return print").eval::<rlua::Value>().expect("print");
+        assert!(lua_to_json(&lua, builtin, &options).is_err());
+    }
+
+    #[test]
+    fn json_type_round_trip() {
+        let lua = Lua::new();
+        let options = ConversionOptions { json_type_metatables: true, ..Default::default() };
+
+        let doc = json!({"empty_list": [], "empty_object": {}, "list": [1, 2]});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        assert_eq!(lua_to_json(&lua, value.clone(), &options).expect("encode"), doc);
+        assert_eq!(lua_to_json(&lua, value, &ConversionOptions::default()).expect("untagged"),
+                   json!({"empty_list": {}, "empty_object": {}, "list": [1, 2]}));
+    }
+
+    #[test]
+    fn numeric_keys() {
+        let lua = Lua::new();
+        let options = ConversionOptions { numeric_keys: true, ..Default::default() };
+        let doc = json!({"tiles": {"1": "grass", "2": "water", "10": "rock"}, "row": {"1": "a", "2": "b"}, "ids": {"07": 1}});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        lua.globals().set("doc", value.clone()).expect("set");
+        let (tile, row_len, padded): (String, i64, bool) = lua.load("doc.tiles[10], #doc.row, doc.ids['07'] == 1")
+            .eval().expect("eval");
+        assert_eq!((tile.as_str(), row_len, padded), ("rock", 2, true));
+
+        assert_eq!(lua_to_json(&lua, value.clone(), &options).expect("encode"), doc);
+        assert_eq!(lua_to_json(&lua, value, &ConversionOptions::default()).expect("without"),
+                   json!({"tiles": {"1": "grass", "2": "water", "10": "rock"}, "row": ["a", "b"], "ids": {"07": 1}}));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use serde_json::Value as JsonValue;
+
+/// The identical arrays and objects of a document, found for
+/// [`dedup_subtrees`](crate::ConversionOptions::dedup_subtrees) in one bottom-up pass.
+#[derive(Default)]
+pub(crate) struct Subtrees {
+    /// The class of each container of up to the size limit; equal containers share one.
+    classes: HashMap<*const JsonValue, usize>,
+    /// How many containers of each class the document has.
+    counts: Vec<usize>,
+}
+
+/// The first container seen of each class, by hash.
+type Representatives<'v> = HashMap<u64, Vec<(&'v JsonValue, usize)>>;
+
+impl Subtrees {
+    pub fn new(value: &JsonValue, limit: usize) -> Self {
+        let mut subtrees = Subtrees::default();
+        subtrees.visit(value, limit, &mut HashMap::new());
+        subtrees
+    }
+
+    /// The class of `value`, if it is a container within the size limit.
+    pub fn class(&self, value: &JsonValue) -> Option<usize> {
+        self.classes.get(&(value as *const JsonValue)).copied()
+    }
+
+    /// Whether the document has another container equal to `value`.
+    pub fn is_shared(&self, value: &JsonValue) -> bool {
+        self.class(value).and_then(|class| self.counts.get(class)).is_some_and(|&count| count > 1)
+    }
+
+    /// The hash and compact JSON length of `value`, built from those of its children. Objects
+    /// hash their entries in key order, so equal objects match whatever order they were
+    /// written in; a hash match is confirmed by comparing the values.
+    fn visit<'v>(&mut self, value: &'v JsonValue, limit: usize, representatives: &mut Representatives<'v>) -> (u64, usize) {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(value).hash(&mut hasher);
+        let size = match value {
+            JsonValue::Null => 4,
+            JsonValue::Bool(b) => {
+                b.hash(&mut hasher);
+                if *b { 4 } else { 5 }
+            },
+            JsonValue::Number(n) => {
+                n.hash(&mut hasher);
+                n.to_string().len()
+            },
+            JsonValue::String(s) => {
+                s.hash(&mut hasher);
+                string_len(s)
+            },
+            JsonValue::Array(a) => {
+                a.len().hash(&mut hasher);
+                a.iter().fold(1 + a.len().max(1), |size, v| {
+                    let (hash, len) = self.visit(v, limit, representatives);
+                    hash.hash(&mut hasher);
+                    size.saturating_add(len)
+                })
+            },
+            JsonValue::Object(o) => {
+                let mut entries = Vec::with_capacity(o.len());
+                let mut size = 1 + o.len().max(1);
+                for (k, v) in o {
+                    let (hash, len) = self.visit(v, limit, representatives);
+                    size = size.saturating_add(string_len(k) + 1 + len);
+                    entries.push((k, hash));
+                }
+                entries.sort_unstable();
+                entries.hash(&mut hasher);
+                size
+            },
+        };
+        let hash = hasher.finish();
+        if (value.is_array() || value.is_object()) && size <= limit {
+            let candidates = representatives.entry(hash).or_default();
+            let class = match candidates.iter().find(|(candidate, _)| *candidate == value) {
+                Some(&(_, class)) => class,
+                None => {
+                    candidates.push((value, self.counts.len()));
+                    self.counts.push(0);
+                    self.counts.len() - 1
+                },
+            };
+            if let Some(count) = self.counts.get_mut(class) {
+                *count += 1;
+            }
+            self.classes.insert(value, class);
+        }
+        (hash, size)
+    }
+}
+
+/// Length of `s` as a JSON string literal, quotes and escapes included.
+fn string_len(s: &str) -> usize {
+    2 + s.chars().map(|c| match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+        c if c < ' ' => 6,
+        c => c.len_utf8(),
+    }).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{Subtrees, string_len};
+
+    #[test]
+    fn subtree_classes() {
+        let doc = json!({"a": {"x": [1, "\n"], "y": null}, "b": {"y": null, "x": [1, "\n"]}, "c": {"x": [1.0, "\n"], "y": null}});
+        let size = serde_json::to_string(&doc["a"]).expect("text").len();
+        let subtrees = Subtrees::new(&doc, size);
+        assert!(subtrees.is_shared(&doc["a"]) && subtrees.is_shared(&doc["b"]["x"]));
+        assert_eq!(subtrees.class(&doc["a"]), subtrees.class(&doc["b"]));
+        assert_ne!(subtrees.class(&doc["a"]), subtrees.class(&doc["c"]));
+        assert!(!subtrees.is_shared(&doc["c"]) && subtrees.class(&doc).is_none());
+        assert!(Subtrees::new(&doc, size - 1).class(&doc["a"]).is_none());
+        assert_eq!(string_len("a\"\u{1}é"), serde_json::to_string("a\"\u{1}é").expect("text").len());
+        assert_eq!(Subtrees::new(&json!([[], {}]), 2).counts, [1, 1]);
+    }
+}
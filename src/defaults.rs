@@ -0,0 +1,61 @@
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, KeyReference, Path, lua_to_json};
+
+/// Deep-merges `overlay` into `base`: objects merge key by key, anything else replaces.
+pub(crate) fn merge(base: &mut JsonValue, overlay: JsonValue) {
+    match (base, overlay) {
+        (JsonValue::Object(base), JsonValue::Object(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(existing) => merge(existing, v),
+                    None => {
+                        base.insert(k, v);
+                    },
+                }
+            }
+        },
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Converts `value` and deep-merges it over `defaults`, the usual "defaults + user config"
+/// pattern. Objects merge key by key; arrays and scalars from `value` replace the default.
+/// Also returns the keys `value` has but `defaults` does not, which are likely typos; they
+/// are kept in the result. See [`KeyReference::Shape`] for how `defaults` is read.
+pub fn decode_with_defaults<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, defaults: &JsonValue, options: &ConversionOptions,
+) -> rlua::Result<(JsonValue, Vec<Path>)> {
+    let overlay = lua_to_json(lua, value, options)?;
+    let unknown = KeyReference::Shape(defaults.clone()).unknown_keys(&overlay);
+    let mut merged = defaults.clone();
+    merge(&mut merged, overlay);
+    Ok((merged, unknown))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, decode_with_defaults};
+
+    #[test]
+    fn overlay_onto_defaults() {
+        let lua = Lua::new();
+        let defaults = json!({
+            "window": {"width": 800, "height": 600, "title": "game"},
+            "keys": ["w", "a", "s", "d"],
+            "aliases": {},
+        });
+        let value = lua.load("{ window = { width = 1024, helath = 3 }, keys = { 'up' }, aliases = { q = 'quit' } }")
+            .eval().expect("table");
+        let (merged, unknown) = decode_with_defaults(&lua, value, &defaults, &ConversionOptions::default()).expect("merge");
+        assert_eq!(merged, json!({
+            "window": {"width": 1024, "height": 600, "title": "game", "helath": 3},
+            "keys": ["up"],
+            "aliases": {"q": "quit"},
+        }));
+        let unknown: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+        assert_eq!(unknown, vec!["/window/helath"]);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::ffi::c_void;
+use serde_json::Value as JsonValue;
+use rlua::{Lua, LuaSerdeExt, SerializeOptions};
+use crate::{AliasPolicy, BigIntegerPolicy, ConversionOptions, DiagnosticKind, FunctionPolicy, GcHint, KeyOrder, MixedTablePolicy, Path, PathSegment, SparseArrayPolicy, UnsupportedPolicy};
+use crate::convert::{MAX_DEPTH, diagnose, fits_lua_integer, is_i64};
+use crate::known_keys::diagnose_unknown_keys;
+
+/// Whether [`json_to_lua`](crate::json_to_lua) can hand `options` to `LuaSerdeExt::to_value`:
+/// anything that depends on the location in the document or builds extra Lua objects cannot.
+pub(crate) fn delegates_decode(options: &ConversionOptions) -> bool {
+    options.delegate_to_mlua
+        && !options.numeric_keys
+        && options.lazy_strings.is_none()
+        && options.set_paths.is_empty()
+        && options.columnar_paths.is_empty()
+        && options.raw_paths.is_empty()
+        && !options.json_type_metatables
+        && options.dedup_subtrees.is_none()
+        && options.aliases != AliasPolicy::Reference
+        && !options.read_only
+        && matches!(options.functions, FunctionPolicy::Unsupported | FunctionPolicy::Skip | FunctionPolicy::Placeholder(_))
+        && options.big_integers == BigIntegerPolicy::Float
+        && options.gc_hint == GcHint::Auto
+        && options.bool_encoding.is_none()
+        && options.bool_paths.is_empty()
+        && options.enum_paths.is_empty()
+        && options.number_format_paths.is_empty()
+        && options.cancellation.is_none()
+        && options.progress.is_none()
+        && options.lua_memory_limit.is_none()
+        && luau_delegates(options)
+}
+
+/// Whether [`lua_to_json`](crate::lua_to_json) can hand `options` to mlua's `Serialize` impl.
+pub(crate) fn delegates_encode(options: &ConversionOptions) -> bool {
+    options.delegate_to_mlua
+        && !options.tojson_metamethod
+        && options.set_array_paths.is_empty()
+        && !options.numeric_keys
+        && !options.json_type_metatables
+        && options.sparse_arrays == SparseArrayPolicy::Object
+        && options.mixed_tables == MixedTablePolicy::ObjectWithNumericKeys
+        && options.aliases == AliasPolicy::Duplicate
+        && matches!(options.unsupported_values, UnsupportedPolicy::Error | UnsupportedPolicy::Skip)
+        && options.functions == FunctionPolicy::Unsupported
+        && !options.structured_errors
+        && options.gc_hint == GcHint::Auto
+        && options.bool_encoding.is_none()
+        && options.bool_paths.is_empty()
+        && options.enum_paths.is_empty()
+        && options.number_format_paths.is_empty()
+        && options.cancellation.is_none()
+        && options.progress.is_none()
+        && (options.sort_keys == KeyOrder::Unsorted || cfg!(not(feature = "preserve_order")))
+        && luau_delegates(options)
+}
+
+/// mlua writes vectors as arrays and reads no tagged data into buffers (buffers themselves are
+/// userdata, which never delegate).
+#[cfg(feature = "luau")]
+fn luau_delegates(options: &ConversionOptions) -> bool {
+    options.vectors == crate::VectorPolicy::Array && options.buffers != crate::BufferPolicy::Tagged
+}
+
+#[cfg(not(feature = "luau"))]
+fn luau_delegates(_: &ConversionOptions) -> bool {
+    true
+}
+
+/// Whether mlua reads every table in `value` as this crate does. mlua writes a table with a
+/// non-empty sequence part as that sequence, dropping any other keys, so tables that are not
+/// plain sequences or plain maps (and cycles, and excess nesting) are left to the native path,
+/// as are this crate's userdata ([`RawJson`](crate::RawJson), [`LazyString`](crate::LazyString)).
+pub(crate) fn plain_tables(value: &rlua::Value) -> rlua::Result<bool> {
+    match value {
+        rlua::Value::Table(table) => plain_table(table, &mut HashSet::new()),
+        rlua::Value::UserData(_) => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+fn plain_table(table: &rlua::Table, ancestors: &mut HashSet<*const c_void>) -> rlua::Result<bool> {
+    if ancestors.len() >= MAX_DEPTH || !ancestors.insert(table.to_pointer()) {
+        return Ok(false);
+    }
+    let (mut count, len) = (0, table.raw_len());
+    for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+        let (_, value) = pair?;
+        count += 1;
+        let plain = match &value {
+            rlua::Value::Table(inner) => plain_table(inner, ancestors)?,
+            rlua::Value::UserData(_) => false,
+            _ => true,
+        };
+        if !plain {
+            return Ok(false);
+        }
+    }
+    ancestors.remove(&table.to_pointer());
+    Ok(len == 0 || count == len)
+}
+
+/// `LuaSerdeExt::to_value`, after checking nesting and reporting integers that become floats.
+pub(crate) fn json_to_lua<'lua>(lua: &'lua Lua, value: &JsonValue, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    check_decoded(value, options, &mut Path::new())?;
+    let serialize = SerializeOptions::new()
+        .set_array_metatable(false)
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    lua.to_value_with(value, serialize)
+}
+
+fn check_decoded(value: &JsonValue, options: &ConversionOptions, path: &mut Path) -> rlua::Result<()> {
+    if path.len() > MAX_DEPTH {
+        diagnose(options, path, DiagnosticKind::DepthLimit { max: MAX_DEPTH });
+        return Err(too_deep(path));
+    }
+    match value {
+        JsonValue::Number(n) if n.as_i64().map_or(n.is_u64(), |i| !fits_lua_integer(i)) => {
+            let converted = n.as_f64().unwrap_or(f64::NAN).to_string();
+            diagnose(options, path, DiagnosticKind::LossyNumber { original: n.to_string(), converted });
+        },
+        JsonValue::Array(a) => for (i, v) in a.iter().enumerate() {
+            path.push(PathSegment::Index(i));
+            check_decoded(v, options, path)?;
+            path.pop();
+        },
+        JsonValue::Object(o) => for (k, v) in o {
+            path.push(PathSegment::Key(k.clone()));
+            check_decoded(v, options, path)?;
+            path.pop();
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// mlua's `Serialize` impl of the value, then integral floats and known keys.
+pub(crate) fn lua_to_json<'lua>(value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<JsonValue> {
+    let serializable = value.to_serializable()
+        .deny_unsupported_types(options.unsupported_values == UnsupportedPolicy::Error)
+        .deny_recursive_tables(true);
+    let mut result = serde_json::to_value(serializable).map_err(|e| rlua::Error::FromLuaConversionError {
+        from: value.type_name(), to: "JsonValue", message: Some(e.to_string()) })?;
+    if options.integral_floats_as_integers {
+        integral_floats_as_integers(&mut result);
+    }
+    diagnose_unknown_keys(options, &result);
+    Ok(result)
+}
+
+fn integral_floats_as_integers(value: &mut JsonValue) {
+    match value {
+        JsonValue::Number(n) => if let Some(f) = n.as_f64().filter(|f| n.is_f64() && is_i64(*f)) {
+            *value = JsonValue::from(f as i64);
+        },
+        JsonValue::Array(a) => a.iter_mut().for_each(integral_floats_as_integers),
+        JsonValue::Object(o) => o.values_mut().for_each(integral_floats_as_integers),
+        _ => {},
+    }
+}
+
+fn too_deep(path: &Path) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}: nesting deeper than {} levels", path, MAX_DEPTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, DiagnosticHandler, MixedTablePolicy, json_to_lua, lua_to_json};
+
+    fn delegated() -> ConversionOptions {
+        ConversionOptions { delegate_to_mlua: true, ..Default::default() }
+    }
+
+    #[test]
+    fn delegated_round_trip() {
+        let lua = Lua::new();
+        let options = delegated();
+        let document = json!({"name": "x", "list": [1, 2.5, "three"], "nested": {"ok": true}, "empty": {}});
+        let value = json_to_lua(&lua, &document, &options).expect("to lua");
+        lua.globals().set("v", value.clone()).expect("set");
+        assert!(lua.load("return getmetatable(v.list) == nil").eval::<bool>().expect("eval"));
+        assert_eq!(json_to_lua(&lua, &json!(null), &options).expect("null"), rlua::Value::Nil);
+        assert_eq!(lua_to_json(&lua, value, &options).expect("to json"), document);
+
+        let sparse: rlua::Value = lua.load("{ [1] = 'a', [3] = 'c', f = 2.0 }").eval().expect("table");
+        let options = ConversionOptions { integral_floats_as_integers: true, ..delegated() };
+        assert_eq!(lua_to_json(&lua, sparse, &options).expect("sparse"), json!({"1": "a", "3": "c", "f": 2}));
+
+        let function: rlua::Value = lua.load("{ a = 1, f = print }").eval().expect("function");
+        assert!(lua_to_json(&lua, function.clone(), &options).is_err());
+        let options = ConversionOptions { unsupported_values: crate::UnsupportedPolicy::Skip, ..delegated() };
+        assert_eq!(lua_to_json(&lua, function, &options).expect("skipped"), json!({"a": 1}));
+    }
+
+    #[test]
+    fn delegated_limits_and_fallback() {
+        let lua = Lua::new();
+        let deep = (0..200).fold(json!(1), |inner, _| serde_json::Value::Array(vec![inner]));
+        assert!(json_to_lua(&lua, &deep, &delegated()).is_err());
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let options = ConversionOptions {
+            diagnostics: Some(DiagnosticHandler::new(move |d| sink.lock().expect("lock").push(d.to_string()))),
+            ..delegated()
+        };
+        json_to_lua(&lua, &json!({"big": u64::MAX}), &options).expect("big");
+        assert_eq!(seen.lock().expect("lock").len(), 1);
+
+        // Options mlua cannot express take the native path.
+        let mixed: rlua::Value = lua.load("{ 1, 2, x = 3 }").eval().expect("mixed");
+        let options = ConversionOptions { mixed_tables: MixedTablePolicy::Error, ..delegated() };
+        assert!(lua_to_json(&lua, mixed, &options).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use crate::{ConversionOptions, Path};
+
+/// Something a conversion did silently that may lose data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// A number was changed to fit the target, e.g. a big integer rounded to a float, or NaN written as `null`.
+    LossyNumber { original: String, converted: String },
+    /// Two Lua keys map to the same JSON object key (e.g. `1` and `"1"`); the later one wins.
+    DuplicateKey { key: String },
+    /// A value was left out of the result.
+    Skipped { type_name: &'static str },
+    /// An object has a key [`ConversionOptions::known_keys`] does not know, likely a typo.
+    UnknownKey { key: String },
+    /// A value at an [`enum_paths`](ConversionOptions::enum_paths) location is not in its enumeration.
+    UnknownEnumValue { value: String },
+    /// Nesting went past `max` levels, failing the conversion.
+    DepthLimit { max: usize },
+    /// The decode would have grown the Lua heap past [`lua_memory_limit`](ConversionOptions::lua_memory_limit).
+    MemoryLimit { limit: usize },
+    /// Script hooks ran out of [`hook_instruction_budget`](ConversionOptions::hook_instruction_budget).
+    BudgetExceeded,
+    /// The [`cancellation`](ConversionOptions::cancellation) token was cancelled.
+    Cancelled,
+}
+
+impl DiagnosticKind {
+    /// Whether this is a limit that failed the conversion rather than a change to the data.
+    pub fn is_limit(&self) -> bool {
+        matches!(self, DiagnosticKind::DepthLimit { .. } | DiagnosticKind::MemoryLimit { .. }
+            | DiagnosticKind::BudgetExceeded | DiagnosticKind::Cancelled)
+    }
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::LossyNumber { .. } => "lossy_number",
+            DiagnosticKind::DuplicateKey { .. } => "duplicate_key",
+            DiagnosticKind::Skipped { .. } => "skipped",
+            DiagnosticKind::UnknownKey { .. } => "unknown_key",
+            DiagnosticKind::UnknownEnumValue { .. } => "unknown_enum_value",
+            DiagnosticKind::DepthLimit { .. } => "depth_limit",
+            DiagnosticKind::MemoryLimit { .. } => "memory_limit",
+            DiagnosticKind::BudgetExceeded => "budget_exceeded",
+            DiagnosticKind::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// A [`DiagnosticKind`] with the JSON Pointer of the affected value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: Path,
+    pub kind: DiagnosticKind,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() { "/".to_string() } else { self.path.to_string() };
+        match &self.kind {
+            DiagnosticKind::LossyNumber { original, converted } =>
+                write!(f, "{}: number {} converted to {}", path, original, converted),
+            DiagnosticKind::DuplicateKey { key } => write!(f, "{}: duplicate key {:?}", path, key),
+            DiagnosticKind::Skipped { type_name } => write!(f, "{}: skipped {}", path, type_name),
+            DiagnosticKind::UnknownKey { key } => write!(f, "{}: unknown key {:?}", path, key),
+            DiagnosticKind::UnknownEnumValue { value } => write!(f, "{}: unknown enumeration value {:?}", path, value),
+            DiagnosticKind::DepthLimit { max } => write!(f, "{}: nesting deeper than {} levels", path, max),
+            DiagnosticKind::MemoryLimit { limit } => write!(f, "{}: Lua memory limit of {} more bytes exceeded", path, limit),
+            DiagnosticKind::BudgetExceeded => write!(f, "{}: instruction budget of conversion hooks exceeded", path),
+            DiagnosticKind::Cancelled => write!(f, "{}: conversion cancelled", path),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Emits a `WARN` event with the target `rlua_json`, the `path` and a `kind` such as
+    /// `lossy_number` or `memory_limit`.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn trace(&self) {
+        let path = if self.path.is_empty() { "/".to_string() } else { self.path.to_string() };
+        tracing::warn!(target: "rlua_json", path, kind = self.kind.name(), limit = self.kind.is_limit(), "{}", self);
+    }
+}
+
+/// One failure found by [`lua_to_json_all_errors`](crate::lua_to_json_all_errors).
+#[derive(Debug, Clone)]
+pub struct ConversionError {
+    pub path: Path,
+    pub error: rlua::Error,
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() { "/".to_string() } else { self.path.to_string() };
+        write!(f, "{}: {}", path, self.error)
+    }
+}
+
+/// Receives diagnostics as they happen, e.g. to forward them to the host's logger.
+#[derive(Clone)]
+pub struct DiagnosticHandler(pub Arc<dyn Fn(&Diagnostic) + Send + Sync>);
+
+impl DiagnosticHandler {
+    pub fn new(f: impl Fn(&Diagnostic) + Send + Sync + 'static) -> Self {
+        DiagnosticHandler(Arc::new(f))
+    }
+}
+
+impl Debug for DiagnosticHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DiagnosticHandler")
+    }
+}
+
+impl PartialEq for DiagnosticHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Every diagnostic of one conversion, returned by the `*_with_report` functions so hosts
+/// can show what was changed or left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ConversionReport {
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Runs `f` with options whose handler also records into the report; a handler already
+    /// set in `options` keeps receiving diagnostics.
+    pub(crate) fn collect<T>(options: &ConversionOptions, f: impl FnOnce(&ConversionOptions) -> T) -> (T, Self) {
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let sink = collected.clone();
+        let forward = options.diagnostics.clone();
+        let options = ConversionOptions {
+            diagnostics: Some(DiagnosticHandler::new(move |d| {
+                sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(d.clone());
+                if let Some(handler) = &forward {
+                    (handler.0)(d);
+                }
+            })),
+            ..options.clone()
+        };
+        let result = f(&options);
+        let diagnostics = std::mem::take(&mut *collected.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        (result, ConversionReport { diagnostics })
+    }
+}
+
+impl Display for ConversionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
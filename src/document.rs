@@ -0,0 +1,492 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, DiagnosticKind, Path, PathPattern, Validator, json_path_to_lua, json_to_lua, lua_to_json, parse_json, to_string};
+use crate::budget::InstructionBudget;
+use crate::convert::diagnose;
+use crate::path::unescape;
+use crate::validate::{affected, affects};
+
+type ChangeFn = dyn Fn(&str, &JsonValue, &JsonValue) + Send + Sync;
+
+/// Registry table of the Lua functions given to `doc:on_change`, by document id. Its values are
+/// weak: each listener table is kept alive by the user value of the userdata it was registered
+/// through, so it goes away with the last such userdata.
+const LUA_LISTENERS_KEY: &str = "rlua_json.change_listeners";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A JSON document shared between threads and Lua states.
+///
+/// Parse it on any thread, then hand it to Lua as userdata: scripts read and write it through
+/// `doc.key`, `doc:get(pointer)`, `doc:set(pointer, value)`, `doc:materialize()` and `doc:encode()`,
+/// and only the parts they touch are converted. `doc:begin()`, `doc:commit()` and `doc:rollback()`
+/// group writes; transactions nest. `doc:on_change(function(pointer, old, new) end)` sees writes
+/// made by scripts of the same Lua state; see [`on_change`](Self::on_change) for how long.
+/// `SharedDocument` and [`ConversionOptions`] are `Send + Sync`; Lua values are not, and must
+/// stay on the thread owning their `Lua` (or move with it under mlua's `send` feature).
+#[derive(Clone)]
+pub struct SharedDocument {
+    /// Identifies the document, and its Lua listeners, across handles.
+    id: u64,
+    value: Arc<RwLock<JsonValue>>,
+    options: Arc<ConversionOptions>,
+    validators: Arc<RwLock<Vec<(PathPattern, Validator)>>>,
+    /// Documents saved by `doc:begin()`, innermost last.
+    snapshots: Arc<Mutex<Vec<JsonValue>>>,
+    listeners: Arc<RwLock<Vec<Arc<ChangeFn>>>>,
+}
+
+impl SharedDocument {
+    pub fn new(value: JsonValue, options: ConversionOptions) -> Self {
+        SharedDocument { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), value: Arc::new(RwLock::new(value)), options: Arc::new(options),
+            validators: Default::default(), snapshots: Default::default(), listeners: Default::default() }
+    }
+
+    pub fn parse(text: impl AsRef<[u8]>, options: ConversionOptions) -> rlua::Result<Self> {
+        Ok(SharedDocument::new(parse_json(text.as_ref(), &options)?, options))
+    }
+
+    pub fn options(&self) -> &ConversionOptions {
+        &self.options
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, JsonValue> {
+        self.value.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, JsonValue> {
+        self.value.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether both handles refer to the same document.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+
+    /// Converts the whole document to Lua.
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua) -> rlua::Result<rlua::Value<'lua>> {
+        json_to_lua(lua, &self.read(), &self.options)
+    }
+
+    /// Replaces the value at a JSON Pointer; the parent must exist. Returns the previous value.
+    /// Validators only apply to writes made by scripts.
+    pub fn set_pointer(&self, pointer: &str, value: JsonValue) -> rlua::Result<JsonValue> {
+        self.replace(pointer, value, false)
+    }
+
+    /// Calls `f(pointer, old, new)` after every write through any handle, including rollbacks,
+    /// which replace the whole document (pointer `""`). Writing directly through
+    /// [`write`](Self::write) does not notify. The listener lives as long as the document.
+    ///
+    /// A Lua listener registered with `doc:on_change(fn)` instead lives as long as the userdata
+    /// `doc` it was called on: it keeps firing for writes through other handles to the same
+    /// document, but is dropped once that userdata is collected, even if other handles remain.
+    pub fn on_change(&self, f: impl Fn(&str, &JsonValue, &JsonValue) + Send + Sync + 'static) {
+        self.listeners.write().unwrap_or_else(|poisoned| poisoned.into_inner()).push(Arc::new(f));
+    }
+
+    fn replace(&self, pointer: &str, value: JsonValue, validate: bool) -> rlua::Result<JsonValue> {
+        let listeners = self.listeners.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let new = (!listeners.is_empty()).then(|| value.clone());
+        let old = if validate { self.validated_set(pointer, value)? } else { set_at(&mut self.write(), pointer, value)? };
+        if let Some(new) = new {
+            for listener in listeners {
+                listener(pointer, &old, &new);
+            }
+        }
+        Ok(old)
+    }
+
+    /// The registry key of this document's Lua listeners; a number, which every backend can
+    /// represent up to 2^53.
+    fn listeners_key(&self) -> f64 {
+        self.id as f64
+    }
+
+    /// A write made by a script, which also notifies the listeners that scripts of the same
+    /// Lua state registered with `doc:on_change(fn)`.
+    fn script_write(&self, lua: &Lua, pointer: &str, value: JsonValue, validate: bool) -> rlua::Result<()> {
+        let listeners = match lua.named_registry_value::<Option<rlua::Table>>(LUA_LISTENERS_KEY)? {
+            Some(all) => all.raw_get::<_, Option<rlua::Table>>(self.listeners_key())?,
+            None => None,
+        };
+        let new = listeners.as_ref().map(|_| value.clone());
+        let old = self.replace(pointer, value, validate)?;
+        if let (Some(listeners), Some(new)) = (listeners, new) {
+            let old = json_to_lua(lua, &old, &self.options)?;
+            let new = json_to_lua(lua, &new, &self.options)?;
+            let budget = InstructionBudget::new(self.options.hook_instruction_budget);
+            InstructionBudget::run(budget.as_ref(), lua, || {
+                for listener in listeners.sequence_values::<rlua::Function>() {
+                    listener?.call::<_, ()>((pointer, old.clone(), new.clone()))?;
+                }
+                Ok(())
+            }).inspect_err(|_| if InstructionBudget::exhausted(budget.as_ref()) {
+                diagnose(&self.options, &Path::new(), DiagnosticKind::BudgetExceeded);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn add_lua_listener<'lua>(&self, lua: &'lua Lua, handle: &rlua::AnyUserData<'lua>, listener: rlua::Function<'lua>) -> rlua::Result<()> {
+        let all = match lua.named_registry_value::<Option<rlua::Table>>(LUA_LISTENERS_KEY)? {
+            Some(all) => all,
+            None => {
+                let all = lua.create_table()?;
+                let mt = lua.create_table()?;
+                mt.raw_set("__mode", "v")?;
+                all.set_metatable(Some(mt));
+                lua.set_named_registry_value(LUA_LISTENERS_KEY, all.clone())?;
+                all
+            },
+        };
+        let listeners = match all.raw_get::<_, Option<rlua::Table>>(self.listeners_key())? {
+            Some(listeners) => listeners,
+            None => {
+                let listeners = lua.create_table()?;
+                all.raw_set(self.listeners_key(), listeners.clone())?;
+                listeners
+            },
+        };
+        handle.set_user_value(listeners.clone())?;
+        listeners.raw_push(listener)
+    }
+
+    /// Checks script writes that can change the values `pattern` matches, whether the
+    /// write targets such a value, one of its ancestors or something inside it.
+    pub fn add_validator(&self, pattern: impl Into<PathPattern>, validator: Validator) {
+        self.validators.write().unwrap_or_else(|poisoned| poisoned.into_inner()).push((pattern.into(), validator));
+    }
+
+    /// Starts a transaction: dropping the guard without [`Transaction::commit`] restores the
+    /// document as it is now, including changes other handles made in between.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction { document: self, snapshot: Some(self.read().clone()) }
+    }
+
+    fn snapshots(&self) -> MutexGuard<'_, Vec<JsonValue>> {
+        self.snapshots.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn pop_snapshot(&self) -> rlua::Result<JsonValue> {
+        self.snapshots().pop()
+            .ok_or_else(|| rlua::Error::RuntimeError("no transaction in progress".to_string()))
+    }
+
+    /// [`set_pointer`](Self::set_pointer) that runs the affected validators on the document with
+    /// the write applied, undoing the write if one rejects it. Validators run on copies of the
+    /// values they check, with no lock held, so they may read or write the document themselves.
+    fn validated_set(&self, pointer: &str, value: JsonValue) -> rlua::Result<JsonValue> {
+        let written: Vec<String> = pointer.split('/').skip(1).map(unescape).collect();
+        let relevant: Vec<_> = self.validators.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter().filter(|(pattern, _)| affects(pattern, &written)).cloned().collect();
+        if relevant.is_empty() {
+            return set_at(&mut self.write(), pointer, value);
+        }
+
+        let (existed, old, checks) = {
+            let mut document = self.write();
+            let existed = document.pointer(pointer).is_some();
+            let old = set_at(&mut document, pointer, value)?;
+            let checks: Vec<_> = relevant.into_iter().map(|(pattern, validator)| {
+                let mut values = Vec::new();
+                affected(pattern.segments(), &written, &document, &mut Path::new(), &mut values);
+                (validator, values.into_iter().map(|(path, value)| (path, value.clone())).collect::<Vec<_>>())
+            }).collect();
+            (existed, old, checks)
+        };
+        let rejection = checks.into_iter().find_map(|(validator, values)| {
+            values.into_iter().find_map(|(path, value)| validator.check(&value).err().map(|message| rlua::Error::RuntimeError(
+                format!("invalid write to {}: {}", if path.is_empty() { "/".to_string() } else { path.to_string() }, message))))
+        });
+        match rejection {
+            None => Ok(old),
+            Some(error) => {
+                let mut document = self.write();
+                match existed {
+                    true => set_at(&mut document, pointer, old)?,
+                    false => unset_at(&mut document, pointer),
+                };
+                Err(error)
+            },
+        }
+    }
+}
+
+impl Default for SharedDocument {
+    fn default() -> Self {
+        SharedDocument::new(JsonValue::default(), ConversionOptions::default())
+    }
+}
+
+impl Debug for SharedDocument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedDocument").field("value", &self.value).field("options", &self.options).finish_non_exhaustive()
+    }
+}
+
+/// A pending change to a [`SharedDocument`], rolled back on drop unless committed.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    document: &'a SharedDocument,
+    snapshot: Option<JsonValue>,
+}
+
+impl Transaction<'_> {
+    pub fn document(&self) -> &SharedDocument {
+        self.document
+    }
+
+    /// Keeps the changes made since the transaction started.
+    pub fn commit(mut self) {
+        self.snapshot = None;
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            // Replacing the root cannot fail.
+            let _ = self.document.replace("", snapshot, false);
+        }
+    }
+}
+
+fn set_at(document: &mut JsonValue, pointer: &str, value: JsonValue) -> rlua::Result<JsonValue> {
+    if pointer.is_empty() {
+        return Ok(std::mem::replace(document, value));
+    }
+    let (parent, last) = pointer.rsplit_once('/')
+        .ok_or_else(|| rlua::Error::RuntimeError(format!("invalid JSON Pointer {:?}", pointer)))?;
+    let last = unescape(last);
+    let missing = || rlua::Error::RuntimeError(format!("{}: parent does not exist", pointer));
+    match document.pointer_mut(parent).ok_or_else(missing)? {
+        JsonValue::Object(o) => Ok(o.insert(last, value).unwrap_or(JsonValue::Null)),
+        JsonValue::Array(a) => {
+            let index: usize = last.parse().map_err(|_| missing())?;
+            let len = a.len();
+            match a.get_mut(index) {
+                Some(slot) => Ok(std::mem::replace(slot, value)),
+                None if index == len => {
+                    a.push(value);
+                    Ok(JsonValue::Null)
+                },
+                None => Err(missing()),
+            }
+        },
+        _ => Err(missing()),
+    }
+}
+
+/// Removes the member or last element [`set_at`] added at `pointer`.
+fn unset_at(document: &mut JsonValue, pointer: &str) -> JsonValue {
+    let Some((parent, last)) = pointer.rsplit_once('/') else { return JsonValue::Null };
+    match document.pointer_mut(parent) {
+        Some(JsonValue::Object(o)) => o.remove(&unescape(last)),
+        Some(JsonValue::Array(a)) => a.pop(),
+        _ => None,
+    }.unwrap_or(JsonValue::Null)
+}
+
+fn key_pointer(key: &str) -> String {
+    format!("/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+impl rlua::UserData for SharedDocument {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |lua, this, pointer: String| {
+            json_path_to_lua(lua, &this.read(), &pointer, &this.options)
+        });
+        methods.add_method("set", |lua, this, (pointer, value): (String, rlua::Value)| {
+            let value = lua_to_json(lua, value, &this.options)?;
+            this.script_write(lua, &pointer, value, true)
+        });
+        methods.add_method("begin", |_, this, ()| {
+            let snapshot = this.read().clone();
+            this.snapshots().push(snapshot);
+            Ok(())
+        });
+        methods.add_method("commit", |_, this, ()| this.pop_snapshot().map(|_| ()));
+        methods.add_method("rollback", |lua, this, ()| {
+            let snapshot = this.pop_snapshot()?;
+            this.script_write(lua, "", snapshot, false)
+        });
+        methods.add_function("on_change", |lua, (handle, listener): (rlua::AnyUserData, rlua::Function)| {
+            handle.borrow::<SharedDocument>()?.add_lua_listener(lua, &handle, listener)
+        });
+        methods.add_method("materialize", |lua, this, ()| this.to_lua(lua));
+        methods.add_method("encode", |_, this, ()| to_string(&this.read(), &this.options));
+        methods.add_meta_method(rlua::MetaMethod::Index, |lua, this, key: String| {
+            json_path_to_lua(lua, &this.read(), &key_pointer(&key), &this.options)
+        });
+        methods.add_meta_method(rlua::MetaMethod::NewIndex, |lua, this, (key, value): (String, rlua::Value)| {
+            let value = lua_to_json(lua, value, &this.options)?;
+            this.script_write(lua, &key_pointer(&key), value, true)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, JsonWrapperValue, SharedDocument, Validator};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shared_across_threads() {
+        assert_send_sync::<SharedDocument>();
+        assert_send_sync::<ConversionOptions>();
+        assert_send_sync::<JsonWrapperValue>();
+
+        let doc = std::thread::spawn(|| {
+            SharedDocument::parse(r#"{"config": {"speed": 1}, "items": [1]}"#, ConversionOptions::default())
+        }).join().expect("thread").expect("parse");
+
+        let lua = Lua::new();
+        lua.globals().set("doc", doc.clone()).expect("set global");
+        let speed: i64 = lua.load(r#"
+            doc.name = "x"
+            doc:set("/items/1", 2)
+            return doc:get("/config/speed") + doc.config.speed
+        "#).eval().expect("eval");
+
+        assert_eq!(speed, 2);
+        assert_eq!(*doc.read(), json!({"config": {"speed": 1}, "items": [1, 2], "name": "x"}));
+    }
+
+    #[test]
+    fn validators() {
+        let doc = SharedDocument::new(json!({"player": {"hp": 10, "name": "a"}, "items": [1]}), ConversionOptions::default());
+        doc.add_validator("/player/hp", Validator::schema(json!({"type": "integer", "minimum": 0})));
+        doc.add_validator("/player", Validator::new(|v| match v.get("name") {
+            Some(name) if name.is_string() && v.get("cheat").is_none() => Ok(()),
+            _ => Err("a player needs a name".to_string()),
+        }));
+        doc.add_validator("/items", Validator::new(|v| match v.as_array().map(Vec::len) {
+            Some(0 | 1) => Ok(()),
+            _ => Err("one item at most".to_string()),
+        }));
+
+        let lua = Lua::new();
+        lua.globals().set("doc", doc.clone()).expect("set global");
+        lua.load("doc:set('/player/hp', 5)").exec().expect("valid write");
+        for script in [
+            "doc:set('/player/hp', -1)", "doc:set('/player/name', nil)", "doc.player = { hp = 'full', name = 'b' }",
+            "doc:set('/player/cheat', true)", "doc:set('/items/1', 2)",
+        ] {
+            assert!(lua.load(script).exec().is_err(), "{} should fail", script);
+        }
+        assert_eq!(*doc.read(), json!({"player": {"hp": 5, "name": "a"}, "items": [1]}));
+    }
+
+    #[test]
+    fn validators_use_the_document() {
+        let doc = SharedDocument::new(json!({"max": 3, "hp": 1}), ConversionOptions::default());
+        let reader = doc.clone();
+        doc.add_validator("/hp", Validator::new(move |v| match (v.as_i64(), reader.read()["max"].as_i64()) {
+            (Some(hp), Some(max)) if hp <= max => Ok(()),
+            _ => Err("hp above max".to_string()),
+        }));
+        let registrar = doc.clone();
+        doc.add_validator("/max", Validator::new(move |_| {
+            registrar.add_validator("/other", Validator::new(|_| Ok(())));
+            Ok(())
+        }));
+
+        let lua = Lua::new();
+        lua.globals().set("doc", doc.clone()).expect("set global");
+        lua.load("doc.hp = 3; doc.max = 5; doc.hp = 5").exec().expect("valid writes");
+        assert!(lua.load("doc.hp = 6").exec().is_err());
+        assert_eq!(*doc.read(), json!({"max": 5, "hp": 5}));
+    }
+
+    #[test]
+    fn transactions() {
+        let doc = SharedDocument::new(json!({"a": 1, "b": 1}), ConversionOptions::default());
+        let lua = Lua::new();
+        lua.globals().set("doc", doc.clone()).expect("set global");
+
+        let failed = lua.load(r#"
+            doc:begin()
+            local ok = pcall(function()
+                doc.a = 2
+                error("halfway")
+            end)
+            if ok then doc:commit() else doc:rollback() end
+            doc:begin()
+            doc.b = 2
+            doc:commit()
+            return not ok
+        "#).eval::<bool>().expect("eval");
+        assert!(failed);
+        assert_eq!(*doc.read(), json!({"a": 1, "b": 2}));
+        assert!(lua.load("doc:commit()").exec().is_err());
+
+        {
+            let transaction = doc.transaction();
+            transaction.document().set_pointer("/a", json!(3)).expect("set");
+        }
+        assert_eq!(doc.read()["a"], json!(1));
+        let transaction = doc.transaction();
+        doc.set_pointer("/a", json!(3)).expect("set");
+        transaction.commit();
+        assert_eq!(doc.read()["a"], json!(3));
+    }
+
+    #[test]
+    fn change_listeners() {
+        let doc = SharedDocument::new(json!({"hp": 10}), ConversionOptions::default());
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let sink = changes.clone();
+        // Registered through a handle that is dropped right away: the listener stays with the document.
+        doc.clone().on_change(move |pointer, old, new| sink.lock().expect("lock").push((pointer.to_string(), old.clone(), new.clone())));
+
+        let lua = Lua::new();
+        lua.globals().set("doc", doc.clone()).expect("set global");
+        let seen: String = lua.load(r#"
+            local seen = ""
+            doc:on_change(function(pointer, old, new) seen = seen .. pointer .. "=" .. tostring(old) .. ">" .. tostring(new) end)
+            doc.hp = 5
+            return seen
+        "#).eval().expect("eval");
+        doc.set_pointer("/hp", json!(7)).expect("set");
+
+        assert_eq!(seen, "/hp=10>5");
+        assert_eq!(*changes.lock().expect("lock"), vec![
+            ("/hp".to_string(), json!(10), json!(5)),
+            ("/hp".to_string(), json!(5), json!(7)),
+        ]);
+    }
+
+    #[test]
+    fn lua_listeners_are_collected() {
+        let lua = Lua::new();
+        let doc = SharedDocument::new(json!({"hp": 10}), ConversionOptions::default());
+        lua.globals().set("a", doc.clone()).expect("set global");
+        lua.globals().set("b", doc.clone()).expect("set global");
+        lua.globals().set("other", SharedDocument::new(json!({"hp": 1}), ConversionOptions::default())).expect("set global");
+        let count = || lua.load("count = 0; for _ in pairs(LISTENERS) do count = count + 1 end; return count").eval::<i64>().expect("count");
+        lua.load(r#"
+            calls = 0
+            a:on_change(function() calls = calls + 1 end)
+            b.hp = 5
+            other.hp = 2
+            assert(calls == 1)
+        "#).exec().expect("shared between handles");
+        let listeners: rlua::Table = lua.named_registry_value(super::LUA_LISTENERS_KEY).expect("registry");
+        lua.globals().set("LISTENERS", listeners).expect("set global");
+        assert_eq!(count(), 1);
+
+        lua.load("a = nil").exec().expect("drop");
+        lua.gc_collect().expect("collect");
+        lua.gc_collect().expect("collect");
+        assert_eq!(count(), 0);
+        lua.load("b.hp = 6; assert(calls == 1)").exec().expect("no listener left");
+    }
+}
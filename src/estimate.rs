@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::ffi::c_void;
+use serde_json::Value as JsonValue;
+
+/// Approximate sizes of Lua 5.4 objects on a 64-bit target.
+const TVALUE: usize = 16;
+const TABLE: usize = 56;
+const NODE: usize = 32;
+const STRING: usize = 24;
+
+fn string_bytes(len: usize) -> usize {
+    STRING + len + 1
+}
+
+fn table_bytes(array: usize, hash: usize) -> usize {
+    let hash = if hash == 0 { 0 } else { hash.next_power_of_two() };
+    TABLE + array * TVALUE + hash * NODE
+}
+
+/// Roughly how many bytes of Lua heap converting `value` with [`json_to_lua`](crate::json_to_lua)
+/// allocates, to decide whether to convert eagerly, lazily or not at all. Does not account for
+/// Lua sharing equal short strings, nor for options that add metatables or proxies.
+pub fn estimate_lua_memory(value: &JsonValue) -> usize {
+    match value {
+        JsonValue::String(s) => string_bytes(s.len()),
+        JsonValue::Array(a) => table_bytes(a.len(), 0) + a.iter().map(estimate_lua_memory).sum::<usize>(),
+        JsonValue::Object(o) => table_bytes(0, o.len())
+            + o.iter().map(|(k, v)| string_bytes(k.len()) + estimate_lua_memory(v)).sum::<usize>(),
+        _ => 0,
+    }
+}
+
+fn json_size(value: &rlua::Value, ancestors: &mut HashSet<*const c_void>) -> rlua::Result<usize> {
+    Ok(match value {
+        rlua::Value::Boolean(true) => 4,
+        rlua::Value::Boolean(false) => 5,
+        rlua::Value::Integer(i) => i.to_string().len(),
+        rlua::Value::Number(n) => n.to_string().len(),
+        rlua::Value::String(s) => s.as_bytes().len() + 2,
+        rlua::Value::Table(t) => {
+            if !ancestors.insert(t.to_pointer()) {
+                return Ok(0);
+            }
+            // Brackets, plus a comma between entries.
+            let mut size = 1;
+            for pair in t.clone().pairs::<rlua::Value, rlua::Value>() {
+                let (key, value) = pair?;
+                size += json_size(&value, ancestors)? + 1;
+                if let rlua::Value::String(key) = key {
+                    size += key.as_bytes().len() + 3;
+                }
+            }
+            ancestors.remove(&t.to_pointer());
+            size.max(2)
+        },
+        _ => 4,
+    })
+}
+
+/// Roughly how many bytes of compact JSON text `value` encodes to, without encoding it.
+/// Escapes, metamethods and the encoding policies are not taken into account; a table
+/// containing itself counts once.
+pub fn estimate_json_size(value: &rlua::Value) -> rlua::Result<usize> {
+    json_size(value, &mut HashSet::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, estimate_json_size, lua_to_string};
+
+    #[test]
+    fn json_size_estimate() {
+        let lua = Lua::new();
+        let value: rlua::Value = lua.load("{ name = 'sensor', readings = { 1, 2.5, 300 }, ok = true }").eval().expect("table");
+        let encoded = lua_to_string(&lua, value.clone(), &ConversionOptions::default()).expect("encode");
+        assert_eq!(estimate_json_size(&value).expect("estimate"), encoded.len());
+    }
+
+    /// The estimate models Lua 5.x objects.
+    #[test]
+    #[cfg(not(feature = "luau"))]
+    fn lua_memory_estimate() {
+        use serde_json::json;
+        use crate::{estimate_lua_memory, json_to_lua};
+
+        let lua = Lua::new();
+        let document = json!({"rows": (0..1000).map(|i| json!({"id": i, "label": format!("row {}", i)})).collect::<Vec<_>>()});
+        let before = lua.used_memory();
+        let converted = json_to_lua(&lua, &document, &ConversionOptions::default()).expect("convert");
+        let used = lua.used_memory() - before;
+        let estimate = estimate_lua_memory(&document);
+        assert!(estimate > used / 2 && estimate < used * 2, "estimated {}, used {}", estimate, used);
+        drop(converted);
+    }
+}
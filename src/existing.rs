@@ -0,0 +1,116 @@
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, JsonType, PathSegment};
+use crate::convert::{Decoder, MAX_DEPTH, integer};
+use crate::readonly::view_contents;
+
+/// What [`json_into_existing_table`] does with keys of the table that the document lacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Remove them, so the table ends up equal to a fresh conversion.
+    Replace,
+    /// Keep them, like JSON Merge Patch: objects merge key by key and `null` removes a key.
+    Merge,
+}
+
+/// Converts an object or array into `table` in place instead of allocating a new tree, e.g. to
+/// sync per-frame state without churning the GC. Nested tables are reused where the document
+/// has an object or array at the same key; arrays are always replaced element-wise, with
+/// excess elements removed. Scripts holding `table` or its nested tables see the new contents.
+pub fn json_into_existing_table<'lua>(
+    lua: &'lua Lua, value: &JsonValue, table: &rlua::Table<'lua>, policy: MergePolicy, options: &ConversionOptions,
+) -> rlua::Result<()> {
+    if !(value.is_object() || value.is_array()) {
+        return Err(rlua::Error::ToLuaConversionError {
+            from: "JsonValue", to: "Table", message: Some("only an object or array can fill a table".to_string()) });
+    }
+    let mut decoder = Decoder::new(lua, options);
+    decoder.index_subtrees(value);
+    fill(&mut decoder, table, value, policy)?;
+    decoder.resolve_references()
+}
+
+fn fill<'lua>(decoder: &mut Decoder<'_, 'lua>, table: &rlua::Table<'lua>, value: &JsonValue, policy: MergePolicy) -> rlua::Result<()> {
+    if decoder.path.len() > MAX_DEPTH {
+        return Err(rlua::Error::RuntimeError(format!("{}: nesting deeper than {} levels", decoder.path, MAX_DEPTH)));
+    }
+    let stale: Vec<rlua::Value> = table.clone().pairs::<rlua::Value, rlua::Value>()
+        .map(|pair| pair.map(|(key, _)| key))
+        .filter(|key| match (key, value) {
+            (Ok(rlua::Value::String(k)), JsonValue::Object(o)) => policy == MergePolicy::Replace
+                && !k.to_str().is_ok_and(|k| o.contains_key(k)),
+            (Ok(rlua::Value::Integer(i)), JsonValue::Array(a)) => *i < 1 || *i as usize > a.len(),
+            (Ok(_), JsonValue::Object(_)) => policy == MergePolicy::Replace,
+            _ => true,
+        })
+        .collect::<rlua::Result<_>>()?;
+    for key in stale {
+        table.raw_set(key, rlua::Value::Nil)?;
+    }
+
+    match value {
+        JsonValue::Object(o) => {
+            for (k, v) in o {
+                decoder.path.push(PathSegment::Key(k.clone()));
+                let result = fill_slot(decoder, table, rlua::Value::String(decoder.lua.create_string(k)?), v, policy);
+                decoder.path.pop();
+                result?;
+            }
+            decoder.mark(table, JsonType::Object)
+        },
+        JsonValue::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                decoder.path.push(PathSegment::Index(i));
+                let result = fill_slot(decoder, table, integer(i as i64 + 1), v, policy);
+                decoder.path.pop();
+                result?;
+            }
+            decoder.mark(table, JsonType::Array)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Reuses the table at `key` for a container, unless it is a read-only view.
+fn fill_slot<'lua>(
+    decoder: &mut Decoder<'_, 'lua>, table: &rlua::Table<'lua>, key: rlua::Value<'lua>, value: &JsonValue, policy: MergePolicy,
+) -> rlua::Result<()> {
+    if value.is_object() || value.is_array() {
+        if let rlua::Value::Table(existing) = table.raw_get(key.clone())? {
+            if view_contents(&existing)?.is_none() {
+                return fill(decoder, &existing, value, policy);
+            }
+        }
+    }
+    table.raw_set(key, decoder.convert(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, MergePolicy, json_into_existing_table, lua_to_json};
+
+    #[test]
+    fn fills_in_place() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let state: rlua::Table = lua.load("{ player = { x = 1, y = 2, name = 'p' }, list = { 1, 2, 3 }, local_only = true }")
+            .eval().expect("state");
+        lua.globals().set("state", state.clone()).expect("set");
+        lua.load("player = state.player").exec().expect("keep");
+
+        let update = json!({"player": {"x": 5, "name": null}, "list": [9]});
+        json_into_existing_table(&lua, &update, &state, MergePolicy::Merge, &options).expect("merge");
+        assert!(lua.load("rawequal(player, state.player)").eval::<bool>().expect("same table"));
+        assert_eq!(lua_to_json(&lua, rlua::Value::Table(state.clone()), &options).expect("merged"),
+            json!({"player": {"x": 5, "y": 2}, "list": [9], "local_only": true}));
+
+        json_into_existing_table(&lua, &update, &state, MergePolicy::Replace, &options).expect("replace");
+        assert!(lua.load("rawequal(player, state.player)").eval::<bool>().expect("same table"));
+        assert_eq!(lua_to_json(&lua, rlua::Value::Table(state.clone()), &options).expect("replaced"),
+            json!({"player": {"x": 5}, "list": [9]}));
+
+        assert!(json_into_existing_table(&lua, &json!(1), &state, MergePolicy::Merge, &options).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use rlua::Lua;
+use crate::{ConversionOptions, decode_reader_into_lua, encode_lua_to_writer};
+
+/// Reads and converts a JSON file.
+pub fn decode_file_into_lua<'lua>(
+    lua: &'lua Lua, path: impl AsRef<Path>, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let file = fs::File::open(path).map_err(rlua::Error::external)?;
+    decode_reader_into_lua(lua, file, options)
+}
+
+/// Includes the process id where there is one, so concurrent writers do not collide.
+#[cfg(not(target_family = "wasm"))]
+fn temporary_suffix() -> String {
+    format!(".{}.tmp", std::process::id())
+}
+
+#[cfg(target_family = "wasm")]
+fn temporary_suffix() -> String {
+    ".tmp".to_string()
+}
+
+/// Encodes a Lua value into a file, pretty-printed if `options.indent` is set. The text goes to
+/// a temporary file next to `path` that then replaces it, so readers never see a partial write.
+pub fn encode_lua_to_file<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, path: impl AsRef<Path>, options: &ConversionOptions,
+) -> rlua::Result<()> {
+    let path = path.as_ref();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(temporary_suffix());
+    let temporary = PathBuf::from(temporary);
+
+    let write = || -> rlua::Result<()> {
+        let mut file = fs::File::create(&temporary).map_err(rlua::Error::external)?;
+        encode_lua_to_writer(lua, value, &mut file, options)?;
+        file.sync_all().map_err(rlua::Error::external)?;
+        fs::rename(&temporary, path).map_err(rlua::Error::external)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// `path` resolved against the filesystem, if it lies in one of `options.file_roots`
+/// and has one of `options.file_extensions`.
+pub(crate) fn allowed_path(path: &str, options: &ConversionOptions) -> rlua::Result<PathBuf> {
+    let denied = || rlua::Error::RuntimeError(format!("access to {:?} is not allowed", path));
+    let path = Path::new(path);
+    let resolved = match fs::canonicalize(path) {
+        Ok(resolved) => resolved,
+        // A file about to be created: resolve its directory instead.
+        Err(_) => {
+            let name = path.file_name().ok_or_else(denied)?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            fs::canonicalize(parent).map_err(|_| denied())?.join(name)
+        },
+    };
+    let in_root = options.file_roots.iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root));
+    let extension = resolved.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let extension_allowed = options.file_extensions.is_empty()
+        || options.file_extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(extension));
+    if in_root && extension_allowed { Ok(resolved) } else { Err(denied()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, register};
+
+    #[test]
+    fn file_round_trip_within_roots() {
+        let dir = std::env::temp_dir().join(format!("rlua_json_file_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let lua = Lua::new();
+        let options = ConversionOptions {
+            file_roots: vec![dir.clone()], file_extensions: vec!["json".to_string()], indent: true, ..Default::default()
+        };
+        register(&lua, options).expect("register");
+        lua.globals().set("dir", dir.to_str().expect("utf-8 path")).expect("set global");
+
+        let level: i64 = lua.load(r#"
+            json.encode_file(dir .. "/save.json", { level = 3 })
+            return json.decode_file(dir .. "/save.json").level
+        "#).eval().expect("eval");
+        assert_eq!(level, 3);
+        assert!(std::fs::read_to_string(dir.join("save.json")).expect("read").contains('\n'));
+        assert!(lua.load(r#"json.decode_file(dir .. "/../outside.json")"#).exec().is_err());
+        assert!(lua.load(r#"json.encode_file("relative.json", {})"#).exec().is_err());
+        assert!(lua.load(r#"json.encode_file(dir .. "/script.lua", {})"#).exec().is_err());
+
+        std::fs::remove_dir_all(&dir).expect("clean up");
+    }
+}
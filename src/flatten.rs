@@ -0,0 +1,164 @@
+use serde_json::{Map, Value as JsonValue};
+use crate::JsonWrapperValue;
+
+/// How [`flatten`] joins keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlattenStyle {
+    /// Between object keys: `a.b`.
+    pub separator: String,
+    /// Write array indices as `a[0]`; otherwise as keys (`a.0`), which [`unflatten`] turns into objects.
+    pub array_brackets: bool,
+}
+
+impl Default for FlattenStyle {
+    fn default() -> Self {
+        FlattenStyle { separator: ".".to_string(), array_brackets: true }
+    }
+}
+
+fn flatten_into(value: &JsonValue, prefix: &mut String, style: &FlattenStyle, out: &mut Map<String, JsonValue>) {
+    let len = prefix.len();
+    match value {
+        JsonValue::Object(o) if !o.is_empty() => for (k, v) in o {
+            if !prefix.is_empty() {
+                prefix.push_str(&style.separator);
+            }
+            prefix.push_str(k);
+            flatten_into(v, prefix, style, out);
+            prefix.truncate(len);
+        },
+        JsonValue::Array(a) if !a.is_empty() => for (i, v) in a.iter().enumerate() {
+            match style.array_brackets {
+                true => prefix.push_str(&format!("[{}]", i)),
+                false if prefix.is_empty() => prefix.push_str(&i.to_string()),
+                false => prefix.push_str(&format!("{}{}", style.separator, i)),
+            }
+            flatten_into(v, prefix, style, out);
+            prefix.truncate(len);
+        },
+        _ => {
+            out.insert(prefix.clone(), value.clone());
+        },
+    }
+}
+
+/// Maps each leaf (scalars, empty arrays and objects) to its joined path, e.g.
+/// `{"a": {"b": [{"c": 1}]}}` to `{"a.b[0].c": 1}`. A scalar root is stored under `""`.
+pub fn flatten(value: &JsonValue, style: &FlattenStyle) -> Map<String, JsonValue> {
+    let mut out = Map::new();
+    flatten_into(value, &mut String::new(), style, &mut out);
+    out
+}
+
+enum Step<'k> {
+    Key(&'k str),
+    Index(usize),
+}
+
+fn steps<'k>(key: &'k str, style: &FlattenStyle) -> Option<Vec<Step<'k>>> {
+    let mut steps = Vec::new();
+    for part in key.split(style.separator.as_str()) {
+        let (name, mut indices) = match style.array_brackets {
+            true => part.find('[').map_or((part, ""), |i| part.split_at(i)),
+            false => (part, ""),
+        };
+        if !name.is_empty() || indices.is_empty() {
+            steps.push(Step::Key(name));
+        }
+        while let Some(rest) = indices.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']')?;
+            steps.push(Step::Index(index.parse().ok()?));
+            indices = rest;
+        }
+        if !indices.is_empty() {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+fn conflict(key: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("unflatten: key {:?} conflicts with another key", key))
+}
+
+/// Reverses [`flatten`]; missing array elements become `null`.
+pub fn unflatten(map: &Map<String, JsonValue>, style: &FlattenStyle) -> rlua::Result<JsonValue> {
+    if let (1, Some(root)) = (map.len(), map.get("")) {
+        return Ok(root.clone());
+    }
+    let mut root = JsonValue::Null;
+    for (key, value) in map {
+        let steps = steps(key, style)
+            .ok_or_else(|| rlua::Error::RuntimeError(format!("unflatten: malformed key {:?}", key)))?;
+        let mut node = &mut root;
+        for step in steps {
+            node = match step {
+                Step::Key(k) => {
+                    if node.is_null() {
+                        *node = JsonValue::Object(Map::new());
+                    }
+                    let JsonValue::Object(o) = node else { return Err(conflict(key)) };
+                    o.entry(k).or_insert(JsonValue::Null)
+                },
+                Step::Index(i) => {
+                    if node.is_null() {
+                        *node = JsonValue::Array(Vec::new());
+                    }
+                    let JsonValue::Array(a) = node else { return Err(conflict(key)) };
+                    if a.len() <= i {
+                        a.resize(i + 1, JsonValue::Null);
+                    }
+                    a.get_mut(i).ok_or_else(|| conflict(key))?
+                },
+            };
+        }
+        if !node.is_null() {
+            return Err(conflict(key));
+        }
+        *node = value.clone();
+    }
+    Ok(root)
+}
+
+impl JsonWrapperValue {
+    /// [`flatten`] with the default style.
+    pub fn flatten(&self) -> JsonWrapperValue {
+        JsonWrapperValue(JsonValue::Object(flatten(&self.0, &FlattenStyle::default())))
+    }
+
+    /// [`unflatten`] with the default style, for a wrapped object.
+    pub fn unflatten(&self) -> rlua::Result<JsonWrapperValue> {
+        match &self.0 {
+            JsonValue::Object(o) => unflatten(o, &FlattenStyle::default()).map(JsonWrapperValue),
+            _ => Err(rlua::Error::RuntimeError("unflatten: expected an object".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, FlattenStyle, flatten, register, unflatten};
+
+    #[test]
+    fn flatten_round_trip() {
+        let doc = json!({"a": {"b": [{"c": 1}, 2]}, "empty": [], "k": null});
+        let flat = flatten(&doc, &FlattenStyle::default());
+        assert_eq!(serde_json::Value::Object(flat.clone()), json!({"a.b[0].c": 1, "a.b[1]": 2, "empty": [], "k": null}));
+        assert_eq!(unflatten(&flat, &FlattenStyle::default()).expect("unflatten"), doc);
+
+        let slash = FlattenStyle { separator: "/".to_string(), array_brackets: false };
+        assert_eq!(serde_json::Value::Object(flatten(&doc, &slash))["a/b/1"], json!(2));
+        let conflicting = json!({"a": 1, "a.b": 2});
+        assert!(unflatten(conflicting.as_object().expect("object"), &FlattenStyle::default()).is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        let (flat_key, round_trip): (i64, i64) = lua.load(r#"
+            local flat = json.flatten({ a = { b = { 10, 20 } } }, ":")
+            return flat["a:b[1]"], json.unflatten(flat, ":").a.b[2]
+        "#).eval().expect("eval");
+        assert_eq!((flat_key, round_trip), (20, 20));
+    }
+}
@@ -0,0 +1,287 @@
+use std::io;
+use serde::Serialize;
+use serde_json::ser::Formatter;
+use serde_json::Value as JsonValue;
+use rlua::Lua;
+use serde_json::value::RawValue;
+use crate::convert::Encoder;
+use crate::raw::WithRaw;
+use crate::{ConversionOptions, parse_json};
+
+/// How floats are written when encoding; integers are never affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// Shortest representation that parses back to the same value.
+    Shortest,
+    /// A fixed number of digits after the decimal point: `Fixed(2)` writes `0.10`.
+    Fixed(usize),
+    /// At most this many significant digits, like C's `%.*g` (cjson's `encode_number_precision`).
+    Precision(usize),
+}
+
+/// `%.*g`: trailing zeros dropped, exponent notation outside `1e-4 ..= 10^precision`.
+fn format_significant(f: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    if f == 0.0 {
+        return "0".to_string();
+    }
+    let scientific = format!("{:.*e}", precision - 1, f);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+
+    fn trim(s: &str) -> &str {
+        if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { s }
+    }
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        trim(&format!("{:.*}", decimals, f)).to_string()
+    }
+}
+
+/// serde_json formatter driven by [`ConversionOptions`]: indentation and float format.
+pub(crate) struct JsonFormatter {
+    indent: Option<&'static [u8]>,
+    current_indent: usize,
+    has_value: bool,
+    float_format: FloatFormat,
+    escape_forward_slash: bool,
+    ensure_ascii: bool,
+}
+
+impl JsonFormatter {
+    pub fn new(options: &ConversionOptions) -> Self {
+        JsonFormatter {
+            indent: if options.indent { Some(b"  ") } else { None },
+            current_indent: 0,
+            has_value: false,
+            float_format: options.float_format,
+            escape_forward_slash: options.escape_forward_slash,
+            ensure_ascii: options.ensure_ascii,
+        }
+    }
+
+    fn newline<W: ?Sized + io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(indent) = self.indent {
+            writer.write_all(b"\n")?;
+            for _ in 0..self.current_indent {
+                writer.write_all(indent)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn begin<W: ?Sized + io::Write>(&mut self, writer: &mut W, bracket: &[u8]) -> io::Result<()> {
+        self.current_indent += 1;
+        self.has_value = false;
+        writer.write_all(bracket)
+    }
+
+    fn end<W: ?Sized + io::Write>(&mut self, writer: &mut W, bracket: &[u8]) -> io::Result<()> {
+        self.current_indent -= 1;
+        if self.has_value {
+            self.newline(writer)?;
+        }
+        writer.write_all(bracket)
+    }
+
+    fn begin_value<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if !first {
+            writer.write_all(b",")?;
+        }
+        self.newline(writer)
+    }
+}
+
+impl Formatter for JsonFormatter {
+    fn write_f64<W: ?Sized + io::Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        match self.float_format {
+            FloatFormat::Shortest => serde_json::ser::CompactFormatter.write_f64(writer, value),
+            FloatFormat::Fixed(decimals) => write!(writer, "{:.*}", decimals, value),
+            FloatFormat::Precision(precision) => writer.write_all(format_significant(value, precision).as_bytes()),
+        }
+    }
+
+    fn write_string_fragment<W: ?Sized + io::Write>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()> {
+        if !self.escape_forward_slash && (!self.ensure_ascii || fragment.is_ascii()) {
+            return writer.write_all(fragment.as_bytes());
+        }
+        let mut start = 0;
+        for (i, c) in fragment.char_indices() {
+            let plain = if c == '/' { !self.escape_forward_slash } else { c.is_ascii() || !self.ensure_ascii };
+            if plain {
+                continue;
+            }
+            writer.write_all(fragment.as_bytes().get(start..i).unwrap_or_default())?;
+            if c == '/' {
+                writer.write_all(b"\\/")?;
+            } else {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    write!(writer, "\\u{:04x}", unit)?;
+                }
+            }
+            start = i + c.len_utf8();
+        }
+        writer.write_all(fragment.as_bytes().get(start..).unwrap_or_default())
+    }
+
+    fn begin_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.begin(writer, b"[")
+    }
+
+    fn end_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.end(writer, b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + io::Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.begin(writer, b"{")
+    }
+
+    fn end_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.end(writer, b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(if self.indent.is_some() { b": " } else { b":" })
+    }
+
+    fn end_object_value<W: ?Sized + io::Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+}
+
+/// Encodes a JSON document to a string as configured by `options` (indentation, float format).
+pub fn to_string(value: &JsonValue, options: &ConversionOptions) -> rlua::Result<String> {
+    to_string_with_raw(value, &[], options)
+}
+
+fn write_with_raw(
+    writer: impl io::Write, value: &JsonValue, raws: &[Box<RawValue>], options: &ConversionOptions,
+) -> rlua::Result<()> {
+    let mut serializer = serde_json::Serializer::with_formatter(writer, JsonFormatter::new(options));
+    WithRaw { value, raws, order: options.sort_keys }.serialize(&mut serializer).map_err(rlua::Error::external)
+}
+
+fn to_string_with_raw(value: &JsonValue, raws: &[Box<RawValue>], options: &ConversionOptions) -> rlua::Result<String> {
+    let mut out = Vec::new();
+    write_with_raw(&mut out, value, raws, options)?;
+    String::from_utf8(out).map_err(rlua::Error::external)
+}
+
+fn encode_lua<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<(JsonValue, Vec<Box<RawValue>>)> {
+    let mut encoder = Encoder::new(lua, options);
+    encoder.raws = Some(Vec::new());
+    #[cfg(feature = "tracing")]
+    if let Some(span) = crate::stats::conversion_span("encode") {
+        let result = span.in_scope(|| encoder.convert_counted(value));
+        crate::stats::record_span(&span, &encoder.stats);
+        return Ok((result?, encoder.raws.unwrap_or_default()));
+    }
+    let json = encoder.convert(value)?;
+    Ok((json, encoder.raws.unwrap_or_default()))
+}
+
+/// Encodes a Lua value to JSON text; [`RawJson`](crate::RawJson) fragments are embedded verbatim.
+pub fn lua_to_string<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<String> {
+    let (json, raws) = encode_lua(lua, value, options)?;
+    to_string_with_raw(&json, &raws, options)
+}
+
+/// [`lua_to_string`] into a writer, e.g. a socket or a compressor such as
+/// `flate2::write::GzEncoder`, through a buffer of `options.io_buffer_size` bytes.
+pub fn encode_lua_to_writer<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, writer: impl io::Write, options: &ConversionOptions,
+) -> rlua::Result<()> {
+    let (json, raws) = encode_lua(lua, value, options)?;
+    let mut writer = io::BufWriter::with_capacity(options.io_buffer_size, writer);
+    write_with_raw(&mut writer, &json, &raws, options)?;
+    io::Write::flush(&mut writer).map_err(rlua::Error::external)
+}
+
+/// Re-serializes JSON text as configured by `options`, without converting it to Lua.
+pub fn reformat(input: &[u8], options: &ConversionOptions) -> rlua::Result<String> {
+    to_string(&parse_json(input, options)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, FloatFormat, decode_reader_into_lua, encode_lua_to_writer, to_string};
+
+    #[test]
+    fn float_formats() {
+        let doc = json!([0.1, 1.0 / 3.0, 2.5e20, 0.00001234, 7]);
+        let encode = |float_format| to_string(&doc, &ConversionOptions { float_format, ..Default::default() })
+            .expect("encode");
+
+        assert_eq!(encode(FloatFormat::Shortest), "[0.1,0.3333333333333333,2.5e20,0.00001234,7]");
+        assert_eq!(encode(FloatFormat::Fixed(2)), "[0.10,0.33,250000000000000000000.00,0.00,7]");
+        assert_eq!(encode(FloatFormat::Precision(3)), "[0.1,0.333,2.5e+20,1.23e-05,7]");
+
+        let pretty = to_string(&json!({"a": [1]}), &ConversionOptions { indent: true, ..Default::default() });
+        assert_eq!(pretty.expect("pretty"), serde_json::to_string_pretty(&json!({"a": [1]})).expect("serde"));
+    }
+
+    #[test]
+    fn string_escaping() {
+        let doc = json!({"url": "a/b", "text": "é😀\"x"});
+        let options = ConversionOptions { escape_forward_slash: true, ensure_ascii: true, ..Default::default() };
+        let encoded = to_string(&doc, &options).expect("encode");
+
+        assert!(encoded.is_ascii());
+        assert!(encoded.contains(r#""a\/b""#));
+        assert!(encoded.contains(r#""\u00e9\ud83d\ude00\"x""#));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&encoded).expect("valid json"), doc);
+    }
+
+    /// Stands in for a compression stream: bytes are XOR-ed on the way through.
+    struct Scramble<W>(W);
+
+    impl<W: Write> Write for Scramble<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write_all(&buf.iter().map(|b| b ^ 0x5a).collect::<Vec<_>>())?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    #[test]
+    fn writer_and_reader_streams() {
+        let lua = Lua::new();
+        let value = lua.load("return { name = 'x', list = { 1, 2 } }").eval::<rlua::Value>().expect("table");
+        let options = ConversionOptions { io_buffer_size: 3, ..Default::default() };
+        let mut scrambled = Vec::new();
+        encode_lua_to_writer(&lua, value, Scramble(&mut scrambled), &options).expect("write");
+
+        let plain: Vec<u8> = scrambled.iter().map(|b| b ^ 0x5a).collect();
+        let decoded = decode_reader_into_lua(&lua, plain.as_slice(), &options).expect("read");
+        lua.globals().set("decoded", decoded).expect("set global");
+        assert_eq!(lua.load("return decoded.name .. #decoded.list").eval::<String>().expect("eval"), "x2");
+    }
+}
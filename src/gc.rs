@@ -0,0 +1,98 @@
+use rlua::Lua;
+
+/// Garbage collector pacing during a conversion, so a very large document does not trigger one
+/// long collection at an unpredictable moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcHint {
+    /// Leave the collector alone.
+    Auto,
+    /// Run an incremental step of `kbytes` every `every` converted values, spreading the
+    /// collection work over the conversion.
+    Step { every: usize, kbytes: i32 },
+    /// Stop the collector for the conversion and restart it afterwards, for the host to collect
+    /// at a moment of its choosing (e.g. behind a loading screen). Memory grows unchecked meanwhile.
+    Defer,
+}
+
+/// Applies a [`GcHint`] for the lifetime of one conversion.
+pub(crate) struct GcPacer<'lua> {
+    lua: &'lua Lua,
+    hint: GcHint,
+    count: usize,
+    stopped: bool,
+}
+
+impl<'lua> GcPacer<'lua> {
+    pub fn new(lua: &'lua Lua, hint: GcHint) -> Self {
+        GcPacer { lua, hint, count: 0, stopped: false }
+    }
+
+    /// Called once per converted value.
+    pub fn tick(&mut self) -> rlua::Result<()> {
+        self.advance(1)
+    }
+
+    /// Counts `n` values converted at once, e.g. the elements of an array of numbers; at most
+    /// one step is run for them.
+    pub fn advance(&mut self, n: usize) -> rlua::Result<()> {
+        let before = self.count;
+        self.count += n;
+        match self.hint {
+            GcHint::Auto => {},
+            GcHint::Step { every, kbytes } => if self.count / every.max(1) > before / every.max(1) {
+                self.lua.gc_step_kbytes(kbytes)?;
+            },
+            GcHint::Defer => if before == 0 && n > 0 && gc_running(self.lua) {
+                self.lua.gc_stop();
+                self.stopped = true;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GcPacer<'_> {
+    fn drop(&mut self) {
+        if self.stopped {
+            self.lua.gc_restart();
+        }
+    }
+}
+
+/// Lua 5.1 and LuaJIT cannot tell; a collector the host stopped is then restarted too.
+#[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52", feature = "luau"))]
+fn gc_running(lua: &Lua) -> bool {
+    lua.gc_is_running()
+}
+
+#[cfg(not(any(feature = "lua54", feature = "lua53", feature = "lua52", feature = "luau")))]
+fn gc_running(_: &Lua) -> bool {
+    true
+}
+
+#[cfg(all(test, not(any(feature = "luajit", feature = "lua51", feature = "luau"))))]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, GcHint, json_to_lua, lua_to_json};
+
+    #[test]
+    fn gc_hints() {
+        let lua = Lua::new();
+        let doc = json!((0..2000).map(|i| json!({"i": i, "s": "x".repeat(20)})).collect::<Vec<_>>());
+        let options = ConversionOptions { gc_hint: GcHint::Step { every: 100, kbytes: 16 }, ..Default::default() };
+        let value = json_to_lua(&lua, &doc, &options).expect("stepped");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+
+        let probe: rlua::Value = lua.load(r#"
+            setmetatable({}, { __tojson = function() return tostring(collectgarbage("isrunning")) end })
+        "#).eval().expect("probe");
+        let options = ConversionOptions { gc_hint: GcHint::Defer, tojson_metamethod: true, ..Default::default() };
+        assert_eq!(lua_to_json(&lua, probe.clone(), &options).expect("deferred"), json!(false));
+        assert!(lua.gc_is_running());
+
+        lua.gc_stop();
+        lua_to_json(&lua, probe, &options).expect("already stopped");
+        assert!(!lua.gc_is_running());
+    }
+}
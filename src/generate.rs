@@ -0,0 +1,282 @@
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua, resolve_pointer};
+
+/// SplitMix64: small, seedable and the same on every platform, which is all fake data needs.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; 0 if `n` is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        match n {
+            0 => 0,
+            n => self.next_u64() % n,
+        }
+    }
+
+    /// Uniform in `lo..=hi`.
+    pub fn between(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = hi.abs_diff(lo).saturating_add(1);
+        lo.saturating_add_unsigned(self.below(span))
+    }
+
+    /// Uniform in `0.0..1.0`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        items.get(self.below(items.len() as u64) as usize)
+    }
+}
+
+const WORDS: [&str; 16] = [
+    "alpha", "bravo", "delta", "echo", "lima", "nova", "orbit", "pixel",
+    "quartz", "river", "solar", "tango", "ultra", "vector", "willow", "zephyr",
+];
+
+/// Nesting below which optional properties and array items are no longer generated, so
+/// recursive schemas end.
+const MAX_DEPTH: usize = 8;
+
+struct Generator<'s> {
+    root: &'s JsonValue,
+    rng: Rng,
+}
+
+impl<'s> Generator<'s> {
+    fn words(&mut self, min: usize, max: usize) -> String {
+        let target = max.min(min.max(4 + self.below(12)));
+        let mut text = String::new();
+        while text.len() < target {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(self.rng.pick(&WORDS).copied().unwrap_or_default());
+        }
+        let mut text: String = text.chars().take(max).collect();
+        while text.chars().count() < min {
+            text.push('x');
+        }
+        text
+    }
+
+    fn below(&mut self, n: u64) -> usize {
+        self.rng.below(n) as usize
+    }
+
+    fn string(&mut self, schema: &Map<String, JsonValue>) -> JsonValue {
+        let hex = |rng: &mut Rng, n: usize| (0..n).map(|_| format!("{:x}", rng.below(16))).collect::<String>();
+        let text = match schema.get("format").and_then(JsonValue::as_str) {
+            Some("date-time") => format!("20{:02}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                self.rng.between(0, 30), self.rng.between(1, 12), self.rng.between(1, 28),
+                self.rng.between(0, 23), self.rng.between(0, 59), self.rng.between(0, 59)),
+            Some("date") => format!("20{:02}-{:02}-{:02}", self.rng.between(0, 30), self.rng.between(1, 12), self.rng.between(1, 28)),
+            Some("email") => format!("{}{}@example.com", self.rng.pick(&WORDS).copied().unwrap_or_default(), self.rng.below(100)),
+            Some("uri") | Some("url") => format!("https://example.com/{}", self.rng.pick(&WORDS).copied().unwrap_or_default()),
+            Some("uuid") => format!("{}-{}-4{}-a{}-{}", hex(&mut self.rng, 8), hex(&mut self.rng, 4), hex(&mut self.rng, 3),
+                hex(&mut self.rng, 3), hex(&mut self.rng, 12)),
+            Some("ipv4") => format!("10.{}.{}.{}", self.rng.below(256), self.rng.below(256), self.rng.below(256)),
+            _ => {
+                let min = schema.get("minLength").and_then(JsonValue::as_u64).unwrap_or(0) as usize;
+                let max = schema.get("maxLength").and_then(JsonValue::as_u64).map_or(usize::MAX, |max| max as usize);
+                return JsonValue::String(self.words(min, max));
+            },
+        };
+        JsonValue::String(text)
+    }
+
+    fn number(&mut self, schema: &Map<String, JsonValue>, integer: bool) -> JsonValue {
+        let bound = |key: &str, exclusive: &str| match (schema.get(key).and_then(JsonValue::as_f64), schema.get(exclusive)) {
+            (_, Some(JsonValue::Number(n))) => n.as_f64().map(|n| (n, true)),
+            (Some(n), Some(JsonValue::Bool(true))) => Some((n, true)),
+            (n, _) => n.map(|n| (n, false)),
+        };
+        let min = bound("minimum", "exclusiveMinimum");
+        let max = bound("maximum", "exclusiveMaximum");
+        let (lo, hi) = match (min, max) {
+            (Some(lo), Some(hi)) => (lo, hi),
+            (Some(lo), None) => (lo, (lo.0 + 100.0, false)),
+            (None, Some(hi)) => ((hi.0 - 100.0, false), hi),
+            (None, None) => ((0.0, false), (100.0, false)),
+        };
+        if integer {
+            let lo = if lo.1 { lo.0.floor() as i64 + 1 } else { lo.0.ceil() as i64 };
+            let hi = if hi.1 { hi.0.ceil() as i64 - 1 } else { hi.0.floor() as i64 };
+            JsonValue::from(self.rng.between(lo, hi.max(lo)))
+        } else {
+            let value = lo.0 + self.rng.unit() * (hi.0 - lo.0);
+            let value = (value * 100.0).round() / 100.0;
+            JsonValue::from(if value <= lo.0 && lo.1 || value >= hi.0 && hi.1 { (lo.0 + hi.0) / 2.0 } else { value })
+        }
+    }
+
+    fn object(&mut self, schema: &Map<String, JsonValue>, depth: usize) -> JsonValue {
+        let required: Vec<&str> = schema.get("required").and_then(JsonValue::as_array).into_iter().flatten()
+            .filter_map(JsonValue::as_str).collect();
+        let mut object = Map::new();
+        for (key, property) in schema.get("properties").and_then(JsonValue::as_object).into_iter().flatten() {
+            if required.contains(&key.as_str()) || depth < MAX_DEPTH && self.rng.below(4) != 0 {
+                object.insert(key.clone(), self.value(property, depth + 1));
+            }
+        }
+        for key in required {
+            if !object.contains_key(key) {
+                object.insert(key.to_string(), JsonValue::String(self.words(1, 16)));
+            }
+        }
+        JsonValue::Object(object)
+    }
+
+    fn array(&mut self, schema: &Map<String, JsonValue>, depth: usize) -> JsonValue {
+        let min = schema.get("minItems").and_then(JsonValue::as_u64).unwrap_or(0);
+        let max = schema.get("maxItems").and_then(JsonValue::as_u64).unwrap_or(min.max(3));
+        let len = match depth < MAX_DEPTH {
+            true => min + self.rng.below(max.saturating_sub(min) + 1),
+            false => min,
+        };
+        let items = schema.get("items").cloned().unwrap_or(JsonValue::Bool(true));
+        JsonValue::Array((0..len).map(|_| self.value(&items, depth + 1)).collect())
+    }
+
+    fn value(&mut self, schema: &JsonValue, depth: usize) -> JsonValue {
+        let Some(schema) = schema.as_object() else { return JsonValue::String(self.words(1, 16)) };
+        if let Some(target) = schema.get("$ref").and_then(JsonValue::as_str).and_then(|r| r.strip_prefix('#')) {
+            return match resolve_pointer(self.root, target) {
+                Some((_, target)) if depth < MAX_DEPTH * 2 => self.value(target, depth + 1),
+                _ => JsonValue::Null,
+            };
+        }
+        if let Some(value) = schema.get("const") {
+            return value.clone();
+        }
+        if let Some(values) = schema.get("enum").and_then(JsonValue::as_array) {
+            return self.rng.pick(values).cloned().unwrap_or(JsonValue::Null);
+        }
+        for alternatives in ["oneOf", "anyOf"] {
+            if let Some(alternative) = schema.get(alternatives).and_then(JsonValue::as_array).and_then(|a| self.rng.pick(a)) {
+                return self.value(alternative, depth + 1);
+            }
+        }
+        if let Some(all) = schema.get("allOf").and_then(JsonValue::as_array) {
+            return self.value(&JsonValue::Object(merge_all(schema, all)), depth);
+        }
+        let kind = match schema.get("type") {
+            Some(JsonValue::String(kind)) => kind.as_str(),
+            Some(JsonValue::Array(kinds)) => {
+                let kinds: Vec<&str> = kinds.iter().filter_map(JsonValue::as_str).filter(|k| *k != "null").collect();
+                self.rng.pick(&kinds).copied().unwrap_or("null")
+            },
+            _ if schema.contains_key("properties") => "object",
+            _ if schema.contains_key("items") => "array",
+            _ => "string",
+        };
+        match kind {
+            "null" => JsonValue::Null,
+            "boolean" => JsonValue::Bool(self.rng.below(2) == 1),
+            "integer" => self.number(schema, true),
+            "number" => self.number(schema, false),
+            "object" => self.object(schema, depth),
+            "array" => self.array(schema, depth),
+            _ => self.string(schema),
+        }
+    }
+}
+
+/// `schema` without `allOf`, its members' keywords merged in: `properties` and `required`
+/// are combined, other keywords of later members win.
+fn merge_all(schema: &Map<String, JsonValue>, all: &[JsonValue]) -> Map<String, JsonValue> {
+    let mut merged: Map<String, JsonValue> = schema.iter().filter(|(k, _)| *k != "allOf").map(|(k, v)| (k.clone(), v.clone())).collect();
+    for member in all.iter().filter_map(JsonValue::as_object) {
+        for (k, v) in member {
+            match (merged.get_mut(k), v) {
+                (Some(JsonValue::Object(properties)), JsonValue::Object(more)) if k == "properties" =>
+                    properties.extend(more.iter().map(|(k, v)| (k.clone(), v.clone()))),
+                (Some(JsonValue::Array(required)), JsonValue::Array(more)) if k == "required" => required.extend(more.iter().cloned()),
+                _ => {
+                    merged.insert(k.clone(), v.clone());
+                },
+            }
+        }
+    }
+    merged
+}
+
+/// A random value conforming to `schema`, the same for the same seed: for handler tests that
+/// need realistic payloads rather than fixtures. Understands `type`, `enum`, `const`,
+/// `properties`, `required`, `items`, `minItems`/`maxItems`, `minimum`/`maximum` (and their
+/// exclusive forms), `minLength`/`maxLength`, common `format`s, `oneOf`/`anyOf`/`allOf` and
+/// local `$ref`s; required properties are always present, optional ones usually.
+pub fn generate_sample(schema: &JsonValue, seed: u64) -> JsonValue {
+    Generator { root: schema, rng: Rng::new(seed) }.value(schema, 0)
+}
+
+/// [`generate_sample`], converted into a Lua value.
+pub fn generate_sample_into_lua<'lua>(
+    lua: &'lua Lua, schema: &JsonValue, seed: u64, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &generate_sample(schema, seed), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, ScriptFacingError, generate_sample, register};
+
+    #[test]
+    fn samples_conform() {
+        let schema = json!({
+            "type": "object",
+            "required": ["id", "email", "tags", "kind"],
+            "properties": {
+                "id": {"type": "integer", "minimum": 1, "maximum": 10},
+                "email": {"type": "string", "format": "email"},
+                "name": {"type": "string", "minLength": 3, "maxLength": 8},
+                "score": {"type": "number", "exclusiveMinimum": 0, "maximum": 1},
+                "tags": {"type": "array", "minItems": 1, "maxItems": 4, "items": {"$ref": "#/definitions/tag"}},
+                "kind": {"enum": ["a", "b"]},
+                "parent": {"$ref": "#"},
+                "extra": {"allOf": [{"properties": {"x": {"type": "boolean"}}, "required": ["x"]}, {"required": ["y"]}]},
+            },
+            "definitions": {"tag": {"type": "string", "maxLength": 5}},
+        });
+        for seed in 0..50 {
+            let sample = generate_sample(&schema, seed);
+            ScriptFacingError::check(&schema, &sample).unwrap_or_else(|e| panic!("seed {}: {} in {}", seed, e, sample));
+            let id = sample["id"].as_i64().expect("id");
+            assert!((1..=10).contains(&id));
+            assert!(sample["email"].as_str().expect("email").ends_with("@example.com"));
+            if let Some(score) = sample.get("score") {
+                assert!(score.as_f64().expect("score") > 0.0);
+            }
+            if let Some(extra) = sample.get("extra") {
+                assert!(extra["x"].is_boolean() && extra["y"].is_string());
+            }
+            assert_eq!(sample, generate_sample(&schema, seed));
+        }
+        assert_ne!(generate_sample(&schema, 1), generate_sample(&schema, 2));
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local schema = { type = "array", minItems = 2, maxItems = 2, items = { type = "integer", minimum = 5, maximum = 5 } }
+            local sample = json.generate(schema, 7)
+            assert(#sample == 2 and sample[1] == 5)
+            assert(json.encode(json.generate(schema)) == "[5,5]")
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue, json};
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+use crate::module::lock;
+
+/// A GeoJSON position; GeoJSON writes longitude first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub lon: f64,
+    pub lat: f64,
+    pub alt: Option<f64>,
+}
+
+/// A GeoJSON geometry object, borrowed from its document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry<'v> {
+    /// `"Point"`, `"LineString"`, ..., `"GeometryCollection"`.
+    pub kind: &'v str,
+    value: &'v Map<String, JsonValue>,
+}
+
+impl<'v> Geometry<'v> {
+    pub fn new(value: &'v JsonValue) -> Option<Self> {
+        let value = value.as_object()?;
+        let kind = value.get("type")?.as_str()?;
+        let valid = match kind {
+            "GeometryCollection" => value.get("geometries").is_some_and(JsonValue::is_array),
+            "Point" | "MultiPoint" | "LineString" | "MultiLineString" | "Polygon" | "MultiPolygon" =>
+                value.get("coordinates").is_some_and(JsonValue::is_array),
+            _ => false,
+        };
+        valid.then_some(Geometry { kind, value })
+    }
+
+    /// Every position of the geometry in document order, those of nested geometries included.
+    /// Malformed positions are skipped.
+    pub fn positions(&self) -> impl Iterator<Item = Position> + 'v {
+        let mut found = Vec::new();
+        collect_positions(self, &mut found);
+        found.into_iter()
+    }
+
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::around(self.positions())
+    }
+
+    /// The members of a `GeometryCollection`; empty for other geometries.
+    pub fn geometries(&self) -> Vec<Geometry<'v>> {
+        self.value.get("geometries").and_then(JsonValue::as_array)
+            .map(|geometries| geometries.iter().filter_map(Geometry::new).collect())
+            .unwrap_or_default()
+    }
+}
+
+fn position(value: &JsonValue) -> Option<Position> {
+    match value.as_array()?.as_slice() {
+        [lon, lat, rest @ ..] => Some(Position {
+            lon: lon.as_f64()?,
+            lat: lat.as_f64()?,
+            alt: rest.first().and_then(JsonValue::as_f64),
+        }),
+        _ => None,
+    }
+}
+
+fn collect_coordinates(value: &JsonValue, found: &mut Vec<Position>) {
+    match position(value) {
+        Some(p) => found.push(p),
+        None => value.as_array().into_iter().flatten().for_each(|v| collect_coordinates(v, found)),
+    }
+}
+
+fn collect_positions(geometry: &Geometry<'_>, found: &mut Vec<Position>) {
+    match geometry.value.get("coordinates") {
+        Some(coordinates) => collect_coordinates(coordinates, found),
+        None => geometry.geometries().iter().for_each(|g| collect_positions(g, found)),
+    }
+}
+
+/// A GeoJSON feature, borrowed from its document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature<'v> {
+    pub id: Option<&'v JsonValue>,
+    /// `None` for a `null` or malformed geometry.
+    pub geometry: Option<Geometry<'v>>,
+    pub properties: Option<&'v Map<String, JsonValue>>,
+}
+
+impl<'v> Feature<'v> {
+    pub fn new(value: &'v JsonValue) -> Option<Self> {
+        let value = value.as_object()?;
+        if value.get("type")?.as_str()? != "Feature" {
+            return None;
+        }
+        Some(Feature {
+            id: value.get("id"),
+            geometry: value.get("geometry").and_then(Geometry::new),
+            properties: value.get("properties").and_then(JsonValue::as_object),
+        })
+    }
+}
+
+/// The features of a `FeatureCollection`, skipping malformed ones; `None` if `value` is not one.
+pub fn as_feature_collection(value: &JsonValue) -> Option<Vec<Feature<'_>>> {
+    let value = value.as_object()?;
+    if value.get("type")?.as_str()? != "FeatureCollection" {
+        return None;
+    }
+    Some(value.get("features")?.as_array()?.iter().filter_map(Feature::new).collect())
+}
+
+/// `[west, south, east, north]` in GeoJSON's `bbox` order; does not handle the antimeridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl BoundingBox {
+    pub fn around(positions: impl IntoIterator<Item = Position>) -> Option<Self> {
+        positions.into_iter().fold(None, |bbox: Option<BoundingBox>, p| Some(match bbox {
+            None => BoundingBox { west: p.lon, south: p.lat, east: p.lon, north: p.lat },
+            Some(b) => BoundingBox { west: b.west.min(p.lon), south: b.south.min(p.lat), east: b.east.max(p.lon), north: b.north.max(p.lat) },
+        }))
+    }
+
+    pub fn contains(&self, p: Position) -> bool {
+        (self.west..=self.east).contains(&p.lon) && (self.south..=self.north).contains(&p.lat)
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.west <= other.east && other.west <= self.east && self.south <= other.north && other.south <= self.north
+    }
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        match value.as_array()?.as_slice() {
+            [west, south, east, north] => Some(BoundingBox {
+                west: west.as_f64()?, south: south.as_f64()?, east: east.as_f64()?, north: north.as_f64()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn geo_error(message: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("geo: {}", message))
+}
+
+/// The bounding box of a geometry, feature or feature collection.
+fn bounding_box(value: &JsonValue) -> Option<BoundingBox> {
+    if let Some(features) = as_feature_collection(value) {
+        return BoundingBox::around(features.iter().filter_map(|f| f.geometry).flat_map(|g| g.positions().collect::<Vec<_>>()));
+    }
+    match Feature::new(value) {
+        Some(feature) => feature.geometry?.bounding_box(),
+        None => Geometry::new(value)?.bounding_box(),
+    }
+}
+
+/// The `json.geo` table: `point(lat, lon)`, `bbox(value)` (`{west, south, east, north}` or `nil`),
+/// `coordinates(geometry)` (a sequence of `{lon, lat}` pairs) and `query(collection, bbox)` (the
+/// features whose bounding box intersects `bbox`, as the same tables).
+pub(crate) fn create_geo_table<'lua>(lua: &'lua Lua, options: Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    let geo = lua.create_table()?;
+
+    let point_options = options.clone();
+    geo.set("point", lua.create_function(move |lua, (lat, lon): (f64, f64)| {
+        json_to_lua(lua, &json!({"type": "Point", "coordinates": [lon, lat]}), &lock(&point_options).clone())
+    })?)?;
+
+    let bbox_options = options.clone();
+    geo.set("bbox", lua.create_function(move |lua, value: rlua::Value| {
+        let bbox = bounding_box(&lua_to_json(lua, value, &lock(&bbox_options).clone())?);
+        Ok(bbox.map(|b| [b.west, b.south, b.east, b.north]))
+    })?)?;
+
+    let coordinates_options = options.clone();
+    geo.set("coordinates", lua.create_function(move |lua, value: rlua::Value| {
+        let value = lua_to_json(lua, value, &lock(&coordinates_options).clone())?;
+        let geometry = Geometry::new(&value).ok_or_else(|| geo_error("expected a geometry"))?;
+        Ok(geometry.positions().map(|p| [p.lon, p.lat]).collect::<Vec<_>>())
+    })?)?;
+
+    geo.set("query", lua.create_function(move |lua, (collection, bbox): (rlua::Table, rlua::Value)| {
+        let options = lock(&options).clone();
+        let bbox = BoundingBox::from_json(&lua_to_json(lua, bbox, &options)?)
+            .ok_or_else(|| geo_error("expected a bounding box {west, south, east, north}"))?;
+        let matches = lua.create_table()?;
+        for feature in collection.get::<_, rlua::Table>("features")?.sequence_values::<rlua::Value>() {
+            let feature = feature?;
+            if bounding_box(&lua_to_json(lua, feature.clone(), &options)?).is_some_and(|b| b.intersects(&bbox)) {
+                matches.raw_push(feature)?;
+            }
+        }
+        Ok(matches)
+    })?)?;
+
+    Ok(geo)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{BoundingBox, ConversionOptions, Geometry, Position, as_feature_collection, register};
+
+    #[test]
+    fn geojson() {
+        let collection = json!({"type": "FeatureCollection", "features": [
+            {"type": "Feature", "id": 1, "geometry": {"type": "Point", "coordinates": [10.0, 50.0]}, "properties": {"name": "a"}},
+            {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[0, 0], [2, 1, 5]]}, "properties": null},
+            {"type": "Feature", "geometry": {"type": "GeometryCollection", "geometries": [
+                {"type": "Point", "coordinates": [-5, -5]},
+                {"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]},
+            ]}},
+            {"type": "Unknown"},
+        ]});
+        let features = as_feature_collection(&collection).expect("collection");
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0].id, Some(&json!(1)));
+        let line = features[1].geometry.expect("geometry");
+        assert_eq!(line.kind, "LineString");
+        assert_eq!(line.positions().collect::<Vec<_>>(), [
+            Position { lon: 0.0, lat: 0.0, alt: None }, Position { lon: 2.0, lat: 1.0, alt: Some(5.0) },
+        ]);
+        let nested = features[2].geometry.expect("collection geometry");
+        assert_eq!(nested.positions().count(), 5);
+        assert_eq!(nested.bounding_box(), Some(BoundingBox { west: -5.0, south: -5.0, east: 1.0, north: 1.0 }));
+        assert_eq!(Geometry::new(&json!({"type": "Point"})), None);
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.globals().set("collection", crate::json_to_lua(&lua, &collection, &ConversionOptions::default()).expect("decode"))
+            .expect("global");
+        lua.load(r#"
+            local p = json.geo.point(50, 10)
+            assert(p.type == "Point" and p.coordinates[1] == 10 and p.coordinates[2] == 50)
+            local b = json.geo.bbox(collection)
+            assert(b[1] == -5 and b[2] == -5 and b[3] == 10 and b[4] == 50, table.concat(b, ","))
+            assert(json.geo.bbox({type = "Point"}) == nil)
+            local c = json.geo.coordinates(collection.features[2].geometry)
+            assert(#c == 2 and c[2][1] == 2 and c[2][2] == 1)
+            local found = json.geo.query(collection, {9, 49, 11, 51})
+            assert(#found == 1 and found[1] == collection.features[1])
+        "#).exec().expect("lua helpers");
+    }
+}
@@ -0,0 +1,198 @@
+//! Canonical text of converted Lua values, for golden-file (snapshot) tests that lock in
+//! conversion behavior across upgrades.
+//!
+//! Each line is a path in Lua syntax, the value's type and, for scalars, the value; keys are
+//! sorted, so the text is stable between runs:
+//!
+//! ```text
+//! : table
+//! .list: table
+//! .list[1]: integer 1
+//! .list[2]: number 2.5
+//! .name: string "x"
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt::Write;
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, LazyString, RawJson, json_to_lua, table_json_type};
+use crate::readonly::view_contents;
+
+/// Converts `value` with `options` and returns the canonical text of the result.
+pub fn json_snapshot(lua: &Lua, value: &JsonValue, options: &ConversionOptions) -> rlua::Result<String> {
+    lua_snapshot(&json_to_lua(lua, value, options)?)
+}
+
+/// The canonical text of a Lua value. Tables reached again (shared subtrees, cycles) are written
+/// as references to the path where they first appear.
+pub fn lua_snapshot(value: &rlua::Value) -> rlua::Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, &mut String::new(), value, &mut HashMap::new())?;
+    Ok(out)
+}
+
+/// Compares `actual` with the golden file at `path`. The file is written instead when it does
+/// not exist yet or the `UPDATE_GOLDEN` environment variable is set; review and commit it.
+pub fn compare_golden(path: impl AsRef<std::path::Path>, actual: &str) -> Result<(), String> {
+    let path = path.as_ref();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() || !path.exists() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        return std::fs::write(path, actual).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    let expected = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if expected == actual {
+        return Ok(());
+    }
+    let mut message = format!("{} differs (set UPDATE_GOLDEN=1 to accept):\n", path.display());
+    let (expected, actual): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+    for line in expected.iter().filter(|line| !actual.contains(line)) {
+        let _ = writeln!(message, "- {}", line);
+    }
+    for line in actual.iter().filter(|line| !expected.contains(line)) {
+        let _ = writeln!(message, "+ {}", line);
+    }
+    Err(message)
+}
+
+fn write_value(
+    out: &mut String, path: &mut String, value: &rlua::Value, seen: &mut HashMap<*const c_void, String>,
+) -> rlua::Result<()> {
+    let _ = write!(out, "{}: ", path);
+    match value {
+        rlua::Value::Nil => out.push_str("nil"),
+        rlua::Value::Boolean(b) => {
+            let _ = write!(out, "boolean {}", b);
+        },
+        rlua::Value::Integer(i) => {
+            let _ = write!(out, "integer {}", i);
+        },
+        rlua::Value::Number(n) => {
+            let _ = write!(out, "number {:?}", n);
+        },
+        rlua::Value::String(s) => {
+            let _ = write!(out, "string {:?}", String::from_utf8_lossy(s.as_bytes()));
+        },
+        rlua::Value::Table(table) => return write_table(out, path, table, seen),
+        rlua::Value::UserData(ud) => match ud.borrow::<RawJson>() {
+            Ok(raw) => {
+                let _ = write!(out, "raw_json {}", raw.get());
+            },
+            Err(_) => match ud.borrow::<LazyString>() {
+                Ok(s) => {
+                    let _ = write!(out, "lazy_string {:?}", s.get());
+                },
+                Err(_) => out.push_str("userdata"),
+            },
+        },
+        other => out.push_str(other.type_name()),
+    }
+    out.push('\n');
+    Ok(())
+}
+
+fn write_table(
+    out: &mut String, path: &mut String, table: &rlua::Table, seen: &mut HashMap<*const c_void, String>,
+) -> rlua::Result<()> {
+    if let Some(first) = seen.get(&table.to_pointer()) {
+        let _ = writeln!(out, "table = {}", if first.is_empty() { "<root>" } else { first });
+        return Ok(());
+    }
+    seen.insert(table.to_pointer(), path.clone());
+
+    let (table, read_only) = match view_contents(table)? {
+        Some(contents) => (contents, true),
+        None => (table.clone(), false),
+    };
+    out.push_str("table");
+    if read_only {
+        out.push_str(" read-only");
+    }
+    match table_json_type(&table)? {
+        Some(json_type) => {
+            let _ = write!(out, " <json {}>", json_type.name());
+        },
+        None if table.get_metatable().is_some() => out.push_str(" <metatable>"),
+        None => {},
+    }
+    out.push('\n');
+
+    let mut entries = Vec::new();
+    for pair in table.pairs::<rlua::Value, rlua::Value>() {
+        let (key, value) = pair?;
+        let (order, segment) = match &key {
+            rlua::Value::Integer(i) => ((0, *i, String::new()), format!("[{}]", i)),
+            rlua::Value::String(s) => {
+                let s = String::from_utf8_lossy(s.as_bytes()).into_owned();
+                let segment = if is_identifier(&s) { format!(".{}", s) } else { format!("[{:?}]", s) };
+                ((1, 0, s), segment)
+            },
+            rlua::Value::Number(n) => ((2, 0, format!("{:?}", n)), format!("[{:?}]", n)),
+            other => ((3, 0, other.type_name().to_string()), format!("[<{}>]", other.type_name())),
+        };
+        entries.push((order, segment, value));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, segment, value) in entries {
+        let len = path.len();
+        path.push_str(&segment);
+        write_value(out, path, &value, seen)?;
+        path.truncate(len);
+    }
+    Ok(())
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, PathPattern};
+    use super::{compare_golden, json_snapshot};
+
+    #[test]
+    fn canonical_text() {
+        let lua = Lua::new();
+        let options = ConversionOptions { json_type_metatables: true, ..Default::default() };
+        let document = json!({"name": "x", "list": [1, 2.5], "odd key": null, "1": true});
+        assert_eq!(json_snapshot(&lua, &document, &options).expect("snapshot"), "\
+: table <json object>
+[\"1\"]: boolean true
+.list: table <json array>
+.list[1]: integer 1
+.list[2]: number 2.5
+.name: string \"x\"
+");
+
+        let options = ConversionOptions { dedup_subtrees: Some(64), ..Default::default() };
+        assert_eq!(json_snapshot(&lua, &json!({"a": [1], "b": [1]}), &options).expect("shared"), "\
+: table
+.a: table read-only
+.a[1]: integer 1
+.b: table = .a
+");
+        let options = ConversionOptions { raw_paths: vec![PathPattern::parse("/raw")], ..Default::default() };
+        assert_eq!(json_snapshot(&lua, &json!({"raw": {"k": 1}}), &options).expect("raw"), "\
+: table
+.raw: raw_json {\"k\":1}
+");
+    }
+
+    #[test]
+    fn golden_files() {
+        let dir = std::env::temp_dir().join(format!("rlua_json_golden_{}", std::process::id()));
+        let path = dir.join("case.txt");
+        compare_golden(&path, "a\nb\n").expect("written");
+        compare_golden(&path, "a\nb\n").expect("same");
+        let message = compare_golden(&path, "a\nc\n").expect_err("differs");
+        assert!(message.contains("- b") && message.contains("+ c"), "{}", message);
+        std::fs::remove_dir_all(dir).expect("cleanup");
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::Read;
+use rlua::Lua;
+use crate::{ConversionOptions, decode_reader_into_lua};
+
+/// Whether a `Content-Type` header value denotes JSON: `application/json` or any `+json` type.
+pub fn is_json_content_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    essence == "application/json" || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
+
+/// Converts an HTTP response body, independently of the HTTP client: pass the `Content-Type`
+/// header and the body as a reader (a `reqwest::blocking::Response` is one). Fails if the
+/// content type is missing or not JSON, or if the body is longer than `max_bytes`.
+pub fn response_json_into_lua<'lua>(
+    lua: &'lua Lua, content_type: Option<&str>, body: impl Read, max_bytes: u64, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    match content_type {
+        Some(content_type) if is_json_content_type(content_type) => {},
+        content_type => return Err(rlua::Error::RuntimeError(
+            format!("expected a JSON response, got content type {:?}", content_type.unwrap_or("(none)")))),
+    }
+    let mut limited = body.take(max_bytes + 1);
+    let mut input = Vec::new();
+    limited.read_to_end(&mut input).map_err(rlua::Error::external)?;
+    if input.len() as u64 > max_bytes {
+        return Err(rlua::Error::RuntimeError(format!("response body is larger than {} bytes", max_bytes)));
+    }
+    decode_reader_into_lua(lua, input.as_slice(), options)
+}
+
+/// [`response_json_into_lua`] of a `reqwest` blocking response, reading its `Content-Type`
+/// header; a `Content-Length` over `max_bytes` fails before any of the body is read. The status
+/// is not checked, since error responses often carry a JSON body (`application/problem+json`).
+#[cfg(feature = "reqwest")]
+pub fn reqwest_response_into_lua<'lua>(
+    lua: &'lua Lua, response: reqwest::blocking::Response, max_bytes: u64, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    if let Some(length) = response.content_length().filter(|&length| length > max_bytes) {
+        return Err(rlua::Error::RuntimeError(format!("response body is {} bytes, more than {}", length, max_bytes)));
+    }
+    let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+    response_json_into_lua(lua, content_type.as_deref(), response, max_bytes, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, response_json_into_lua};
+
+    #[test]
+    fn content_type_and_size_checks() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let body = br#"{"ok": true}"#.as_slice();
+
+        let value = response_json_into_lua(&lua, Some("application/problem+json; charset=utf-8"), body, 64, &options);
+        assert!(matches!(value.expect("json"), rlua::Value::Table(_)));
+        assert!(response_json_into_lua(&lua, Some("text/html"), body, 64, &options).is_err());
+        assert!(response_json_into_lua(&lua, None, body, 64, &options).is_err());
+        assert!(response_json_into_lua(&lua, Some("application/json"), body, 5, &options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "reqwest")]
+    fn reqwest_responses() {
+        use std::io::{Read, Write};
+        use crate::reqwest_response_into_lua;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}", listener.local_addr().expect("address"));
+        let server = std::thread::spawn(move || {
+            let responses = [
+                "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Type: application/problem+json\r\nContent-Length: 15\r\n\r\n{\"title\": \"no\"}",
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/html\r\nContent-Length: 2\r\n\r\n{}",
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: 100000\r\n\r\n[",
+            ];
+            for response in responses {
+                let (mut stream, _) = listener.accept().expect("accept");
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                stream.write_all(response.as_bytes()).expect("respond");
+            }
+        });
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let client = reqwest::blocking::Client::builder().no_proxy().build().expect("client");
+        let get = || client.get(&url).send().expect("get");
+        let value = reqwest_response_into_lua(&lua, get(), 64, &options).expect("json");
+        assert_eq!(value.as_table().expect("table").get::<_, String>("title").expect("title"), "no");
+        let error = reqwest_response_into_lua(&lua, get(), 64, &options).expect_err("html");
+        assert!(error.to_string().contains("text/html"), "{}", error);
+        let error = reqwest_response_into_lua(&lua, get(), 64, &options).expect_err("large");
+        assert!(error.to_string().contains("100000 bytes"), "{}", error);
+        server.join().expect("server");
+    }
+}
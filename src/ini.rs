@@ -0,0 +1,288 @@
+//! INI and Java `.properties` files as nested tables: section names and keys are split on
+//! dots (`[graphics.window]` `width = 800` is `graphics.window.width`) and `key[0]` indexes an
+//! array, as [`flatten`] writes them.
+
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, FlattenStyle, flatten, json_to_lua, lua_to_json, unflatten};
+use crate::module::format_table;
+
+fn format_error(format: &str, line: usize, message: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}: line {}: {}", format, line, message))
+}
+
+fn unflattened(map: &Map<String, JsonValue>, format: &str) -> rlua::Result<JsonValue> {
+    match unflatten(map, &FlattenStyle::default())? {
+        JsonValue::Null => Ok(JsonValue::Object(Map::new())),
+        value @ JsonValue::Object(_) => Ok(value),
+        _ => Err(rlua::Error::RuntimeError(format!("{}: a key is empty", format))),
+    }
+}
+
+/// An INI value: `true`/`false`, a number or a string, unquoted if in double quotes.
+fn ini_value(text: &str) -> JsonValue {
+    if let Some(quoted) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).filter(|_| text.len() >= 2) {
+        return JsonValue::String(quoted.replace("\\\"", "\"").replace("\\n", "\n").replace("\\t", "\t").replace("\\\\", "\\"));
+    }
+    let text = [" ;", " #", "\t;", "\t#"].iter().filter_map(|c| text.find(c)).min().map_or(text, |i| text.get(..i).unwrap_or(text)).trim_end();
+    match text {
+        "true" => JsonValue::Bool(true),
+        "false" => JsonValue::Bool(false),
+        _ => text.parse::<i64>().map(JsonValue::from)
+            .ok()
+            .or_else(|| text.parse::<f64>().ok().filter(|n| n.is_finite() && text.bytes().any(|b| b.is_ascii_digit())).map(JsonValue::from))
+            .unwrap_or_else(|| JsonValue::String(text.to_string())),
+    }
+}
+
+/// Parses an INI file: `[section]` headers, `key = value` (or `key: value`) lines and `;` or
+/// `#` comments, also after a value. Values are typed as [`ini_value`] describes; a section
+/// without keys is an empty object.
+pub fn ini_to_json(text: &str) -> rlua::Result<JsonValue> {
+    let mut flat = Map::new();
+    let mut section = String::new();
+    let mut empty_sections = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.strip_suffix(']').ok_or_else(|| format_error("ini", i + 1, "expected ] after the section name"))?;
+            section = name.trim().to_string();
+            empty_sections.push(section.clone());
+            continue;
+        }
+        let (key, value) = line.split_once('=').or_else(|| line.split_once(':'))
+            .ok_or_else(|| format_error("ini", i + 1, "expected key = value"))?;
+        let key = match section.as_str() {
+            "" => key.trim().to_string(),
+            section => format!("{}.{}", section, key.trim()),
+        };
+        empty_sections.retain(|s| s != &section);
+        flat.insert(key, ini_value(value.trim()));
+    }
+    for name in empty_sections {
+        flat.entry(name).or_insert_with(|| JsonValue::Object(Map::new()));
+    }
+    unflattened(&flat, "ini")
+}
+
+fn write_ini_value(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => match ini_value(s) == JsonValue::String(s.clone()) && !s.contains(['\n', '"']) && s.trim() == s {
+            true => s.clone(),
+            false => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t")),
+        },
+        JsonValue::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Writes an object as INI: its non-object members first, then a section for each object
+/// member, with deeper members as dotted keys. `null` is written as an empty value, which
+/// reads back as `""`.
+pub fn json_to_ini(value: &JsonValue) -> rlua::Result<String> {
+    let JsonValue::Object(o) = value else {
+        return Err(rlua::Error::RuntimeError("ini: only an object can be written".to_string()));
+    };
+    let mut out = String::new();
+    let (sections, top): (Vec<_>, Vec<_>) = o.iter().partition(|(_, v)| v.is_object());
+    for (k, v) in top {
+        for (key, leaf) in flatten(&JsonValue::Object(Map::from_iter([(k.clone(), v.clone())])), &FlattenStyle::default()) {
+            out.push_str(&format!("{} = {}\n", key, write_ini_value(&leaf)));
+        }
+    }
+    for (name, section) in sections {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{}]\n", name));
+        for (key, leaf) in flatten(section, &FlattenStyle::default()).into_iter().filter(|(key, _)| !key.is_empty()) {
+            out.push_str(&format!("{} = {}\n", key, write_ini_value(&leaf)));
+        }
+    }
+    Ok(out)
+}
+
+/// Java's escapes: `\t`, `\n`, `\r`, `\f`, `\uXXXX`, and any other character as itself.
+fn unescape_properties(text: &str, line: usize) -> rlua::Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let code = u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4)
+                    .ok_or_else(|| format_error("properties", line, "malformed \\u escape"))?;
+                out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            },
+            Some(c) => out.push(c),
+            None => {},
+        }
+    }
+    Ok(out)
+}
+
+/// Parses a `.properties` file: `key=value`, `key: value` or `key value` lines, `#` and `!`
+/// comments, and lines continued by a trailing backslash. Values are strings.
+pub fn properties_to_json(text: &str) -> rlua::Result<JsonValue> {
+    let mut flat = Map::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let mut logical = line.trim_start().to_string();
+        if logical.is_empty() || logical.starts_with('#') || logical.starts_with('!') {
+            continue;
+        }
+        while logical.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1 {
+            logical.pop();
+            match lines.next() {
+                Some((_, next)) => logical.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+        let mut escaped = false;
+        let end = logical.char_indices().find(|(_, c)| {
+            let separator = !escaped && matches!(c, '=' | ':' | ' ' | '\t' | '\u{c}');
+            escaped = !escaped && *c == '\\';
+            separator
+        }).map_or(logical.len(), |(end, _)| end);
+        let (key, rest) = logical.split_at(end);
+        let rest = rest.trim_start_matches([' ', '\t', '\u{c}']);
+        let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest).trim_start_matches([' ', '\t', '\u{c}']);
+        flat.insert(unescape_properties(key, i + 1)?, JsonValue::String(unescape_properties(rest, i + 1)?));
+    }
+    unflattened(&flat, "properties")
+}
+
+fn escape_properties(text: &str, key: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\u{c}' => out.push_str("\\f"),
+            '=' | ':' | '#' | '!' if key => out.extend(['\\', c]),
+            ' ' if key || i == 0 => out.push_str("\\ "),
+            c if c.is_ascii() => out.push(c),
+            c => c.encode_utf16(&mut [0; 2]).iter().for_each(|unit| out.push_str(&format!("\\u{:04X}", unit))),
+        }
+    }
+    out
+}
+
+/// Writes an object as a `.properties` file of dotted keys, ASCII only, non-ASCII characters
+/// as `\uXXXX` escapes. Numbers and booleans are written as text, `null` as an empty value.
+pub fn json_to_properties(value: &JsonValue) -> rlua::Result<String> {
+    if !value.is_object() {
+        return Err(rlua::Error::RuntimeError("properties: only an object can be written".to_string()));
+    }
+    let mut out = String::new();
+    for (key, leaf) in flatten(value, &FlattenStyle::default()) {
+        let text = match leaf {
+            JsonValue::String(s) => s,
+            JsonValue::Null => String::new(),
+            other => other.to_string(),
+        };
+        out.push_str(&format!("{}={}\n", escape_properties(&key, true), escape_properties(&text, false)));
+    }
+    Ok(out)
+}
+
+/// [`ini_to_json`], converted into a Lua table with `options`.
+pub fn ini_to_lua<'lua>(lua: &'lua Lua, text: &str, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &ini_to_json(text)?, options)
+}
+
+/// [`json_to_ini`] of a Lua table, converted with `options`.
+pub fn lua_to_ini<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<String> {
+    json_to_ini(&lua_to_json(lua, value, options)?)
+}
+
+/// [`properties_to_json`], converted into a Lua table with `options`.
+pub fn properties_to_lua<'lua>(lua: &'lua Lua, text: &str, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &properties_to_json(text)?, options)
+}
+
+/// [`json_to_properties`] of a Lua table, converted with `options`.
+pub fn lua_to_properties<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<String> {
+    json_to_properties(&lua_to_json(lua, value, options)?)
+}
+
+fn text(bytes: &[u8]) -> rlua::Result<&str> {
+    std::str::from_utf8(bytes).map_err(rlua::Error::external)
+}
+
+/// The `json.ini` table: `decode(text)` and `encode(table)`.
+pub(crate) fn create_ini_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options,
+        |lua, bytes, options| ini_to_lua(lua, text(bytes)?, options),
+        |lua, value, (), options| lua_to_ini(lua, value, options).map(String::into_bytes))
+}
+
+/// The `json.properties` table: `decode(text)` and `encode(table)`.
+pub(crate) fn create_properties_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options,
+        |lua, bytes, options| properties_to_lua(lua, text(bytes)?, options),
+        |lua, value, (), options| lua_to_properties(lua, value, options).map(String::into_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, ini_to_json, json_to_ini, json_to_properties, properties_to_json, register};
+
+    #[test]
+    fn ini_files() {
+        let text = "; game settings\nname = Quest ; trailing comment\nversion = 1.5\n\n[graphics.window]\nwidth = 800\n\
+            fullscreen = false\ntitle = \"Quest; the game\"\nsize.unit: px\n\n[audio]\n[keys]\nbinds[0] = W\nbinds[1] = \"1\"\n";
+        let value = ini_to_json(text).expect("parse");
+        assert_eq!(value, json!({
+            "name": "Quest", "version": 1.5,
+            "graphics": {"window": {"width": 800, "fullscreen": false, "title": "Quest; the game", "size": {"unit": "px"}}},
+            "audio": {}, "keys": {"binds": ["W", "1"]},
+        }));
+        assert_eq!(ini_to_json(&json_to_ini(&value).expect("write")).expect("reparse"), value);
+        assert!(ini_to_json("[open\n").is_err());
+        assert!(ini_to_json("no separator\n").is_err());
+        assert!(ini_to_json("a = 1\na.b = 2\n").is_err());
+        assert!(json_to_ini(&json!([1])).is_err());
+    }
+
+    #[test]
+    fn properties_files() {
+        let text = "# comment\n! also a comment\napp.name = My App\napp.greeting=Hello \\\n    world\napp.path:C:\\\\games\n\
+            key\\ with\\ spaces value\nunicode=caf\\u00e9\nempty\n";
+        let value = properties_to_json(text).expect("parse");
+        assert_eq!(value, json!({
+            "app": {"name": "My App", "greeting": "Hello world", "path": "C:\\games"},
+            "key with spaces": "value", "unicode": "café", "empty": "",
+        }));
+        let written = json_to_properties(&value).expect("write");
+        assert!(written.contains("unicode=caf\\u00E9\n") && written.contains("key\\ with\\ spaces=value\n"));
+        assert_eq!(properties_to_json(&written).expect("reparse"), value);
+        assert_eq!(properties_to_json("n=1\nlist[1]=b\nlist[0]=a\n").expect("parse"), json!({"n": "1", "list": ["a", "b"]}));
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local config = json.ini.decode("[server]\nport = 8080\n")
+            assert(config.server.port == 8080)
+            assert(json.ini.encode(config) == "[server]\nport = 8080\n")
+            local props = json.properties.decode("a.b=c")
+            assert(props.a.b == "c" and json.properties.encode(props) == "a.b=c\n")
+        "#).exec().expect("lua");
+    }
+}
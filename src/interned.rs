@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use rlua::Lua;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+/// Shared storage for the strings of [`InternedValue`]s: each distinct string is allocated once,
+/// however many documents use it. Strings are never evicted; drop the interner to free them.
+#[derive(Debug, Clone, Default)]
+pub struct Interner(HashSet<Arc<str>>);
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        match self.0.get(s) {
+            Some(interned) => interned.clone(),
+            None => {
+                let interned: Arc<str> = Arc::from(s);
+                self.0.insert(interned.clone());
+                interned
+            },
+        }
+    }
+
+    /// Distinct strings stored.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A JSON document whose keys and string values are shared through an [`Interner`], for hosts
+/// that keep many similar documents, e.g. telemetry buffers. Objects keep their key order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InternedValue {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Arc<str>),
+    Array(Vec<InternedValue>),
+    Object(Vec<(Arc<str>, InternedValue)>),
+}
+
+impl InternedValue {
+    pub fn from_json(value: &JsonValue, interner: &mut Interner) -> Self {
+        match value {
+            JsonValue::Null => InternedValue::Null,
+            JsonValue::Bool(b) => InternedValue::Bool(*b),
+            JsonValue::Number(n) => InternedValue::Number(n.clone()),
+            JsonValue::String(s) => InternedValue::String(interner.intern(s)),
+            JsonValue::Array(a) => InternedValue::Array(a.iter().map(|v| InternedValue::from_json(v, interner)).collect()),
+            JsonValue::Object(o) => InternedValue::Object(o.iter()
+                .map(|(k, v)| (interner.intern(k), InternedValue::from_json(v, interner)))
+                .collect()),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            InternedValue::Null => JsonValue::Null,
+            InternedValue::Bool(b) => JsonValue::Bool(*b),
+            InternedValue::Number(n) => JsonValue::Number(n.clone()),
+            InternedValue::String(s) => JsonValue::from(&**s),
+            InternedValue::Array(a) => JsonValue::Array(a.iter().map(InternedValue::to_json).collect()),
+            InternedValue::Object(o) => JsonValue::Object(o.iter()
+                .map(|(k, v)| (k.to_string(), v.to_json()))
+                .collect::<Map<_, _>>()),
+        }
+    }
+
+    /// The value of `key` in an object; `None` for other values.
+    pub fn get(&self, key: &str) -> Option<&InternedValue> {
+        match self {
+            InternedValue::Object(o) => o.iter().find(|(k, _)| &**k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for InternedValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            InternedValue::Null => serializer.serialize_unit(),
+            InternedValue::Bool(b) => serializer.serialize_bool(*b),
+            InternedValue::Number(n) => n.serialize(serializer),
+            InternedValue::String(s) => serializer.serialize_str(s),
+            InternedValue::Array(a) => a.serialize(serializer),
+            InternedValue::Object(o) => {
+                let mut map = serializer.serialize_map(Some(o.len()))?;
+                for (key, value) in o {
+                    map.serialize_entry(&**key, value)?;
+                }
+                map.end()
+            },
+        }
+    }
+}
+
+/// Like [`lua_to_json`], storing the document's strings in `interner`.
+pub fn lua_to_interned<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, interner: &mut Interner, options: &ConversionOptions,
+) -> rlua::Result<InternedValue> {
+    Ok(InternedValue::from_json(&lua_to_json(lua, value, options)?, interner))
+}
+
+/// Like [`json_to_lua`], for an interned document.
+pub fn interned_to_lua<'lua>(
+    lua: &'lua Lua, value: &InternedValue, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &value.to_json(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, Interner, interned_to_lua, lua_to_interned, lua_to_json};
+
+    #[test]
+    fn documents_share_strings() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let mut interner = Interner::new();
+        let mut events = Vec::new();
+        for i in 0..3 {
+            let value = lua.load(format!("{{ event = 'tick', source = 'sensor', n = {} }}", i)).eval().expect("table");
+            events.push(lua_to_interned(&lua, value, &mut interner, &options).expect("intern"));
+        }
+        assert_eq!(interner.len(), 5);
+        let (Some(crate::InternedValue::String(a)), Some(crate::InternedValue::String(b))) =
+            (events[0].get("event"), events[2].get("event")) else { panic!("strings") };
+        assert!(Arc::ptr_eq(a, b));
+
+        assert_eq!(serde_json::to_value(&events[1]).expect("serialize"), json!({"event": "tick", "source": "sensor", "n": 1}));
+        let value = interned_to_lua(&lua, &events[2], &options).expect("to lua");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), events[2].to_json());
+    }
+}
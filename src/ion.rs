@@ -0,0 +1,157 @@
+use std::sync::{Arc, Mutex};
+use ion_rs::{Element, IonType, Value as IonValue};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua, lua_to_json};
+use crate::module::format_table;
+
+/// How [`json_to_ion`] writes a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IonFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+fn ion_error(message: impl std::fmt::Display) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("ion: {}", message))
+}
+
+fn tagged(type_name: &str, value: JsonValue) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("$type".to_string(), JsonValue::from(type_name));
+    o.insert("value".to_string(), value);
+    JsonValue::Object(o)
+}
+
+fn from_ion(element: &Element) -> JsonValue {
+    match element.value() {
+        IonValue::Null(_) => JsonValue::Null,
+        IonValue::Bool(b) => JsonValue::Bool(*b),
+        IonValue::Int(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => JsonValue::from(n),
+            (None, Some(n)) => JsonValue::from(n),
+            (None, None) => JsonValue::String(n.to_string()),
+        },
+        IonValue::Float(n) => Number::from_f64(*n).map_or(JsonValue::Null, JsonValue::Number),
+        // `1.50`, `15d-1`: the JSON number of the same value.
+        IonValue::Decimal(d) => d.to_string().replace(['d', 'D'], "e").parse::<f64>().ok()
+            .and_then(Number::from_f64).map_or_else(|| JsonValue::String(d.to_string()), JsonValue::Number),
+        IonValue::Timestamp(t) => tagged("timestamp", JsonValue::String(t.to_string())),
+        IonValue::Symbol(s) => s.text().map_or(JsonValue::Null, JsonValue::from),
+        IonValue::String(s) => JsonValue::from(s.text()),
+        IonValue::Clob(bytes) => JsonValue::String(String::from_utf8_lossy(bytes.as_ref()).into_owned()),
+        IonValue::Blob(bytes) => tagged("data", JsonValue::String(base64::encode(bytes.as_ref()))),
+        IonValue::List(items) | IonValue::SExp(items) => JsonValue::Array(items.iter().map(from_ion).collect()),
+        IonValue::Struct(fields) => JsonValue::Object(fields.iter()
+            .filter_map(|(name, value)| Some((name.text()?.to_string(), from_ion(value))))
+            .collect()),
+    }
+}
+
+fn to_ion(value: &JsonValue) -> rlua::Result<Element> {
+    Ok(match value {
+        JsonValue::Null => IonValue::Null(IonType::Null).into(),
+        JsonValue::Bool(b) => (*b).into(),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => n.into(),
+            (None, Some(n)) => n.into(),
+            _ => n.as_f64().unwrap_or_default().into(),
+        },
+        JsonValue::String(s) => s.as_str().into(),
+        JsonValue::Array(a) => ion_rs::List::from(a.iter().map(to_ion).collect::<rlua::Result<Vec<_>>>()?).into(),
+        JsonValue::Object(o) if o.len() == 2 && o.contains_key("value") => match (o.get("$type").and_then(JsonValue::as_str), o.get("value")) {
+            (Some("data"), Some(JsonValue::String(text))) => {
+                base64::decode(text).ok_or_else(|| ion_error(format!("invalid base64 data {:?}", text)))?.into()
+            },
+            (Some("timestamp"), Some(JsonValue::String(text))) => match Element::read_one(text.as_bytes()) {
+                Ok(element) if element.ion_type() == IonType::Timestamp => element,
+                _ => return Err(ion_error(format!("invalid timestamp {:?}", text))),
+            },
+            _ => structure(o)?,
+        },
+        JsonValue::Object(o) => structure(o)?,
+    })
+}
+
+fn structure(o: &Map<String, JsonValue>) -> rlua::Result<Element> {
+    Ok(o.iter().map(|(k, v)| Ok((k.as_str(), to_ion(v)?))).collect::<rlua::Result<ion_rs::Struct>>()?.into())
+}
+
+/// Reads a single Ion value, text or binary. Timestamps become
+/// `{"$type": "timestamp", "value": "2024-05-01T12:00:00+00:00"}` and blobs
+/// `{"$type": "data", "value": "<base64>"}`; otherwise Ion's own JSON down-conversion applies:
+/// typed nulls are `null`, decimals numbers, symbols and clobs strings, s-expressions arrays,
+/// and annotations are dropped. Integers past 64 bits become their digits, and infinite or NaN
+/// floats `null`.
+pub fn ion_to_json(bytes: &[u8]) -> rlua::Result<JsonValue> {
+    Ok(from_ion(&Element::read_one(bytes).map_err(ion_error)?))
+}
+
+/// Writes a value as Ion, reading the tagged timestamps and blobs [`ion_to_json`] produces back
+/// into their Ion types.
+pub fn json_to_ion(value: &JsonValue, format: IonFormat) -> rlua::Result<Vec<u8>> {
+    let element = to_ion(value)?;
+    match format {
+        IonFormat::Text => element.encode_as(ion_rs::v1_0::Text).map(String::into_bytes),
+        IonFormat::Binary => element.encode_as(ion_rs::v1_0::Binary),
+    }.map_err(ion_error)
+}
+
+/// [`ion_to_json`], converted into a Lua value with `options`.
+pub fn ion_to_lua<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &ion_to_json(bytes)?, options)
+}
+
+/// [`json_to_ion`] of a Lua value, converted with `options`.
+pub fn lua_to_ion<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, format: IonFormat, options: &ConversionOptions) -> rlua::Result<Vec<u8>> {
+    json_to_ion(&lua_to_json(lua, value, options)?, format)
+}
+
+/// The `json.ion` table: `decode(bytes)` and `encode(value, "text" | "binary")`.
+pub(crate) fn create_ion_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options, ion_to_lua, |lua, value, format: Option<String>, options| {
+        let format = match format.as_deref() {
+            None | Some("text") => IonFormat::Text,
+            Some("binary") => IonFormat::Binary,
+            Some(other) => return Err(ion_error(format!("unknown format {:?}, expected \"text\" or \"binary\"", other))),
+        };
+        lua_to_ion(lua, value, format, options)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, IonFormat, ion_to_json, json_to_ion, register};
+
+    #[test]
+    fn ion_values() {
+        let text = br#"order::{ id: 18446744073709551615, total: 12.50, placed: 2024-05-01T12:00:00Z, tags: [gift, "rush"],
+            note: null.string, expr: (+ 1 2), icon: {{ AAH/ }}, big: 123456789012345678901234567890, ratio: 1.5e0 }"#;
+        let value = ion_to_json(text).expect("parse");
+        let expected = json!({
+            "id": 18446744073709551615u64, "total": 12.5, "placed": {"$type": "timestamp", "value": "2024-05-01T12:00:00+00:00"},
+            "tags": ["gift", "rush"], "note": null, "expr": ["+", 1, 2], "icon": {"$type": "data", "value": "AAH/"},
+            "big": "123456789012345678901234567890", "ratio": 1.5,
+        });
+        assert_eq!(value, expected);
+        let written = json!({"placed": expected["placed"], "icon": expected["icon"], "n": [1, 2.5, null, true]});
+        for format in [IonFormat::Text, IonFormat::Binary] {
+            assert_eq!(ion_to_json(&json_to_ion(&written, format).expect("write")).expect("reparse"), written);
+        }
+        assert!(json_to_ion(&written, IonFormat::Binary).expect("binary").starts_with(b"\xe0\x01\x00\xea"));
+        assert!(json_to_ion(&json!({"$type": "timestamp", "value": "soon"}), IonFormat::Text).is_err());
+        assert!(ion_to_json(b"{ unclosed: 1").is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local bytes = json.ion.encode({ name = "x", at = { ["$type"] = "timestamp", value = "2024-05-01T" } }, "binary")
+            local value = json.ion.decode(bytes)
+            assert(value.name == "x" and value.at["$type"] == "timestamp" and value.at.value == "2024-05-01T")
+            assert(json.ion.decode(json.ion.encode({ 1, 2 })) [2] == 2)
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,506 @@
+use serde_json::{Number, Value as JsonValue};
+use crate::JsonWrapperValue;
+use crate::arrays::compare;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    DotDot,
+    Field(String),
+    Ident(String),
+    Literal(JsonValue),
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    Pipe,
+    Comma,
+    Op(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Identity,
+    Recurse,
+    Literal(JsonValue),
+    Index(Box<Expr>, Box<Expr>),
+    Iterate(Box<Expr>),
+    Array(Option<Box<Expr>>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(&'static str, Option<Box<Expr>>),
+}
+
+fn jq_error(message: impl Into<String>) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("jq: {}", message.into()))
+}
+
+fn tokenize(expr: &str) -> rlua::Result<Vec<Token>> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            _ if c.is_whitespace() => {
+                rest = &rest[c.len_utf8()..];
+                continue;
+            },
+            '.' if rest[1..].starts_with('.') => (Token::DotDot, 2),
+            '.' if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                let len = rest[1..].find(|c| !is_ident(c)).unwrap_or(rest.len() - 1);
+                (Token::Field(rest[1..=len].to_string()), len + 1)
+            },
+            '.' => (Token::Dot, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            '|' => (Token::Pipe, 1),
+            ',' => (Token::Comma, 1),
+            '"' => {
+                let mut escaped = false;
+                let end = rest.char_indices().skip(1).find(|&(_, c)| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                }).map(|(i, _)| i).ok_or_else(|| jq_error("unterminated string"))?;
+                let s: String = serde_json::from_str(&rest[..=end]).map_err(|e| jq_error(e.to_string()))?;
+                (Token::Literal(JsonValue::String(s)), end + 1)
+            },
+            '0'..='9' => {
+                let len = rest.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))).unwrap_or(rest.len());
+                let n: Number = serde_json::from_str(&rest[..len]).map_err(|_| jq_error(format!("bad number {:?}", &rest[..len])))?;
+                (Token::Literal(JsonValue::Number(n)), len)
+            },
+            _ if is_ident(c) => {
+                let len = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+                let token = match &rest[..len] {
+                    "true" => Token::Literal(JsonValue::Bool(true)),
+                    "false" => Token::Literal(JsonValue::Bool(false)),
+                    "null" => Token::Literal(JsonValue::Null),
+                    ident => Token::Ident(ident.to_string()),
+                };
+                (token, len)
+            },
+            _ => {
+                let op = ["==", "!=", "<=", ">=", "<", ">", "+", "-"].into_iter().find(|op| rest.starts_with(op))
+                    .ok_or_else(|| jq_error(format!("unexpected {:?}", c)))?;
+                (Token::Op(op), op.len())
+            },
+        };
+        tokens.push(token);
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> rlua::Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(jq_error(format!("expected {:?}, found {:?}", expected, token))),
+        }
+    }
+
+    fn pipe(&mut self) -> rlua::Result<Expr> {
+        let mut lhs = self.comma()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            lhs = Expr::Pipe(Box::new(lhs), Box::new(self.comma()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comma(&mut self) -> rlua::Result<Expr> {
+        let mut lhs = self.or()?;
+        while self.peek() == Some(&Token::Comma) {
+            self.next();
+            lhs = Expr::Comma(Box::new(lhs), Box::new(self.or()?));
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> rlua::Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Ident("or".to_string())) {
+            self.next();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> rlua::Result<Expr> {
+        let mut lhs = self.comparison()?;
+        while self.peek() == Some(&Token::Ident("and".to_string())) {
+            self.next();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> rlua::Result<Expr> {
+        let lhs = self.additive()?;
+        match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "+" | "-") => {
+                let op = *op;
+                self.next();
+                Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.additive()?)))
+            },
+            _ => Ok(lhs),
+        }
+    }
+
+    fn additive(&mut self) -> rlua::Result<Expr> {
+        let mut lhs = self.postfix()?;
+        while let Some(Token::Op(op @ ("+" | "-"))) = self.peek() {
+            let op = *op;
+            self.next();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.postfix()?));
+        }
+        Ok(lhs)
+    }
+
+    fn postfix(&mut self) -> rlua::Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Field(name)) => {
+                    let key = Expr::Literal(JsonValue::String(name.clone()));
+                    self.next();
+                    expr = Expr::Index(Box::new(expr), Box::new(key));
+                },
+                Some(Token::LBracket) => {
+                    self.next();
+                    if self.peek() == Some(&Token::RBracket) {
+                        self.next();
+                        expr = Expr::Iterate(Box::new(expr));
+                    } else {
+                        let key = self.pipe()?;
+                        self.expect(Token::RBracket)?;
+                        expr = Expr::Index(Box::new(expr), Box::new(key));
+                    }
+                },
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn primary(&mut self) -> rlua::Result<Expr> {
+        match self.next() {
+            Some(Token::Dot) => Ok(Expr::Identity),
+            Some(Token::DotDot) => Ok(Expr::Recurse),
+            Some(Token::Field(name)) =>
+                Ok(Expr::Index(Box::new(Expr::Identity), Box::new(Expr::Literal(JsonValue::String(name))))),
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Op("-")) =>
+                Ok(Expr::Binary("-", Box::new(Expr::Literal(JsonValue::from(0))), Box::new(self.postfix()?))),
+            Some(Token::LParen) => {
+                let expr = self.pipe()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::LBracket) => {
+                if self.peek() == Some(&Token::RBracket) {
+                    self.next();
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.pipe()?;
+                self.expect(Token::RBracket)?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            },
+            Some(Token::Ident(name)) => {
+                let (name, takes_argument) = match name.as_str() {
+                    "select" => ("select", true),
+                    "map" => ("map", true),
+                    "has" => ("has", true),
+                    "length" => ("length", false),
+                    "keys" => ("keys", false),
+                    "not" => ("not", false),
+                    "empty" => ("empty", false),
+                    "type" => ("type", false),
+                    _ => return Err(jq_error(format!("unknown function {}", name))),
+                };
+                if !takes_argument {
+                    return Ok(Expr::Call(name, None));
+                }
+                self.expect(Token::LParen)?;
+                let argument = self.pipe()?;
+                self.expect(Token::RParen)?;
+                Ok(Expr::Call(name, Some(Box::new(argument))))
+            },
+            token => Err(jq_error(format!("unexpected {:?}", token))),
+        }
+    }
+}
+
+fn truthy(value: &JsonValue) -> bool {
+    !matches!(value, JsonValue::Null | JsonValue::Bool(false))
+}
+
+fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+fn number(n: f64) -> JsonValue {
+    match n.fract() == 0.0 && n.abs() < 9e15 {
+        true => JsonValue::from(n as i64),
+        false => JsonValue::from(n),
+    }
+}
+
+fn binary(op: &str, a: JsonValue, b: JsonValue) -> rlua::Result<JsonValue> {
+    let mismatch = |a: &JsonValue, b: &JsonValue| jq_error(format!("cannot apply {} to {} and {}", op, type_name(a), type_name(b)));
+    Ok(match op {
+        "==" => JsonValue::Bool(compare(&a, &b).is_eq()),
+        "!=" => JsonValue::Bool(compare(&a, &b).is_ne()),
+        "<" => JsonValue::Bool(compare(&a, &b).is_lt()),
+        "<=" => JsonValue::Bool(compare(&a, &b).is_le()),
+        ">" => JsonValue::Bool(compare(&a, &b).is_gt()),
+        ">=" => JsonValue::Bool(compare(&a, &b).is_ge()),
+        "+" => match (a, b) {
+            (JsonValue::Null, b) => b,
+            (a, JsonValue::Null) => a,
+            (JsonValue::Number(x), JsonValue::Number(y)) =>
+                number(x.as_f64().unwrap_or_default() + y.as_f64().unwrap_or_default()),
+            (JsonValue::String(x), JsonValue::String(y)) => JsonValue::String(x + &y),
+            (JsonValue::Array(mut x), JsonValue::Array(y)) => {
+                x.extend(y);
+                JsonValue::Array(x)
+            },
+            (JsonValue::Object(mut x), JsonValue::Object(y)) => {
+                x.extend(y);
+                JsonValue::Object(x)
+            },
+            (a, b) => return Err(mismatch(&a, &b)),
+        },
+        _ => match (a, b) {
+            (JsonValue::Number(x), JsonValue::Number(y)) =>
+                number(x.as_f64().unwrap_or_default() - y.as_f64().unwrap_or_default()),
+            (JsonValue::Array(x), JsonValue::Array(y)) =>
+                JsonValue::Array(x.into_iter().filter(|v| !y.contains(v)).collect()),
+            (a, b) => return Err(mismatch(&a, &b)),
+        },
+    })
+}
+
+fn index(target: &JsonValue, key: &JsonValue) -> rlua::Result<JsonValue> {
+    Ok(match (target, key) {
+        (JsonValue::Null, _) => JsonValue::Null,
+        (JsonValue::Object(o), JsonValue::String(k)) => o.get(k).cloned().unwrap_or(JsonValue::Null),
+        (JsonValue::Array(a), JsonValue::Number(n)) => {
+            let i = n.as_f64().unwrap_or_default().floor() as i64;
+            let i = if i < 0 { a.len() as i64 + i } else { i };
+            usize::try_from(i).ok().and_then(|i| a.get(i)).cloned().unwrap_or(JsonValue::Null)
+        },
+        _ => return Err(jq_error(format!("cannot index {} with {}", type_name(target), type_name(key)))),
+    })
+}
+
+fn values(value: &JsonValue) -> rlua::Result<Vec<JsonValue>> {
+    match value {
+        JsonValue::Array(a) => Ok(a.clone()),
+        JsonValue::Object(o) => Ok(o.values().cloned().collect()),
+        _ => Err(jq_error(format!("cannot iterate over {}", type_name(value)))),
+    }
+}
+
+fn recurse(value: &JsonValue, out: &mut Vec<JsonValue>) {
+    out.push(value.clone());
+    if let JsonValue::Array(_) | JsonValue::Object(_) = value {
+        for child in values(value).unwrap_or_default() {
+            recurse(&child, out);
+        }
+    }
+}
+
+fn eval(expr: &Expr, input: &JsonValue) -> rlua::Result<Vec<JsonValue>> {
+    Ok(match expr {
+        Expr::Identity => vec![input.clone()],
+        Expr::Recurse => {
+            let mut out = Vec::new();
+            recurse(input, &mut out);
+            out
+        },
+        Expr::Literal(value) => vec![value.clone()],
+        Expr::Index(target, key) => {
+            let mut out = Vec::new();
+            for target in eval(target, input)? {
+                for key in eval(key, input)? {
+                    out.push(index(&target, &key)?);
+                }
+            }
+            out
+        },
+        Expr::Iterate(target) => {
+            let mut out = Vec::new();
+            for target in eval(target, input)? {
+                out.extend(values(&target)?);
+            }
+            out
+        },
+        Expr::Array(items) => vec![JsonValue::Array(match items {
+            Some(items) => eval(items, input)?,
+            None => Vec::new(),
+        })],
+        Expr::Pipe(lhs, rhs) => {
+            let mut out = Vec::new();
+            for value in eval(lhs, input)? {
+                out.extend(eval(rhs, &value)?);
+            }
+            out
+        },
+        Expr::Comma(lhs, rhs) => {
+            let mut out = eval(lhs, input)?;
+            out.extend(eval(rhs, input)?);
+            out
+        },
+        Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+            let is_and = matches!(expr, Expr::And(..));
+            let mut out = Vec::new();
+            for l in eval(lhs, input)? {
+                if truthy(&l) != is_and {
+                    out.push(JsonValue::Bool(!is_and));
+                    continue;
+                }
+                for r in eval(rhs, input)? {
+                    out.push(JsonValue::Bool(truthy(&r)));
+                }
+            }
+            out
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            let mut out = Vec::new();
+            for r in eval(rhs, input)? {
+                for l in eval(lhs, input)? {
+                    out.push(binary(op, l, r.clone())?);
+                }
+            }
+            out
+        },
+        Expr::Call(name, argument) => call(name, argument.as_deref(), input)?,
+    })
+}
+
+fn call(name: &str, argument: Option<&Expr>, input: &JsonValue) -> rlua::Result<Vec<JsonValue>> {
+    let argument = || argument.ok_or_else(|| jq_error(format!("{} needs an argument", name)));
+    Ok(match name {
+        "select" => match eval(argument()?, input)?.iter().any(truthy) {
+            true => vec![input.clone()],
+            false => Vec::new(),
+        },
+        "map" => {
+            let mut out = Vec::new();
+            for value in values(input)? {
+                out.extend(eval(argument()?, &value)?);
+            }
+            vec![JsonValue::Array(out)]
+        },
+        "has" => eval(argument()?, input)?.iter().map(|key| match (input, key) {
+            (JsonValue::Object(o), JsonValue::String(k)) => Ok(JsonValue::Bool(o.contains_key(k))),
+            (JsonValue::Array(a), JsonValue::Number(n)) =>
+                Ok(JsonValue::Bool(n.as_u64().is_some_and(|i| (i as usize) < a.len()))),
+            _ => Err(jq_error(format!("cannot check whether {} has a {} key", type_name(input), type_name(key)))),
+        }).collect::<rlua::Result<_>>()?,
+        "length" => vec![match input {
+            JsonValue::Null => JsonValue::from(0),
+            JsonValue::Bool(_) => return Err(jq_error("boolean has no length")),
+            JsonValue::Number(n) => number(n.as_f64().unwrap_or_default().abs()),
+            JsonValue::String(s) => JsonValue::from(s.chars().count()),
+            JsonValue::Array(a) => JsonValue::from(a.len()),
+            JsonValue::Object(o) => JsonValue::from(o.len()),
+        }],
+        "keys" => vec![match input {
+            JsonValue::Object(o) => {
+                let mut keys: Vec<_> = o.keys().cloned().map(JsonValue::String).collect();
+                keys.sort_by(compare);
+                JsonValue::Array(keys)
+            },
+            JsonValue::Array(a) => JsonValue::Array((0..a.len()).map(JsonValue::from).collect()),
+            _ => return Err(jq_error(format!("{} has no keys", type_name(input)))),
+        }],
+        "not" => vec![JsonValue::Bool(!truthy(input))],
+        "empty" => Vec::new(),
+        _ => vec![JsonValue::from(type_name(input))],
+    })
+}
+
+/// Runs a jq program over `value`, returning its outputs in order. Supports paths (`.a.b`,
+/// `.[0]`, `.[]`, `..`), `|`, `,`, `[...]`, literals, comparisons, `and`/`or`, `+`/`-` and
+/// `select`, `map`, `has`, `length`, `keys`, `not`, `empty` and `type`.
+pub fn jq(value: &JsonValue, program: &str) -> rlua::Result<Vec<JsonValue>> {
+    let mut parser = Parser { tokens: tokenize(program)?, position: 0 };
+    let expr = parser.pipe()?;
+    if let Some(token) = parser.peek() {
+        return Err(jq_error(format!("unexpected {:?}", token)));
+    }
+    eval(&expr, value)
+}
+
+impl JsonWrapperValue {
+    /// [`jq`] over the wrapped value.
+    pub fn jq(&self, program: &str) -> rlua::Result<Vec<JsonWrapperValue>> {
+        Ok(jq(&self.0, program)?.into_iter().map(JsonWrapperValue).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, jq, register};
+
+    #[test]
+    fn programs() {
+        let doc = json!({"items": [
+            {"name": "a", "price": 5, "tags": ["x"]},
+            {"name": "b", "price": 12, "tags": []},
+            {"name": "c", "price": 30, "tags": ["x", "y"]},
+        ]});
+        let run = |program| jq(&doc, program).expect(program);
+
+        assert_eq!(run(".items[] | select(.price > 10) | .name"), vec![json!("b"), json!("c")]);
+        assert_eq!(run("[.items[] | .tags | length]"), vec![json!([1, 0, 2])]);
+        assert_eq!(run(".items | map(.price) | .[0] + .[-1]"), vec![json!(35)]);
+        assert_eq!(run(".items[0] | keys, has(\"name\"), (.name == \"a\" and .price < 6)"),
+                   vec![json!(["name", "price", "tags"]), json!(true), json!(true)]);
+        assert_eq!(run("[.. | select(type == \"string\")] | length"), vec![json!(6)]);
+        assert!(jq(&doc, ".items[").is_err());
+        assert!(jq(&doc, ".items | frobnicate").is_err());
+    }
+
+    #[test]
+    fn lua_jq() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        let names: String = lua.load(r#"
+            local result = json.jq({ users = { { name = "x", admin = true }, { name = "y" } } }, ".users[] | select(.admin) | .name")
+            return table.concat(result, ",")
+        "#).eval().expect("eval");
+        assert_eq!(names, "x");
+    }
+}
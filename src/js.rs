@@ -0,0 +1,42 @@
+use rlua::Lua;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use wasm_bindgen::JsValue;
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+/// Converts a JavaScript value into a Lua value, through its JSON equivalent: `undefined` and
+/// `null` become `nil`, and values without one (functions, symbols) fail.
+pub fn js_to_lua<'lua>(lua: &'lua Lua, value: &JsValue, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    let json: JsonValue = serde_wasm_bindgen::from_value(value.clone()).map_err(|e| rlua::Error::ToLuaConversionError {
+        from: "JsValue", to: "Value", message: Some(e.to_string()) })?;
+    json_to_lua(lua, &json, options)
+}
+
+/// Converts a Lua value into a plain JavaScript value: objects rather than `Map`s, and numbers.
+/// Integers beyond `Number.MAX_SAFE_INTEGER` fail instead of losing precision.
+pub fn lua_to_js<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<JsValue> {
+    let json = lua_to_json(lua, value, options)?;
+    json.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).map_err(|e| rlua::Error::FromLuaConversionError {
+        from: "Value", to: "JsValue", message: Some(e.to_string()) })
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use rlua::Lua;
+    use wasm_bindgen::JsValue;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use crate::ConversionOptions;
+    use super::{js_to_lua, lua_to_js};
+
+    #[wasm_bindgen_test]
+    fn js_round_trip() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let value: rlua::Value = lua.load("{ name = 'x', list = { 1, 2.5 } }").eval().expect("table");
+        let js = lua_to_js(&lua, value, &options).expect("to js");
+        let back = js_to_lua(&lua, &js, &options).expect("to lua");
+        lua.globals().set("v", back).expect("set");
+        assert!(lua.load("return v.name == 'x' and v.list[2] == 2.5").eval::<bool>().expect("eval"));
+        assert!(matches!(js_to_lua(&lua, &JsValue::UNDEFINED, &options), Ok(rlua::Value::Nil)));
+    }
+}
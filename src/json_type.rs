@@ -0,0 +1,120 @@
+use rlua::Lua;
+use crate::{ConversionOptions, LazyString, Path};
+use crate::convert::{encode_as_array, is_i64, key_shape, set_keys};
+use crate::raw::RawJson;
+use crate::readonly::view_contents;
+
+/// Shape recorded in a table's `__jsontype` metafield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonType {
+    Array,
+    Object,
+}
+
+impl JsonType {
+    pub fn name(self) -> &'static str {
+        match self {
+            JsonType::Array => "array",
+            JsonType::Object => "object",
+        }
+    }
+
+    fn registry_key(self) -> &'static str {
+        match self {
+            JsonType::Array => "rlua_json.array_mt",
+            JsonType::Object => "rlua_json.object_mt",
+        }
+    }
+}
+
+/// The shared metatable `{__jsontype = "array"}` (or `"object"`) of this Lua state.
+pub fn json_type_metatable(lua: &Lua, json_type: JsonType) -> rlua::Result<rlua::Table<'_>> {
+    if let Some(mt) = lua.named_registry_value::<Option<rlua::Table>>(json_type.registry_key())? {
+        return Ok(mt);
+    }
+    let mt = lua.create_table()?;
+    mt.set("__jsontype", json_type.name())?;
+    lua.set_named_registry_value(json_type.registry_key(), mt.clone())?;
+    Ok(mt)
+}
+
+/// Reads the `__jsontype` metafield of a table, if any.
+pub fn table_json_type(table: &rlua::Table) -> rlua::Result<Option<JsonType>> {
+    let Some(mt) = table.get_metatable() else { return Ok(None) };
+    let json_type = match mt.raw_get::<_, Option<rlua::String>>("__jsontype")? {
+        Some(s) if s == "array" => Some(JsonType::Array),
+        Some(s) if s == "object" => Some(JsonType::Object),
+        _ => None,
+    };
+    Ok(json_type)
+}
+
+/// The JSON type `value` would be encoded as under `options`: `"object"`, `"array"`, `"null"`,
+/// `"integer"`, `"number"`, `"string"` or `"boolean"`; `None` for values without a JSON
+/// equivalent. `__tojson` metamethods are not called.
+pub(crate) fn value_type_name(value: &rlua::Value, options: &ConversionOptions) -> rlua::Result<Option<&'static str>> {
+    let name = match value {
+        rlua::Value::Nil => "null",
+        rlua::Value::LightUserData(ud) if ud.0.is_null() => "null",
+        rlua::Value::Boolean(_) => "boolean",
+        rlua::Value::Integer(_) => "integer",
+        rlua::Value::Number(n) if !n.is_finite() => "null",
+        rlua::Value::Number(n) if options.integral_floats_as_integers && is_i64(*n) => "integer",
+        rlua::Value::Number(_) => "number",
+        rlua::Value::String(_) => "string",
+        rlua::Value::Table(t) => {
+            let t = view_contents(t)?.unwrap_or_else(|| t.clone());
+            if let (true, Some(json_type)) = (options.json_type_metatables, table_json_type(&t)?) {
+                return Ok(Some(json_type.name()));
+            }
+            if options.set_array_paths.iter().any(|p| p.matches(&Path::new())) && set_keys(&t)?.is_some() {
+                return Ok(Some("array"));
+            }
+            let shape = key_shape(&t)?;
+            match shape.integers > 0 && shape.others == 0 && encode_as_array(shape.integers, shape.max, options)? {
+                true => "array",
+                false => "object",
+            }
+        },
+        rlua::Value::UserData(ud) => match ud.borrow::<RawJson>() {
+            Ok(raw) => match raw.get().trim_start().bytes().next().unwrap_or(b'n') {
+                b'{' => "object",
+                b'[' => "array",
+                b'n' => "null",
+                b't' | b'f' => "boolean",
+                b'"' => "string",
+                _ if raw.get().contains(['.', 'e', 'E']) => "number",
+                _ => "integer",
+            },
+            Err(_) if ud.is::<LazyString>() => "string",
+            Err(_) => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, register};
+
+    #[test]
+    fn type_inspection() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions { json_type_metatables: true, ..Default::default() }).expect("register");
+        let types: Vec<Option<String>> = lua.load(r#"
+            local doc = json.decode('{"a": [], "o": {}, "n": null, "i": 1, "f": 1.5, "s": "x", "b": false}')
+            return { json.type(doc), json.type(doc.a), json.type(doc.o), json.type(doc.n), json.type(doc.i),
+                     json.type(doc.f), json.type(doc.s), json.type(doc.b), json.type({1, 2}), json.type({}),
+                     json.type(json.raw("[1]")), json.type(nil), json.type(print) or "none" }
+        "#).eval().expect("eval");
+        let expected = ["object", "array", "object", "null", "integer", "number", "string", "boolean",
+            "array", "object", "array", "null", "none"];
+        assert_eq!(types, expected.map(|t| Some(t.to_string())));
+
+        let (array, object): (bool, bool) = lua.load("local t = json.decode('[]') return json.is_array(t), json.is_object(t)")
+            .eval().expect("eval");
+        assert_eq!((array, object), (true, false));
+    }
+}
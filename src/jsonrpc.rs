@@ -0,0 +1,333 @@
+//! JSON-RPC 2.0 envelopes: building and validating requests, notifications and responses, and
+//! converting their params and results to and from Lua. The `json.rpc` table of the module
+//! exposes the same to scripts.
+
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use rlua::{IntoLua, IntoLuaMulti, Lua};
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua, lua_to_json, to_string};
+use crate::module::lock;
+
+/// A request id: the spec allows strings, integers and `null`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Id {
+    Number(i64),
+    String(String),
+    Null,
+}
+
+impl Id {
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        match value {
+            JsonValue::Null => Some(Id::Null),
+            JsonValue::String(s) => Some(Id::String(s.clone())),
+            JsonValue::Number(n) => n.as_i64().map(Id::Number),
+            _ => None,
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Id::Number(n) => JsonValue::from(*n),
+            Id::String(s) => JsonValue::from(s.as_str()),
+            Id::Null => JsonValue::Null,
+        }
+    }
+}
+
+impl<'lua> rlua::FromLua<'lua> for Id {
+    #[allow(clippy::useless_conversion)] // `rlua::Integer` is 32 bits wide on Luau
+    fn from_lua(value: rlua::Value<'lua>, _: &'lua Lua) -> rlua::Result<Self> {
+        match value {
+            rlua::Value::Nil => Ok(Id::Null),
+            rlua::Value::LightUserData(ud) if ud.0.is_null() => Ok(Id::Null),
+            rlua::Value::Integer(i) => Ok(Id::Number(i.into())),
+            rlua::Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.0e15 => Ok(Id::Number(n as i64)),
+            rlua::Value::String(s) => Ok(Id::String(s.to_str()?.to_string())),
+            _ => Err(rlua::Error::FromLuaConversionError {
+                from: value.type_name(), to: "Id", message: Some("ids must be integers, strings or nil".to_string()) }),
+        }
+    }
+}
+
+impl<'lua> IntoLua<'lua> for Id {
+    fn into_lua(self, lua: &'lua Lua) -> rlua::Result<rlua::Value<'lua>> {
+        match self {
+            Id::Number(n) => n.into_lua(lua),
+            Id::String(s) => s.into_lua(lua),
+            Id::Null => Ok(rlua::Value::NULL),
+        }
+    }
+}
+
+/// The `error` member of a response, and the error of parsing an invalid message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<JsonValue>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+
+    fn invalid(message: &str) -> Self {
+        RpcError::new(Self::INVALID_REQUEST, format!("Invalid Request: {}", message))
+    }
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        let error = value.as_object()?;
+        Some(RpcError {
+            code: error.get("code")?.as_i64()?,
+            message: error.get("message")?.as_str()?.to_string(),
+            data: error.get("data").cloned(),
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut error = Map::new();
+        error.insert("code".to_string(), JsonValue::from(self.code));
+        error.insert("message".to_string(), JsonValue::from(self.message.as_str()));
+        if let Some(data) = &self.data {
+            error.insert("data".to_string(), data.clone());
+        }
+        JsonValue::Object(error)
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A JSON-RPC message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// A request; a notification if `id` is `None`.
+    Request { id: Option<Id>, method: String, params: Option<JsonValue> },
+    Response { id: Id, result: Result<JsonValue, RpcError> },
+}
+
+impl Message {
+    pub fn request(id: Id, method: impl Into<String>, params: Option<JsonValue>) -> Self {
+        Message::Request { id: Some(id), method: method.into(), params }
+    }
+
+    pub fn notification(method: impl Into<String>, params: Option<JsonValue>) -> Self {
+        Message::Request { id: None, method: method.into(), params }
+    }
+
+    /// Validates the envelope: the version, the id type, params being structured, and a
+    /// response having exactly one of `result` and `error`.
+    pub fn from_json(value: &JsonValue) -> Result<Self, RpcError> {
+        let message = value.as_object().ok_or_else(|| RpcError::invalid("expected an object"))?;
+        if message.get("jsonrpc").and_then(JsonValue::as_str) != Some("2.0") {
+            return Err(RpcError::invalid("jsonrpc must be \"2.0\""));
+        }
+        let id = message.get("id").map(|id| Id::from_json(id).ok_or_else(|| RpcError::invalid("id must be an integer, a string or null")))
+            .transpose()?;
+        if let Some(method) = message.get("method") {
+            let method = method.as_str().ok_or_else(|| RpcError::invalid("method must be a string"))?;
+            let params = message.get("params").cloned();
+            if params.as_ref().is_some_and(|p| !p.is_array() && !p.is_object()) {
+                return Err(RpcError::invalid("params must be an array or an object"));
+            }
+            return Ok(Message::Request { id, method: method.to_string(), params });
+        }
+        let id = id.ok_or_else(|| RpcError::invalid("a response needs an id"))?;
+        match (message.get("result"), message.get("error")) {
+            (Some(result), None) => Ok(Message::Response { id, result: Ok(result.clone()) }),
+            (None, Some(error)) => Ok(Message::Response {
+                id, result: Err(RpcError::from_json(error).ok_or_else(|| RpcError::invalid("malformed error object"))?),
+            }),
+            _ => Err(RpcError::invalid("a response needs exactly one of result and error")),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, RpcError> {
+        let value: JsonValue = serde_json::from_str(text).map_err(|e| RpcError::new(RpcError::PARSE_ERROR, format!("Parse error: {}", e)))?;
+        Message::from_json(&value)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut message = Map::new();
+        message.insert("jsonrpc".to_string(), JsonValue::from("2.0"));
+        match self {
+            Message::Request { id, method, params } => {
+                if let Some(id) = id {
+                    message.insert("id".to_string(), id.to_json());
+                }
+                message.insert("method".to_string(), JsonValue::from(method.as_str()));
+                if let Some(params) = params {
+                    message.insert("params".to_string(), params.clone());
+                }
+            },
+            Message::Response { id, result } => {
+                message.insert("id".to_string(), id.to_json());
+                match result {
+                    Ok(result) => message.insert("result".to_string(), result.clone()),
+                    Err(error) => message.insert("error".to_string(), error.to_json()),
+                };
+            },
+        }
+        JsonValue::Object(message)
+    }
+}
+
+/// The messages of a batch, each validated on its own, or a single message as a batch of one.
+pub fn parse_batch(text: &str) -> Result<Vec<Result<Message, RpcError>>, RpcError> {
+    let value: JsonValue = serde_json::from_str(text).map_err(|e| RpcError::new(RpcError::PARSE_ERROR, format!("Parse error: {}", e)))?;
+    match value {
+        JsonValue::Array(batch) if batch.is_empty() => Err(RpcError::invalid("empty batch")),
+        JsonValue::Array(batch) => Ok(batch.iter().map(Message::from_json).collect()),
+        single => Ok(vec![Message::from_json(&single)]),
+    }
+}
+
+/// `params` (a table, or `nil` for none) as JSON-RPC params.
+pub fn params_from_lua<'lua>(lua: &'lua Lua, params: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<Option<JsonValue>> {
+    match lua_to_json(lua, params, options)? {
+        JsonValue::Null => Ok(None),
+        params @ (JsonValue::Array(_) | JsonValue::Object(_)) => Ok(Some(params)),
+        _ => Err(rlua::Error::external(RpcError::invalid("params must be an array or an object"))),
+    }
+}
+
+fn error_to_lua<'lua>(lua: &'lua Lua, error: &RpcError, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &error.to_json(), options)
+}
+
+/// A parsed message as a table: `{id =, method =, params =}` for requests (without `id` for
+/// notifications), `{id =, result =}` or `{id =, error = {code =, message =, data =}}` for
+/// responses.
+fn message_to_lua<'lua>(lua: &'lua Lua, message: Message, options: &ConversionOptions) -> rlua::Result<rlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    match message {
+        Message::Request { id, method, params } => {
+            if let Some(id) = id {
+                table.set("id", id)?;
+            }
+            table.set("method", method)?;
+            if let Some(params) = params {
+                table.set("params", json_to_lua(lua, &params, options)?)?;
+            }
+        },
+        Message::Response { id, result } => {
+            table.set("id", id)?;
+            match result {
+                Ok(result) => table.set("result", json_to_lua(lua, &result, options)?)?,
+                Err(error) => table.set("error", error_to_lua(lua, &error, options)?)?,
+            }
+        },
+    }
+    Ok(table)
+}
+
+/// The `json.rpc` table: `request(id, method, params)`, `notification(method, params)`,
+/// `result(id, result)` and `error(id, code, message, data)` return message text;
+/// `parse(text)` returns a message table, or `nil` and an error object to send back.
+pub(crate) fn create_rpc_table<'lua>(lua: &'lua Lua, options: Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    let rpc = lua.create_table()?;
+
+    let request_options = options.clone();
+    rpc.set("request", lua.create_function(move |lua, (id, method, params): (Id, String, rlua::Value)| {
+        let options = lock(&request_options).clone();
+        to_string(&Message::request(id, method, params_from_lua(lua, params, &options)?).to_json(), &options)
+    })?)?;
+
+    let notification_options = options.clone();
+    rpc.set("notification", lua.create_function(move |lua, (method, params): (String, rlua::Value)| {
+        let options = lock(&notification_options).clone();
+        to_string(&Message::notification(method, params_from_lua(lua, params, &options)?).to_json(), &options)
+    })?)?;
+
+    let result_options = options.clone();
+    rpc.set("result", lua.create_function(move |lua, (id, result): (Id, rlua::Value)| {
+        let options = lock(&result_options).clone();
+        let result = lua_to_json(lua, result, &options)?;
+        to_string(&Message::Response { id, result: Ok(result) }.to_json(), &options)
+    })?)?;
+
+    let error_options = options.clone();
+    rpc.set("error", lua.create_function(move |lua, (id, code, message, data): (Id, i64, String, rlua::Value)| {
+        let options = lock(&error_options).clone();
+        let data = Some(lua_to_json(lua, data, &options)?).filter(|data| !data.is_null());
+        to_string(&Message::Response { id, result: Err(RpcError { code, message, data }) }.to_json(), &options)
+    })?)?;
+
+    rpc.set("parse", lua.create_function(move |lua, text: String| {
+        let options = lock(&options).clone();
+        match Message::parse(&text) {
+            Ok(message) => (message_to_lua(lua, message, &options)?, rlua::Value::Nil).into_lua_multi(lua),
+            Err(error) => (rlua::Value::Nil, error_to_lua(lua, &error, &options)?).into_lua_multi(lua),
+        }
+    })?)?;
+
+    Ok(rpc)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, register};
+    use super::{Id, Message, RpcError, parse_batch};
+
+    #[test]
+    fn envelopes() {
+        let request = Message::parse(r#"{"jsonrpc": "2.0", "id": 1, "method": "sum", "params": [1, 2]}"#).expect("request");
+        assert_eq!(request, Message::request(Id::Number(1), "sum", Some(json!([1, 2]))));
+        assert_eq!(Message::from_json(&request.to_json()), Ok(request));
+
+        let error = Message::parse(r#"{"jsonrpc": "2.0", "id": "a", "error": {"code": -32601, "message": "no"}}"#).expect("error");
+        assert_eq!(error, Message::Response { id: Id::String("a".to_string()), result: Err(RpcError::new(RpcError::METHOD_NOT_FOUND, "no")) });
+
+        let code = |text| Message::parse(text).map(|_| ()).map_err(|e| e.code);
+        assert_eq!(code(r#"{"jsonrpc": "2.0", "method": "ping"}"#), Ok(()));
+        assert_eq!(code("{"), Err(RpcError::PARSE_ERROR));
+        assert_eq!(code(r#"{"jsonrpc": "1.0", "id": 1, "method": "a"}"#), Err(RpcError::INVALID_REQUEST));
+        assert_eq!(code(r#"{"jsonrpc": "2.0", "id": 1.5, "method": "a"}"#), Err(RpcError::INVALID_REQUEST));
+        assert_eq!(code(r#"{"jsonrpc": "2.0", "id": 1, "method": "a", "params": 3}"#), Err(RpcError::INVALID_REQUEST));
+        assert_eq!(code(r#"{"jsonrpc": "2.0", "id": 1, "result": 1, "error": {"code": 1, "message": ""}}"#), Err(RpcError::INVALID_REQUEST));
+
+        let batch = parse_batch(r#"[{"jsonrpc": "2.0", "method": "a"}, 1]"#).expect("batch");
+        assert!(batch[0].is_ok() && batch[1].is_err());
+        assert!(parse_batch("[]").is_err());
+    }
+
+    #[test]
+    fn lua_helpers() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local text = json.rpc.request(7, "move", { x = 1 })
+            local message = assert(json.rpc.parse(text))
+            assert(message.id == 7 and message.method == "move" and message.params.x == 1)
+
+            local note = assert(json.rpc.parse(json.rpc.notification("ping")))
+            assert(note.id == nil and note.method == "ping" and note.params == nil)
+
+            local reply = assert(json.rpc.parse(json.rpc.result("q", { ok = true })))
+            assert(reply.id == "q" and reply.result.ok)
+
+            local failed = assert(json.rpc.parse(json.rpc.error(nil, -32602, "bad", { field = "x" })))
+            assert(failed.id == json.null and failed.error.code == -32602 and failed.error.data.field == "x")
+
+            local none, err = json.rpc.parse('{"id": 1}')
+            assert(none == nil and err.code == -32600, err.message)
+            assert(not pcall(json.rpc.request, 1, "a", 5))
+        "#).exec().expect("lua helpers");
+    }
+}
@@ -0,0 +1,189 @@
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, KeyOrder};
+use crate::convert::key_shape;
+use crate::json_type::value_type_name;
+use crate::readonly::view_contents;
+
+/// Weak-keyed table from decoded objects to the array of their keys in document order.
+#[cfg(feature = "preserve_order")]
+const KEY_ORDER_KEY: &str = "rlua_json.key_order";
+/// Weak-keyed set of the objects decoded under [`ConversionOptions::numeric_keys`].
+const NUMERIC_OBJECTS_KEY: &str = "rlua_json.numeric_objects";
+
+fn weak_keyed_registry_table<'lua>(lua: &'lua Lua, key: &str) -> rlua::Result<rlua::Table<'lua>> {
+    if let Some(table) = lua.named_registry_value::<Option<rlua::Table>>(key)? {
+        return Ok(table);
+    }
+    let table = lua.create_table()?;
+    let mt = lua.create_table()?;
+    mt.set("__mode", "k")?;
+    table.set_metatable(Some(mt));
+    lua.set_named_registry_value(key, table.clone())?;
+    Ok(table)
+}
+
+#[cfg(feature = "preserve_order")]
+fn key_orders(lua: &Lua) -> rlua::Result<rlua::Table<'_>> {
+    weak_keyed_registry_table(lua, KEY_ORDER_KEY)
+}
+
+/// Keys of an object that are all integers in canonical form (`"7"`, `"-1"`, not `"07"` or `"+7"`).
+pub(crate) fn numeric_keys(o: &Map<String, JsonValue>) -> Option<Vec<i64>> {
+    if o.is_empty() {
+        return None;
+    }
+    o.keys()
+        .map(|k| k.parse::<i64>().ok().filter(|i| i.to_string() == *k))
+        .collect()
+}
+
+/// Remembers that `table` was an object with numeric keys, so it encodes as one again.
+pub(crate) fn mark_numeric_object<'lua>(lua: &'lua Lua, table: &rlua::Table<'lua>) -> rlua::Result<()> {
+    weak_keyed_registry_table(lua, NUMERIC_OBJECTS_KEY)?.raw_set(table.clone(), true)
+}
+
+pub(crate) fn is_numeric_object<'lua>(lua: &'lua Lua, table: &rlua::Table<'lua>) -> rlua::Result<bool> {
+    weak_keyed_registry_table(lua, NUMERIC_OBJECTS_KEY)?.raw_get(table.clone())
+}
+
+/// Remembers the key order of a decoded object.
+#[cfg(feature = "preserve_order")]
+pub(crate) fn record_key_order<'lua>(
+    lua: &'lua Lua, table: &rlua::Table<'lua>, keys: impl Iterator<Item = &'lua str>,
+) -> rlua::Result<()> {
+    key_orders(lua)?.raw_set(table.clone(), lua.create_sequence_from(keys)?)
+}
+
+/// The pairs of `table`, in decoded key order where known; keys added later follow in `pairs` order.
+#[cfg(feature = "preserve_order")]
+pub(crate) fn ordered_pairs<'lua>(
+    lua: &'lua Lua, table: &rlua::Table<'lua>,
+) -> rlua::Result<Vec<(rlua::Value<'lua>, rlua::Value<'lua>)>> {
+    let pairs = table.clone().pairs::<rlua::Value, rlua::Value>().collect::<rlua::Result<Vec<_>>>()?;
+    let Some(order) = key_orders(lua)?.raw_get::<_, Option<rlua::Table>>(table.clone())? else { return Ok(pairs) };
+    let mut ordered = Vec::with_capacity(pairs.len());
+    let known = lua.create_table()?;
+    for key in order.sequence_values::<rlua::Value>() {
+        let key = key?;
+        let value = table.raw_get::<_, rlua::Value>(key.clone())?;
+        if !value.is_nil() {
+            known.raw_set(key.clone(), true)?;
+            ordered.push((key, value));
+        }
+    }
+    for (key, value) in pairs {
+        if !known.raw_get::<_, bool>(key.clone())? {
+            ordered.push((key, value));
+        }
+    }
+    Ok(ordered)
+}
+
+#[cfg(not(feature = "preserve_order"))]
+pub(crate) fn ordered_pairs<'lua>(
+    _: &'lua Lua, table: &rlua::Table<'lua>,
+) -> rlua::Result<Vec<(rlua::Value<'lua>, rlua::Value<'lua>)>> {
+    table.clone().pairs::<rlua::Value, rlua::Value>().collect()
+}
+
+/// Reorders an encoded object per [`KeyOrder`]; without `preserve_order`, maps are always in
+/// byte order, and only encoded text follows [`KeyOrder::Natural`].
+#[cfg(feature = "preserve_order")]
+pub(crate) fn sort_object(o: Map<String, JsonValue>, order: KeyOrder) -> Map<String, JsonValue> {
+    if order == KeyOrder::Unsorted {
+        return o;
+    }
+    let mut entries: Vec<_> = o.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| order.compare(a, b));
+    entries.into_iter().collect()
+}
+
+#[cfg(not(feature = "preserve_order"))]
+pub(crate) fn sort_object(o: Map<String, JsonValue>, _: KeyOrder) -> Map<String, JsonValue> {
+    o
+}
+
+fn not_a_container(function: &str, value: &rlua::Value) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}: expected an array or object, got {}", function, value.type_name()))
+}
+
+/// Member count of a value encoded as an object, or the length of an array (holes included).
+pub(crate) fn json_length(value: &rlua::Value, options: &ConversionOptions) -> rlua::Result<usize> {
+    let rlua::Value::Table(table) = value else { return Err(not_a_container("json.length", value)) };
+    let table = view_contents(table)?.unwrap_or_else(|| table.clone());
+    let shape = key_shape(&table)?;
+    match value_type_name(value, options)? {
+        Some("array") if shape.others > 0 => Ok(shape.others),
+        Some("array") => Ok(shape.max),
+        _ => Ok(shape.integers + shape.others),
+    }
+}
+
+/// Keys of a value encoded as an object, in decoded order with the `preserve_order` feature
+/// and sorted otherwise; `1..length` for an array.
+pub(crate) fn json_keys<'lua>(
+    lua: &'lua Lua, value: &rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<rlua::Table<'lua>> {
+    let rlua::Value::Table(table) = value else { return Err(not_a_container("json.keys", value)) };
+    let contents = view_contents(table)?.unwrap_or_else(|| table.clone());
+    if value_type_name(value, options)? == Some("array") {
+        return lua.create_sequence_from(1..=json_length(value, options)? as i64);
+    }
+    let mut keys: Vec<rlua::Value> = ordered_pairs(lua, &contents)?.into_iter().map(|(k, _)| k).collect();
+    if cfg!(not(feature = "preserve_order")) {
+        let mut named = Vec::with_capacity(keys.len());
+        for key in keys {
+            named.push((lua.coerce_string(key.clone())?.map(|s| s.to_string_lossy().into_owned()), key));
+        }
+        named.sort_by(|a, b| a.0.cmp(&b.0));
+        keys = named.into_iter().map(|(_, key)| key).collect();
+    }
+    lua.create_sequence_from(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, KeyOrder, lua_to_json, lua_to_string, register};
+
+    #[test]
+    fn length_and_keys() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions { json_type_metatables: true, ..Default::default() }).expect("register");
+        let (object_len, array_len, keys): (usize, usize, String) = lua.load(r#"
+            local doc = json.decode('{"b": 1, "a": [1, null, 3], "c": {}}')
+            return json.length(doc), json.length(doc.a), table.concat(json.keys(doc), ",")
+        "#).eval().expect("eval");
+        assert_eq!((object_len, array_len), (3, 3));
+        if cfg!(feature = "preserve_order") {
+            assert_eq!(keys, "b,a,c");
+        } else {
+            assert_eq!(keys, "a,b,c");
+        }
+        assert!(lua.load("json.length('text')").exec().is_err());
+    }
+
+    #[test]
+    fn sorted_keys() {
+        let lua = Lua::new();
+        let value: rlua::Value = lua.load("{ item10 = 1, item2 = 2, item02 = 3, Item = 4, [10] = 5, [9] = 6 }").eval().expect("table");
+        let options = ConversionOptions { sort_keys: KeyOrder::Sorted, ..Default::default() };
+        assert_eq!(lua_to_string(&lua, value.clone(), &options).expect("sorted"),
+            r#"{"10":5,"9":6,"Item":4,"item02":3,"item10":1,"item2":2}"#);
+        let options = ConversionOptions { sort_keys: KeyOrder::Natural, ..Default::default() };
+        assert_eq!(lua_to_string(&lua, value.clone(), &options).expect("natural"),
+            r#"{"9":6,"10":5,"Item":4,"item2":2,"item02":3,"item10":1}"#);
+        if cfg!(feature = "preserve_order") {
+            let json = lua_to_json(&lua, value, &options).expect("json");
+            let keys: Vec<&str> = json.as_object().expect("object").keys().map(String::as_str).collect();
+            assert_eq!(keys, ["9", "10", "Item", "item2", "item02", "item10"]);
+        }
+
+        register(&lua, ConversionOptions::default()).expect("register");
+        let text: String = lua.load(r#"json.encode({ b = { y = 1, x = 2 }, a = 0 }, { sort_keys = "natural" })"#)
+            .eval().expect("encode");
+        assert_eq!(text, r#"{"a":0,"b":{"x":2,"y":1}}"#);
+        assert!(lua.load("json.encode({}, { sort_keys = 1 })").exec().is_err());
+    }
+}
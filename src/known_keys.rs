@@ -0,0 +1,112 @@
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, DiagnosticKind, Path, PathSegment};
+use crate::convert::diagnose;
+
+/// What encoded objects are checked against by [`ConversionOptions::known_keys`], to catch
+/// likely typos such as `helath`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyReference {
+    /// A sample document. An empty object in it accepts any key, and the first element of
+    /// an array describes every element.
+    Shape(JsonValue),
+    /// A JSON Schema: keys outside `properties` are unknown unless `additionalProperties`
+    /// allows them; `items` describes array elements.
+    Schema(JsonValue),
+}
+
+impl KeyReference {
+    /// Paths of the object keys in `value` the reference does not know, the key included.
+    pub fn unknown_keys(&self, value: &JsonValue) -> Vec<Path> {
+        let mut out = Vec::new();
+        match self {
+            KeyReference::Shape(shape) => by_shape(value, shape, &mut Path::new(), &mut out),
+            KeyReference::Schema(schema) => by_schema(value, schema, &mut Path::new(), &mut out),
+        }
+        out
+    }
+}
+
+fn by_shape(value: &JsonValue, shape: &JsonValue, path: &mut Path, out: &mut Vec<Path>) {
+    match (value, shape) {
+        (JsonValue::Object(o), JsonValue::Object(known)) if !known.is_empty() => for (k, v) in o {
+            path.push(PathSegment::Key(k.clone()));
+            match known.get(k) {
+                Some(shape) => by_shape(v, shape, path, out),
+                None => out.push(path.clone()),
+            }
+            path.pop();
+        },
+        (JsonValue::Array(a), JsonValue::Array(known)) => if let Some(shape) = known.first() {
+            for (i, v) in a.iter().enumerate() {
+                path.push(PathSegment::Index(i));
+                by_shape(v, shape, path, out);
+                path.pop();
+            }
+        },
+        _ => {},
+    }
+}
+
+fn by_schema(value: &JsonValue, schema: &JsonValue, path: &mut Path, out: &mut Vec<Path>) {
+    let Some(schema) = schema.as_object() else { return };
+    match value {
+        JsonValue::Object(o) => {
+            let properties = schema.get("properties").and_then(JsonValue::as_object);
+            let additional = schema.get("additionalProperties");
+            for (k, v) in o {
+                path.push(PathSegment::Key(k.clone()));
+                match properties.and_then(|p| p.get(k)).or(additional.filter(|a| a.is_object())) {
+                    Some(schema) => by_schema(v, schema, path, out),
+                    None if properties.is_some() && additional != Some(&JsonValue::Bool(true)) => out.push(path.clone()),
+                    None => {},
+                }
+                path.pop();
+            }
+        },
+        JsonValue::Array(a) => if let Some(items) = schema.get("items") {
+            for (i, v) in a.iter().enumerate() {
+                path.push(PathSegment::Index(i));
+                by_schema(v, items, path, out);
+                path.pop();
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Reports every unknown key of an encoded document as a [`DiagnosticKind::UnknownKey`].
+pub(crate) fn diagnose_unknown_keys(options: &ConversionOptions, value: &JsonValue) {
+    let Some(reference) = &options.known_keys else { return };
+    for mut path in reference.unknown_keys(value) {
+        let Some(PathSegment::Key(key)) = path.segments().last().cloned() else { continue };
+        path.pop();
+        diagnose(options, &path, DiagnosticKind::UnknownKey { key });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, KeyReference, lua_to_json_with_report};
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        let lua = Lua::new();
+        let source = "{ health = 3, helath = 4, items = { { name = 'a', cuont = 1 } }, tags = { x = true } }";
+        let shape = json!({"health": 0, "items": [{"name": "", "count": 0}], "tags": {}});
+        let schema = json!({"properties": {
+            "health": {"type": "integer"},
+            "items": {"items": {"properties": {"name": {}, "count": {}}}},
+            "tags": {"additionalProperties": {"type": "boolean"}},
+        }});
+        for reference in [KeyReference::Shape(shape), KeyReference::Schema(schema)] {
+            let options = ConversionOptions { known_keys: Some(reference), ..Default::default() };
+            let value = lua.load(source).eval().expect("table");
+            let (_, report) = lua_to_json_with_report(&lua, value, &options).expect("encode");
+            let mut lines: Vec<String> = report.diagnostics.iter().map(ToString::to_string).collect();
+            lines.sort();
+            assert_eq!(lines, vec![r#"/: unknown key "helath""#, r#"/items/0: unknown key "cuont""#]);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use rlua::{MetaMethod, UserData, UserDataMethods};
+
+/// A long decoded string kept on the Rust side until a script reads it, created by
+/// [`ConversionOptions::lazy_strings`](crate::ConversionOptions::lazy_strings). Scripts call
+/// `:len()` (or `#s`), `:sub(i [, j])` with `string.sub` semantics for a part, and
+/// `:materialize()` (or `tostring(s)`) for the whole string; encoding writes it back as a string.
+#[derive(Debug, Clone)]
+pub struct LazyString(pub String);
+
+impl LazyString {
+    pub fn get(&self) -> &str {
+        &self.0
+    }
+
+    /// The bytes `string.sub(s, i, j)` returns: 1-based and inclusive, negative from the end.
+    fn sub(&self, i: i64, j: Option<i64>) -> &[u8] {
+        let bytes = self.0.as_bytes();
+        let len = bytes.len() as i64;
+        let position = |p: i64| if p < 0 { (len + p + 1).max(0) } else { p };
+        let start = position(i).max(1);
+        let end = position(j.unwrap_or(-1)).min(len);
+        match start <= end {
+            true => bytes.get(start as usize - 1..end as usize).unwrap_or_default(),
+            false => &[],
+        }
+    }
+}
+
+impl UserData for LazyString {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("len", |_, this, ()| Ok(this.0.len()));
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.0.len()));
+        methods.add_method("sub", |lua, this, (i, j): (i64, Option<i64>)| lua.create_string(this.sub(i, j)));
+        methods.add_method("materialize", |lua, this, ()| lua.create_string(&this.0));
+        methods.add_meta_method(MetaMethod::ToString, |lua, this, ()| lua.create_string(&this.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+    #[test]
+    fn lazy_strings() {
+        let lua = Lua::new();
+        let options = ConversionOptions { lazy_strings: Some(8), ..Default::default() };
+        let doc = json!({"short": "abc", "texture": "0123456789abcdef"});
+        let value = json_to_lua(&lua, &doc, &options).expect("decode");
+        lua.globals().set("doc", value.clone()).expect("set");
+        let (short, len, sub, tail, whole, empty): (String, usize, String, String, String, String) = lua.load(r#"
+            local t = doc.texture
+            return doc.short, #t, t:sub(2, 4), t:sub(-3), t:materialize(), t:sub(5, 2)
+        "#).eval().expect("eval");
+        assert_eq!((short.as_str(), len, sub.as_str(), tail.as_str()), ("abc", 16, "123", "def"));
+        assert_eq!((whole.as_str(), empty.as_str()), ("0123456789abcdef", ""));
+        assert_eq!(lua.load("type(doc.texture)").eval::<String>().expect("type"), "userdata");
+        assert_eq!(lua_to_json(&lua, value, &ConversionOptions::default()).expect("encode"), doc);
+    }
+}
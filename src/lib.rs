@@ -1,11 +1,186 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable, clippy::todo, clippy::indexing_slicing))]
+
 use std::fmt::{Display, Formatter};
-use rlua;
-use rlua::{Lua, FromLua, ToLua};
-use serde_json::{json, Value as JsonValue};
+use rlua::{Lua, FromLua, IntoLua};
+use serde_json::Value as JsonValue;
+#[cfg(feature = "serde-derive")]
 use serde::{Deserialize, Serialize};
 
+mod arrays;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod base64;
+mod budget;
+mod bulk;
+mod cache;
+mod cancel;
+#[cfg(feature = "bytecode")]
+mod bytecode;
+mod coerce;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compress;
+mod convert;
+mod dedup;
+mod defaults;
+#[cfg(feature = "serde-delegate")]
+mod delegate;
+mod diagnostics;
+mod document;
+mod estimate;
+mod existing;
+mod file;
+mod flatten;
+mod format;
+mod gc;
+mod generate;
+#[cfg(feature = "geojson")]
+mod geo;
+#[cfg(feature = "snapshot-tests")]
+pub mod golden;
+mod http;
+#[cfg(feature = "ini")]
+mod ini;
+#[cfg(feature = "ion")]
+mod ion;
+mod interned;
+#[cfg(feature = "jq")]
+mod jq;
+#[cfg(feature = "wasm-js")]
+pub mod js;
+mod json_type;
+mod jwt;
+pub mod jsonrpc;
+mod keys;
+mod lazy;
+mod memory;
+mod known_keys;
+mod module;
+mod multipart;
+mod options;
+#[cfg(feature = "openapi")]
+mod openapi;
+mod parse;
+mod path;
+#[cfg(feature = "plist")]
+mod plist;
+mod progress;
+#[cfg(feature = "prost-reflect")]
+mod proto;
+#[cfg(feature = "proptest-support")]
+pub mod proptest_support;
+mod raw;
+mod readonly;
+mod resp;
+mod refs;
+mod registry;
+#[cfg(feature = "rlua-compat")]
+pub mod rlua_compat;
+mod sample;
+mod scramble;
+mod select;
+mod shape;
+mod snapshot;
+#[cfg(feature = "spreadsheet")]
+mod spreadsheet;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod stream;
+mod template;
+#[cfg(feature = "testdata")]
+pub mod testdata;
+mod typed;
+#[cfg(feature = "ubjson")]
+mod ubjson;
+mod urlencoded;
+mod validate;
+#[cfg(feature = "webhooks")]
+mod webhook;
+
+/// The Lua bindings this crate is built on, for downstream crates to agree on their version.
+pub use rlua as mlua;
+
+pub use bulk::{bulk_into_lua, bulk_parse};
+pub use cache::DecodeCache;
+pub use cancel::{CancellationToken, Cancelled};
+pub use coerce::{BoolEncoding, EnumMapping, NumberFormat};
+#[cfg(feature = "gzip")]
+pub use compress::{decode_gzip, encode_gzip};
+#[cfg(feature = "zstd")]
+pub use compress::{decode_zstd, encode_zstd};
+pub use convert::{json_to_lua, json_to_lua_with_report, json_to_lua_with_stats, lua_to_json, lua_to_json_all_errors, lua_to_json_with_report, lua_to_json_with_stats};
+pub use http::{is_json_content_type, response_json_into_lua};
+#[cfg(feature = "reqwest")]
+pub use http::reqwest_response_into_lua;
+#[cfg(feature = "ini")]
+pub use ini::{ini_to_json, ini_to_lua, json_to_ini, json_to_properties, lua_to_ini, lua_to_properties, properties_to_json, properties_to_lua};
+pub use interned::{InternedValue, Interner, interned_to_lua, lua_to_interned};
+#[cfg(feature = "jq")]
+pub use jq::jq;
+pub use json_type::{JsonType, json_type_metatable, table_json_type};
+pub use defaults::decode_with_defaults;
+pub use diagnostics::{ConversionError, ConversionReport, Diagnostic, DiagnosticHandler, DiagnosticKind};
+pub use document::{SharedDocument, Transaction};
+pub use estimate::{estimate_json_size, estimate_lua_memory};
+pub use existing::{MergePolicy, json_into_existing_table};
+pub use file::{decode_file_into_lua, encode_lua_to_file};
+pub use flatten::{FlattenStyle, flatten, unflatten};
+pub use gc::GcHint;
+pub use generate::{generate_sample, generate_sample_into_lua};
+#[cfg(feature = "geojson")]
+pub use geo::{BoundingBox, Feature, Geometry, Position, as_feature_collection};
+pub use format::{FloatFormat, encode_lua_to_writer, lua_to_string, reformat, to_string};
+pub use jwt::{JwtError, decode_jwt};
+#[cfg(feature = "jwt-verify")]
+pub use jwt::{verify_jwt, verify_jwt_at};
+pub use known_keys::KeyReference;
+pub use lazy::LazyString;
+pub use memory::MemoryLimitExceeded;
+pub use module::{RegisterTarget, create_module, install_compat_loaders, install_loader, register, register_as, register_at};
+#[cfg(feature = "openapi")]
+pub use openapi::{OpenApiError, OpenApiSpec};
+#[cfg(feature = "ion")]
+pub use ion::{IonFormat, ion_to_json, ion_to_lua, json_to_ion, lua_to_ion};
+#[cfg(feature = "plist")]
+pub use plist::{PlistFormat, json_to_plist, lua_to_plist, plist_to_json, plist_to_lua};
+#[cfg(feature = "spreadsheet")]
+pub use spreadsheet::{SheetOptions, spreadsheet_to_json, spreadsheet_to_lua};
+#[cfg(feature = "arrow")]
+pub use arrow::{parquet_to_lua_iterator, record_batch_to_json, record_batch_to_lua, record_batches_to_lua_iterator};
+#[cfg(feature = "prost-reflect")]
+pub use proto::{json_to_message, lua_to_message, message_to_json, message_to_lua};
+#[cfg(feature = "ubjson")]
+pub use ubjson::{json_to_ubjson, lua_to_ubjson, ubjson_to_json, ubjson_to_lua};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{execute_with_lua, lua_to_sql_params, query_to_lua, row_to_json};
+pub use multipart::{Multipart, lua_to_multipart};
+pub use resp::{encode_resp_command, lua_to_command_args, resp_to_json, resp_to_lua};
+pub use options::{AliasPolicy, BigIntegerPolicy, ConversionOptions, FunctionPolicy, KeyOrder, MixedTablePolicy, SparseArrayPolicy, UnsupportedPolicy};
+#[cfg(feature = "luau")]
+pub use options::{BufferPolicy, VectorPolicy};
+pub use parse::{UntrustedLimits, decode_reader_into_lua, parse_into_lua, parse_json, parse_untrusted_into_lua};
+pub use path::{Path, PathPattern, PathSegment};
+pub use progress::{Progress, ProgressHandler};
+pub use raw::RawJson;
+pub use refs::{RefError, RefPolicy, ResolverOptions, json_to_lua_with_refs, resolve_refs};
+pub use registry::{Handle, JsonRegistry};
+pub use shape::{ShapePlan, json_to_lua_with_plan};
+pub use snapshot::Snapshots;
+pub use stats::ConversionStats;
+pub use stream::{StreamFormat, StreamLimits, stream_into_lua};
+pub use sample::sample;
+pub use scramble::{ScrambleOptions, scramble, scramble_lua};
+pub use select::{FieldSource, extract_fields, json_path_to_lua, jsonpath_to_lua, resolve_pointer, select_json_path};
+pub use template::{TEMPLATE_INSTRUCTION_BUDGET, TEMPLATE_MEMORY_LIMIT, render_template};
+pub use typed::{FieldError, from_lua_partial, from_lua_t};
+#[cfg(feature = "webhooks")]
+pub use webhook::{SignatureScheme, WebhookError, WebhookVerifier, canonical_json};
+pub use urlencoded::{json_to_urlencoded, lua_to_urlencoded, urlencoded_to_json, urlencoded_to_lua};
+pub use validate::{ScriptFacingError, Validator, json_to_lua_with_schema, lua_to_json_with_schema};
+
 /// Because you cannot impl an external trait for an external struct.
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde-derive", derive(Serialize, Deserialize))]
 pub struct JsonWrapperValue(JsonValue);
 
 impl Display for JsonWrapperValue {
@@ -30,96 +205,26 @@ impl From<JsonValue> for JsonWrapperValue {
     }
 }
 
-impl Into<JsonValue> for JsonWrapperValue {
-    fn into(self) -> JsonValue { self.0 }
+impl From<JsonWrapperValue> for JsonValue {
+    fn from(val: JsonWrapperValue) -> Self { val.0 }
 }
 
-impl<'lua> ToLua<'lua> for JsonWrapperValue {
+impl<'lua> IntoLua<'lua> for JsonWrapperValue {
     fn into_lua(self, lua: &'lua Lua) -> rlua::Result<rlua::Value<'lua>> {
-        let result = match self.into() {
-            JsonValue::Null => rlua::Value::Nil,
-            JsonValue::String(s) => s.as_str().into_lua(lua)?,
-            JsonValue::Number(n) => {
-
-                if let Some(ni) = n.as_i64() {
-                    return ni.into_lua(lua);
-                }
-
-                (
-                    n.as_f64().ok_or_else(|| rlua::Error::ToLuaConversionError {
-                        from: "JsonValue::Number",
-                        to: "Value::Number",
-                        message: None,
-                    })? as f64
-                ).into_lua(lua)?
-            },
-            JsonValue::Bool(b) => b.into_lua(lua)?,
-            JsonValue::Object(o) => {
-                let iter = o.into_iter()
-                    .map(|(k, v)| (k, JsonWrapperValue::new(v.clone())));
-                rlua::Value::Table(
-                    lua.create_table_from(iter)?
-                )
-            },
-            JsonValue::Array(a) => {
-                let iter = a.into_iter()
-                    .map(|it| JsonWrapperValue::new(it));
-                rlua::Value::Table(
-                    lua.create_table_from(iter.enumerate())?
-                )
-            },
-        };
-
-        Ok(result)
+        json_to_lua(lua, &self.0, &ConversionOptions::default())
     }
 }
 
 impl<'lua> FromLua<'lua> for JsonWrapperValue {
     fn from_lua(lua_value: rlua::Value<'lua>, lua: &'lua Lua) -> rlua::Result<Self> {
-        let result = match lua_value {
-            rlua::Value::Nil => JsonValue::Null,
-            rlua::Value::Boolean(b) => JsonValue::Bool(b),
-            rlua::Value::LightUserData(_) => return Err(
-                rlua::Error::FromLuaConversionError {
-                    from: "LightUserData", to: "JsonValue", message: Some("Impossible to convert".to_string()) }),
-            rlua::Value::Integer(i) => JsonValue::from(i),
-            rlua::Value::Number(n) => JsonValue::from(n),
-            rlua::Value::String(s) => JsonValue::from(s.to_str()?),
-            rlua::Value::Table(t) => {
-                let mut o = json!({});
-                for pair in t.pairs::<rlua::String, rlua::Value>() {
-                    let (key, value) = pair?;
-                    let key = key.to_str()?;
-                    let value = JsonWrapperValue::from_lua(value, lua)?.0;
-                    o
-                        .as_object_mut()
-                        .unwrap()
-                        .insert(key.to_string(), value);
-                }
-                o
-            }
-            rlua::Value::Function(_) => return Err(
-                rlua::Error::FromLuaConversionError {
-                    from: "Function", to: "JsonValue", message: Some("Impossible to convert".to_string()) }),
-            rlua::Value::Thread(_) => return Err(
-                rlua::Error::FromLuaConversionError {
-                    from: "Thread", to: "JsonValue", message: Some("Impossible to convert".to_string()) }),
-            rlua::Value::UserData(_) => return Err(
-                rlua::Error::FromLuaConversionError {
-                    from: "UserData", to: "JsonValue", message: Some("Impossible to convert".to_string()) }),
-            rlua::Value::Error(_) => return Err(
-                rlua::Error::FromLuaConversionError {
-                    from: "Error", to: "JsonValue", message: Some("Impossible to convert".to_string()) }),
-        };
-
-        return Ok( JsonWrapperValue(result) )
+        lua_to_json(lua, lua_value, &ConversionOptions::default()).map(JsonWrapperValue)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use serde_json::json;
-    use rlua::{Lua, ToLua, FromLua, Value};
+    use rlua::{Lua, IntoLua, FromLua, Value};
     use crate::JsonWrapperValue;
 
     #[test]
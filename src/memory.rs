@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use rlua::Lua;
+use crate::Path;
+
+/// The error of a decode that hit `lua_memory_limit`, found with `rlua::Error::downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryLimitExceeded {
+    /// Bytes the decode was allowed to add to the Lua heap.
+    pub limit: usize,
+    /// Where the allocation failed.
+    pub path: String,
+    /// Values converted before it.
+    pub values: usize,
+}
+
+impl Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: Lua memory limit of {} more bytes exceeded after {} values", self.path, self.limit, self.values)
+    }
+}
+
+impl Error for MemoryLimitExceeded {}
+
+/// Caps the Lua heap for the lifetime of one conversion, restoring the previous limit after it.
+pub(crate) struct MemoryLimit<'lua> {
+    lua: &'lua Lua,
+    extra: Option<usize>,
+    previous: Option<usize>,
+}
+
+impl<'lua> MemoryLimit<'lua> {
+    pub fn new(lua: &'lua Lua, extra: Option<usize>) -> Self {
+        MemoryLimit { lua, extra, previous: None }
+    }
+
+    /// Called once per converted value; sets the limit at the first one. A tighter limit the
+    /// host has set stays in force.
+    pub fn tick(&mut self) -> rlua::Result<()> {
+        let Some(extra) = self.extra else { return Ok(()) };
+        if self.previous.is_none() {
+            let limit = self.lua.used_memory().saturating_add(extra);
+            let previous = self.lua.set_memory_limit(limit)?;
+            if previous != 0 && previous < limit {
+                self.lua.set_memory_limit(previous)?;
+            }
+            self.previous = Some(previous);
+        }
+        Ok(())
+    }
+
+    /// `error` as [`MemoryLimitExceeded`] if it is an allocation failure under the limit.
+    pub fn exceeded(&self, error: rlua::Error, path: &Path, values: usize) -> rlua::Error {
+        match (&error, self.extra) {
+            (rlua::Error::MemoryError(_), Some(limit)) => rlua::Error::external(MemoryLimitExceeded { limit, path: path.to_string(), values }),
+            _ => error,
+        }
+    }
+}
+
+impl Drop for MemoryLimit<'_> {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            let _ = self.lua.set_memory_limit(previous);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "luajit")))]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, MemoryLimitExceeded, json_to_lua};
+
+    #[test]
+    fn lua_memory_limit() {
+        let lua = Lua::new();
+        let doc = json!((0..10_000).map(|i| json!({"name": format!("item {}", i)})).collect::<Vec<_>>());
+        let options = ConversionOptions { lua_memory_limit: Some(512 * 1024), ..Default::default() };
+        let error = json_to_lua(&lua, &doc, &options).expect_err("over the limit");
+        let exceeded = error.downcast_ref::<MemoryLimitExceeded>().expect("translated");
+        assert_eq!(exceeded.limit, 512 * 1024);
+        assert!(exceeded.values > 100 && exceeded.values < 10_000, "{}", exceeded);
+        assert!(exceeded.path.starts_with('/'), "{}", exceeded);
+        assert_eq!(lua.set_memory_limit(0).expect("unlimited again"), 0);
+
+        let options = ConversionOptions { lua_memory_limit: Some(16 * 1024 * 1024), ..Default::default() };
+        json_to_lua(&lua, &doc, &options).expect("under the limit");
+    }
+}
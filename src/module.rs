@@ -0,0 +1,478 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use rlua::{Lua, IntoLuaMulti};
+use serde_json::Value as JsonValue;
+use crate::arrays::{lua_group_by, lua_sort_by};
+use crate::file::allowed_path;
+use crate::json_type::value_type_name;
+use crate::keys::{json_keys, json_length};
+use crate::parse::decode_text;
+use crate::{ConversionOptions, FlattenStyle, FloatFormat, JsonType, KeyOrder, decode_file_into_lua, encode_lua_to_file, flatten, json_to_lua, json_type_metatable, lua_to_json, lua_to_string, reformat, unflatten, json_to_lua_with_refs, generate_sample_into_lua, render_template, sample, scramble_lua, lua_to_urlencoded, urlencoded_to_lua, lua_to_multipart, PathPattern, RawJson, ResolverOptions, ScrambleOptions};
+
+/// Byte offset (1-based, like dkjson reports it) of a serde_json error position.
+fn error_position(text: &str, e: &serde_json::Error) -> usize {
+    let line_start: usize = text.split_inclusive('\n')
+        .take(e.line().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    line_start + e.column().max(1)
+}
+
+/// The options are shared by the module functions, which are `Send` under mlua's `send` feature.
+pub(crate) fn lock(options: &Mutex<ConversionOptions>) -> MutexGuard<'_, ConversionOptions> {
+    options.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(any(feature = "ini", feature = "plist", feature = "ion", feature = "ubjson", feature = "gzip", feature = "zstd"))]
+pub(crate) type FormatDecode = for<'lua> fn(&'lua Lua, &[u8], &ConversionOptions) -> rlua::Result<rlua::Value<'lua>>;
+#[cfg(any(feature = "ini", feature = "plist", feature = "ion", feature = "ubjson", feature = "gzip", feature = "zstd"))]
+pub(crate) type FormatEncode<A> = for<'lua> fn(&'lua Lua, rlua::Value<'lua>, A, &ConversionOptions) -> rlua::Result<Vec<u8>>;
+
+/// The table of a format, such as `json.ion`: `decode(bytes)`, and `encode(value, ...)` with
+/// the arguments after the value passed to `encode` as `A`.
+#[cfg(any(feature = "ini", feature = "plist", feature = "ion", feature = "ubjson", feature = "gzip", feature = "zstd"))]
+pub(crate) fn format_table<'lua, A: rlua::FromLuaMulti<'lua> + 'static>(
+    lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>, decode: FormatDecode, encode: FormatEncode<A>,
+) -> rlua::Result<rlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    let decode_options = options.clone();
+    table.set("decode", lua.create_function(move |lua, bytes: rlua::String| {
+        let options = lock(&decode_options).clone();
+        decode(lua, bytes.as_bytes(), &options)
+    })?)?;
+    let encode_options = options.clone();
+    table.set("encode", lua.create_function(move |lua, mut args: rlua::MultiValue| {
+        let value = args.pop_front().unwrap_or(rlua::Value::Nil);
+        let options = lock(&encode_options).clone();
+        lua.create_string(encode(lua, value, A::from_lua_multi(args, lua)?, &options)?)
+    })?)?;
+    Ok(table)
+}
+
+/// Module options overridden by a per-call state table (`indent`, `ensure_ascii`, `escape_forward_slash`,
+/// and `sort_keys`: `true`, `"natural"` or `false`).
+fn with_state(options: &ConversionOptions, state: Option<rlua::Table>) -> rlua::Result<ConversionOptions> {
+    let mut options = options.clone();
+    if let Some(state) = state {
+        if let Some(indent) = state.get::<_, Option<bool>>("indent")? {
+            options.indent = indent;
+        }
+        if let Some(ensure_ascii) = state.get::<_, Option<bool>>("ensure_ascii")? {
+            options.ensure_ascii = ensure_ascii;
+        }
+        if let Some(escape) = state.get::<_, Option<bool>>("escape_forward_slash")? {
+            options.escape_forward_slash = escape;
+        }
+        match state.get::<_, rlua::Value>("sort_keys")? {
+            rlua::Value::Nil => {},
+            rlua::Value::Boolean(false) => options.sort_keys = KeyOrder::Unsorted,
+            rlua::Value::Boolean(true) => options.sort_keys = KeyOrder::Sorted,
+            rlua::Value::String(s) if s.as_bytes() == b"natural" => options.sort_keys = KeyOrder::Natural,
+            other => return Err(rlua::Error::RuntimeError(format!(
+                "sort_keys must be true, false or \"natural\", got {}", other.type_name()))),
+        }
+    }
+    Ok(options)
+}
+
+/// Builds a table with `encode`, `decode` and the null sentinel, bound to `options`.
+pub fn create_module<'lua>(lua: &'lua Lua, options: ConversionOptions) -> rlua::Result<rlua::Table<'lua>> {
+    let module = lua.create_table()?;
+    module.set(options.null_name.as_str(), rlua::Value::NULL)?;
+    if options.json_type_metatables {
+        module.set("array_mt", json_type_metatable(lua, JsonType::Array)?)?;
+        module.set("object_mt", json_type_metatable(lua, JsonType::Object)?)?;
+    }
+    let options = Arc::new(Mutex::new(options));
+
+    let encode_options = options.clone();
+    module.set("encode", lua.create_function(
+        move |lua, (value, state): (rlua::Value, Option<rlua::Table>)| {
+            let options = with_state(&lock(&encode_options), state)?;
+            lua_to_string(lua, value, &options)
+        })?)?;
+
+    let type_options = options.clone();
+    module.set("type", lua.create_function(move |_, value: rlua::Value| {
+        value_type_name(&value, &lock(&type_options))
+    })?)?;
+
+    let is_array_options = options.clone();
+    module.set("is_array", lua.create_function(move |_, value: rlua::Value| {
+        Ok(value_type_name(&value, &lock(&is_array_options))? == Some("array"))
+    })?)?;
+
+    let is_object_options = options.clone();
+    module.set("is_object", lua.create_function(move |_, value: rlua::Value| {
+        Ok(value_type_name(&value, &lock(&is_object_options))? == Some("object"))
+    })?)?;
+
+    let length_options = options.clone();
+    module.set("length", lua.create_function(move |_, value: rlua::Value| {
+        json_length(&value, &lock(&length_options))
+    })?)?;
+
+    let keys_options = options.clone();
+    module.set("keys", lua.create_function(move |lua, value: rlua::Value| {
+        json_keys(lua, &value, &lock(&keys_options))
+    })?)?;
+
+    module.set("raw", lua.create_function(|_, text: String| RawJson::new(text))?)?;
+
+    let decode_options = options.clone();
+    module.set("decode", lua.create_function(move |lua, text: rlua::String| {
+        let decode_options = lock(&decode_options);
+        let text = decode_text(text.as_bytes(), &decode_options)?;
+        match serde_json::from_str::<JsonValue>(&text) {
+            Ok(value) => json_to_lua(lua, &value, &decode_options)?.into_lua_multi(lua),
+            Err(e) if decode_options.decode_error_values =>
+                (rlua::Value::Nil, error_position(&text, &e), e.to_string()).into_lua_multi(lua),
+            Err(e) => Err(rlua::Error::external(e)),
+        }
+    })?)?;
+
+    let minify_options = options.clone();
+    module.set("minify", lua.create_function(move |_, text: rlua::String| {
+        let mut options = lock(&minify_options).clone();
+        options.indent = false;
+        reformat(text.as_bytes(), &options)
+    })?)?;
+
+    let reformat_options = options.clone();
+    module.set("reformat", lua.create_function(
+        move |_, (text, state): (rlua::String, Option<rlua::Table>)| {
+            reformat(text.as_bytes(), &with_state(&lock(&reformat_options), state)?)
+        })?)?;
+
+    let read_options = options.clone();
+    module.set("decode_file", lua.create_function(move |lua, path: String| {
+        let options = lock(&read_options).clone();
+        decode_file_into_lua(lua, allowed_path(&path, &options)?, &options)
+    })?)?;
+
+    let write_options = options.clone();
+    module.set("encode_file", lua.create_function(
+        move |lua, (path, value, state): (String, rlua::Value, Option<rlua::Table>)| {
+            let options = with_state(&lock(&write_options), state)?;
+            encode_lua_to_file(lua, value, allowed_path(&path, &options)?, &options)
+        })?)?;
+
+    let sort_options = options.clone();
+    module.set("sort_by", lua.create_function(move |lua, (array, pointer): (rlua::Table, String)| {
+        let options = lock(&sort_options).clone();
+        lua_sort_by(lua, array, &pointer, &options)
+    })?)?;
+
+    let group_options = options.clone();
+    module.set("group_by", lua.create_function(move |lua, (array, pointer): (rlua::Table, String)| {
+        let options = lock(&group_options).clone();
+        lua_group_by(lua, array, &pointer, &options)
+    })?)?;
+
+    let flatten_options = options.clone();
+    module.set("flatten", lua.create_function(move |lua, (value, separator): (rlua::Value, Option<String>)| {
+        let options = lock(&flatten_options).clone();
+        let style = FlattenStyle { separator: separator.unwrap_or_else(|| ".".to_string()), ..Default::default() };
+        json_to_lua(lua, &JsonValue::Object(flatten(&lua_to_json(lua, value, &options)?, &style)), &options)
+    })?)?;
+
+    let unflatten_options = options.clone();
+    module.set("unflatten", lua.create_function(move |lua, (value, separator): (rlua::Value, Option<String>)| {
+        let options = lock(&unflatten_options).clone();
+        let style = FlattenStyle { separator: separator.unwrap_or_else(|| ".".to_string()), ..Default::default() };
+        let JsonValue::Object(flat) = lua_to_json(lua, value, &options)? else {
+            return Err(rlua::Error::RuntimeError("unflatten: expected a table of paths".to_string()));
+        };
+        json_to_lua(lua, &unflatten(&flat, &style)?, &options)
+    })?)?;
+
+    #[cfg(feature = "jq")]
+    {
+        let jq_options = options.clone();
+        module.set("jq", lua.create_function(move |lua, (value, program): (rlua::Value, String)| {
+            let options = lock(&jq_options).clone();
+            let results = crate::jq(&crate::lua_to_json(lua, value, &options)?, &program)?;
+            let table = lua.create_table_with_capacity(results.len(), 0)?;
+            for result in &results {
+                table.raw_push(json_to_lua(lua, result, &options)?)?;
+            }
+            Ok(table)
+        })?)?;
+    }
+
+    let refs_options = options.clone();
+    module.set("resolve_refs", lua.create_function(move |lua, value: rlua::Value| {
+        let options = lock(&refs_options).clone();
+        let mut resolver = ResolverOptions { file_roots: options.file_roots.clone(), ..Default::default() };
+        if !options.file_extensions.is_empty() {
+            resolver.file_extensions = options.file_extensions.clone();
+        }
+        json_to_lua_with_refs(lua, &lua_to_json(lua, value, &options)?, &resolver, &options)
+    })?)?;
+
+    let render_options = options.clone();
+    module.set("render", lua.create_function(move |lua, (template, variables): (rlua::Value, Option<rlua::Table>)| {
+        let options = lock(&render_options).clone();
+        json_to_lua(lua, &render_template(lua, &lua_to_json(lua, template, &options)?, variables, &options)?, &options)
+    })?)?;
+
+    let generate_options = options.clone();
+    module.set("generate", lua.create_function(move |lua, (schema, seed): (rlua::Value, Option<u64>)| {
+        let options = lock(&generate_options).clone();
+        generate_sample_into_lua(lua, &lua_to_json(lua, schema, &options)?, seed.unwrap_or_default(), &options)
+    })?)?;
+
+    let scramble_options = options.clone();
+    module.set("scramble", lua.create_function(move |lua, (value, seed, keep): (rlua::Value, Option<u64>, Option<Vec<String>>)| {
+        let keep = keep.unwrap_or_default().iter().map(|pointer| PathPattern::parse(pointer)).collect();
+        let scramble = ScrambleOptions { seed: seed.unwrap_or_default(), keep, ..Default::default() };
+        scramble_lua(lua, value, &scramble, &lock(&scramble_options))
+    })?)?;
+
+    let sample_options = options.clone();
+    module.set("sample", lua.create_function(move |lua, (value, max_items, max_depth): (rlua::Value, Option<usize>, Option<usize>)| {
+        let options = lock(&sample_options).clone();
+        json_to_lua(lua, &sample(&lua_to_json(lua, value, &options)?, max_items.unwrap_or(10), max_depth.unwrap_or(8)), &options)
+    })?)?;
+
+    let urlencode_options = options.clone();
+    module.set("urlencode", lua.create_function(move |lua, value: rlua::Value| {
+        lua_to_urlencoded(lua, value, &lock(&urlencode_options))
+    })?)?;
+
+    let urldecode_options = options.clone();
+    module.set("urldecode", lua.create_function(move |lua, text: String| {
+        urlencoded_to_lua(lua, &text, &lock(&urldecode_options))
+    })?)?;
+
+    let multipart_options = options.clone();
+    module.set("multipart", lua.create_function(move |lua, (fields, boundary): (rlua::Table, Option<String>)| {
+        let multipart = lua_to_multipart(lua, fields, boundary.as_deref(), &lock(&multipart_options))?;
+        Ok((multipart.content_type, lua.create_string(&multipart.body)?))
+    })?)?;
+
+    module.set("rpc", crate::jsonrpc::create_rpc_table(lua, options.clone())?)?;
+    module.set("jwt", crate::jwt::create_jwt_table(lua, options.clone())?)?;
+    module.set("resp", crate::resp::create_resp_table(lua, options.clone())?)?;
+
+    #[cfg(feature = "geojson")]
+    module.set("geo", crate::geo::create_geo_table(lua, options.clone())?)?;
+
+    #[cfg(feature = "ini")]
+    {
+        module.set("ini", crate::ini::create_ini_table(lua, &options)?)?;
+        module.set("properties", crate::ini::create_properties_table(lua, &options)?)?;
+    }
+
+    #[cfg(feature = "openapi")]
+    module.set("openapi", crate::openapi::create_openapi_table(lua, options.clone())?)?;
+
+    #[cfg(feature = "plist")]
+    module.set("plist", crate::plist::create_plist_table(lua, &options)?)?;
+
+    #[cfg(feature = "gzip")]
+    module.set("gzip", crate::compress::create_gzip_table(lua, &options)?)?;
+
+    #[cfg(feature = "zstd")]
+    module.set("zstd", crate::compress::create_zstd_table(lua, &options)?)?;
+
+    #[cfg(feature = "ion")]
+    module.set("ion", crate::ion::create_ion_table(lua, &options)?)?;
+
+    #[cfg(feature = "ubjson")]
+    module.set("ubjson", crate::ubjson::create_ubjson_table(lua, &options)?)?;
+
+    let precision_options = options.clone();
+    module.set("encode_number_precision", lua.create_function(move |_, precision: usize| {
+        if !(1..=17).contains(&precision) {
+            return Err(rlua::Error::RuntimeError("bad precision (must be 1..17)".to_string()));
+        }
+        lock(&precision_options).float_format = FloatFormat::Precision(precision);
+        Ok(())
+    })?)?;
+
+    Ok(module)
+}
+
+/// Where [`register_at`] mounts the module.
+#[derive(Debug, Clone)]
+pub enum RegisterTarget<'lua> {
+    /// A global variable.
+    Global(String),
+    /// The functions and the null sentinel are copied into this table, e.g. a sandbox namespace.
+    Table(rlua::Table<'lua>),
+    /// `package.preload[key]`, so `require(key)` builds the module on first use. Luau has no
+    /// `package` library.
+    Preload(String),
+}
+
+/// Installs the module at `target`.
+pub fn register_at<'lua>(lua: &'lua Lua, target: RegisterTarget<'lua>, options: ConversionOptions) -> rlua::Result<()> {
+    match target {
+        RegisterTarget::Global(name) => lua.globals().set(name, create_module(lua, options)?),
+        RegisterTarget::Table(table) => {
+            for pair in create_module(lua, options)?.pairs::<rlua::Value, rlua::Value>() {
+                let (k, v) = pair?;
+                table.set(k, v)?;
+            }
+            Ok(())
+        },
+        RegisterTarget::Preload(key) => {
+            let package: rlua::Table = lua.globals().get("package")
+                .map_err(|_| rlua::Error::RuntimeError("the package library is not loaded".to_string()))?;
+            let preload: rlua::Table = package.get("preload")?;
+            preload.set(key, lua.create_function(move |lua, _: rlua::MultiValue| create_module(lua, options.clone()))?)
+        },
+    }
+}
+
+/// Makes `require "json"` return a module with default options, without setting any global.
+pub fn install_loader(lua: &Lua) -> rlua::Result<()> {
+    register_at(lua, RegisterTarget::Preload("json".to_string()), ConversionOptions::default())
+}
+
+/// [`install_loader`], plus `require "cjson"` and `require "dkjson"` bound to
+/// [`ConversionOptions::cjson`] and [`ConversionOptions::dkjson`].
+pub fn install_compat_loaders(lua: &Lua) -> rlua::Result<()> {
+    install_loader(lua)?;
+    register_at(lua, RegisterTarget::Preload("cjson".to_string()), ConversionOptions::cjson())?;
+    register_at(lua, RegisterTarget::Preload("dkjson".to_string()), ConversionOptions::dkjson())
+}
+
+/// Installs the module as the global `json`.
+pub fn register(lua: &Lua, options: ConversionOptions) -> rlua::Result<()> {
+    register_as(lua, "json", options)
+}
+
+/// Installs the module as the global `name`. Each call makes an independent module, so
+/// e.g. `json_strict` and `json_lossy` can live in one state with their own options.
+pub fn register_as(lua: &Lua, name: &str, options: ConversionOptions) -> rlua::Result<()> {
+    register_at(lua, RegisterTarget::Global(name.to_string()), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, MixedTablePolicy, register, register_as};
+
+    #[test]
+    fn dkjson_profile() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::dkjson()).expect("register");
+
+        let encoded: String = lua.load(r#"
+            local point = setmetatable({}, { __tojson = function(self, state)
+                return '"point"'
+            end })
+            return json.encode({ p = point, n = json.null, list = {1, 2} }, { indent = true })
+        "#).eval().expect("encode");
+        let value: serde_json::Value = serde_json::from_str(&encoded).expect("valid json");
+        assert_eq!(value, serde_json::json!({"p": "point", "n": null, "list": [1, 2]}));
+        assert!(encoded.contains('\n'));
+
+        let (ok, pos): (bool, usize) = lua.load(r#"
+            local obj, pos, err = json.decode('{"a": }')
+            return obj == nil and type(err) == "string", pos
+        "#).eval().expect("decode");
+        assert!(ok);
+        assert_eq!(pos, 7);
+    }
+
+    #[test]
+    fn encode_number_precision() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+
+        let encoded: String = lua.load(r#"
+            json.encode_number_precision(4)
+            return json.encode({ 1 / 3, 10 })
+        "#).eval().expect("encode");
+        assert_eq!(encoded, "[0.3333,10]");
+    }
+
+    #[test]
+    fn raw_passthrough() {
+        let lua = Lua::new();
+        let options = ConversionOptions { raw_paths: vec!["/payload".into()], ..Default::default() };
+        register(&lua, options).expect("register");
+
+        let encoded: String = lua.load(r#"
+            local doc = json.decode('{"id": 1, "payload": {"b": 1.50, "a": [ 1 ]}}')
+            assert(type(doc.payload) == "userdata" and type(doc.id) == "number")
+            doc.extra = json.raw('[1.0,  2]')
+            return json.encode({ doc.payload, doc.extra })
+        "#).eval().expect("eval");
+        match cfg!(feature = "preserve_order") {
+            true => assert_eq!(encoded, r#"[{"b":1.5,"a":[1]},[1.0,  2]]"#),
+            false => assert_eq!(encoded, r#"[{"a":[1],"b":1.5},[1.0,  2]]"#),
+        }
+    }
+
+    #[test]
+    fn minify_and_reformat() {
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+
+        let (minified, pretty): (String, String) = lua.load(r#"
+            local text = '{ "a" : [ 1, 2 ],\n "b": "é" }'
+            return json.minify(text), json.reformat(text, { indent = true, ensure_ascii = true })
+        "#).eval().expect("eval");
+        assert_eq!(minified, r#"{"a":[1,2],"b":"é"}"#);
+        assert_eq!(pretty, "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": \"\\u00e9\"\n}");
+    }
+
+    #[test]
+    fn independent_instances() {
+        let lua = Lua::new();
+        let strict = ConversionOptions { mixed_tables: MixedTablePolicy::Error, ..Default::default() };
+        register_as(&lua, "json_strict", strict).expect("register");
+        register_as(&lua, "json_lossy", ConversionOptions::default()).expect("register");
+
+        let (strict_ok, lossy): (bool, String) = lua.load(r#"
+            json_lossy.encode_number_precision(3)
+            local mixed = { 1, name = "x" }
+            return pcall(json_strict.encode, mixed), json_lossy.encode({ 1 / 3 })
+        "#).eval().expect("eval");
+        assert!(!strict_ok);
+        assert_eq!(lossy, "[0.333]");
+        assert_eq!(lua.load("return json_strict.encode({ 1 / 3 })").eval::<String>().expect("eval"), "[0.3333333333333333]");
+    }
+
+    #[test]
+    #[cfg(not(feature = "luau"))]
+    fn register_targets() {
+        use crate::{RegisterTarget, register_at};
+
+        let lua = Lua::new();
+        let api = lua.create_table().expect("table");
+        api.set("version", 1).expect("set");
+        lua.globals().set("api", api.clone()).expect("set global");
+        register_at(&lua, RegisterTarget::Table(api), ConversionOptions::default()).expect("table");
+        register_at(&lua, RegisterTarget::Preload("util.json".to_string()), ConversionOptions::default()).expect("preload");
+
+        let (encoded, same): (String, bool) = lua.load(r#"
+            local json = require("util.json")
+            return api.encode({ api.version }), json == require("util.json") and json.null == api.null
+        "#).eval().expect("eval");
+        assert_eq!(encoded, "[1]");
+        assert!(same);
+        assert!(lua.load("return json").eval::<rlua::Value>().expect("eval").is_nil());
+    }
+
+    #[test]
+    #[cfg(not(feature = "luau"))]
+    fn compat_loaders() {
+        use crate::install_compat_loaders;
+
+        let lua = Lua::new();
+        install_compat_loaders(&lua).expect("install");
+
+        let (json, cjson, dkjson_error): (String, String, bool) = lua.load(r#"
+            local value = { [1] = "a/b", [3] = 1 / 3 }
+            local _, _, err = require("dkjson").decode("[")
+            return require("json").encode(value), require("cjson").encode(value), type(err) == "string"
+        "#).eval().expect("eval");
+        assert_eq!(json, r#"{"1":"a/b","3":0.3333333333333333}"#);
+        assert_eq!(cjson, r#"["a\/b",null,0.33333333333333]"#);
+        assert!(dkjson_error);
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rlua::Lua;
+use crate::generate::Rng;
+use crate::{ConversionOptions, lua_to_string};
+
+/// A `multipart/form-data` request body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multipart {
+    /// `multipart/form-data; boundary=...`, for the `Content-Type` header.
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Quoted-string escaping of names in `Content-Disposition`, as browsers do it.
+fn quoted(text: &str) -> String {
+    text.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn random_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+    let mut rng = Rng::new(nanos ^ COUNTER.fetch_add(1, Ordering::Relaxed).rotate_left(32));
+    format!("rlua-json-{:016x}{:08x}", rng.next_u64(), rng.next_u64() as u32)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+fn json_part<'lua>(lua: &'lua Lua, name: String, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<Part> {
+    let data = lua_to_string(lua, value, options)?.into_bytes();
+    Ok(Part { name, filename: None, content_type: Some("application/json".to_string()), data })
+}
+
+fn parts_of<'lua>(lua: &'lua Lua, name: String, value: rlua::Value<'lua>, parts: &mut Vec<Part>, options: &ConversionOptions) -> rlua::Result<()> {
+    match value {
+        rlua::Value::String(s) => parts.push(Part { name, filename: None, content_type: None, data: s.as_bytes().to_vec() }),
+        rlua::Value::Integer(_) | rlua::Value::Number(_) | rlua::Value::Boolean(_) => {
+            parts.push(Part { name, filename: None, content_type: None, data: lua_to_string(lua, value, options)?.into_bytes() });
+        },
+        rlua::Value::Table(t) if t.contains_key("filename")? => parts.push(Part {
+            name,
+            filename: Some(t.get("filename")?),
+            content_type: Some(t.get::<_, Option<String>>("content_type")?.unwrap_or_else(|| "application/octet-stream".to_string())),
+            data: t.get::<_, rlua::String>("data")?.as_bytes().to_vec(),
+        }),
+        rlua::Value::Table(t) if t.raw_len() > 0 && t.clone().pairs::<rlua::Value, rlua::Value>().count() == t.raw_len() => {
+            for value in t.sequence_values::<rlua::Value>() {
+                match value? {
+                    rlua::Value::Table(element) if !element.contains_key("filename")? => {
+                        parts.push(json_part(lua, name.clone(), rlua::Value::Table(element), options)?);
+                    },
+                    value => parts_of(lua, name.clone(), value, parts, options)?,
+                }
+            }
+        },
+        rlua::Value::Nil => {},
+        other => parts.push(json_part(lua, name, other, options)?),
+    }
+    Ok(())
+}
+
+/// Builds a `multipart/form-data` body from a table of fields, in key order:
+///
+/// - strings, numbers and booleans become plain fields; strings are sent as they are, bytes
+///   and all;
+/// - a table with a `filename` is a file: its `data` string, with `content_type` (by default
+///   `application/octet-stream`);
+/// - a sequence repeats the field for each element;
+/// - any other table is sent as JSON, converted with `options`.
+///
+/// `boundary` is random if `None`; a given one that occurs in the content is an error.
+pub fn lua_to_multipart<'lua>(
+    lua: &'lua Lua, fields: rlua::Table<'lua>, boundary: Option<&str>, options: &ConversionOptions,
+) -> rlua::Result<Multipart> {
+    let mut fields = fields.pairs::<String, rlua::Value>().collect::<rlua::Result<Vec<_>>>()?;
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut parts = Vec::new();
+    for (name, value) in fields {
+        parts_of(lua, name, value, &mut parts, options)?;
+    }
+
+    let collides = |boundary: &str| parts.iter().any(|p| contains(&p.data, format!("--{}", boundary).as_bytes()));
+    let boundary = match boundary {
+        Some(boundary) if collides(boundary) => {
+            return Err(rlua::Error::RuntimeError(format!("multipart boundary {:?} occurs in the content", boundary)));
+        },
+        Some(boundary) => boundary.to_string(),
+        None => std::iter::repeat_with(random_boundary).find(|b| !collides(b)).unwrap_or_default(),
+    };
+
+    let mut body = Vec::new();
+    for part in &parts {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", boundary, quoted(&part.name)).as_bytes());
+        if let Some(filename) = &part.filename {
+            body.extend_from_slice(format!("; filename=\"{}\"", quoted(filename)).as_bytes());
+        }
+        if let Some(content_type) = &part.content_type {
+            body.extend_from_slice(format!("\r\nContent-Type: {}", content_type).as_bytes());
+        }
+        body.extend_from_slice(b"\r\n\r\n");
+        body.extend_from_slice(&part.data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(Multipart { content_type: format!("multipart/form-data; boundary={}", boundary), body })
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, lua_to_multipart, register};
+
+    #[test]
+    fn multipart_bodies() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let fields = lua.load(r#"{
+            title = 'Report "Q1"',
+            count = 3,
+            tags = { "a", "b" },
+            meta = { draft = true },
+            file = { filename = "data.bin", data = "\0\1\255" },
+            image = { filename = "a.png", content_type = "image/png", data = "PNG" },
+        }"#).eval().expect("fields");
+        let multipart = lua_to_multipart(&lua, fields, Some("XyZ"), &options).expect("multipart");
+        assert_eq!(multipart.content_type, "multipart/form-data; boundary=XyZ");
+        let mut expected = b"--XyZ\r\nContent-Disposition: form-data; name=\"count\"\r\n\r\n3\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"data.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n\0\x01\xff\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"image\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\nPNG\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"meta\"\r\nContent-Type: application/json\r\n\r\n{\"draft\":true}\r\n".to_vec();
+        expected.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"tags\"\r\n\r\na\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"tags\"\r\n\r\nb\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nReport \"Q1\"\r\n--XyZ--\r\n");
+        assert_eq!(multipart.body, expected);
+
+        let fields = lua.load(r#"{ text = "--XyZ" }"#).eval().expect("fields");
+        assert!(lua_to_multipart(&lua, fields, Some("XyZ"), &options).is_err());
+        let fields: rlua::Table = lua.load(r#"{ text = "--XyZ" }"#).eval().expect("fields");
+        let random = lua_to_multipart(&lua, fields, None, &options).expect("random boundary");
+        assert!(random.content_type.starts_with("multipart/form-data; boundary=rlua-json-"));
+
+        register(&lua, options).expect("register");
+        lua.load(r#"
+            local content_type, body = json.multipart({ name = "x" }, "b")
+            assert(content_type == "multipart/form-data; boundary=b")
+            assert(body == '--b\r\nContent-Disposition: form-data; name="name"\r\n\r\nx\r\n--b--\r\n', body)
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,254 @@
+//! OpenAPI 3 and Swagger 2 documents, for scripted API tests: schemas with their `$ref`s
+//! resolved, and request bodies built by scripts checked against an operation's schema. The
+//! `json.openapi` table of the module exposes the same to scripts.
+
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, RefPolicy, ResolverOptions, ScriptFacingError, json_to_lua, lua_to_json};
+use crate::module::lock;
+use crate::refs::resolved;
+
+const METHODS: [&str; 8] = ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// Why a spec could not be loaded or an operation used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpenApiError {
+    /// Neither JSON nor YAML, or not an object.
+    Parse(String),
+    UnknownOperation(String),
+    /// The operation takes no JSON request body.
+    NoRequestBody(String),
+    Invalid(ScriptFacingError),
+}
+
+impl Display for OpenApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenApiError::Parse(message) => write!(f, "openapi: {}", message),
+            OpenApiError::UnknownOperation(operation) => write!(f, "openapi: no operation {:?}", operation),
+            OpenApiError::NoRequestBody(operation) => write!(f, "openapi: {:?} takes no JSON request body", operation),
+            OpenApiError::Invalid(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for OpenApiError {}
+
+/// A loaded OpenAPI 3 or Swagger 2 document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenApiSpec {
+    document: JsonValue,
+}
+
+impl OpenApiSpec {
+    pub fn new(document: JsonValue) -> Result<Self, OpenApiError> {
+        match document {
+            JsonValue::Object(_) => Ok(OpenApiSpec { document }),
+            _ => Err(OpenApiError::Parse("the document is not an object".to_string())),
+        }
+    }
+
+    /// Loads a JSON or YAML document.
+    pub fn parse(text: &str) -> Result<Self, OpenApiError> {
+        let document = match serde_json::from_str(text) {
+            Ok(document) => document,
+            Err(_) => serde_yaml::from_str(text).map_err(|e| OpenApiError::Parse(e.to_string()))?,
+        };
+        OpenApiSpec::new(document)
+    }
+
+    pub fn document(&self) -> &JsonValue {
+        &self.document
+    }
+
+    /// `value` with every local `$ref` (`#/components/...`) replaced by a copy of its target, as
+    /// [`resolve_refs`](crate::resolve_refs) does. A reference back into one being expanded, and
+    /// one that cannot be followed, e.g. to another file, is left as it is. A value that would
+    /// expand past [`ResolverOptions::max_nodes`]'s default is returned unresolved.
+    pub fn resolve(&self, value: &JsonValue) -> JsonValue {
+        let options = ResolverOptions { cycles: RefPolicy::Keep, missing: RefPolicy::Keep, max_depth: usize::MAX, ..Default::default() };
+        resolved(&self.document, value, &options).unwrap_or_else(|_| value.clone())
+    }
+
+    /// The named schema of `components/schemas` (or Swagger 2 `definitions`), resolved.
+    pub fn schema(&self, name: &str) -> Option<JsonValue> {
+        let schemas = self.document.pointer("/components/schemas").or_else(|| self.document.get("definitions"))?;
+        Some(self.resolve(schemas.get(name)?))
+    }
+
+    pub fn schema_names(&self) -> Vec<&str> {
+        let schemas = self.document.pointer("/components/schemas").or_else(|| self.document.get("definitions"));
+        schemas.and_then(JsonValue::as_object).map(|s| s.keys().map(String::as_str).collect()).unwrap_or_default()
+    }
+
+    /// Every operation as `(operationId or "METHOD /path", operation object)`.
+    pub fn operations(&self) -> Vec<(String, &JsonValue)> {
+        let mut found = Vec::new();
+        for (path, item) in self.document.get("paths").and_then(JsonValue::as_object).into_iter().flatten() {
+            for method in METHODS {
+                if let Some(operation) = item.get(method) {
+                    let name = match operation.get("operationId").and_then(JsonValue::as_str) {
+                        Some(id) => id.to_string(),
+                        None => format!("{} {}", method.to_uppercase(), path),
+                    };
+                    found.push((name, operation));
+                }
+            }
+        }
+        found
+    }
+
+    /// An operation by its `operationId` or as `"POST /pets"`.
+    pub fn operation(&self, operation: &str) -> Option<&JsonValue> {
+        if let Some((method, path)) = operation.split_once(' ') {
+            let found = self.document.get("paths").and_then(|p| p.get(path)).and_then(|item| item.get(method.to_lowercase()));
+            if found.is_some() {
+                return found;
+            }
+        }
+        self.operations().into_iter().find(|(name, _)| name == operation).map(|(_, operation)| operation)
+    }
+
+    /// The resolved schema of the JSON request body of an operation: OpenAPI 3
+    /// `requestBody.content["application/json"].schema` or the Swagger 2 `in: body` parameter.
+    pub fn request_schema(&self, operation: &str) -> Result<JsonValue, OpenApiError> {
+        let found = self.operation(operation).ok_or_else(|| OpenApiError::UnknownOperation(operation.to_string()))?;
+        let found = self.resolve(found);
+        let schema = found.pointer("/requestBody/content")
+            .and_then(JsonValue::as_object)
+            .and_then(|content| content.iter().find(|(media_type, _)| media_type.starts_with("application/json")))
+            .and_then(|(_, media)| media.get("schema"))
+            .or_else(|| found.get("parameters").and_then(JsonValue::as_array).into_iter().flatten()
+                .find(|p| p.get("in").and_then(JsonValue::as_str) == Some("body"))
+                .and_then(|p| p.get("schema")));
+        schema.cloned().ok_or_else(|| OpenApiError::NoRequestBody(operation.to_string()))
+    }
+
+    /// Checks a request body against its operation's schema, with the keywords
+    /// [`Validator::schema`](crate::Validator::schema) supports.
+    pub fn validate_request(&self, operation: &str, body: &JsonValue) -> Result<(), OpenApiError> {
+        ScriptFacingError::check(&self.request_schema(operation)?, body).map_err(OpenApiError::Invalid)
+    }
+}
+
+fn spec_table<'lua>(lua: &'lua Lua, spec: Arc<OpenApiSpec>, options: Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    let table = lua.create_table()?;
+
+    let (schema_spec, schema_options) = (spec.clone(), options.clone());
+    table.set("schema", lua.create_function(move |lua, name: String| match schema_spec.schema(&name) {
+        Some(schema) => json_to_lua(lua, &schema, &lock(&schema_options).clone()),
+        None => Ok(rlua::Value::Nil),
+    })?)?;
+
+    let names_spec = spec.clone();
+    table.set("schema_names", lua.create_function(move |_, ()| {
+        Ok(names_spec.schema_names().into_iter().map(str::to_string).collect::<Vec<_>>())
+    })?)?;
+
+    let operations_spec = spec.clone();
+    table.set("operations", lua.create_function(move |_, ()| {
+        Ok(operations_spec.operations().into_iter().map(|(name, _)| name).collect::<Vec<_>>())
+    })?)?;
+
+    let (request_spec, request_options) = (spec.clone(), options.clone());
+    table.set("request_schema", lua.create_function(move |lua, operation: String| {
+        json_to_lua(lua, &request_spec.request_schema(&operation).map_err(rlua::Error::external)?, &lock(&request_options).clone())
+    })?)?;
+
+    table.set("validate", lua.create_function(move |lua, (operation, body): (String, rlua::Value)| {
+        let body = lua_to_json(lua, body, &lock(&options).clone())?;
+        match spec.validate_request(&operation, &body) {
+            Ok(()) => Ok((true, None)),
+            Err(OpenApiError::Invalid(error)) => Ok((false, Some(error.to_string()))),
+            Err(error) => Err(rlua::Error::external(error)),
+        }
+    })?)?;
+
+    Ok(table)
+}
+
+/// The `json.openapi` table: `load(text)` parses a JSON or YAML spec into a table of functions,
+/// `schema(name)`, `schema_names()`, `operations()`, `request_schema(operation)` and
+/// `validate(operation, body)`, which returns `true`, or `false` and a message.
+pub(crate) fn create_openapi_table<'lua>(lua: &'lua Lua, options: Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    let openapi = lua.create_table()?;
+    openapi.set("load", lua.create_function(move |lua, text: String| {
+        spec_table(lua, Arc::new(OpenApiSpec::parse(&text).map_err(rlua::Error::external)?), options.clone())
+    })?)?;
+    Ok(openapi)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, OpenApiError, OpenApiSpec, register};
+
+    const SPEC: &str = r##"
+openapi: 3.0.0
+info: {title: Pets, version: "1"}
+paths:
+  /pets:
+    post:
+      operationId: createPet
+      requestBody:
+        content:
+          application/json:
+            schema: {$ref: "#/components/schemas/Pet"}
+    get:
+      responses: {}
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      properties:
+        name: {type: string, minLength: 1}
+        tag: {$ref: "#/components/schemas/Tag"}
+        parent: {$ref: "#/components/schemas/Pet"}
+    Tag:
+      type: string
+      enum: [cat, dog]
+"##;
+
+    #[test]
+    fn specs() {
+        let spec = OpenApiSpec::parse(SPEC).expect("spec");
+        let pet = spec.schema("Pet").expect("Pet");
+        assert_eq!(pet.pointer("/properties/tag"), Some(&json!({"type": "string", "enum": ["cat", "dog"]})));
+        assert_eq!(pet.pointer("/properties/parent/properties/parent"), Some(&json!({"$ref": "#/components/schemas/Pet"})));
+        assert_eq!(spec.operations().into_iter().map(|(name, _)| name).collect::<Vec<_>>(), ["GET /pets", "createPet"]);
+        assert!(spec.operation("POST /pets").is_some());
+
+        assert!(spec.validate_request("createPet", &json!({"name": "Rex", "tag": "dog"})).is_ok());
+        match spec.validate_request("createPet", &json!({"name": "Rex", "tag": "cow"})) {
+            Err(OpenApiError::Invalid(error)) => assert_eq!(error.path.dotted(), "tag"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(spec.validate_request("GET /pets", &json!({})), Err(OpenApiError::NoRequestBody("GET /pets".to_string())));
+        assert_eq!(spec.validate_request("deletePet", &json!({})), Err(OpenApiError::UnknownOperation("deletePet".to_string())));
+
+        let swagger = OpenApiSpec::new(json!({"swagger": "2.0", "definitions": {"Id": {"type": "integer"}}, "paths": {"/x": {"put": {
+            "parameters": [{"in": "query", "name": "q"}, {"in": "body", "name": "body", "schema": {"$ref": "#/definitions/Id"}}],
+        }}}})).expect("swagger");
+        assert_eq!(swagger.request_schema("PUT /x"), Ok(json!({"type": "integer"})));
+        assert!(OpenApiSpec::parse("[1, 2]").is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.globals().set("text", SPEC).expect("text");
+        lua.load(r#"
+            local spec = json.openapi.load(text)
+            assert(spec.schema("Pet").properties.tag.enum[2] == "dog")
+            assert(spec.schema("Nope") == nil)
+            assert(#spec.operations() == 2 and #spec.schema_names() == 2)
+            assert(spec.request_schema("createPet").required[1] == "name")
+            assert(spec.validate("createPet", {name = "Rex"}))
+            local ok, message = spec.validate("createPet", {name = ""})
+            assert(not ok and message:find("too short"), message)
+            assert(not pcall(spec.validate, "nope", {}))
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use crate::{BoolEncoding, CancellationToken, DiagnosticHandler, EnumMapping, NumberFormat, FloatFormat, GcHint, KeyReference, PathPattern, ProgressHandler};
+
+/// What to do with a table whose keys are positive integers with holes, e.g. `{[1]=1, [2]=2, [5]=5}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SparseArrayPolicy {
+    /// Encode as an object with stringified integer keys.
+    Object,
+    /// Encode as an array padded with `null` if the highest index is at most `safe`
+    /// or at most `ratio` times the number of values; otherwise encode as an object (cjson).
+    Pad { ratio: usize, safe: usize },
+    /// Fail the conversion.
+    Error,
+}
+
+/// What to do with a table that has both array values and other keys, e.g. `{1, 2, 3, name = "x"}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MixedTablePolicy {
+    /// Fail the conversion.
+    Error,
+    /// Encode as one object; array indices become string keys.
+    ObjectWithNumericKeys,
+    /// Encode the array part under `items_key`: `{"items": [1, 2, 3], "name": "x"}`.
+    Split { items_key: String },
+}
+
+/// What to do with a JSON integer the Lua backend cannot represent exactly: beyond `i64`,
+/// or beyond 2^53 on LuaJIT, which has no native integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BigIntegerPolicy {
+    /// Round to the nearest float.
+    Float,
+    /// Deliver the decimal digits as a Lua string.
+    String,
+    /// Fail the conversion.
+    Error,
+    /// Deliver an `int64_t`/`uint64_t` cdata; the `ffi` library must be loaded.
+    #[cfg(feature = "luajit")]
+    Cdata,
+}
+
+/// What to do when the same Lua table appears more than once in the value being encoded.
+/// A table containing itself is an error unless references are enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasPolicy {
+    /// Encode every occurrence in full.
+    Duplicate,
+    /// Fail the conversion.
+    Error,
+    /// Encode later occurrences as `{"$ref": "#/pointer/to/first"}`; decoding with this
+    /// policy turns such objects back into shared tables.
+    Reference,
+}
+
+/// What to do with a Lua value JSON has no equivalent for: a function, a coroutine, a
+/// light userdata other than the null sentinel, a foreign userdata or an error value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedPolicy {
+    /// Fail the conversion.
+    Error,
+    /// Encode the result of Lua's `tostring`, e.g. `"function: 0x5581..."`.
+    ToString,
+    /// Encode as `{"$type": "function", "value": "function: 0x5581..."}`.
+    Tagged,
+    /// Leave object members out; encode array elements and the top-level value as `null`.
+    Skip,
+}
+
+/// Order of object keys in encoded JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// As `serde_json::Map` keeps them: byte order, or with the `preserve_order` feature the
+    /// decoded order where known and otherwise Lua's iteration order, which varies between runs.
+    Unsorted,
+    /// Byte order, the same on every run and platform.
+    Sorted,
+    /// Byte order, except that runs of ASCII digits compare by value: `item2` before `item10`.
+    /// Locale-independent.
+    Natural,
+}
+
+impl KeyOrder {
+    pub(crate) fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            KeyOrder::Unsorted => Ordering::Equal,
+            KeyOrder::Sorted => a.cmp(b),
+            KeyOrder::Natural => natural_cmp(a.as_bytes(), b.as_bytes()),
+        }
+    }
+}
+
+fn natural_cmp(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    loop {
+        match (a.first(), b.first()) {
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x_run, x_rest) = a.split_at(a.iter().take_while(|c| c.is_ascii_digit()).count());
+                let (y_run, y_rest) = b.split_at(b.iter().take_while(|c| c.is_ascii_digit()).count());
+                let x_digits = x_run.iter().position(|c| *c != b'0').map_or(&[][..], |i| x_run.split_at(i).1);
+                let y_digits = y_run.iter().position(|c| *c != b'0').map_or(&[][..], |i| y_run.split_at(i).1);
+                // Equal values: fewer leading zeros first, so distinct keys never compare equal.
+                let order = x_digits.len().cmp(&y_digits.len())
+                    .then_with(|| x_digits.cmp(y_digits))
+                    .then_with(|| x_run.len().cmp(&y_run.len()));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                (a, b) = (x_rest, y_rest);
+            },
+            (Some(x), Some(y)) if x == y => (a, b) = (a.split_at(1).1, b.split_at(1).1),
+            (x, y) => return x.cmp(&y),
+        }
+    }
+}
+
+/// What to do with Lua functions when encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionPolicy {
+    /// Handle them like other values without a JSON equivalent, per [`UnsupportedPolicy`].
+    Unsupported,
+    /// Leave object members out; encode array elements and the top-level value as `null`.
+    Skip,
+    /// Encode this string instead.
+    Placeholder(String),
+    /// Keep the function in the Lua registry and encode `{"$func": <handle>}`; decoding with
+    /// this policy in the same Lua state turns the object back into the function.
+    Handle,
+    /// Encode Lua functions as `{"$bytecode": "<base64 string.dump>"}`, which decoding with this
+    /// policy loads back, in any Lua state of the same version. Upvalues are not saved.
+    #[cfg(feature = "bytecode")]
+    Bytecode,
+}
+
+/// What to do with Luau `buffer` values.
+#[cfg(feature = "luau")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferPolicy {
+    /// Encode as `{"$type": "data", "value": "<base64>"}`, as the binary formats write bytes;
+    /// decoding with this policy turns such objects back into buffers.
+    Tagged,
+    /// Encode as a base64 string.
+    Base64,
+    /// Handle them like other values without a JSON equivalent, per [`UnsupportedPolicy`].
+    Unsupported,
+}
+
+/// What to do with Luau `vector` values when encoding.
+#[cfg(feature = "luau")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorPolicy {
+    /// Encode as `[x, y, z]`.
+    Array,
+    /// Encode as `{"x": x, "y": y, "z": z}`.
+    Object,
+    /// Handle them like other values without a JSON equivalent, per [`UnsupportedPolicy`].
+    Unsupported,
+}
+
+/// Settings shared by the Rust-side conversions and the Lua module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionOptions {
+    /// Key under which the null sentinel is published in the Lua module table.
+    pub null_name: String,
+    /// Call `__tojson(self, state)` metamethods when encoding; the returned string is embedded as JSON.
+    pub tojson_metamethod: bool,
+    /// Pretty-print encoded output.
+    pub indent: bool,
+    /// Make `decode` return `nil, position, message` instead of raising a Lua error.
+    pub decode_error_values: bool,
+    /// Handling of integer-keyed tables with holes.
+    pub sparse_arrays: SparseArrayPolicy,
+    /// Handling of tables with both an array part and other keys.
+    pub mixed_tables: MixedTablePolicy,
+    /// Encode set-like tables (`{a = true, b = true}`) at these locations as sorted arrays of
+    /// their keys, the reverse of `set_paths`. Scoped by location because any table of `true`
+    /// values is set-like, including flag objects such as `{enabled = true, visible = true}`.
+    pub set_array_paths: Vec<PathPattern>,
+    /// Booleans of a legacy API written as `1`/`0` or `"true"`/`"false"`: such values decode into
+    /// Lua booleans, and Lua booleans encode into them. Applies everywhere, so with
+    /// [`BoolEncoding::Integer`](crate::BoolEncoding::Integer) every `0` and `1` in the document
+    /// becomes a boolean; prefer `bool_paths` then.
+    pub bool_encoding: Option<BoolEncoding>,
+    /// Like `bool_encoding` at these locations, taking precedence over it.
+    pub bool_paths: Vec<(PathPattern, BoolEncoding)>,
+    /// Decode strings at these locations into the integers of the enumeration, and encode those
+    /// integers back into the names. Other values are left as they are, reported as
+    /// [`DiagnosticKind::UnknownEnumValue`](crate::DiagnosticKind::UnknownEnumValue) if they are
+    /// strings when decoding or integers when encoding.
+    pub enum_paths: Vec<(PathPattern, EnumMapping)>,
+    /// Decode strings at these locations, such as `"1,234.5"` or `"12%"`, into numbers per the
+    /// format, and encode numbers back into strings in it. Strings not in the format are left as
+    /// they are.
+    pub number_format_paths: Vec<(PathPattern, NumberFormat)>,
+    /// Decode arrays at these locations into set tables, e.g. `["a", "b"]` into `{a = true, b = true}`.
+    pub set_paths: Vec<PathPattern>,
+    /// Decode objects whose keys are all integers (`{"1": .., "2": .., "10": ..}`, as in tile maps
+    /// and sparse grids) into tables with integer keys, which encode back into objects with
+    /// numeric string keys rather than arrays.
+    pub numeric_keys: bool,
+    /// Decode arrays of objects with identical keys at these locations column-wise, e.g.
+    /// `[{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]` into `{id = {1, 2}, name = {"a", "b"}}`,
+    /// which is faster to scan than a table per row. `null` fields leave holes in their column;
+    /// other arrays there decode as usual. The result encodes as an object of arrays.
+    pub columnar_paths: Vec<PathPattern>,
+    /// Tag decoded tables with a shared `{__jsontype = "array" | "object"}` metatable and
+    /// honor the tag when encoding, so empty arrays and objects survive a round trip.
+    pub json_type_metatables: bool,
+    /// Encode Lua floats with an integral value that fits `i64` (e.g. `3.0`) as JSON integers.
+    /// JSON integers within `i64` always decode to Lua integers.
+    pub integral_floats_as_integers: bool,
+    /// Decoding of integers the backend cannot hold exactly (past `i64`, or past 2^53 on LuaJIT):
+    /// `Float` rounds them, reporting a [`LossyNumber`](crate::DiagnosticKind::LossyNumber)
+    /// diagnostic; `String` delivers their digits; `Error` fails the conversion. `Cdata`, with the
+    /// `luajit` feature, delivers a 64-bit cdata and fails unless the `ffi` library is loaded
+    /// (`StdLib::FFI`, which only the unsafe constructors such as `Lua::unsafe_new_with` load).
+    pub big_integers: BigIntegerPolicy,
+    /// How floats are written by [`to_string`](crate::to_string) and `json.encode`.
+    pub float_format: FloatFormat,
+    /// Order of object keys when encoding, for reproducible output (hashes, caches, diffs).
+    pub sort_keys: KeyOrder,
+    /// Write `/` as `\/` in strings.
+    pub escape_forward_slash: bool,
+    /// Write every non-ASCII character as a `\uXXXX` escape.
+    pub ensure_ascii: bool,
+    /// Accept JSON text with a UTF-8 byte order mark, and UTF-16LE/BE text (with or without BOM).
+    pub detect_encoding: bool,
+    /// Decode values at these locations into [`RawJson`](crate::RawJson) userdata holding compact
+    /// JSON text, instead of Lua tables; `json.encode` writes them back verbatim.
+    pub raw_paths: Vec<PathPattern>,
+    /// Decode strings longer than this many bytes into [`LazyString`](crate::LazyString) handles,
+    /// so large fields (embedded base64, logs) enter the Lua heap only when a script reads them.
+    pub lazy_strings: Option<usize>,
+    /// Decode arrays of at least this many numbers into zero-based `double[?]` cdata, built
+    /// through the `ffi` library (which the host must have opened) without a Lua table per
+    /// array. Scripts index them from 0; encoding them is unsupported. Not used while
+    /// `raw_paths`, `number_format_paths`, `bool_paths` or `bool_encoding` are set.
+    #[cfg(feature = "luajit")]
+    pub ffi_number_arrays: Option<usize>,
+    /// Handling of Luau buffers.
+    #[cfg(feature = "luau")]
+    pub buffers: BufferPolicy,
+    /// Handling of Luau vectors.
+    #[cfg(feature = "luau")]
+    pub vectors: VectorPolicy,
+    /// Called for lossy conversions and duplicate keys, with the affected path.
+    pub diagnostics: Option<DiagnosticHandler>,
+    /// Report object keys of encoded documents that this reference does not know as
+    /// [`DiagnosticKind::UnknownKey`](crate::DiagnosticKind::UnknownKey) diagnostics.
+    pub known_keys: Option<KeyReference>,
+    /// Convert identical arrays and objects of up to this many bytes of JSON into one shared
+    /// Lua table per conversion, whatever their key order. Shared tables are delivered as
+    /// read-only proxies, as with `read_only`, so a change to one copy cannot show up in the
+    /// others. Ignored when
+    /// `set_paths`, `raw_paths` or `columnar_paths` are set, since those make conversion depend
+    /// on location.
+    pub dedup_subtrees: Option<usize>,
+    /// Handling of a Lua table reached more than once while encoding.
+    pub aliases: AliasPolicy,
+    /// Deliver decoded arrays and objects as read-only proxies, so scripts cannot change
+    /// host-provided data; the encoder sees through them. Each proxy gets its own metatable.
+    pub read_only: bool,
+    /// Lua VM instructions that script hooks (`__tojson`, and `doc:on_change` listeners of a
+    /// [`SharedDocument`](crate::SharedDocument)) may run per conversion or write before it fails.
+    /// The budget is enforced with `Lua::set_hook`, replacing any hook the host has set. LuaJIT
+    /// only calls hooks from interpreted code, so there the JIT compiler is turned off and its
+    /// code flushed while hooks run, and turned back on after; a script that can reach the `jit`
+    /// library can turn it on itself and escape the budget.
+    pub hook_instruction_budget: Option<u32>,
+    /// Encoding of values JSON has no equivalent for (coroutines, foreign userdata, error values,
+    /// and functions unless `functions` says otherwise): an error by default, or their `tostring`
+    /// text, a tagged object or nothing.
+    pub unsupported_values: UnsupportedPolicy,
+    /// Encode Lua error values (e.g. the second result of a failed `pcall` of a Rust function) as
+    /// `{"$error": {"message": "...", "traceback": "..."}}` rather than per `unsupported_values`;
+    /// `traceback` is `null` when the error carries none.
+    pub structured_errors: bool,
+    /// Encoding of Lua functions. The default, `Unsupported`, leaves them to
+    /// `unsupported_values`; the handle and bytecode policies also decode back into functions.
+    pub functions: FunctionPolicy,
+    /// Directories `json.decode_file` and `json.encode_file` may access, and `json.resolve_refs`
+    /// may read referenced files from, after resolving symbolic links and `..`; none by default.
+    pub file_roots: Vec<PathBuf>,
+    /// File extensions (without the dot, compared case-insensitively) those functions may
+    /// access; any extension if empty.
+    pub file_extensions: Vec<String>,
+    /// Checked every few hundred converted values; once cancelled, conversions fail with
+    /// [`Cancelled`](crate::Cancelled). Parsing JSON text is not interrupted.
+    pub cancellation: Option<CancellationToken>,
+    /// Called every 1024 converted values, and while the reader entry points parse their input.
+    pub progress: Option<ProgressHandler>,
+    /// Bytes a decode may add to the Lua heap, enforced with `Lua::set_memory_limit` for its
+    /// duration, so untrusted documents cannot exhaust the host's memory through Lua. Exceeding
+    /// it fails with [`MemoryLimitExceeded`](crate::MemoryLimitExceeded); LuaJIT does not
+    /// support it.
+    pub lua_memory_limit: Option<usize>,
+    /// Garbage collector pacing during conversions; see [`GcHint`](crate::GcHint).
+    pub gc_hint: GcHint,
+    /// Buffer size of the reader and writer entry points.
+    pub io_buffer_size: usize,
+    /// Let [`json_to_lua`](crate::json_to_lua) and [`lua_to_json`](crate::lua_to_json) convert
+    /// through mlua's `LuaSerdeExt`, checking nesting, integral floats, known keys and lossy
+    /// integers around it. Calls whose other options mlua cannot express (location-dependent
+    /// decoding, metatables, non-default table policies, function policies) take the native
+    /// path, and so do values with sparse or mixed tables, which mlua would truncate.
+    /// Delegated encoding reports no NaN or skip diagnostics.
+    #[cfg(feature = "serde-delegate")]
+    pub delegate_to_mlua: bool,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            null_name: "null".to_string(),
+            tojson_metamethod: false,
+            indent: false,
+            decode_error_values: false,
+            sparse_arrays: SparseArrayPolicy::Object,
+            mixed_tables: MixedTablePolicy::ObjectWithNumericKeys,
+            set_array_paths: Vec::new(),
+            bool_encoding: None,
+            bool_paths: Vec::new(),
+            enum_paths: Vec::new(),
+            number_format_paths: Vec::new(),
+            set_paths: Vec::new(),
+            columnar_paths: Vec::new(),
+            numeric_keys: false,
+            json_type_metatables: false,
+            integral_floats_as_integers: false,
+            big_integers: BigIntegerPolicy::Float,
+            float_format: FloatFormat::Shortest,
+            sort_keys: KeyOrder::Unsorted,
+            escape_forward_slash: false,
+            ensure_ascii: false,
+            detect_encoding: false,
+            raw_paths: Vec::new(),
+            lazy_strings: None,
+            #[cfg(feature = "luajit")]
+            ffi_number_arrays: None,
+            #[cfg(feature = "luau")]
+            buffers: BufferPolicy::Tagged,
+            #[cfg(feature = "luau")]
+            vectors: VectorPolicy::Array,
+            diagnostics: None,
+            known_keys: None,
+            dedup_subtrees: None,
+            aliases: AliasPolicy::Duplicate,
+            read_only: false,
+            hook_instruction_budget: None,
+            unsupported_values: UnsupportedPolicy::Error,
+            structured_errors: false,
+            functions: FunctionPolicy::Unsupported,
+            file_roots: Vec::new(),
+            file_extensions: Vec::new(),
+            cancellation: None,
+            progress: None,
+            lua_memory_limit: None,
+            gc_hint: GcHint::Auto,
+            io_buffer_size: 8 * 1024,
+            #[cfg(feature = "serde-delegate")]
+            delegate_to_mlua: false,
+        }
+    }
+}
+
+impl ConversionOptions {
+    /// Behaves like [dkjson](http://dkolf.de/dkjson-lua/), for scripts that already target it.
+    pub fn dkjson() -> Self {
+        ConversionOptions {
+            tojson_metamethod: true,
+            decode_error_values: true,
+            ..Default::default()
+        }
+    }
+
+    /// Behaves like [lua-cjson](https://github.com/mpx/lua-cjson): sparse arrays are padded
+    /// when safe, `/` is escaped and numbers are written with 14 significant digits.
+    pub fn cjson() -> Self {
+        ConversionOptions {
+            sparse_arrays: SparseArrayPolicy::Pad { ratio: 2, safe: 10 },
+            escape_forward_slash: true,
+            float_format: FloatFormat::Precision(14),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::borrow::Cow;
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, estimate_lua_memory, json_to_lua};
+use crate::progress::CountingReader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Detects the encoding from a BOM or, since JSON text starts with ASCII, from zero bytes.
+/// Returns the encoding and the BOM length.
+fn sniff(input: &[u8]) -> (Encoding, usize) {
+    match input {
+        [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+        [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+        [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+        [0, a, ..] if *a != 0 => (Encoding::Utf16Be, 0),
+        [a, 0, ..] if *a != 0 => (Encoding::Utf16Le, 0),
+        _ => (Encoding::Utf8, 0),
+    }
+}
+
+fn utf16(input: &[u8], unit: fn([u8; 2]) -> u16) -> rlua::Result<String> {
+    if !input.len().is_multiple_of(2) {
+        return Err(rlua::Error::external("UTF-16 input has an odd number of bytes"));
+    }
+    let units = input.chunks_exact(2).map(|pair| unit([pair.first().copied().unwrap_or(0), pair.get(1).copied().unwrap_or(0)]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| rlua::Error::external(format!("invalid UTF-16 input: {}", e)))
+}
+
+/// Input bytes as UTF-8 text. With `options.detect_encoding` a UTF-8 BOM is skipped and
+/// UTF-16LE/BE input is transcoded; otherwise such input is rejected with an explicit message.
+pub(crate) fn decode_text<'t>(input: &'t [u8], options: &ConversionOptions) -> rlua::Result<Cow<'t, str>> {
+    let (encoding, bom) = sniff(input);
+    if !options.detect_encoding && (bom > 0 || encoding != Encoding::Utf8) {
+        return Err(rlua::Error::external(format!(
+            "input starts with a {:?} byte order mark or is UTF-16; enable detect_encoding", encoding)));
+    }
+    let body = input.get(bom..).unwrap_or_default();
+    match encoding {
+        Encoding::Utf8 => std::str::from_utf8(body)
+            .map(Cow::Borrowed)
+            .map_err(|e| rlua::Error::external(format!("invalid UTF-8 input: {}", e))),
+        Encoding::Utf16Le => utf16(body, u16::from_le_bytes).map(Cow::Owned),
+        Encoding::Utf16Be => utf16(body, u16::from_be_bytes).map(Cow::Owned),
+    }
+}
+
+/// Parses JSON text into a document, applying `options.detect_encoding`.
+pub fn parse_json(input: &[u8], options: &ConversionOptions) -> rlua::Result<JsonValue> {
+    serde_json::from_str(&decode_text(input, options)?).map_err(rlua::Error::external)
+}
+
+/// `options` reporting `bytes` of parsed input along with conversion progress.
+fn after_bytes(options: &ConversionOptions, bytes: u64) -> Cow<'_, ConversionOptions> {
+    match &options.progress {
+        Some(handler) => Cow::Owned(ConversionOptions { progress: Some(handler.after_bytes(bytes)), ..options.clone() }),
+        None => Cow::Borrowed(options),
+    }
+}
+
+/// Parses JSON text and converts it into a Lua value.
+pub fn parse_into_lua<'lua>(
+    lua: &'lua Lua, input: impl AsRef<[u8]>, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let input = input.as_ref();
+    json_to_lua(lua, &parse_json(input, options)?, &after_bytes(options, input.len() as u64))
+}
+
+/// [`parse_into_lua`] from a reader, e.g. a socket or a decompressor such as
+/// `flate2::read::GzDecoder`, through a buffer of `options.io_buffer_size` bytes.
+/// With `detect_encoding`, the whole input is read first to sniff it.
+pub fn decode_reader_into_lua<'lua>(
+    lua: &'lua Lua, reader: impl std::io::Read, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let reader = CountingReader { inner: reader, handler: options.progress.as_ref(), bytes: 0 };
+    let mut reader = std::io::BufReader::with_capacity(options.io_buffer_size, reader);
+    if options.detect_encoding {
+        let mut input = Vec::new();
+        std::io::Read::read_to_end(&mut reader, &mut input).map_err(rlua::Error::external)?;
+        return parse_into_lua(lua, input, options);
+    }
+    let value: JsonValue = serde_json::from_reader(&mut reader).map_err(rlua::Error::external)?;
+    json_to_lua(lua, &value, &after_bytes(options, reader.get_ref().bytes))
+}
+
+/// Limits of [`parse_untrusted_into_lua`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UntrustedLimits {
+    /// Length of the input.
+    pub max_bytes: usize,
+    /// Nesting of arrays and objects; the parser itself stops at 128.
+    pub max_depth: usize,
+    /// Lua heap the result may take, per [`estimate_lua_memory`].
+    pub max_lua_bytes: usize,
+}
+
+impl Default for UntrustedLimits {
+    fn default() -> Self {
+        UntrustedLimits { max_bytes: 1024 * 1024, max_depth: 64, max_lua_bytes: 16 * 1024 * 1024 }
+    }
+}
+
+fn depth(value: &JsonValue) -> usize {
+    match value {
+        JsonValue::Array(a) => 1 + a.iter().map(depth).max().unwrap_or(0),
+        JsonValue::Object(o) => 1 + o.values().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// [`parse_into_lua`] for adversarial input, e.g. from the network or a fuzzer. It returns an
+/// error rather than panicking on any input, like every entry point of this crate, and checks
+/// `limits` before creating any Lua value.
+pub fn parse_untrusted_into_lua<'lua>(
+    lua: &'lua Lua, input: &[u8], limits: &UntrustedLimits, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    if input.len() > limits.max_bytes {
+        return Err(rlua::Error::RuntimeError(format!("input is larger than {} bytes", limits.max_bytes)));
+    }
+    let value = parse_json(input, options)?;
+    if depth(&value) > limits.max_depth {
+        return Err(rlua::Error::RuntimeError(format!("input is nested deeper than {} levels", limits.max_depth)));
+    }
+    let estimate = estimate_lua_memory(&value);
+    if estimate > limits.max_lua_bytes {
+        return Err(rlua::Error::RuntimeError(format!(
+            "input would take about {} bytes of Lua memory, more than {}", estimate, limits.max_lua_bytes)));
+    }
+    json_to_lua(lua, &value, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, UntrustedLimits, lua_to_json, parse_into_lua, parse_untrusted_into_lua};
+
+    #[test]
+    fn bom_and_utf16() {
+        let lua = Lua::new();
+        let options = ConversionOptions { detect_encoding: true, ..Default::default() };
+        let text = r#"{"name": "Zoë"}"#;
+
+        let mut utf8 = vec![0xEF, 0xBB, 0xBF];
+        utf8.extend_from_slice(text.as_bytes());
+        let mut le = vec![0xFF, 0xFE];
+        le.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+
+        for input in [utf8.clone(), le, be] {
+            let value = parse_into_lua(&lua, &input, &options).expect("parse");
+            assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), json!({"name": "Zoë"}));
+        }
+
+        let error = parse_into_lua(&lua, &utf8, &ConversionOptions::default()).expect_err("BOM rejected");
+        assert!(error.to_string().contains("detect_encoding"));
+    }
+
+    #[test]
+    fn untrusted_input() {
+        let lua = Lua::new();
+        let limits = UntrustedLimits { max_bytes: 64, max_depth: 3, max_lua_bytes: 1024 };
+        let options = ConversionOptions { detect_encoding: true, ..Default::default() };
+        let inputs: [&[u8]; 8] = [b"", b"\xff\xfe\x00", b"\xfe", b"[[[[1]]]]", b"{\"a\":", b"1e999", b"\"\\ud800\"", &[b'['; 65]];
+        for input in inputs {
+            assert!(parse_untrusted_into_lua(&lua, input, &limits, &options).is_err(), "{:?}", input);
+        }
+        let value = parse_untrusted_into_lua(&lua, br#"{"a": [1, 2]}"#, &limits, &options).expect("parse");
+        assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), json!({"a": [1, 2]}));
+        let wide = format!("[{}]", ["\"xxxxxxxxxxxxxxxx\""; 3].join(","));
+        let limits = UntrustedLimits { max_lua_bytes: 100, ..Default::default() };
+        assert!(parse_untrusted_into_lua(&lua, wide.as_bytes(), &limits, &options).is_err());
+    }
+}
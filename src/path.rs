@@ -0,0 +1,108 @@
+use std::fmt::{Display, Formatter};
+
+/// One step into a JSON document.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Location inside a JSON document, displayed as a JSON Pointer (`/items/0/name`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Path(Vec<PathSegment>);
+
+impl Path {
+    pub fn new() -> Self {
+        Path(Vec::new())
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn push(&mut self, segment: PathSegment) {
+        self.0.push(segment);
+    }
+
+    pub fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Script-style rendering, e.g. `graphics.resolution[1]`; empty for the root.
+    pub(crate) fn dotted(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            match segment {
+                PathSegment::Key(k) if out.is_empty() => out.push_str(k),
+                PathSegment::Key(k) => {
+                    out.push('.');
+                    out.push_str(k);
+                },
+                PathSegment::Index(i) => out.push_str(&format!("[{}]", i)),
+            }
+        }
+        out
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('~', "~0").replace('/', "~1")
+}
+
+pub(crate) fn unescape(s: &str) -> String {
+    s.replace("~1", "/").replace("~0", "~")
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.0 {
+            match segment {
+                PathSegment::Key(k) => write!(f, "/{}", escape(k))?,
+                PathSegment::Index(i) => write!(f, "/{}", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A JSON Pointer where a `*` segment matches any key or index, e.g. `/users/*/tags`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern(Vec<Option<String>>);
+
+impl PathPattern {
+    pub fn parse(pointer: &str) -> Self {
+        let segments = pointer.split('/')
+            .skip(1)
+            .map(|s| if s == "*" { None } else { Some(unescape(s)) })
+            .collect();
+        PathPattern(segments)
+    }
+
+    /// Unescaped segments; `None` is a wildcard.
+    pub(crate) fn segments(&self) -> &[Option<String>] {
+        &self.0
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.0.len() == path.0.len()
+            && self.0.iter().zip(&path.0).all(|(pattern, segment)| match (pattern, segment) {
+                (None, _) => true,
+                (Some(p), PathSegment::Key(k)) => p == k,
+                (Some(p), PathSegment::Index(i)) => p.parse() == Ok(*i),
+            })
+    }
+}
+
+impl From<&str> for PathPattern {
+    fn from(pointer: &str) -> Self {
+        PathPattern::parse(pointer)
+    }
+}
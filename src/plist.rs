@@ -0,0 +1,181 @@
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua, lua_to_json};
+use crate::module::format_table;
+
+/// How [`json_to_plist`] writes a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlistFormat {
+    /// `<?xml ...?><plist version="1.0">`, as Xcode writes `Info.plist`.
+    #[default]
+    Xml,
+    /// `bplist00`, as most preference files are stored.
+    Binary,
+}
+
+fn plist_error(message: impl std::fmt::Display) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("plist: {}", message))
+}
+
+/// Values JSON has no type for, in the shape `UnsupportedPolicy::Tagged` writes.
+fn tagged(type_name: &str, value: JsonValue) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("$type".to_string(), JsonValue::from(type_name));
+    o.insert("value".to_string(), value);
+    JsonValue::Object(o)
+}
+
+fn from_plist(value: plist::Value) -> rlua::Result<JsonValue> {
+    Ok(match value {
+        plist::Value::Array(a) => JsonValue::Array(a.into_iter().map(from_plist).collect::<rlua::Result<_>>()?),
+        plist::Value::Dictionary(d) => JsonValue::Object(d.into_iter().map(|(k, v)| Ok((k, from_plist(v)?))).collect::<rlua::Result<_>>()?),
+        plist::Value::Boolean(b) => JsonValue::Bool(b),
+        plist::Value::Data(bytes) => tagged("data", JsonValue::String(base64::encode(&bytes))),
+        plist::Value::Date(date) => tagged("date", JsonValue::String(date.to_xml_format())),
+        plist::Value::Real(n) => Number::from_f64(n).map(JsonValue::Number).ok_or_else(|| plist_error(format!("{} has no JSON form", n)))?,
+        plist::Value::Integer(n) => match (n.as_signed(), n.as_unsigned()) {
+            (Some(n), _) => JsonValue::from(n),
+            (None, Some(n)) => JsonValue::from(n),
+            (None, None) => return Err(plist_error("integer out of range")),
+        },
+        plist::Value::String(s) => JsonValue::String(s),
+        plist::Value::Uid(uid) => tagged("uid", JsonValue::from(uid.get())),
+        _ => return Err(plist_error("unsupported value")),
+    })
+}
+
+fn to_plist(value: &JsonValue) -> rlua::Result<plist::Value> {
+    Ok(match value {
+        JsonValue::Null => return Err(plist_error("null has no plist form")),
+        JsonValue::Bool(b) => plist::Value::Boolean(*b),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => plist::Value::Integer(n.into()),
+            (None, Some(n)) => plist::Value::Integer(n.into()),
+            _ => plist::Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => plist::Value::String(s.clone()),
+        JsonValue::Array(a) => plist::Value::Array(a.iter().map(to_plist).collect::<rlua::Result<_>>()?),
+        JsonValue::Object(o) if o.len() == 2 && o.contains_key("value") => match (o.get("$type").and_then(JsonValue::as_str), o.get("value")) {
+            (Some("data"), Some(JsonValue::String(text))) => {
+                plist::Value::Data(base64::decode(text).ok_or_else(|| plist_error(format!("invalid base64 data {:?}", text)))?)
+            },
+            (Some("date"), Some(JsonValue::String(text))) => {
+                plist::Value::Date(plist::Date::from_xml_format(text).map_err(|_| plist_error(format!("invalid date {:?}", text)))?)
+            },
+            (Some("uid"), Some(JsonValue::Number(n))) => {
+                plist::Value::Uid(plist::Uid::new(n.as_u64().ok_or_else(|| plist_error(format!("invalid uid {}", n)))?))
+            },
+            _ => dictionary(o)?,
+        },
+        JsonValue::Object(o) => dictionary(o)?,
+    })
+}
+
+fn dictionary(o: &Map<String, JsonValue>) -> rlua::Result<plist::Value> {
+    Ok(plist::Value::Dictionary(o.iter().map(|(k, v)| Ok((k.clone(), to_plist(v)?))).collect::<rlua::Result<_>>()?))
+}
+
+/// Parses an XML or binary plist, whichever `bytes` is. Dates become
+/// `{"$type": "date", "value": "2024-05-01T12:00:00Z"}`, data
+/// `{"$type": "data", "value": "<base64>"}` and keyed-archive UIDs `{"$type": "uid", "value": 7}`.
+pub fn plist_to_json(bytes: &[u8]) -> rlua::Result<JsonValue> {
+    from_plist(plist::Value::from_reader(Cursor::new(bytes)).map_err(plist_error)?)
+}
+
+/// Writes a plist, reading the tagged dates, data and UIDs [`plist_to_json`] produces back into
+/// their plist types. `null` has no plist form and is an error.
+pub fn json_to_plist(value: &JsonValue, format: PlistFormat) -> rlua::Result<Vec<u8>> {
+    let value = to_plist(value)?;
+    let mut bytes = Vec::new();
+    match format {
+        PlistFormat::Xml => value.to_writer_xml(&mut bytes),
+        PlistFormat::Binary => value.to_writer_binary(&mut bytes),
+    }.map_err(plist_error)?;
+    Ok(bytes)
+}
+
+/// [`plist_to_json`], converted into a Lua table with `options`.
+pub fn plist_to_lua<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &plist_to_json(bytes)?, options)
+}
+
+/// [`json_to_plist`] of a Lua value, converted with `options`.
+pub fn lua_to_plist<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, format: PlistFormat, options: &ConversionOptions,
+) -> rlua::Result<Vec<u8>> {
+    json_to_plist(&lua_to_json(lua, value, options)?, format)
+}
+
+/// The `json.plist` table: `decode(bytes)` and `encode(value, "xml" | "binary")`.
+pub(crate) fn create_plist_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options, plist_to_lua, |lua, value, format: Option<String>, options| {
+        let format = match format.as_deref() {
+            None | Some("xml") => PlistFormat::Xml,
+            Some("binary") => PlistFormat::Binary,
+            Some(other) => return Err(plist_error(format!("unknown format {:?}, expected \"xml\" or \"binary\"", other))),
+        };
+        lua_to_plist(lua, value, format, options)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, PlistFormat, json_to_plist, plist_to_json, register};
+
+    const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleIdentifier</key>
+    <string>com.example.app</string>
+    <key>LSRequiresIPhoneOS</key>
+    <true/>
+    <key>Build</key>
+    <integer>42</integer>
+    <key>Scale</key>
+    <real>1.5</real>
+    <key>Released</key>
+    <date>2024-05-01T12:00:00Z</date>
+    <key>Icon</key>
+    <data>AAEC/w==</data>
+    <key>Orientations</key>
+    <array><string>portrait</string></array>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn plists() {
+        let value = plist_to_json(INFO_PLIST.as_bytes()).expect("xml");
+        let expected = json!({
+            "CFBundleIdentifier": "com.example.app", "LSRequiresIPhoneOS": true, "Build": 42, "Scale": 1.5,
+            "Released": {"$type": "date", "value": "2024-05-01T12:00:00Z"},
+            "Icon": {"$type": "data", "value": "AAEC/w=="},
+            "Orientations": ["portrait"],
+        });
+        assert_eq!(value, expected);
+        let binary = json_to_plist(&value, PlistFormat::Binary).expect("binary");
+        assert!(binary.starts_with(b"bplist00"));
+        assert_eq!(plist_to_json(&binary).expect("reparse binary"), expected);
+        let xml = json_to_plist(&value, PlistFormat::Xml).expect("xml");
+        assert!(String::from_utf8_lossy(&xml).contains("<date>2024-05-01T12:00:00Z</date>"));
+        assert_eq!(plist_to_json(&xml).expect("reparse xml"), expected);
+
+        assert!(json_to_plist(&json!({"a": null}), PlistFormat::Xml).is_err());
+        assert!(json_to_plist(&json!({"$type": "date", "value": "yesterday"}), PlistFormat::Xml).is_err());
+        assert!(plist_to_json(b"not a plist").is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local bytes = json.plist.encode({ name = "x", blob = { ["$type"] = "data", value = "AQI=" } }, "binary")
+            assert(bytes:sub(1, 8) == "bplist00")
+            local value = json.plist.decode(bytes)
+            assert(value.name == "x" and value.blob["$type"] == "data" and value.blob.value == "AQI=")
+            assert(json.plist.encode({ n = 1 }):find("<integer>1</integer>", 1, true))
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt::{Debug, Formatter};
+use std::io::Read;
+use std::sync::Arc;
+
+/// Values converted between progress reports.
+const REPORT_INTERVAL: u64 = 1024;
+
+/// How far a conversion has got, e.g. for a loading screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Values converted so far, counting every array element, object entry and scalar.
+    pub values: u64,
+    /// Input bytes parsed so far by the entry points that read JSON text; 0 for the others.
+    pub bytes: u64,
+}
+
+/// Receives [`Progress`] during long conversions.
+#[derive(Clone)]
+pub struct ProgressHandler(pub Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressHandler {
+    pub fn new(f: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        ProgressHandler(Arc::new(f))
+    }
+
+    /// A handler reporting `bytes` already consumed along with the values of a conversion.
+    pub(crate) fn after_bytes(&self, bytes: u64) -> Self {
+        let inner = self.0.clone();
+        ProgressHandler::new(move |p| inner(&Progress { bytes, ..*p }))
+    }
+}
+
+impl Debug for ProgressHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressHandler")
+    }
+}
+
+impl PartialEq for ProgressHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Reports every [`REPORT_INTERVAL`] values of one conversion.
+pub(crate) struct ProgressReporter {
+    handler: Option<ProgressHandler>,
+    values: u64,
+}
+
+impl ProgressReporter {
+    pub fn new(handler: Option<&ProgressHandler>) -> Self {
+        ProgressReporter { handler: handler.cloned(), values: 0 }
+    }
+
+    /// Called once per converted value.
+    pub fn tick(&mut self) {
+        self.advance(1);
+    }
+
+    /// Counts `n` values converted at once, e.g. by a fast path for the elements of an array.
+    pub fn advance(&mut self, n: u64) {
+        let Some(handler) = &self.handler else { return };
+        let before = self.values / REPORT_INTERVAL;
+        self.values += n;
+        if self.values / REPORT_INTERVAL > before {
+            (handler.0)(&Progress { values: self.values, bytes: 0 });
+        }
+    }
+}
+
+/// Reports the bytes read through it, once per read of the underlying reader.
+pub(crate) struct CountingReader<'h, R> {
+    pub inner: R,
+    pub handler: Option<&'h ProgressHandler>,
+    pub bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        if let Some(handler) = self.handler {
+            (handler.0)(&Progress { values: 0, bytes: self.bytes });
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, Progress, ProgressHandler, decode_reader_into_lua, lua_to_json};
+
+    #[test]
+    fn progress() {
+        let lua = Lua::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let options = ConversionOptions {
+            progress: Some(ProgressHandler::new(move |p| sink.lock().unwrap().push(*p))),
+            io_buffer_size: 1024,
+            ..Default::default()
+        };
+        let doc = json!((0..1500).map(|i| json!({"i": i})).collect::<Vec<_>>());
+        let text = doc.to_string();
+        let value = decode_reader_into_lua(&lua, text.as_bytes(), &options).expect("decode");
+
+        let total = text.len() as u64;
+        let reports = seen.lock().unwrap().split_off(0);
+        let (reading, converting): (Vec<Progress>, Vec<Progress>) = reports.into_iter().partition(|p| p.values == 0);
+        assert!(reading.len() > 1);
+        assert!(reading.windows(2).all(|w| w[0].bytes <= w[1].bytes));
+        assert_eq!(reading.last().map(|p| p.bytes), Some(total));
+        assert_eq!(converting, [1024, 2048].map(|values| Progress { values, bytes: total }));
+
+        lua_to_json(&lua, value, &options).expect("encode");
+        assert_eq!(*seen.lock().unwrap(), [1024, 2048].map(|values| Progress { values, bytes: 0 }));
+    }
+}
@@ -0,0 +1,142 @@
+//! Generators and round-trip assertions for fuzzing converter configurations with proptest.
+//!
+//! Generated documents round-trip under [`ConversionOptions::default()`]: they hold no `null`
+//! (it becomes `nil`) and no empty arrays or objects (they become `{}`), and integers stay
+//! within ±2^53 so every backend represents them exactly.
+
+use std::fmt::Write;
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+const MAX_SAFE_INTEGER: i64 = 1 << 53;
+
+/// Booleans, integers, finite floats and arbitrary strings.
+pub fn json_scalar() -> impl Strategy<Value = JsonValue> {
+    prop_oneof![
+        any::<bool>().prop_map(JsonValue::Bool),
+        (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).prop_map(JsonValue::from),
+        any::<f64>().prop_filter("finite", |f| f.is_finite()).prop_map(JsonValue::from),
+        ".{0,16}".prop_map(JsonValue::String),
+    ]
+}
+
+/// Documents up to `depth` levels of non-empty arrays and objects deep.
+pub fn json_document(depth: u32) -> impl Strategy<Value = JsonValue> {
+    json_scalar().prop_recursive(depth, 64, 6, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 1..6).prop_map(JsonValue::Array),
+        prop::collection::btree_map(".{0,8}", inner, 1..6).prop_map(|o| JsonValue::Object(o.into_iter().collect())),
+    ])
+}
+
+/// Documents paired with Lua source that evaluates to the equivalent value.
+pub fn json_and_lua(depth: u32) -> impl Strategy<Value = (JsonValue, String)> {
+    json_document(depth).prop_map(|value| {
+        let source = lua_source(&value);
+        (value, source)
+    })
+}
+
+fn lua_string(out: &mut String, s: &str) {
+    out.push('"');
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            },
+            b' '..=b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", b);
+            },
+        }
+    }
+    out.push('"');
+}
+
+fn write_lua(out: &mut String, value: &JsonValue) {
+    match value {
+        JsonValue::Null => out.push_str("nil"),
+        JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => {
+                let _ = write!(out, "{}", i);
+            },
+            // `{:?}` writes the shortest text that parses back to the same float, with a `.` or exponent.
+            (None, Some(f)) => {
+                let _ = write!(out, "({:?})", f);
+            },
+            (None, None) => out.push_str("nil"),
+        },
+        JsonValue::String(s) => lua_string(out, s),
+        JsonValue::Array(a) => {
+            out.push('{');
+            for v in a {
+                write_lua(out, v);
+                out.push(',');
+            }
+            out.push('}');
+        },
+        JsonValue::Object(o) => {
+            out.push('{');
+            for (k, v) in o {
+                out.push('[');
+                lua_string(out, k);
+                out.push_str("]=");
+                write_lua(out, v);
+                out.push(',');
+            }
+            out.push('}');
+        },
+    }
+}
+
+/// A Lua expression for `value`: arrays become sequences, `null` becomes `nil`.
+pub fn lua_source(value: &JsonValue) -> String {
+    let mut out = String::new();
+    write_lua(&mut out, value);
+    out
+}
+
+fn failed(e: rlua::Error) -> TestCaseError {
+    TestCaseError::fail(e.to_string())
+}
+
+/// Converts `value` to Lua and back, failing the test case unless the result is equal.
+pub fn assert_json_round_trip(lua: &Lua, value: &JsonValue, options: &ConversionOptions) -> Result<(), TestCaseError> {
+    let converted = json_to_lua(lua, value, options).map_err(failed)?;
+    let back = lua_to_json(lua, converted, options).map_err(failed)?;
+    prop_assert_eq!(&back, value);
+    Ok(())
+}
+
+/// Evaluates `source`, failing the test case unless it converts to `expected` and back to an equal Lua value.
+pub fn assert_lua_round_trip(
+    lua: &Lua, source: &str, expected: &JsonValue, options: &ConversionOptions,
+) -> Result<(), TestCaseError> {
+    let value: rlua::Value = lua.load(source).eval().map_err(failed)?;
+    let json = lua_to_json(lua, value, options).map_err(failed)?;
+    prop_assert_eq!(&json, expected);
+    let back = json_to_lua(lua, &json, options).map_err(failed)?;
+    prop_assert_eq!(&lua_to_json(lua, back, options).map_err(failed)?, expected);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rlua::Lua;
+    use crate::ConversionOptions;
+    use super::{assert_json_round_trip, assert_lua_round_trip, json_and_lua};
+
+    proptest! {
+        #[test]
+        fn default_options_round_trip((value, source) in json_and_lua(4)) {
+            let lua = Lua::new();
+            assert_json_round_trip(&lua, &value, &ConversionOptions::default())?;
+            assert_lua_round_trip(&lua, &source, &value, &ConversionOptions::default())?;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+/// A message in its proto3 JSON form: lowerCamelCase field names, enums by name, 64-bit
+/// integers as strings, bytes as base64, well-known types (`Timestamp`, `Duration`, `Struct`,
+/// wrappers) as their special forms, and fields left at their default value omitted.
+pub fn message_to_json(message: &DynamicMessage) -> rlua::Result<JsonValue> {
+    serde_json::to_value(message).map_err(rlua::Error::external)
+}
+
+/// The message of type `descriptor` a proto3 JSON value describes. Both lowerCamelCase and
+/// original field names are accepted, and 64-bit integers as numbers or strings; an unknown
+/// field is an error.
+pub fn json_to_message(value: JsonValue, descriptor: MessageDescriptor) -> rlua::Result<DynamicMessage> {
+    DynamicMessage::deserialize(descriptor, value).map_err(rlua::Error::external)
+}
+
+/// [`message_to_json`], converted into a Lua table with `options`.
+pub fn message_to_lua<'lua>(lua: &'lua Lua, message: &DynamicMessage, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &message_to_json(message)?, options)
+}
+
+/// [`json_to_message`] of a Lua table, converted with `options`.
+pub fn lua_to_message<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, descriptor: MessageDescriptor, options: &ConversionOptions,
+) -> rlua::Result<DynamicMessage> {
+    json_to_message(lua_to_json(lua, value, options)?, descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_reflect::prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+        field_descriptor_proto::{Label, Type},
+    };
+    use prost_reflect::{DescriptorPool, Value};
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_message, lua_to_message, message_to_json, message_to_lua};
+
+    fn field(name: &str, number: i32, kind: Type, label: Label, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()), number: Some(number), r#type: Some(kind.into()), label: Some(label.into()),
+            type_name: type_name.map(str::to_string), ..Default::default()
+        }
+    }
+
+    fn pool() -> DescriptorPool {
+        let file = FileDescriptorProto {
+            name: Some("shop.proto".to_string()), package: Some("shop".to_string()), syntax: Some("proto3".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Item".to_string()),
+                field: vec![
+                    field("display_name", 1, Type::String, Label::Optional, None),
+                    field("id", 2, Type::Int64, Label::Optional, None),
+                    field("tags", 3, Type::String, Label::Repeated, None),
+                    field("kind", 4, Type::Enum, Label::Optional, Some(".shop.Kind")),
+                    field("data", 5, Type::Bytes, Label::Optional, None),
+                ],
+                ..Default::default()
+            }],
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("Kind".to_string()),
+                value: ["KIND_UNSPECIFIED", "KIND_BOOK"].iter().enumerate().map(|(i, name)| EnumValueDescriptorProto {
+                    name: Some(name.to_string()), number: Some(i as i32), ..Default::default()
+                }).collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: vec![file] }).expect("pool")
+    }
+
+    #[test]
+    fn dynamic_messages() {
+        let descriptor = pool().get_message_by_name("shop.Item").expect("descriptor");
+        let message = json_to_message(json!({"display_name": "Dune", "id": 7, "tags": ["sf"], "kind": "KIND_BOOK", "data": "AAE="}), descriptor.clone())
+            .expect("message");
+        assert_eq!(message.get_field_by_name("id").as_deref(), Some(&Value::I64(7)));
+        assert_eq!(message_to_json(&message).expect("json"), json!({
+            "displayName": "Dune", "id": "7", "tags": ["sf"], "kind": "KIND_BOOK", "data": "AAE=",
+        }));
+        assert!(json_to_message(json!({"price": 1}), descriptor.clone()).is_err());
+        assert!(json_to_message(json!({"kind": "KIND_FILM"}), descriptor.clone()).is_err());
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let table = message_to_lua(&lua, &message, &options).expect("lua");
+        lua.globals().set("item", table).expect("set");
+        let edited = lua.load(r#"
+            assert(item.displayName == "Dune" and item.kind == "KIND_BOOK" and item.tags[1] == "sf")
+            item.displayName = "Dune Messiah"
+            item.id = 8
+            return item
+        "#).eval().expect("edit");
+        let edited = lua_to_message(&lua, edited, descriptor, &options).expect("message");
+        assert_eq!(edited.get_field_by_name("display_name").as_deref(), Some(&Value::String("Dune Messiah".to_string())));
+        assert_eq!(edited.get_field_by_name("id").as_deref(), Some(&Value::I64(8)));
+    }
+}
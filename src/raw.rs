@@ -0,0 +1,70 @@
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::value::RawValue;
+use serde_json::Value as JsonValue;
+use crate::KeyOrder;
+
+/// JSON text embedded verbatim when encoding; created in Lua by `json.raw(text)`
+/// or by decoding a document with [`ConversionOptions::raw_paths`](crate::ConversionOptions::raw_paths).
+#[derive(Debug, Clone)]
+pub struct RawJson(pub Box<RawValue>);
+
+impl RawJson {
+    /// Checks that `text` is a single JSON value, without building a document from it.
+    pub fn new(text: String) -> rlua::Result<Self> {
+        RawValue::from_string(text).map(RawJson).map_err(rlua::Error::external)
+    }
+
+    pub fn get(&self) -> &str {
+        self.0.get()
+    }
+}
+
+impl rlua::UserData for RawJson {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(rlua::MetaMethod::ToString, |_, this, ()| Ok(this.get().to_string()));
+    }
+}
+
+/// While encoding to text, raw fragments are left in the document as `{RAW_KEY: index}`.
+pub(crate) const RAW_KEY: &str = "$rlua_json::raw";
+
+pub(crate) fn raw_index(value: &JsonValue) -> Option<usize> {
+    match value {
+        JsonValue::Object(o) if o.len() == 1 => o.get(RAW_KEY)?.as_u64().map(|i| i as usize),
+        _ => None,
+    }
+}
+
+/// Serializes a document, replacing raw markers with their fragments.
+pub(crate) struct WithRaw<'a> {
+    pub value: &'a JsonValue,
+    pub raws: &'a [Box<RawValue>],
+    pub order: KeyOrder,
+}
+
+impl<'a> Serialize for WithRaw<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some(raw) = raw_index(self.value).and_then(|i| self.raws.get(i)) {
+            return raw.serialize(serializer);
+        }
+        match self.value {
+            JsonValue::Array(a) => {
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for value in a {
+                    seq.serialize_element(&WithRaw { value, ..*self })?;
+                }
+                seq.end()
+            },
+            JsonValue::Object(o) => {
+                let mut entries: Vec<_> = o.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| self.order.compare(a, b));
+                let mut map = serializer.serialize_map(Some(o.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, &WithRaw { value, ..*self })?;
+                }
+                map.end()
+            },
+            other => other.serialize(serializer),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use rlua::Lua;
+
+const VIEW_KEY: &str = "__jsonview";
+
+fn contents<'lua>(proxy: &rlua::Table<'lua>) -> rlua::Result<rlua::Table<'lua>> {
+    view_contents(proxy)?.ok_or_else(|| rlua::Error::RuntimeError("not a read-only JSON view".to_string()))
+}
+
+/// The metamethods shared by every view of this Lua state, cached in the registry.
+fn view_metamethods(lua: &Lua) -> rlua::Result<rlua::Table<'_>> {
+    const REGISTRY_KEY: &str = "rlua_json.readonly_mt";
+    if let Some(methods) = lua.named_registry_value::<Option<rlua::Table>>(REGISTRY_KEY)? {
+        return Ok(methods);
+    }
+    let methods = lua.create_table()?;
+    methods.set("__newindex", lua.create_function(|_, (_, key): (rlua::Table, rlua::Value)| -> rlua::Result<()> {
+        let key = match key {
+            rlua::Value::String(s) => s.to_string_lossy().into_owned(),
+            key => format!("<{}>", key.type_name()),
+        };
+        Err(rlua::Error::RuntimeError(format!("attempt to modify read-only JSON data (key {:?})", key)))
+    })?)?;
+    methods.set("__len", lua.create_function(|_, proxy: rlua::Table| Ok(contents(&proxy)?.raw_len()))?)?;
+    methods.set("__pairs", lua.create_function(|lua, proxy: rlua::Table| {
+        Ok((lua.globals().raw_get::<_, rlua::Function>("next")?, contents(&proxy)?, rlua::Value::Nil))
+    })?)?;
+    lua.set_named_registry_value(REGISTRY_KEY, methods.clone())?;
+    Ok(methods)
+}
+
+/// Wraps `table` in an empty proxy that reads through to it and raises a Lua error on
+/// assignment. `getmetatable` on the proxy returns a string, so scripts cannot unwrap it.
+/// `next` and `rawget` see the empty proxy; `pairs`, `ipairs`, `#` and indexing see the data
+/// (under LuaJIT only indexing and `ipairs` do, since Lua 5.1 ignores `__pairs` and `__len` on tables).
+pub(crate) fn read_only_view<'lua>(lua: &'lua Lua, table: rlua::Table<'lua>) -> rlua::Result<rlua::Table<'lua>> {
+    let mt = lua.create_table()?;
+    for pair in view_metamethods(lua)?.pairs::<rlua::Value, rlua::Value>() {
+        let (k, v) = pair?;
+        mt.raw_set(k, v)?;
+    }
+    if let Some(json_type) = table.get_metatable().map(|mt| mt.raw_get::<_, rlua::Value>("__jsontype")).transpose()? {
+        mt.raw_set("__jsontype", json_type)?;
+    }
+    mt.raw_set("__index", table.clone())?;
+    mt.raw_set(VIEW_KEY, table)?;
+    mt.raw_set("__metatable", "read-only")?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(mt));
+    Ok(proxy)
+}
+
+/// The table behind a view made by [`read_only_view`].
+pub(crate) fn view_contents<'lua>(table: &rlua::Table<'lua>) -> rlua::Result<Option<rlua::Table<'lua>>> {
+    match table.get_metatable() {
+        Some(mt) => mt.raw_get(VIEW_KEY),
+        None => Ok(None),
+    }
+}
+
+#[cfg(all(test, not(any(feature = "luajit", feature = "lua51", feature = "luau"))))]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+    #[test]
+    fn read_only_views() {
+        let lua = Lua::new();
+        let options = ConversionOptions { read_only: true, ..Default::default() };
+        let value = json!({"name": "x", "items": [1, 2, {"deep": true}]});
+        lua.globals().set("data", json_to_lua(&lua, &value, &options).expect("convert")).expect("set global");
+
+        let (len, keys): (i64, i64) = lua.load(r#"
+            local keys = 0
+            for _ in pairs(data) do keys = keys + 1 end
+            return #data.items, keys
+        "#).eval().expect("read");
+        assert_eq!((len, keys), (3, 2));
+
+        for script in ["data.name = 'y'", "data.items[1] = 0", "data.items[3].deep = false", "setmetatable(data, nil)"] {
+            assert!(lua.load(script).exec().is_err(), "{} should fail", script);
+        }
+        let data = lua.globals().get("data").expect("get global");
+        assert_eq!(lua_to_json(&lua, data, &options).expect("encode"), value);
+    }
+}
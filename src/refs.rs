@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, json_to_lua, resolve_pointer};
+
+/// What [`resolve_refs`] does with a `$ref` it cannot replace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefPolicy {
+    /// Fail with a [`RefError`].
+    Error,
+    /// Leave the `$ref` object in place.
+    Keep,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverOptions {
+    /// Directories file references (`other.json#/definitions/x`) may read from, after resolving
+    /// symbolic links and `..`; with none, file references are denied.
+    pub file_roots: Vec<PathBuf>,
+    /// Extensions (without the dot, compared case-insensitively) referenced files may have;
+    /// any if empty. `json` by default.
+    pub file_extensions: Vec<String>,
+    /// What relative file references in the document itself are relative to; references in a
+    /// referenced file are relative to that file. The working directory if `None`.
+    pub base_dir: Option<PathBuf>,
+    /// A reference into a target being expanded, which cannot be inlined into a tree.
+    pub cycles: RefPolicy,
+    /// A reference that cannot be followed: a pointer to nothing, a URL, or a file outside the
+    /// roots or that cannot be read.
+    pub missing: RefPolicy,
+    /// How many references may be expanded within one another.
+    pub max_depth: usize,
+    /// How many values the resolved document may hold, counting every copy of a target, against
+    /// documents that blow up exponentially: `a` referencing `b` twice, `b` referencing `c`
+    /// twice, and so on. Checked as each reference is expanded.
+    pub max_nodes: usize,
+}
+
+impl Default for ResolverOptions {
+    fn default() -> Self {
+        ResolverOptions {
+            file_roots: Vec::new(),
+            file_extensions: vec!["json".to_string()],
+            base_dir: None,
+            cycles: RefPolicy::Error,
+            missing: RefPolicy::Error,
+            max_depth: 64,
+            max_nodes: 1_000_000,
+        }
+    }
+}
+
+/// Why a `$ref` could not be resolved; each holds the reference as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefError {
+    Unresolved(String),
+    /// A file outside the roots, or any file without roots.
+    Denied(String),
+    Io { reference: String, message: String },
+    Cycle(String),
+    TooDeep(String),
+    /// Expanding the reference would take the document past `max_nodes`.
+    TooLarge(String),
+}
+
+impl Display for RefError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefError::Unresolved(reference) => write!(f, "$ref {:?} does not resolve", reference),
+            RefError::Denied(reference) => write!(f, "$ref {:?}: access is not allowed", reference),
+            RefError::Io { reference, message } => write!(f, "$ref {:?}: {}", reference, message),
+            RefError::Cycle(reference) => write!(f, "$ref {:?} refers to itself", reference),
+            RefError::TooDeep(reference) => write!(f, "$ref {:?} is nested too deeply", reference),
+            RefError::TooLarge(reference) => write!(f, "$ref {:?} expands the document past its size limit", reference),
+        }
+    }
+}
+
+impl std::error::Error for RefError {}
+
+/// `path` made absolute against the working directory, with `.` and `..` removed without
+/// following symbolic links.
+fn lexically_normalized(path: &Path) -> PathBuf {
+    let path = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir().unwrap_or_default().join(path),
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// A document references are resolved in: the one being resolved, or a file.
+type DocumentId = Option<PathBuf>;
+
+struct Resolver<'a> {
+    root: &'a JsonValue,
+    options: &'a ResolverOptions,
+    files: HashMap<PathBuf, JsonValue>,
+    /// The targets being expanded, innermost last.
+    expanding: Vec<(DocumentId, String)>,
+    /// The values produced so far.
+    nodes: usize,
+}
+
+impl Resolver<'_> {
+    fn document(&self, id: &DocumentId) -> Option<&JsonValue> {
+        match id {
+            None => Some(self.root),
+            Some(path) => self.files.get(path),
+        }
+    }
+
+    /// Reads the file `reference` names, relative to the document it is in.
+    fn load(&mut self, reference: &str, file: &str, from: &DocumentId) -> Result<PathBuf, RefError> {
+        let denied = || RefError::Denied(reference.to_string());
+        if file.contains("://") {
+            return Err(RefError::Unresolved(reference.to_string()));
+        }
+        let base = match from {
+            Some(path) => path.parent().map(PathBuf::from),
+            None => self.options.base_dir.clone(),
+        };
+        // Roots as written and as resolved, so that a root reached through a symbolic link
+        // matches both the path as referenced and the file it resolves to.
+        let roots: Vec<PathBuf> = self.options.file_roots.iter()
+            .flat_map(|root| [fs::canonicalize(root).ok(), Some(lexically_normalized(root))])
+            .flatten()
+            .collect();
+        let allowed = |path: &Path| {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            roots.iter().any(|root| path.starts_with(root))
+                && (self.options.file_extensions.is_empty()
+                    || self.options.file_extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(extension)))
+        };
+        // Checked before touching the file, so a denied reference does not tell whether it exists.
+        let path = lexically_normalized(&base.unwrap_or_default().join(file));
+        if !allowed(&path) {
+            return Err(denied());
+        }
+        let path = fs::canonicalize(&path).map_err(|e| RefError::Io { reference: reference.to_string(), message: e.to_string() })?;
+        if !allowed(&path) {
+            return Err(denied());
+        }
+        if !self.files.contains_key(&path) {
+            let io = |message: String| RefError::Io { reference: reference.to_string(), message };
+            let text = fs::read_to_string(&path).map_err(|e| io(e.to_string()))?;
+            self.files.insert(path.clone(), serde_json::from_str(&text).map_err(|e| io(e.to_string()))?);
+        }
+        Ok(path)
+    }
+
+    /// The document and pointer `reference` leads to, or `None` to keep it.
+    fn target(&mut self, reference: &str, from: &DocumentId) -> Result<Option<(DocumentId, String)>, RefError> {
+        let (file, pointer) = reference.split_once('#').unwrap_or((reference, ""));
+        let document = match file {
+            "" => from.clone(),
+            file => Some(self.load(reference, file, from)?),
+        };
+        let key = (document, pointer.to_string());
+        if self.expanding.contains(&key) {
+            return match self.options.cycles {
+                RefPolicy::Error => Err(RefError::Cycle(reference.to_string())),
+                RefPolicy::Keep => Ok(None),
+            };
+        }
+        if self.expanding.len() >= self.options.max_depth {
+            return Err(RefError::TooDeep(reference.to_string()));
+        }
+        if self.nodes >= self.options.max_nodes {
+            return Err(RefError::TooLarge(reference.to_string()));
+        }
+        Ok(Some(key))
+    }
+
+    fn resolve(&mut self, value: &JsonValue, from: &DocumentId) -> Result<JsonValue, RefError> {
+        self.nodes += 1;
+        match value {
+            JsonValue::Object(o) => {
+                let Some(reference) = o.get("$ref").and_then(JsonValue::as_str) else {
+                    return o.iter().map(|(k, v)| Ok((k.clone(), self.resolve(v, from)?))).collect::<Result<_, _>>().map(JsonValue::Object);
+                };
+                let missing = |error| match self.options.missing {
+                    RefPolicy::Error => Err(error),
+                    RefPolicy::Keep => Ok(value.clone()),
+                };
+                let key = match self.target(reference, from) {
+                    Ok(Some(key)) => key,
+                    Ok(None) => return Ok(value.clone()),
+                    Err(error @ (RefError::Unresolved(_) | RefError::Denied(_) | RefError::Io { .. })) => return missing(error),
+                    Err(error) => return Err(error),
+                };
+                let target = self.document(&key.0).and_then(|document| resolve_pointer(document, &key.1)).map(|(_, t)| t.clone());
+                let Some(target) = target else { return missing(RefError::Unresolved(reference.to_string())) };
+                let document = key.0.clone();
+                self.expanding.push(key);
+                let resolved = self.resolve(&target, &document);
+                self.expanding.pop();
+                let mut resolved = resolved?;
+                if let JsonValue::Object(resolved) = &mut resolved {
+                    for (k, v) in o.iter().filter(|(k, _)| *k != "$ref") {
+                        resolved.insert(k.clone(), self.resolve(v, from)?);
+                    }
+                }
+                Ok(resolved)
+            },
+            JsonValue::Array(a) => a.iter().map(|v| self.resolve(v, from)).collect::<Result<_, _>>().map(JsonValue::Array),
+            scalar => Ok(scalar.clone()),
+        }
+    }
+}
+
+/// `value` with its `$ref`s replaced by copies of their targets in `root`.
+pub(crate) fn resolved(root: &JsonValue, value: &JsonValue, options: &ResolverOptions) -> Result<JsonValue, RefError> {
+    Resolver { root, options, files: HashMap::new(), expanding: Vec::new(), nodes: 0 }.resolve(value, &None)
+}
+
+/// Replaces every `{"$ref": "..."}` object in `value` by a copy of its target: a JSON pointer
+/// into `value` itself (`#/definitions/x`) or into a JSON file within `options.file_roots`
+/// (`common.json#/x`, or a whole file). Keys next to a `$ref` are kept over the target's.
+/// On error, `value` is left as it was.
+pub fn resolve_refs(value: &mut JsonValue, options: &ResolverOptions) -> Result<(), RefError> {
+    *value = resolved(value, value, options)?;
+    Ok(())
+}
+
+/// Resolves the references of `value`, then converts it.
+pub fn json_to_lua_with_refs<'lua>(
+    lua: &'lua Lua, value: &JsonValue, resolver: &ResolverOptions, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &resolved(value, value, resolver).map_err(rlua::Error::external)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, RefError, RefPolicy, ResolverOptions, register, resolve_refs};
+
+    #[test]
+    fn internal_refs() {
+        let mut value = json!({
+            "definitions": {"id": {"type": "integer"}, "pair": {"items": {"$ref": "#/definitions/id"}}},
+            "properties": {"a": {"$ref": "#/definitions/pair", "maxItems": 2}, "b": [{"$ref": "#/definitions/id"}]},
+        });
+        resolve_refs(&mut value, &ResolverOptions::default()).expect("resolve");
+        assert_eq!(value["properties"], json!({"a": {"items": {"type": "integer"}, "maxItems": 2}, "b": [{"type": "integer"}]}));
+
+        let cyclic = json!({"node": {"next": {"$ref": "#/node"}}, "root": {"$ref": "#/node"}});
+        let mut value = cyclic.clone();
+        assert_eq!(resolve_refs(&mut value, &ResolverOptions::default()), Err(RefError::Cycle("#/node".to_string())));
+        assert_eq!(value, cyclic);
+        let keep = ResolverOptions { cycles: RefPolicy::Keep, ..Default::default() };
+        resolve_refs(&mut value, &keep).expect("resolve");
+        assert_eq!(value["root"], json!({"next": {"$ref": "#/node"}}));
+
+        let mut value = json!({"a": {"$ref": "#/nope"}, "b": {"$ref": "https://example.com/x.json"}});
+        assert_eq!(resolve_refs(&mut value, &ResolverOptions::default()), Err(RefError::Unresolved("#/nope".to_string())));
+        resolve_refs(&mut value, &ResolverOptions { missing: RefPolicy::Keep, ..Default::default() }).expect("keep");
+        assert_eq!(value["b"], json!({"$ref": "https://example.com/x.json"}));
+
+        let mut value = json!({"a": {"$ref": "#/b"}, "b": {"$ref": "#/c"}, "c": 1});
+        assert!(matches!(resolve_refs(&mut value, &ResolverOptions { max_depth: 1, ..Default::default() }), Err(RefError::TooDeep(_))));
+    }
+
+    #[test]
+    fn fan_out() {
+        let mut definitions = serde_json::Map::new();
+        for i in 0..40 {
+            definitions.insert(format!("a{}", i), json!([{"$ref": format!("#/$defs/a{}", i + 1)}, {"$ref": format!("#/$defs/a{}", i + 1)}]));
+        }
+        definitions.insert("a40".to_string(), json!(0));
+        let bomb = json!({"$defs": definitions, "root": {"$ref": "#/$defs/a0"}});
+        let mut value = bomb.clone();
+        assert!(matches!(resolve_refs(&mut value, &ResolverOptions::default()), Err(RefError::TooLarge(_))));
+        assert_eq!(value, bomb);
+
+        let mut value = json!({"$defs": {"x": [1, 2]}, "a": {"$ref": "#/$defs/x"}, "b": {"$ref": "#/$defs/x"}});
+        assert!(matches!(resolve_refs(&mut value, &ResolverOptions { max_nodes: 8, ..Default::default() }), Err(RefError::TooLarge(_))));
+        resolve_refs(&mut value, &ResolverOptions { max_nodes: 12, ..Default::default() }).expect("within limit");
+        assert_eq!(value["b"], json!([1, 2]));
+    }
+
+    #[test]
+    fn file_refs() {
+        let dir = std::env::temp_dir().join(format!("rlua_json_refs_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).expect("create dir");
+        std::fs::write(dir.join("common/types.json"), r##"{"id": {"type": "integer"}, "ids": {"items": {"$ref": "#/id"}}}"##).expect("write");
+        std::fs::write(dir.join("common/all.json"), r#"{"$ref": "types.json#/ids"}"#).expect("write");
+
+        let options = ResolverOptions { file_roots: vec![dir.clone()], base_dir: Some(dir.clone()), ..Default::default() };
+        let mut value = json!({"a": {"$ref": "common/types.json#/id"}, "b": {"$ref": "common/all.json"}});
+        resolve_refs(&mut value, &options).expect("resolve");
+        assert_eq!(value, json!({"a": {"type": "integer"}, "b": {"items": {"type": "integer"}}}));
+
+        let mut value = json!({"$ref": "common/types.json#/id"});
+        let sandboxed = ResolverOptions { base_dir: Some(dir.clone()), ..Default::default() };
+        assert_eq!(resolve_refs(&mut value, &sandboxed), Err(RefError::Denied("common/types.json#/id".to_string())));
+        for outside in ["../missing.json", "../../../../../../etc/passwd.json", "common/../../x.json"] {
+            let mut value = json!({"$ref": outside});
+            assert_eq!(resolve_refs(&mut value, &options), Err(RefError::Denied(outside.to_string())));
+        }
+        std::fs::write(dir.join("common/notes.txt"), "{}").expect("write");
+        let mut value = json!({"$ref": "common/notes.txt"});
+        assert_eq!(resolve_refs(&mut value, &options), Err(RefError::Denied("common/notes.txt".to_string())));
+        let mut value = json!({"$ref": "common/missing.json"});
+        assert!(matches!(resolve_refs(&mut value, &options), Err(RefError::Io { .. })));
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions {
+            file_roots: vec![dir.clone()], file_extensions: vec!["json".to_string(), "txt".to_string()], ..Default::default()
+        }).expect("register");
+        lua.globals().set("dir", dir.to_str().expect("utf-8 path")).expect("dir");
+        lua.load(r##"
+            local schema = json.resolve_refs({ items = { ["$ref"] = dir .. "/common/types.json#/id" }, id = { ["$ref"] = "#/items" } })
+            assert(schema.items.type == "integer" and schema.id.type == "integer")
+            assert(not pcall(json.resolve_refs, { ["$ref"] = "#/nope" }))
+            assert(json.resolve_refs({ ["$ref"] = dir .. "/common/notes.txt" }) ~= nil)
+        "##).exec().expect("lua");
+        let lua = Lua::new();
+        register(&lua, ConversionOptions {
+            file_roots: vec![dir.clone()], file_extensions: vec!["json".to_string()], ..Default::default()
+        }).expect("register");
+        lua.globals().set("dir", dir.to_str().expect("utf-8 path")).expect("dir");
+        lua.load(r##"
+            local ok, err = pcall(json.resolve_refs, { ["$ref"] = dir .. "/common/notes.txt" })
+            assert(not ok and tostring(err):find("not allowed"), tostring(err))
+        "##).exec().expect("extension denied");
+
+        std::fs::remove_dir_all(&dir).expect("clean up");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use rlua::Lua;
+use crate::SharedDocument;
+
+/// Key of a document in a [`JsonRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Handle {
+    Name(String),
+    Id(i64),
+}
+
+impl From<&str> for Handle {
+    fn from(name: &str) -> Self {
+        Handle::Name(name.to_string())
+    }
+}
+
+impl From<String> for Handle {
+    fn from(name: String) -> Self {
+        Handle::Name(name)
+    }
+}
+
+impl From<i64> for Handle {
+    fn from(id: i64) -> Self {
+        Handle::Id(id)
+    }
+}
+
+impl<'lua> rlua::FromLua<'lua> for Handle {
+    fn from_lua(value: rlua::Value<'lua>, _: &'lua Lua) -> rlua::Result<Self> {
+        match value {
+            // `Integer` is `i32` on Luau.
+            #[allow(clippy::unnecessary_cast)]
+            rlua::Value::Integer(id) => Ok(Handle::Id(id as i64)),
+            rlua::Value::String(name) => Ok(Handle::Name(name.to_str()?.to_string())),
+            value => Err(rlua::Error::FromLuaConversionError {
+                from: value.type_name(), to: "Handle", message: Some("expected a string or an integer".to_string()) }),
+        }
+    }
+}
+
+/// Documents kept on the Rust side and handed to scripts by handle: `json.open(handle)`
+/// returns the [`SharedDocument`] userdata, so only the parts a script reads are converted.
+#[derive(Debug, Clone, Default)]
+pub struct JsonRegistry {
+    documents: Arc<RwLock<HashMap<Handle, SharedDocument>>>,
+}
+
+impl JsonRegistry {
+    pub fn new() -> Self {
+        JsonRegistry::default()
+    }
+
+    /// Stores a document, returning the one previously under `handle`.
+    pub fn insert(&self, handle: impl Into<Handle>, document: SharedDocument) -> Option<SharedDocument> {
+        self.documents.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(handle.into(), document)
+    }
+
+    pub fn get(&self, handle: impl Into<Handle>) -> Option<SharedDocument> {
+        self.documents.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&handle.into()).cloned()
+    }
+
+    /// Forgets a document; scripts still holding it keep a working handle.
+    pub fn remove(&self, handle: impl Into<Handle>) -> Option<SharedDocument> {
+        self.documents.write().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&handle.into())
+    }
+
+    /// Adds `open(handle)` to a module table, e.g. the one from [`create_module`](crate::create_module).
+    /// It returns `nil` for unknown handles.
+    pub fn install(&self, lua: &Lua, module: &rlua::Table) -> rlua::Result<()> {
+        let registry = self.clone();
+        module.set("open", lua.create_function(move |_, handle: Handle| Ok(registry.get(handle)))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, JsonRegistry, SharedDocument, create_module};
+
+    #[test]
+    fn open_by_handle() {
+        let registry = JsonRegistry::new();
+        registry.insert("config", SharedDocument::new(json!({"speed": 3}), ConversionOptions::default()));
+        registry.insert(7, SharedDocument::new(json!([1, 2]), ConversionOptions::default()));
+
+        let lua = Lua::new();
+        let module = create_module(&lua, ConversionOptions::default()).expect("module");
+        registry.install(&lua, &module).expect("install");
+        lua.globals().set("json", module).expect("set global");
+
+        let (speed, missing): (i64, bool) = lua.load(r#"
+            local config = json.open("config")
+            config.speed = config.speed + json.open(7):get("/1")
+            return config.speed, json.open("other") == nil
+        "#).eval().expect("eval");
+        assert_eq!((speed, missing), (5, true));
+        assert_eq!(registry.get("config").expect("config").read()["speed"], json!(5));
+    }
+}
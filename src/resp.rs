@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua, lua_to_string};
+use crate::module::lock;
+
+/// Aggregates a reply may nest before it is rejected.
+const MAX_DEPTH: usize = 128;
+
+fn resp_error(message: impl std::fmt::Display) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("resp: {}", message))
+}
+
+fn tagged(type_name: &str, value: JsonValue) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("$type".to_string(), JsonValue::from(type_name));
+    o.insert("value".to_string(), value);
+    JsonValue::Object(o)
+}
+
+/// `{"err": "ERR unknown command"}`, as Redis hands error replies to its own Lua scripts.
+fn error_reply(message: String) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("err".to_string(), JsonValue::String(message));
+    JsonValue::Object(o)
+}
+
+fn bytes_value(bytes: &[u8]) -> JsonValue {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => JsonValue::String(text.to_string()),
+        Err(_) => tagged("data", JsonValue::String(base64::encode(bytes))),
+    }
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// A reply being read; `Ok(None)` anywhere means the input stops before the reply does.
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&mut self) -> Option<&'a [u8]> {
+        let rest = self.input.get(self.position..)?;
+        let end = rest.windows(2).position(|w| w == b"\r\n")?;
+        self.position += end + 2;
+        rest.get(..end)
+    }
+
+    fn length(&mut self) -> rlua::Result<Option<Option<usize>>> {
+        let Some(line) = self.line() else { return Ok(None) };
+        match std::str::from_utf8(line).ok().and_then(|l| l.parse::<i64>().ok()) {
+            Some(-1) => Ok(Some(None)),
+            Some(n) if n >= 0 => Ok(Some(Some(n as usize))),
+            _ => Err(resp_error(format!("invalid length {:?}", text(line)))),
+        }
+    }
+
+    fn blob(&mut self) -> rlua::Result<Option<Option<&'a [u8]>>> {
+        let Some(length) = self.length()? else { return Ok(None) };
+        let Some(length) = length else { return Ok(Some(None)) };
+        let Some(blob) = self.input.get(self.position..self.position + length) else { return Ok(None) };
+        match self.input.get(self.position + length..self.position + length + 2) {
+            Some(b"\r\n") => {},
+            Some(_) => return Err(resp_error("bulk string longer than its length")),
+            None => return Ok(None),
+        }
+        self.position += length + 2;
+        Ok(Some(Some(blob)))
+    }
+
+    fn values(&mut self, count: usize, depth: usize) -> rlua::Result<Option<Vec<JsonValue>>> {
+        let mut values = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            match self.value(depth + 1)? {
+                Some(value) => values.push(value),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(values))
+    }
+
+    fn value(&mut self, depth: usize) -> rlua::Result<Option<JsonValue>> {
+        if depth > MAX_DEPTH {
+            return Err(resp_error("reply nested too deeply"));
+        }
+        let Some(&kind) = self.input.get(self.position) else { return Ok(None) };
+        self.position += 1;
+        let value = match kind {
+            b'+' | b'-' | b':' | b',' | b'(' | b'#' | b'_' => {
+                let Some(line) = self.line() else { return Ok(None) };
+                let line = text(line);
+                match kind {
+                    b'+' => JsonValue::String(line),
+                    b'-' => error_reply(line),
+                    b':' => JsonValue::from(line.parse::<i64>().map_err(|_| resp_error(format!("invalid integer {:?}", line)))?),
+                    b',' => match line.parse::<f64>().ok().and_then(Number::from_f64) {
+                        Some(n) => JsonValue::Number(n),
+                        None if matches!(line.as_str(), "inf" | "-inf" | "nan") => JsonValue::String(line),
+                        None => return Err(resp_error(format!("invalid double {:?}", line))),
+                    },
+                    b'(' => match (line.parse::<i64>(), line.parse::<u64>()) {
+                        (Ok(n), _) => JsonValue::from(n),
+                        (_, Ok(n)) => JsonValue::from(n),
+                        _ if line.strip_prefix('-').unwrap_or(&line).bytes().all(|b| b.is_ascii_digit()) && !line.is_empty() => JsonValue::String(line),
+                        _ => return Err(resp_error(format!("invalid big number {:?}", line))),
+                    },
+                    b'#' => match line.as_str() {
+                        "t" => JsonValue::Bool(true),
+                        "f" => JsonValue::Bool(false),
+                        _ => return Err(resp_error(format!("invalid boolean {:?}", line))),
+                    },
+                    _ => JsonValue::Null,
+                }
+            },
+            b'$' | b'!' | b'=' => match (kind, self.blob()?) {
+                (_, None) => return Ok(None),
+                (_, Some(None)) => JsonValue::Null,
+                (b'$', Some(Some(blob))) => bytes_value(blob),
+                (b'!', Some(Some(blob))) => error_reply(text(blob)),
+                // `txt:` or `mkd:` and the text; the format is dropped.
+                (_, Some(Some(blob))) => bytes_value(blob.get(4..).unwrap_or_default()),
+            },
+            b'*' | b'~' | b'>' => match self.length()? {
+                None => return Ok(None),
+                Some(None) => JsonValue::Null,
+                Some(Some(count)) => match self.values(count, depth)? {
+                    Some(values) => JsonValue::Array(values),
+                    None => return Ok(None),
+                },
+            },
+            b'%' | b'|' => {
+                let Some(count) = self.length()? else { return Ok(None) };
+                let Some(values) = self.values(count.unwrap_or(0) * 2, depth)? else { return Ok(None) };
+                let mut o = Map::new();
+                let mut values = values.into_iter();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    let key = match key {
+                        JsonValue::String(key) => key,
+                        other => other.to_string(),
+                    };
+                    o.insert(key, value);
+                }
+                match kind {
+                    b'%' => JsonValue::Object(o),
+                    // Attributes describe the reply that follows them; scripts get the reply.
+                    _ => return self.value(depth),
+                }
+            },
+            other => return Err(resp_error(format!("unknown reply type {:?}", char::from(other)))),
+        };
+        Ok(Some(value))
+    }
+}
+
+/// Reads one RESP2 or RESP3 reply from the start of `bytes`, returning it with the number of
+/// bytes it took, or `None` if `bytes` ends before the reply does. Simple, bulk and verbatim
+/// strings become strings (bulk strings that are not UTF-8 become
+/// `{"$type": "data", "value": "<base64>"}`); errors become `{"err": "ERR ..."}`; arrays, sets
+/// and pushes become arrays; maps become objects, with keys that are not strings written as
+/// JSON. Null replies are `null`, big numbers past 64 bits their digits, and infinite or NaN
+/// doubles `"inf"`, `"-inf"` or `"nan"`.
+pub fn resp_to_json(bytes: &[u8]) -> rlua::Result<Option<(JsonValue, usize)>> {
+    let mut parser = Parser { input: bytes, position: 0 };
+    Ok(parser.value(0)?.map(|value| (value, parser.position)))
+}
+
+/// [`resp_to_json`], converted into a Lua value with `options`.
+pub fn resp_to_lua<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<Option<(rlua::Value<'lua>, usize)>> {
+    resp_to_json(bytes)?.map(|(value, used)| Ok((json_to_lua(lua, &value, options)?, used))).transpose()
+}
+
+/// The arguments of a command given as a sequence, `{ "SET", "user:1", user, "EX", 60 }`:
+/// strings as they are, bytes and all, numbers as their decimal text, booleans as `1`/`0`
+/// and tables as JSON text converted with `options`.
+pub fn lua_to_command_args<'lua>(lua: &'lua Lua, command: rlua::Table<'lua>, options: &ConversionOptions) -> rlua::Result<Vec<Vec<u8>>> {
+    command.sequence_values::<rlua::Value>().map(|value| Ok(match value? {
+        rlua::Value::String(s) => s.as_bytes().to_vec(),
+        rlua::Value::Boolean(b) => if b { b"1".to_vec() } else { b"0".to_vec() },
+        rlua::Value::Nil => return Err(resp_error("nil command argument")),
+        other => lua_to_string(lua, other, options)?.into_bytes(),
+    })).collect()
+}
+
+/// The command as it goes over the wire: an array of bulk strings.
+pub fn encode_resp_command(args: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// The `json.resp` table: `decode(bytes)`, returning the reply and the bytes it took or `nil`
+/// if more are needed, and `command(args)`, returning the wire form of a command.
+pub(crate) fn create_resp_table<'lua>(lua: &'lua Lua, options: Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    let decode_options = options.clone();
+    table.set("decode", lua.create_function(move |lua, bytes: rlua::String| {
+        match resp_to_lua(lua, bytes.as_bytes(), &lock(&decode_options).clone())? {
+            Some((value, used)) => Ok((value, Some(used))),
+            None => Ok((rlua::Value::Nil, None)),
+        }
+    })?)?;
+    table.set("command", lua.create_function(move |lua, command: rlua::Table| {
+        lua.create_string(encode_resp_command(&lua_to_command_args(lua, command, &lock(&options).clone())?))
+    })?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, encode_resp_command, lua_to_command_args, register, resp_to_json};
+
+    #[test]
+    fn resp_replies() {
+        let reply = b"%4\r\n+name\r\n$3\r\nAnn\r\n:7\r\n*3\r\n#t\r\n_\r\n,1.5\r\n$4\r\nbody\r\n=15\r\ntxt:Some string\r\n\
+            $3\r\nbig\r\n(3492890328409238509324850943850943825024385\r\n";
+        let (value, used) = resp_to_json(reply).expect("parse").expect("complete");
+        assert_eq!(used, reply.len());
+        assert_eq!(value, json!({
+            "name": "Ann", "7": [true, null, 1.5], "body": "Some string", "big": "3492890328409238509324850943850943825024385",
+        }));
+
+        assert_eq!(resp_to_json(b"-ERR unknown command\r\n").expect("parse"), Some((json!({"err": "ERR unknown command"}), 22)));
+        assert_eq!(resp_to_json(b"$-1\r\n").expect("parse"), Some((json!(null), 5)));
+        assert_eq!(resp_to_json(b"(18446744073709551615\r\n").expect("parse").map(|r| r.0), Some(json!(18446744073709551615u64)));
+        assert_eq!(resp_to_json(b"(-99999999999999999999\r\n").expect("parse").map(|r| r.0), Some(json!("-99999999999999999999")));
+        assert_eq!(resp_to_json(b",inf\r\n").expect("parse").map(|r| r.0), Some(json!("inf")));
+        assert_eq!(resp_to_json(b"~2\r\n:1\r\n:2\r\n").expect("parse").map(|r| r.0), Some(json!([1, 2])));
+        assert_eq!(resp_to_json(b"|1\r\n+ttl\r\n:3\r\n+OK\r\n").expect("parse"), Some((json!("OK"), 19)));
+        assert_eq!(resp_to_json(b"$3\r\n\xff\x00\x01\r\n").expect("parse").map(|r| r.0), Some(json!({"$type": "data", "value": "/wAB"})));
+        assert_eq!(resp_to_json(b"*2\r\n:1\r\n").expect("parse"), None);
+        assert_eq!(resp_to_json(b"$5\r\nab").expect("parse"), None);
+        assert!(resp_to_json(b"?1\r\n").is_err());
+        assert!(resp_to_json(b"$2\r\nabc\r\n").is_err());
+        assert!(resp_to_json(&b"*1\r\n".repeat(200)).is_err());
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let command = lua.load(r#"{ "SET", "user:1", { name = "Ann" }, "EX", 60, true }"#).eval().expect("command");
+        let args = lua_to_command_args(&lua, command, &options).expect("args");
+        assert_eq!(args, [&b"SET"[..], b"user:1", br#"{"name":"Ann"}"#, b"EX", b"60", b"1"]);
+        assert_eq!(encode_resp_command(&args[..2]), b"*2\r\n$3\r\nSET\r\n$6\r\nuser:1\r\n");
+
+        register(&lua, options).expect("register");
+        lua.load(r#"
+            assert(json.resp.command({ "GET", "k" }) == "*2\r\n$3\r\nGET\r\n$1\r\nk\r\n")
+            local reply, used = json.resp.decode("*2\r\n$1\r\na\r\n:2\r\n+extra")
+            assert(reply[1] == "a" and reply[2] == 2 and used == 15)
+            assert(json.resp.decode("*2\r\n") == nil)
+            assert(json.resp.decode("-WRONGTYPE bad\r\n").err == "WRONGTYPE bad")
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,184 @@
+//! The conversions for the legacy rlua crate (0.19 and older, with `Context`), so projects can
+//! adopt this crate before migrating to mlua.
+//!
+//! Values are converted directly, without going through mlua. Of [`ConversionOptions`], these
+//! are honored: `sparse_arrays`, `mixed_tables` (except `Split`), `integral_floats_as_integers`,
+//! `big_integers` (except `Cdata`) and `unsupported_values`. Nesting is limited as in
+//! [`lua_to_json`](crate::lua_to_json); there is no separate cycle detection.
+
+use rlua_legacy::{Context, Table, Value};
+use serde_json::{Map, Value as JsonValue};
+use crate::{BigIntegerPolicy, ConversionOptions, JsonWrapperValue, MixedTablePolicy, UnsupportedPolicy};
+use crate::convert::{KeyShape, MAX_DEPTH, encode_as_array, is_i64};
+
+fn error(message: impl Into<String>) -> rlua_legacy::Error {
+    rlua_legacy::Error::RuntimeError(message.into())
+}
+
+fn too_deep() -> rlua_legacy::Error {
+    error(format!("nesting deeper than {} levels", MAX_DEPTH))
+}
+
+/// Converts a JSON document into a Lua value. Arrays become 1-based sequences; `null` becomes `nil`.
+pub fn json_to_lua<'lua>(ctx: Context<'lua>, value: &JsonValue, options: &ConversionOptions) -> rlua_legacy::Result<Value<'lua>> {
+    decode(ctx, value, options, 0)
+}
+
+fn decode<'lua>(ctx: Context<'lua>, value: &JsonValue, options: &ConversionOptions, depth: usize) -> rlua_legacy::Result<Value<'lua>> {
+    if depth > MAX_DEPTH {
+        return Err(too_deep());
+    }
+    Ok(match value {
+        JsonValue::Null => Value::Nil,
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Value::Integer(i),
+            (None, Some(f)) if n.is_f64() => Value::Number(f),
+            (None, f) => match options.big_integers {
+                BigIntegerPolicy::String => Value::String(ctx.create_string(&n.to_string())?),
+                BigIntegerPolicy::Error => return Err(error(format!("{} does not fit a Lua integer", n))),
+                _ => Value::Number(f.unwrap_or(f64::NAN)),
+            },
+        },
+        JsonValue::String(s) => Value::String(ctx.create_string(s)?),
+        JsonValue::Array(a) => {
+            let table = ctx.create_table()?;
+            for (i, v) in a.iter().enumerate() {
+                table.raw_set(i + 1, decode(ctx, v, options, depth + 1)?)?;
+            }
+            Value::Table(table)
+        },
+        JsonValue::Object(o) => {
+            let table = ctx.create_table()?;
+            for (k, v) in o {
+                table.raw_set(k.as_str(), decode(ctx, v, options, depth + 1)?)?;
+            }
+            Value::Table(table)
+        },
+    })
+}
+
+/// Converts a Lua value into a JSON document. A null light userdata becomes `null`.
+pub fn lua_to_json<'lua>(ctx: Context<'lua>, value: Value<'lua>, options: &ConversionOptions) -> rlua_legacy::Result<JsonValue> {
+    Ok(encode(ctx, value, options, 0)?.unwrap_or(JsonValue::Null))
+}
+
+/// `None` for a value skipped under [`UnsupportedPolicy::Skip`].
+fn encode<'lua>(
+    ctx: Context<'lua>, value: Value<'lua>, options: &ConversionOptions, depth: usize,
+) -> rlua_legacy::Result<Option<JsonValue>> {
+    let type_name = value.type_name();
+    Ok(Some(match value {
+        Value::Nil => JsonValue::Null,
+        Value::Boolean(b) => JsonValue::Bool(b),
+        Value::LightUserData(ud) if ud.0.is_null() => JsonValue::Null,
+        Value::Integer(i) => JsonValue::from(i),
+        Value::Number(n) if options.integral_floats_as_integers && is_i64(n) => JsonValue::from(n as i64),
+        Value::Number(n) if !n.is_finite() => JsonValue::Null,
+        Value::Number(n) => JsonValue::from(n),
+        Value::String(s) => JsonValue::from(s.to_str()?),
+        Value::Table(t) => table(ctx, t, options, depth)?,
+        value => match options.unsupported_values {
+            UnsupportedPolicy::Error => return Err(error(format!("cannot convert a {} to JSON", type_name))),
+            UnsupportedPolicy::Skip => return Ok(None),
+            UnsupportedPolicy::ToString | UnsupportedPolicy::Tagged => {
+                let tostring: rlua_legacy::Function = ctx.globals().get("tostring")?;
+                let text = JsonValue::from(tostring.call::<_, rlua_legacy::String>(value)?.to_str()?);
+                match options.unsupported_values {
+                    UnsupportedPolicy::Tagged => {
+                        let mut o = Map::new();
+                        o.insert("$type".to_string(), JsonValue::from(type_name));
+                        o.insert("value".to_string(), text);
+                        JsonValue::Object(o)
+                    },
+                    _ => text,
+                }
+            },
+        },
+    }))
+}
+
+fn table<'lua>(ctx: Context<'lua>, table: Table<'lua>, options: &ConversionOptions, depth: usize) -> rlua_legacy::Result<JsonValue> {
+    if depth >= MAX_DEPTH {
+        return Err(too_deep());
+    }
+    let mut shape = KeyShape::default();
+    for pair in table.clone().pairs::<Value, Value>() {
+        match pair?.0 {
+            Value::Integer(i) if i >= 1 => {
+                shape.integers += 1;
+                shape.max = shape.max.max(i as usize);
+            },
+            _ => shape.others += 1,
+        }
+    }
+
+    let as_array = shape.others == 0 && shape.integers > 0
+        && encode_as_array(shape.integers, shape.max, options).map_err(|e| error(e.to_string()))?;
+    if as_array {
+        let mut a = Vec::with_capacity(shape.max);
+        for i in 1..=shape.max {
+            a.push(encode(ctx, table.raw_get(i)?, options, depth + 1)?.unwrap_or(JsonValue::Null));
+        }
+        return Ok(JsonValue::Array(a));
+    }
+    if shape.integers > 0 && shape.others > 0 {
+        match options.mixed_tables {
+            MixedTablePolicy::ObjectWithNumericKeys => {},
+            MixedTablePolicy::Error => return Err(error(format!(
+                "mixed table: {} array values and {} other keys", shape.integers, shape.others))),
+            MixedTablePolicy::Split { .. } => return Err(error("MixedTablePolicy::Split is not supported for rlua")),
+        }
+    }
+
+    let mut o = Map::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let key = match ctx.coerce_string(key)? {
+            Some(key) => key.to_str()?.to_string(),
+            None => return Err(error("object keys must be strings or numbers")),
+        };
+        if let Some(value) = encode(ctx, value, options, depth + 1)? {
+            o.insert(key, value);
+        }
+    }
+    Ok(JsonValue::Object(o))
+}
+
+impl<'lua> rlua_legacy::ToLua<'lua> for JsonWrapperValue {
+    fn to_lua(self, ctx: Context<'lua>) -> rlua_legacy::Result<Value<'lua>> {
+        json_to_lua(ctx, &self.0, &ConversionOptions::default())
+    }
+}
+
+impl<'lua> rlua_legacy::FromLua<'lua> for JsonWrapperValue {
+    fn from_lua(value: Value<'lua>, ctx: Context<'lua>) -> rlua_legacy::Result<Self> {
+        lua_to_json(ctx, value, &ConversionOptions::default()).map(JsonWrapperValue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua_legacy::{Lua, Value};
+    use serde_json::{Value as JsonValue, json};
+    use crate::{ConversionOptions, JsonWrapperValue, SparseArrayPolicy};
+    use super::{json_to_lua, lua_to_json};
+
+    #[test]
+    fn legacy_round_trip() {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            let options = ConversionOptions::default();
+            let document = json!({"name": "x", "list": [1, 2.5, "three"], "nested": {"ok": true}});
+            let value = json_to_lua(ctx, &document, &options).expect("to lua");
+            assert_eq!(lua_to_json(ctx, value, &options).expect("to json"), document);
+
+            let sparse: Value = ctx.load("{ 1, 2, nil, nil, 5 }").eval().expect("table");
+            let options = ConversionOptions { sparse_arrays: SparseArrayPolicy::Pad { ratio: 2, safe: 10 }, ..Default::default() };
+            assert_eq!(lua_to_json(ctx, sparse, &options).expect("padded"), json!([1, 2, null, null, 5]));
+
+            let wrapped: JsonWrapperValue = ctx.load("{ a = { 1, 2 } }").eval().expect("wrapper");
+            assert_eq!(JsonValue::from(wrapped), json!({"a": [1, 2]}));
+        });
+    }
+}
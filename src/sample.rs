@@ -0,0 +1,63 @@
+use serde_json::{Map, Value as JsonValue};
+
+/// The text standing in for what [`sample`] leaves out.
+fn elision(count: usize, what: &str) -> JsonValue {
+    JsonValue::String(format!("... {} {}{}", count, what, if count == 1 { "" } else { "s" }))
+}
+
+fn walk(value: &JsonValue, max_items: usize, depth: usize) -> JsonValue {
+    match value {
+        JsonValue::Array(a) if depth == 0 && !a.is_empty() => JsonValue::Array(vec![elision(a.len(), "item")]),
+        JsonValue::Object(o) if depth == 0 && !o.is_empty() => {
+            let mut elided = Map::new();
+            elided.insert("...".to_string(), elision(o.len(), "key"));
+            JsonValue::Object(elided)
+        },
+        JsonValue::Array(a) if a.len() > max_items => {
+            let (head, tail) = (max_items.div_ceil(2), max_items / 2);
+            let mut kept: Vec<JsonValue> = a.iter().take(head).map(|v| walk(v, max_items, depth.saturating_sub(1))).collect();
+            kept.push(elision(a.len() - head - tail, "more item"));
+            kept.extend(a.iter().skip(a.len() - tail).map(|v| walk(v, max_items, depth.saturating_sub(1))));
+            JsonValue::Array(kept)
+        },
+        JsonValue::Array(a) => JsonValue::Array(a.iter().map(|v| walk(v, max_items, depth.saturating_sub(1))).collect()),
+        JsonValue::Object(o) => JsonValue::Object(o.iter().map(|(k, v)| (k.clone(), walk(v, max_items, depth.saturating_sub(1)))).collect()),
+        scalar => scalar.clone(),
+    }
+}
+
+/// A slimmed copy of `doc` for logs and previews. Arrays longer than `max_items_per_array` keep
+/// their first and last items, half each, around a `"... N more items"` string; containers
+/// nested deeper than `max_depth` become `["... N items"]` or `{"...": "... N keys"}`. Scalars
+/// and object keys are kept as they are.
+pub fn sample(doc: &JsonValue, max_items_per_array: usize, max_depth: usize) -> JsonValue {
+    walk(doc, max_items_per_array, max_depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, register, sample};
+
+    #[test]
+    fn samples() {
+        let doc = json!({"items": (1..=10).collect::<Vec<_>>(), "deep": {"a": {"b": [1, 2]}, "empty": []}, "n": 1});
+        assert_eq!(sample(&doc, 4, 8), json!({
+            "items": [1, 2, "... 6 more items", 9, 10], "deep": {"a": {"b": [1, 2]}, "empty": []}, "n": 1,
+        }));
+        assert_eq!(sample(&doc, 3, 2), json!({
+            "items": [1, 2, "... 7 more items", 10], "deep": {"a": {"...": "... 1 key"}, "empty": []}, "n": 1,
+        }));
+        assert_eq!(sample(&json!([[1], [2, 3]]), 5, 0), json!(["... 2 items"]));
+        assert_eq!(sample(&json!([[1], [2, 3]]), 1, 1), json!([["... 1 item"], "... 1 more item"]));
+        assert_eq!(sample(&json!("text"), 0, 0), json!("text"));
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local s = json.sample({ list = { 1, 2, 3, 4, 5 } }, 2)
+            assert(#s.list == 3 and s.list[1] == 1 and s.list[2] == "... 3 more items" and s.list[3] == 5)
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,141 @@
+use rlua::Lua;
+use serde_json::{Number, Value as JsonValue};
+use crate::generate::Rng;
+use crate::{ConversionOptions, Path, PathPattern, PathSegment, json_to_lua, lua_to_json};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrambleOptions {
+    /// The same text scrambles the same way under the same seed, so equal values stay equal
+    /// across documents; a different seed gives unrelated output.
+    pub seed: u64,
+    /// Values left as they are, e.g. `/events/*/type`, and everything inside them.
+    pub keep: Vec<PathPattern>,
+    /// Whether numbers are scrambled too, keeping their sign, number of digits and decimals.
+    pub numbers: bool,
+}
+
+impl Default for ScrambleOptions {
+    fn default() -> Self {
+        ScrambleOptions { seed: 0, keep: Vec::new(), numbers: true }
+    }
+}
+
+/// FNV-1a: stable across Rust releases, unlike `DefaultHasher`.
+fn hash(seed: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325 ^ seed, |h, b| (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01B3))
+}
+
+/// Letters become random letters of the same case, digits random digits; everything else,
+/// e.g. spaces, `@` and `-`, stays, so the text keeps its shape.
+fn scramble_text(text: &str, seed: u64) -> String {
+    let mut rng = Rng::new(hash(seed, text.as_bytes()));
+    text.chars().map(|c| {
+        let offset = |n: u64, rng: &mut Rng| rng.below(n) as u8;
+        match c {
+            'a'..='z' => char::from(b'a' + offset(26, &mut rng)),
+            'A'..='Z' => char::from(b'A' + offset(26, &mut rng)),
+            '0'..='9' => char::from(b'0' + offset(10, &mut rng)),
+            c => c,
+        }
+    }).collect()
+}
+
+/// The digits scrambled, the leading one staying zero or nonzero so the magnitude stays.
+fn scramble_number(n: &Number, seed: u64) -> JsonValue {
+    let text = n.to_string();
+    let (mantissa, exponent) = text.split_once(['e', 'E']).map_or((text.as_str(), None), |(m, e)| (m, Some(e)));
+    let mut scrambled = scramble_text(mantissa, seed);
+    if let Some(i) = mantissa.find(|c: char| c.is_ascii_digit()) {
+        match (mantissa.get(i..i + 1), scrambled.get(i..i + 1)) {
+            (Some("0"), _) => scrambled.replace_range(i..i + 1, "0"),
+            (_, Some("0")) => scrambled.replace_range(i..i + 1, "1"),
+            _ => {},
+        }
+    }
+    if let Some(exponent) = exponent {
+        scrambled = format!("{}e{}", scrambled, exponent);
+    }
+    serde_json::from_str::<Number>(&scrambled).map_or_else(|_| JsonValue::Number(n.clone()), JsonValue::Number)
+}
+
+fn walk(value: &JsonValue, options: &ScrambleOptions, path: &mut Path) -> JsonValue {
+    if options.keep.iter().any(|pattern| pattern.matches(path)) {
+        return value.clone();
+    }
+    match value {
+        JsonValue::String(s) => JsonValue::String(scramble_text(s, options.seed)),
+        JsonValue::Number(n) if options.numbers => scramble_number(n, options.seed),
+        JsonValue::Array(a) => JsonValue::Array(a.iter().enumerate().map(|(i, v)| {
+            path.push(PathSegment::Index(i));
+            let scrambled = walk(v, options, path);
+            path.pop();
+            scrambled
+        }).collect()),
+        JsonValue::Object(o) => JsonValue::Object(o.iter().map(|(k, v)| {
+            path.push(PathSegment::Key(k.clone()));
+            let scrambled = walk(v, options, path);
+            path.pop();
+            (k.clone(), scrambled)
+        }).collect()),
+        other => other.clone(),
+    }
+}
+
+/// A copy of `value` safe to attach to a bug report: strings and numbers replaced by random
+/// ones of the same shape, keys, booleans, nulls and structure untouched.
+pub fn scramble(value: &JsonValue, options: &ScrambleOptions) -> JsonValue {
+    walk(value, options, &mut Path::new())
+}
+
+/// [`scramble`] for a Lua value.
+pub fn scramble_lua<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, scramble_options: &ScrambleOptions, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &scramble(&lua_to_json(lua, value, options)?, scramble_options), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, PathPattern, ScrambleOptions, register, scramble};
+
+    #[test]
+    fn scrambling() {
+        let doc = json!({
+            "user": {"email": "Ann.Lee@example.com", "age": 42, "balance": -1234.5, "big": 1.5e300, "zero": 0.25},
+            "events": [{"type": "login", "user": "Ann.Lee@example.com"}, {"type": "logout", "ok": true, "note": null}],
+        });
+        let options = ScrambleOptions { seed: 7, keep: vec![PathPattern::parse("/events/*/type")], ..Default::default() };
+        let scrambled = scramble(&doc, &options);
+        let email = scrambled["user"]["email"].as_str().expect("email");
+        assert_ne!(email, "Ann.Lee@example.com");
+        assert!(email.chars().zip("Ann.Lee@example.com".chars()).all(|(a, b)| {
+            a.is_ascii_uppercase() == b.is_ascii_uppercase() && a.is_ascii_lowercase() == b.is_ascii_lowercase() && (a.is_alphanumeric() || a == b)
+        }));
+        assert_eq!(scrambled["events"][0]["user"], scrambled["user"]["email"]);
+        assert_eq!(scrambled["events"][0]["type"], "login");
+        assert_eq!(scrambled["events"][1]["ok"], true);
+        assert_eq!(scrambled["events"][1]["note"], json!(null));
+
+        let age = scrambled["user"]["age"].as_i64().expect("age");
+        assert!((10..100).contains(&age));
+        let balance = scrambled["user"]["balance"].as_f64().expect("balance");
+        assert!((-9999.9..=-1000.0).contains(&balance), "{}", balance);
+        assert!(scrambled["user"]["big"].as_f64().expect("big") >= 1e300);
+        assert!(scrambled["user"]["zero"].as_f64().expect("zero") < 1.0);
+
+        assert_eq!(scramble(&doc, &options), scrambled);
+        assert_ne!(scramble(&doc, &ScrambleOptions { seed: 8, ..options.clone() }), scrambled);
+        assert_eq!(scramble(&doc, &ScrambleOptions { numbers: false, ..options })["user"]["age"], 42);
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local s = json.scramble({ name = "Bob", id = 123, kind = "x" }, 1, { "/kind" })
+            assert(s.name ~= "Bob" and #s.name == 3 and s.name:match("^%u%l%l$"))
+            assert(s.id >= 100 and s.id <= 999 and s.kind == "x")
+            assert(json.scramble("Bob", 1) == s.name)
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,261 @@
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::convert::Decoder;
+use crate::readonly::view_contents;
+use crate::{ConversionOptions, Path, PathSegment};
+
+/// Resolves a JSON Pointer, returning the value and its location.
+pub fn resolve_pointer<'v>(value: &'v JsonValue, pointer: &str) -> Option<(Path, &'v JsonValue)> {
+    if pointer.is_empty() {
+        return Some((Path::new(), value));
+    }
+    if !pointer.starts_with('/') {
+        return None;
+    }
+    let mut path = Path::new();
+    let mut current = value;
+    for token in pointer[1..].split('/') {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        current = match current {
+            JsonValue::Object(o) => {
+                let next = o.get(&token)?;
+                path.push(PathSegment::Key(token));
+                next
+            },
+            JsonValue::Array(a) => {
+                let index: usize = token.parse().ok()?;
+                path.push(PathSegment::Index(index));
+                a.get(index)?
+            },
+            _ => return None,
+        };
+    }
+    Some((path, current))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+    Descendants(Option<String>),
+}
+
+fn syntax_error(expr: &str, message: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("JSONPath {:?}: {}", expr, message))
+}
+
+/// Parses the JSONPath subset `$`, `.key`, `['key']`, `[n]`, `[*]`, `.*` and `..key`.
+fn parse_json_path(expr: &str) -> rlua::Result<Vec<Step>> {
+    let rest = expr.strip_prefix('$').ok_or_else(|| syntax_error(expr, "must start with $"))?;
+    let mut steps = Vec::new();
+    let mut chars = rest.chars().peekable();
+    let name = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '.' || c == '[' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        name
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if chars.peek() == Some(&'.') => {
+                chars.next();
+                let key = name(&mut chars);
+                steps.push(Step::Descendants(if key == "*" || key.is_empty() { None } else { Some(key) }));
+            },
+            '.' => match name(&mut chars).as_str() {
+                "" => return Err(syntax_error(expr, "empty member name")),
+                "*" => steps.push(Step::Wildcard),
+                key => steps.push(Step::Key(key.to_string())),
+            },
+            '[' => {
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    inner.push(c);
+                }
+                let inner = inner.trim();
+                let quoted = inner.strip_prefix('\'').and_then(|s| s.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                match quoted {
+                    Some(key) => steps.push(Step::Key(key.to_string())),
+                    None if inner == "*" => steps.push(Step::Wildcard),
+                    None => steps.push(Step::Index(inner.parse()
+                        .map_err(|_| syntax_error(expr, "expected an index, '*' or a quoted name"))?)),
+                }
+            },
+            _ => return Err(syntax_error(expr, "expected '.' or '['")),
+        }
+    }
+    Ok(steps)
+}
+
+fn children<'v>(path: &Path, value: &'v JsonValue) -> Vec<(Path, &'v JsonValue)> {
+    let child = |segment| {
+        let mut path = path.clone();
+        path.push(segment);
+        path
+    };
+    match value {
+        JsonValue::Object(o) => o.iter().map(|(k, v)| (child(PathSegment::Key(k.clone())), v)).collect(),
+        JsonValue::Array(a) => a.iter().enumerate().map(|(i, v)| (child(PathSegment::Index(i)), v)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn descendants<'v>(path: Path, value: &'v JsonValue, out: &mut Vec<(Path, &'v JsonValue)>) {
+    for (path, child) in children(&path, value) {
+        out.push((path.clone(), child));
+        descendants(path, child, out);
+    }
+}
+
+/// Evaluates a JSONPath expression, returning every match with its location in document order.
+pub fn select_json_path<'v>(value: &'v JsonValue, expr: &str) -> rlua::Result<Vec<(Path, &'v JsonValue)>> {
+    let mut current = vec![(Path::new(), value)];
+    for step in parse_json_path(expr)? {
+        let mut next = Vec::new();
+        for (path, value) in current {
+            match &step {
+                Step::Key(key) => if let Some(child) = value.as_object().and_then(|o| o.get(key)) {
+                    let mut path = path;
+                    path.push(PathSegment::Key(key.clone()));
+                    next.push((path, child));
+                },
+                Step::Index(i) => if let Some(child) = value.as_array().and_then(|a| a.get(*i)) {
+                    let mut path = path;
+                    path.push(PathSegment::Index(*i));
+                    next.push((path, child));
+                },
+                Step::Wildcard => next.extend(children(&path, value)),
+                Step::Descendants(key) => {
+                    let mut all = vec![(path.clone(), value)];
+                    descendants(path, value, &mut all);
+                    match key {
+                        None => next.extend(all.into_iter().skip(1)),
+                        Some(key) => next.extend(all.into_iter()
+                            .flat_map(|(p, v)| children(&p, v))
+                            .filter(|(p, _)| p.segments().last() == Some(&PathSegment::Key(key.clone())))),
+                    }
+                },
+            }
+        }
+        current = next;
+    }
+    Ok(current)
+}
+
+/// Converts only the subtree at a JSON Pointer (`/data/items/3`); `nil` if it does not exist.
+///
+/// Path-based options such as `set_paths` still match against locations in the whole document.
+pub fn json_path_to_lua<'lua>(
+    lua: &'lua Lua, value: &JsonValue, pointer: &str, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let Some((path, subtree)) = resolve_pointer(value, pointer) else { return Ok(rlua::Value::Nil) };
+    let mut decoder = Decoder::new(lua, options);
+    decoder.path = path;
+    decoder.decode(subtree)
+}
+
+/// Converts every match of a JSONPath expression (`$.data.items[*].name`) into a Lua sequence.
+pub fn jsonpath_to_lua<'lua>(
+    lua: &'lua Lua, value: &JsonValue, expr: &str, options: &ConversionOptions,
+) -> rlua::Result<rlua::Table<'lua>> {
+    let matches = select_json_path(value, expr)?;
+    let table = lua.create_table_with_capacity(matches.len(), 0)?;
+    for (i, (path, subtree)) in matches.into_iter().enumerate() {
+        let mut decoder = Decoder::new(lua, options);
+        decoder.path = path;
+        table.raw_set(i + 1, decoder.decode(subtree)?)?;
+    }
+    Ok(table)
+}
+
+/// A document or table [`extract_fields`] reads top-level fields of.
+pub trait FieldSource {
+    type Field;
+
+    fn field(&self, key: &str) -> rlua::Result<Option<Self::Field>>;
+}
+
+impl<'v> FieldSource for &'v JsonValue {
+    type Field = &'v JsonValue;
+
+    fn field(&self, key: &str) -> rlua::Result<Option<&'v JsonValue>> {
+        Ok(self.get(key))
+    }
+}
+
+/// Reads through read-only views; `nil` fields are absent.
+impl<'lua> FieldSource for rlua::Table<'lua> {
+    type Field = rlua::Value<'lua>;
+
+    fn field(&self, key: &str) -> rlua::Result<Option<rlua::Value<'lua>>> {
+        let value: rlua::Value = match view_contents(self)? {
+            Some(contents) => contents.raw_get(key)?,
+            None => self.raw_get(key)?,
+        };
+        Ok(Some(value).filter(|v| !v.is_nil()))
+    }
+}
+
+/// A fixed set of top-level fields of a document or Lua table, in the order of `keys`, without
+/// converting anything else or allocating: `let [id, name] = extract_fields(&doc, &["id", "name"])?`.
+pub fn extract_fields<S: FieldSource, const N: usize>(source: S, keys: &[&str; N]) -> rlua::Result<[Option<S::Field>; N]> {
+    let mut fields = [(); N].map(|()| None);
+    for (field, key) in fields.iter_mut().zip(keys) {
+        *field = source.field(key)?;
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, extract_fields, json_path_to_lua, json_to_lua, jsonpath_to_lua, lua_to_json, select_json_path};
+
+    #[test]
+    fn pointer_and_json_path() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let doc = json!({"data": {"items": [{"name": "a", "tags": ["x"]}, {"name": "b"}]}});
+
+        let item = json_path_to_lua(&lua, &doc, "/data/items/1", &options).expect("pointer");
+        assert_eq!(lua_to_json(&lua, item, &options).expect("encode"), json!({"name": "b"}));
+        assert!(json_path_to_lua(&lua, &doc, "/data/missing", &options).expect("missing").is_nil());
+
+        let encode = |expr| lua_to_json(&lua, rlua::Value::Table(jsonpath_to_lua(&lua, &doc, expr, &options)
+            .expect("json path")), &options).expect("encode");
+        assert_eq!(encode("$.data.items[*].name"), json!(["a", "b"]));
+        assert_eq!(encode("$..name"), json!(["a", "b"]));
+        assert_eq!(encode("$['data'].items[0].tags[0]"), json!(["x"]));
+        for expr in ["$.data.items.name", "$.data[0]", "$.data.items[2]", "$.missing"] {
+            assert!(select_json_path(&doc, expr).expect("json path").is_empty(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let lua = Lua::new();
+        let doc = json!({"id": 7, "name": "a", "pos": [1, 2], "rest": {"big": true}});
+        let [id, pos, missing] = extract_fields(&doc, &["id", "pos", "missing"]).expect("json");
+        assert_eq!((id, pos, missing), (Some(&json!(7)), Some(&json!([1, 2])), None));
+
+        for read_only in [false, true] {
+            let options = ConversionOptions { read_only, ..Default::default() };
+            let rlua::Value::Table(table) = json_to_lua(&lua, &doc, &options).expect("decode") else { panic!("table") };
+            let [name, missing] = extract_fields(table, &["name", "missing"]).expect("lua");
+            assert_eq!(name.and_then(|v| v.as_str().map(str::to_string)).as_deref(), Some("a"));
+            assert!(missing.is_none());
+        }
+    }
+}
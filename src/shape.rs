@@ -0,0 +1,86 @@
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::ConversionOptions;
+use crate::convert::Decoder;
+
+/// The structure of a sample document, with its object keys already created as Lua strings.
+pub(crate) enum Shape<'lua> {
+    Object(Vec<(String, rlua::String<'lua>, Shape<'lua>)>),
+    /// The shape of the first element.
+    Array(Box<Shape<'lua>>),
+    Scalar,
+}
+
+impl<'lua> Shape<'lua> {
+    fn compile(lua: &'lua Lua, value: &JsonValue) -> rlua::Result<Self> {
+        Ok(match value {
+            JsonValue::Object(o) => Shape::Object(o.iter()
+                .map(|(k, v)| Ok((k.clone(), lua.create_string(k)?, Shape::compile(lua, v)?)))
+                .collect::<rlua::Result<_>>()?),
+            JsonValue::Array(a) => Shape::Array(Box::new(match a.first() {
+                Some(first) => Shape::compile(lua, first)?,
+                None => Shape::Scalar,
+            })),
+            _ => Shape::Scalar,
+        })
+    }
+
+    /// The planned Lua key and value shape of the `i`th entry of an object, if it is `key`.
+    pub fn entry(&self, i: usize, key: &str) -> Option<(&rlua::String<'lua>, &Self)> {
+        match self {
+            Shape::Object(entries) => entries.get(i).filter(|(k, _, _)| k == key).map(|(_, key, shape)| (key, shape)),
+            _ => None,
+        }
+    }
+
+    pub fn element(&self) -> Option<&Self> {
+        match self {
+            Shape::Array(element) => Some(element),
+            _ => None,
+        }
+    }
+}
+
+/// Object keys and array element shapes of a sample document, for converting many documents
+/// of the same structure: entries found at the planned position with the planned key reuse its
+/// Lua string instead of creating one. Documents may deviate from the plan; those parts are
+/// converted as usual.
+pub struct ShapePlan<'lua>(Shape<'lua>);
+
+impl<'lua> ShapePlan<'lua> {
+    pub fn compile(lua: &'lua Lua, sample: &JsonValue) -> rlua::Result<Self> {
+        Shape::compile(lua, sample).map(ShapePlan)
+    }
+}
+
+/// Like [`json_to_lua`](crate::json_to_lua), following `plan`.
+pub fn json_to_lua_with_plan<'lua>(
+    lua: &'lua Lua, value: &JsonValue, plan: &ShapePlan<'lua>, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let mut decoder = Decoder::new(lua, options);
+    decoder.shape = Some(&plan.0);
+    decoder.decode(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, ShapePlan, json_to_lua_with_plan, lua_to_json};
+
+    #[test]
+    fn shape_plans() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let plan = ShapePlan::compile(&lua, &json!({"id": 1, "pos": {"x": 0, "y": 0}, "tags": [{"k": "a"}]})).expect("plan");
+        for doc in [
+            json!({"id": 2, "pos": {"x": 3, "y": 4}, "tags": [{"k": "b"}, {"k": "c"}]}),
+            json!({"pos": {"y": 4, "x": 3}, "id": 2, "extra": true, "tags": {"k": [1]}}),
+            json!([{"id": 1}]),
+            json!("scalar"),
+        ] {
+            let value = json_to_lua_with_plan(&lua, &doc, &plan, &options).expect("decode");
+            assert_eq!(lua_to_json(&lua, value, &options).expect("encode"), doc);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+type MigrateFn = dyn Fn(&mut JsonValue) -> Result<(), String> + Send + Sync;
+
+/// Versioned save format: [`save`](Self::save) writes `{"version": n, "data": ...}`, and
+/// [`load`](Self::load) upgrades older snapshots one version at a time before converting them.
+#[derive(Clone)]
+pub struct Snapshots {
+    version: u64,
+    migrations: BTreeMap<u64, Arc<MigrateFn>>,
+}
+
+impl Debug for Snapshots {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshots")
+            .field("version", &self.version)
+            .field("migrations", &self.migrations.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn snapshot_error(message: String) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("snapshot: {}", message))
+}
+
+impl Snapshots {
+    /// Snapshots of the current data shape, `version`.
+    pub fn new(version: u64) -> Self {
+        Snapshots { version, migrations: BTreeMap::new() }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Registers the upgrade of data from `from_version` to `from_version + 1`.
+    pub fn on_migrate(
+        &mut self, from_version: u64, f: impl Fn(&mut JsonValue) -> Result<(), String> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.migrations.insert(from_version, Arc::new(f));
+        self
+    }
+
+    pub fn save<'lua>(&self, lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<JsonValue> {
+        let mut snapshot = Map::new();
+        snapshot.insert("version".to_string(), JsonValue::from(self.version));
+        snapshot.insert("data".to_string(), lua_to_json(lua, value, options)?);
+        Ok(JsonValue::Object(snapshot))
+    }
+
+    /// The data of a snapshot, migrated to the current version.
+    pub fn upgrade(&self, snapshot: JsonValue) -> rlua::Result<JsonValue> {
+        let JsonValue::Object(mut snapshot) = snapshot else {
+            return Err(snapshot_error("expected an object".to_string()));
+        };
+        let mut version = snapshot.get("version").and_then(JsonValue::as_u64)
+            .ok_or_else(|| snapshot_error("missing version".to_string()))?;
+        let mut data = snapshot.remove("data").ok_or_else(|| snapshot_error("missing data".to_string()))?;
+        if version > self.version {
+            return Err(snapshot_error(format!("version {} is newer than {}", version, self.version)));
+        }
+        while version < self.version {
+            let migrate = self.migrations.get(&version)
+                .ok_or_else(|| snapshot_error(format!("no migration from version {}", version)))?;
+            migrate(&mut data).map_err(|message| snapshot_error(format!("migration from version {}: {}", version, message)))?;
+            version += 1;
+        }
+        Ok(data)
+    }
+
+    pub fn load<'lua>(&self, lua: &'lua Lua, snapshot: JsonValue, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+        json_to_lua(lua, &self.upgrade(snapshot)?, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, Snapshots};
+
+    #[test]
+    fn migrations() {
+        let mut snapshots = Snapshots::new(3);
+        snapshots
+            .on_migrate(1, |data| {
+                data["hp"] = data["health"].take();
+                Ok(())
+            })
+            .on_migrate(2, |data| match data.as_object_mut() {
+                Some(o) => {
+                    o.remove("health");
+                    o.insert("level".to_string(), json!(1));
+                    Ok(())
+                },
+                None => Err("expected an object".to_string()),
+            });
+
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let old = json!({"version": 1, "data": {"health": 7}});
+        lua.globals().set("save", snapshots.load(&lua, old, &options).expect("load")).expect("set global");
+        assert_eq!(lua.load("return save.hp + save.level").eval::<i64>().expect("eval"), 8);
+
+        let saved = snapshots.save(&lua, lua.globals().get("save").expect("get global"), &options).expect("save");
+        assert_eq!(saved, json!({"version": 3, "data": {"hp": 7, "level": 1}}));
+        assert!(snapshots.upgrade(json!({"version": 0, "data": {}})).is_err());
+        assert!(snapshots.upgrade(json!({"version": 4, "data": {}})).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+use calamine::{Data, Reader};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetOptions {
+    /// The sheet to read, by name; by default the first one.
+    pub sheet: Option<String>,
+    /// Whether the first row names the columns, so that rows become tables keyed by them. A
+    /// blank header cell is named by its column letter, `"C"`. Without headers rows are arrays.
+    pub headers: bool,
+    /// Whether rows with no values are left out instead of read as empty tables.
+    pub skip_empty_rows: bool,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        SheetOptions { sheet: None, headers: true, skip_empty_rows: true }
+    }
+}
+
+fn sheet_error(message: impl std::fmt::Display) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("spreadsheet: {}", message))
+}
+
+/// `0` is `A`, `26` is `AA`.
+fn column_letter(mut column: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(char::from(b'A' + (column % 26) as u8));
+        match column / 26 {
+            0 => break,
+            next => column = next - 1,
+        }
+    }
+    letters.iter().rev().collect()
+}
+
+fn cell_value(cell: &Data) -> JsonValue {
+    match cell {
+        Data::Int(n) => JsonValue::from(*n),
+        // Excel stores every number as a double; whole ones are read as the integers they were typed as.
+        Data::Float(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => JsonValue::from(*n as i64),
+        Data::Float(n) => Number::from_f64(*n).map_or(JsonValue::Null, JsonValue::Number),
+        Data::String(s) => JsonValue::String(s.clone()),
+        Data::Bool(b) => JsonValue::Bool(*b),
+        // A duration is a number of days; seconds are what scripts compare.
+        Data::DateTime(t) if t.is_duration() => Number::from_f64(t.as_f64() * 86_400.0).map_or(JsonValue::Null, JsonValue::Number),
+        Data::DateTime(t) => {
+            let (year, month, day, hour, minute, second, milli) = t.to_ymd_hms_milli();
+            JsonValue::String(match milli {
+                0 => format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, hour, minute, second),
+                milli => format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}", year, month, day, hour, minute, second, milli),
+            })
+        },
+        Data::DateTimeIso(s) | Data::DurationIso(s) => JsonValue::String(s.clone()),
+        Data::Error(e) => JsonValue::String(e.to_string()),
+        Data::Empty => JsonValue::Null,
+    }
+}
+
+/// The rows of an `.xlsx`, `.xlsm`, `.xlsb`, `.xls` or `.ods` sheet, with typed cells: numbers
+/// (whole ones as integers), booleans and strings as they are, dates as ISO 8601 strings
+/// (`2024-05-01T00:00:00`), durations as seconds and error cells as their text (`#DIV/0!`).
+/// Empty cells are `null`.
+pub fn spreadsheet_to_json(path: impl AsRef<Path>, options: &SheetOptions) -> rlua::Result<JsonValue> {
+    let mut workbook = calamine::open_workbook_auto(path).map_err(sheet_error)?;
+    let sheet = match &options.sheet {
+        Some(sheet) => sheet.clone(),
+        None => workbook.sheet_names().into_iter().next().ok_or_else(|| sheet_error("the workbook has no sheets"))?,
+    };
+    let range = workbook.worksheet_range(&sheet).map_err(sheet_error)?;
+    let mut rows = range.rows();
+    let headers: Option<Vec<String>> = match options.headers {
+        true => rows.next().map(|header| (0..range.width()).map(|i| match header.get(i).map(cell_value) {
+            Some(JsonValue::String(name)) if !name.trim().is_empty() => name,
+            Some(JsonValue::Null) | Some(JsonValue::String(_)) | None => column_letter(range.start().map_or(0, |(_, c)| c as usize) + i),
+            Some(other) => other.to_string(),
+        }).collect()),
+        false => None,
+    };
+    Ok(JsonValue::Array(rows
+        .filter(|row| !options.skip_empty_rows || row.iter().any(|cell| *cell != Data::Empty))
+        .map(|row| match &headers {
+            Some(headers) => JsonValue::Object(headers.iter().zip(row)
+                .filter(|(_, cell)| **cell != Data::Empty)
+                .map(|(name, cell)| (name.clone(), cell_value(cell)))
+                .collect::<Map<_, _>>()),
+            None => JsonValue::Array(row.iter().map(cell_value).collect()),
+        })
+        .collect()))
+}
+
+/// [`spreadsheet_to_json`] as a Lua array of row tables, converted with `options`.
+pub fn spreadsheet_to_lua<'lua>(
+    lua: &'lua Lua, path: impl AsRef<Path>, sheet_options: &SheetOptions, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &spreadsheet_to_json(path, sheet_options)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, SheetOptions, spreadsheet_to_json, spreadsheet_to_lua};
+    use super::column_letter;
+
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0u32, |crc, b| (0..8).fold(crc ^ u32::from(*b), |crc, _| (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())))
+    }
+
+    /// An uncompressed zip archive, which is all an `.xlsx` is.
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let (mut out, mut directory) = (Vec::new(), Vec::new());
+        for (name, content) in files {
+            let offset = out.len() as u32;
+            let header = |signature: u32, central: bool| {
+                let mut h = signature.to_le_bytes().to_vec();
+                if central {
+                    h.extend(20u16.to_le_bytes());
+                }
+                h.extend([20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+                h.extend(crc32(content.as_bytes()).to_le_bytes());
+                h.extend((content.len() as u32).to_le_bytes());
+                h.extend((content.len() as u32).to_le_bytes());
+                h.extend((name.len() as u16).to_le_bytes());
+                h.extend([0, 0]);
+                if central {
+                    h.extend([0; 10]);
+                    h.extend(offset.to_le_bytes());
+                }
+                h.extend(name.as_bytes());
+                h
+            };
+            out.extend(header(0x0403_4b50, false));
+            out.extend(content.as_bytes());
+            directory.extend(header(0x0201_4b50, true));
+        }
+        let (start, size) = (out.len() as u32, directory.len() as u32);
+        out.extend(directory);
+        out.extend(0x0605_4b50u32.to_le_bytes());
+        out.extend([0, 0, 0, 0]);
+        out.extend((files.len() as u16).to_le_bytes());
+        out.extend((files.len() as u16).to_le_bytes());
+        out.extend(size.to_le_bytes());
+        out.extend(start.to_le_bytes());
+        out.extend([0, 0]);
+        out
+    }
+
+    const SHEET: &str = r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>
+        <row r="1"><c r="A1" t="inlineStr"><is><t>name</t></is></c><c r="B1" t="inlineStr"><is><t>age</t></is></c><c r="C1" t="inlineStr"><is><t>active</t></is></c><c r="D1" t="inlineStr"><is><t>joined</t></is></c></row>
+        <row r="2"><c r="A2" t="inlineStr"><is><t>Ann</t></is></c><c r="B2"><v>42</v></c><c r="C2" t="b"><v>1</v></c><c r="D2" s="1"><v>45413</v></c><c r="E2"><v>1.5</v></c></row>
+        <row r="4"><c r="A4" t="inlineStr"><is><t>Bob</t></is></c><c r="C4" t="b"><v>0</v></c><c r="E4" t="e"><v>#DIV/0!</v></c></row>
+        </sheetData></worksheet>"#;
+
+    fn write_workbook(path: &std::path::Path) {
+        std::fs::write(path, zip(&[
+            ("[Content_Types].xml", r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#),
+            ("_rels/.rels", r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#),
+            ("xl/workbook.xml", r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="People" sheetId="1" r:id="rId1"/></sheets></workbook>"#),
+            ("xl/_rels/workbook.xml.rels", r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#),
+            ("xl/styles.xml", r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><cellXfs count="2"><xf numFmtId="0"/><xf numFmtId="14" applyNumberFormat="1"/></cellXfs></styleSheet>"#),
+            ("xl/worksheets/sheet1.xml", SHEET),
+        ])).expect("write workbook");
+    }
+
+    #[test]
+    fn spreadsheets() {
+        assert_eq!([0, 25, 26, 701, 702].map(column_letter), ["A", "Z", "AA", "ZZ", "AAA"]);
+
+        let dir = std::env::temp_dir().join(format!("rlua_json_spreadsheet_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let path = dir.join("people.xlsx");
+        write_workbook(&path);
+
+        let rows = spreadsheet_to_json(&path, &SheetOptions::default()).expect("read");
+        assert_eq!(rows, json!([
+            {"name": "Ann", "age": 42, "active": true, "joined": "2024-05-01T00:00:00", "E": 1.5},
+            {"name": "Bob", "active": false, "E": "#DIV/0!"},
+        ]));
+        let options = SheetOptions { sheet: Some("People".to_string()), headers: false, skip_empty_rows: false };
+        let rows = spreadsheet_to_json(&path, &options).expect("read");
+        assert_eq!(rows.as_array().map(Vec::len), Some(4));
+        assert_eq!(rows[0], json!(["name", "age", "active", "joined", null]));
+        assert_eq!(rows[2], json!([null, null, null, null, null]));
+        assert!(spreadsheet_to_json(&path, &SheetOptions { sheet: Some("Missing".to_string()), ..Default::default() }).is_err());
+        assert!(spreadsheet_to_json(dir.join("missing.xlsx"), &SheetOptions::default()).is_err());
+
+        let lua = Lua::new();
+        let rows = spreadsheet_to_lua(&lua, &path, &SheetOptions::default(), &ConversionOptions::default()).expect("lua");
+        lua.globals().set("rows", rows).expect("set");
+        lua.load(r#"assert(#rows == 2 and rows[1].name == "Ann" and rows[1].age == 42 and rows[2].age == nil)"#).exec().expect("lua");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,130 @@
+use rlua::Lua;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua, lua_to_json};
+
+fn column_value(value: ValueRef) -> JsonValue {
+    match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Integer(n) => JsonValue::from(n),
+        ValueRef::Real(n) => Number::from_f64(n).map_or(JsonValue::Null, JsonValue::Number),
+        ValueRef::Text(text) => JsonValue::String(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => {
+            let mut o = Map::new();
+            o.insert("$type".to_string(), JsonValue::from("data"));
+            o.insert("value".to_string(), JsonValue::String(base64::encode(bytes)));
+            JsonValue::Object(o)
+        },
+    }
+}
+
+/// A row as an object keyed by column name: integers, reals and text as they are, `NULL` as
+/// `null` and blobs as `{"$type": "data", "value": "<base64>"}`, as `UnsupportedPolicy::Tagged`
+/// tags values JSON has no type for.
+pub fn row_to_json(row: &rusqlite::Row) -> rusqlite::Result<JsonValue> {
+    let statement = row.as_ref();
+    let mut o = Map::new();
+    for (i, name) in statement.column_names().into_iter().enumerate() {
+        o.insert(name.to_string(), column_value(row.get_ref(i)?));
+    }
+    Ok(JsonValue::Object(o))
+}
+
+fn sql_value(value: JsonValue) -> rlua::Result<SqlValue> {
+    Ok(match value {
+        JsonValue::Null => SqlValue::Null,
+        JsonValue::Bool(b) => SqlValue::Integer(i64::from(b)),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(n) => SqlValue::Integer(n),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => SqlValue::Text(s),
+        JsonValue::Object(o) if o.len() == 2 && o.get("$type").and_then(JsonValue::as_str) == Some("data") => {
+            let text = o.get("value").and_then(JsonValue::as_str).unwrap_or_default();
+            SqlValue::Blob(base64::decode(text).ok_or_else(|| rlua::Error::RuntimeError(format!("invalid base64 data {:?}", text)))?)
+        },
+        // SQLite's JSON functions read it back: `json_extract(:tags, '$[0]')`.
+        container => SqlValue::Text(container.to_string()),
+    })
+}
+
+/// A table of named parameters, converted with `options`: `{ id = 7 }` binds `:id`; a key that
+/// already starts with `:`, `@` or `$` is used as it is. Booleans bind as `0`/`1`, nested tables
+/// as JSON text and tagged data as blobs.
+pub fn lua_to_sql_params<'lua>(lua: &'lua Lua, params: rlua::Table<'lua>, options: &ConversionOptions) -> rlua::Result<Vec<(String, SqlValue)>> {
+    let JsonValue::Object(o) = lua_to_json(lua, rlua::Value::Table(params), options)? else {
+        return Err(rlua::Error::RuntimeError("named parameters must be a table with string keys".to_string()));
+    };
+    o.into_iter().map(|(name, value)| {
+        let name = match name.starts_with([':', '@', '$']) {
+            true => name,
+            false => format!(":{}", name),
+        };
+        Ok((name, sql_value(value)?))
+    }).collect()
+}
+
+fn bound(params: &[(String, SqlValue)]) -> Vec<(&str, &SqlValue)> {
+    params.iter().map(|(name, value)| (name.as_str(), value)).collect()
+}
+
+/// Runs a query and returns its rows as a Lua array of [`row_to_json`] tables, converted with
+/// `options`, binding `params` as [`lua_to_sql_params`] describes.
+pub fn query_to_lua<'lua>(
+    lua: &'lua Lua, connection: &rusqlite::Connection, sql: &str, params: Option<rlua::Table<'lua>>, options: &ConversionOptions,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let params = params.map(|p| lua_to_sql_params(lua, p, options)).transpose()?.unwrap_or_default();
+    let mut statement = connection.prepare(sql).map_err(rlua::Error::external)?;
+    let rows = statement.query(bound(&params).as_slice()).map_err(rlua::Error::external)?
+        .mapped(row_to_json)
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(rlua::Error::external)?;
+    json_to_lua(lua, &JsonValue::Array(rows), options)
+}
+
+/// Runs a statement that returns no rows, binding `params` as [`query_to_lua`] does, and returns
+/// the number of rows changed.
+pub fn execute_with_lua<'lua>(
+    lua: &'lua Lua, connection: &rusqlite::Connection, sql: &str, params: rlua::Table<'lua>, options: &ConversionOptions,
+) -> rlua::Result<usize> {
+    let params = lua_to_sql_params(lua, params, options)?;
+    connection.execute(sql, bound(&params).as_slice()).map_err(rlua::Error::external)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, execute_with_lua, query_to_lua};
+
+    #[test]
+    fn sqlite_rows() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let connection = rusqlite::Connection::open_in_memory().expect("open");
+        connection.execute_batch("CREATE TABLE items (id INTEGER, name TEXT, price REAL, active INTEGER, tags TEXT, icon BLOB)").expect("create");
+
+        let insert = "INSERT INTO items VALUES (:id, :name, :price, :active, :tags, :icon)";
+        let apple: rlua::Table = lua.load(r#"{
+            id = 1, name = "apple", price = 1.25, active = true, tags = { "fruit", "red" }, icon = { ["$type"] = "data", value = "AAH/" },
+        }"#).eval().expect("apple");
+        assert_eq!(execute_with_lua(&lua, &connection, insert, apple, &options).expect("insert"), 1);
+        let pear: rlua::Table = lua.load(r#"{ id = 2, name = "pear", price = 0.5, active = false, tags = "[]", [":icon"] = "" }"#).eval().expect("pear");
+        execute_with_lua(&lua, &connection, insert, pear, &options).expect("insert");
+        let unknown: rlua::Table = lua.load(r#"{ id = 3, colour = "red" }"#).eval().expect("unknown");
+        assert!(execute_with_lua(&lua, &connection, "INSERT INTO items (id) VALUES (:id)", unknown, &options).is_err());
+
+        let params: rlua::Table = lua.load(r#"{ min = 0 }"#).eval().expect("params");
+        let result = query_to_lua(&lua, &connection,
+            "SELECT id, name, price, active, json_extract(tags, '$[1]') AS second_tag, icon FROM items WHERE price > :min ORDER BY id",
+            Some(params), &options).expect("query");
+        lua.globals().set("rows", result).expect("set");
+        lua.load(r#"
+            assert(#rows == 2)
+            local apple, pear = rows[1], rows[2]
+            assert(apple.id == 1 and apple.name == "apple" and apple.price == 1.25 and apple.active == 1)
+            assert(apple.second_tag == "red" and apple.icon["$type"] == "data" and apple.icon.value == "AAH/")
+            assert(pear.active == 0 and pear.second_tag == nil and pear.icon == "")
+        "#).exec().expect("lua");
+        assert!(query_to_lua(&lua, &connection, "SELECT * FROM missing", None, &options).is_err());
+    }
+}
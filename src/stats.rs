@@ -0,0 +1,141 @@
+use std::time::Duration;
+use serde_json::Value as JsonValue;
+
+/// Counters collected during one conversion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionStats {
+    /// Values converted, containers included.
+    pub nodes: usize,
+    /// Deepest nesting level reached; the root is at depth 0.
+    pub max_depth: usize,
+    /// Strings created, object keys included.
+    pub strings: usize,
+    /// Total length of those strings in bytes.
+    pub string_bytes: usize,
+    /// Wall time of the conversion.
+    pub elapsed: Duration,
+    /// Bytes produced: the net growth of the Lua heap when decoding, the length of the
+    /// compact JSON text of the result when encoding.
+    pub output_bytes: usize,
+}
+
+impl ConversionStats {
+    pub(crate) fn node(&mut self, depth: usize) {
+        self.nodes += 1;
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    pub(crate) fn string(&mut self, len: usize) {
+        self.strings += 1;
+        self.string_bytes += len;
+    }
+
+    /// Length of the compact JSON text of `value`, without building it.
+    pub(crate) fn json_bytes(value: &JsonValue) -> usize {
+        struct Counter(usize);
+        impl std::io::Write for Counter {
+            fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+                self.0 += data.len();
+                Ok(data.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut counter = Counter(0);
+        serde_json::to_writer(&mut counter, value).map_or(0, |()| counter.0)
+    }
+
+    /// `elapsed` stays zero on `wasm32-unknown-unknown`, which has no clock.
+    #[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+    pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let start = std::time::Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    }
+
+    #[cfg(all(target_family = "wasm", target_os = "unknown"))]
+    pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        (f(), Duration::ZERO)
+    }
+}
+
+/// A `conversion` span for one conversion in `direction` (`"decode"` or `"encode"`), or `None`
+/// when no subscriber is interested, so the counters need not be collected.
+#[cfg(feature = "tracing")]
+pub(crate) fn conversion_span(direction: &'static str) -> Option<tracing::Span> {
+    use tracing::field::Empty;
+    let span = tracing::debug_span!("conversion", direction, nodes = Empty, max_depth = Empty, output_bytes = Empty, elapsed_us = Empty);
+    (!span.is_disabled()).then_some(span)
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn record_span(span: &tracing::Span, stats: &ConversionStats) {
+    span.record("nodes", stats.nodes);
+    span.record("max_depth", stats.max_depth);
+    span.record("output_bytes", stats.output_bytes);
+    span.record("elapsed_us", u64::try_from(stats.elapsed.as_micros()).unwrap_or(u64::MAX));
+}
+
+/// A subscriber that writes down the spans and events it sees, one line each.
+#[cfg(all(test, feature = "tracing"))]
+pub(crate) mod capture {
+    use std::fmt::{Debug, Write};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    pub struct Capture {
+        pub lines: Arc<Mutex<Vec<String>>>,
+        next_id: AtomicU64,
+    }
+
+    struct Line<'a>(&'a mut String);
+
+    impl Visit for Line<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+
+    impl Capture {
+        fn push(&self, line: String) {
+            self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(line);
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut line = format!("span {}", span.metadata().name());
+            span.record(&mut Line(&mut line));
+            self.push(line);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            let mut line = "record".to_string();
+            values.record(&mut Line(&mut line));
+            self.push(line);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = format!("event {}", event.metadata().level());
+            event.record(&mut Line(&mut line));
+            self.push(line);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::BufRead;
+use rlua::Lua;
+use crate::{ConversionOptions, parse_into_lua};
+
+/// Framing of a stream of JSON values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// One value per line; blank lines are skipped.
+    Ndjson,
+    /// Server-Sent Events: the `data:` lines of an event form one value, and `event:` names it.
+    /// A `[DONE]` data payload ends the stream.
+    ServerSentEvents,
+}
+
+/// Limits of [`stream_into_lua`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamLimits {
+    /// Stop after this many events.
+    pub max_events: Option<usize>,
+    /// Fail on a line or SSE event longer than this.
+    pub max_event_bytes: usize,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        StreamLimits { max_events: None, max_event_bytes: 1024 * 1024 }
+    }
+}
+
+/// Calls `callback(value, event_name)` for each value of a stream; `event_name` is `nil` for
+/// NDJSON and unnamed events. Input is read only as the callback asks for more, so a slow
+/// callback slows the producer down; returning `false` stops the stream. Returns the number
+/// of events delivered.
+pub fn stream_into_lua<'lua>(
+    lua: &'lua Lua, mut reader: impl BufRead, format: StreamFormat, callback: rlua::Function<'lua>,
+    limits: &StreamLimits, options: &ConversionOptions,
+) -> rlua::Result<usize> {
+    let mut delivered = 0;
+    let mut line = Vec::new();
+    let mut data = Vec::new();
+    let mut event: Option<String> = None;
+
+    let mut deliver = |payload: &[u8], event: Option<String>| -> rlua::Result<bool> {
+        let value = parse_into_lua(lua, payload, options)?;
+        delivered += 1;
+        let more = callback.call::<_, Option<bool>>((value, event))? != Some(false);
+        Ok(more && limits.max_events.is_none_or(|max| delivered < max))
+    };
+
+    loop {
+        line.clear();
+        let read = std::io::Read::take(&mut reader, limits.max_event_bytes as u64 + 2).read_until(b'\n', &mut line)
+            .map_err(rlua::Error::external)?;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if text.len() > limits.max_event_bytes || data.len() + text.len() > limits.max_event_bytes {
+            return Err(rlua::Error::RuntimeError(format!("stream event longer than {} bytes", limits.max_event_bytes)));
+        }
+
+        match format {
+            StreamFormat::Ndjson if !text.iter().all(u8::is_ascii_whitespace) => {
+                if !deliver(text, None)? {
+                    break;
+                }
+            },
+            StreamFormat::Ndjson => {},
+            StreamFormat::ServerSentEvents if text.is_empty() => {
+                if !data.is_empty() {
+                    if data == b"[DONE]" || !deliver(&data, event.take())? {
+                        break;
+                    }
+                    data.clear();
+                }
+                event = None;
+            },
+            StreamFormat::ServerSentEvents => {
+                let (field, value) = match text.iter().position(|b| *b == b':') {
+                    Some(i) => {
+                        let (field, rest) = text.split_at(i);
+                        let rest = rest.get(1..).unwrap_or_default();
+                        (field, rest.strip_prefix(b" ").unwrap_or(rest))
+                    },
+                    None => (text, &b""[..]),
+                };
+                match field {
+                    b"data" => {
+                        if !data.is_empty() {
+                            data.push(b'\n');
+                        }
+                        data.extend_from_slice(value);
+                    },
+                    b"event" => event = Some(String::from_utf8_lossy(value).into_owned()),
+                    _ => {},
+                }
+            },
+        }
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(delivered)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, StreamFormat, StreamLimits, stream_into_lua};
+
+    #[test]
+    fn ndjson_and_sse() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        lua.load("seen = {}").exec().expect("init");
+        let collect = lua.load("return function(v, event) seen[#seen + 1] = (event or '-') .. ':' .. v.n; return v.n < 3 end")
+            .eval::<rlua::Function>().expect("callback");
+
+        let ndjson = "{\"n\": 1}\n\n{\"n\": 2}\r\n{\"n\": 3}\n{\"n\": 4}\n";
+        let count = stream_into_lua(&lua, ndjson.as_bytes(), StreamFormat::Ndjson, collect.clone(), &StreamLimits::default(), &options);
+        assert_eq!(count.expect("ndjson"), 3);
+
+        let sse = ": comment\nevent: token\ndata: {\"n\":\ndata: 1}\n\ndata: {\"n\": 2}\n\ndata: [DONE]\n\ndata: {\"n\": 9}\n\n";
+        let count = stream_into_lua(&lua, sse.as_bytes(), StreamFormat::ServerSentEvents, collect.clone(), &StreamLimits::default(), &options);
+        assert_eq!(count.expect("sse"), 2);
+        assert_eq!(lua.load("return table.concat(seen, ' ')").eval::<String>().expect("eval"), "-:1 -:2 -:3 token:1 -:2");
+
+        let limits = StreamLimits { max_events: Some(1), max_event_bytes: 8 };
+        assert!(stream_into_lua(&lua, ndjson.as_bytes(), StreamFormat::Ndjson, collect, &limits, &options).is_ok());
+        let long = "{\"n\": 12345}\n";
+        let noop = lua.create_function(|_, ()| Ok(())).expect("function");
+        assert!(stream_into_lua(&lua, long.as_bytes(), StreamFormat::Ndjson, noop, &limits, &options).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::budget::InstructionBudget;
+use crate::{ConversionOptions, DiagnosticKind, Path, PathSegment, lua_to_json, to_string};
+use crate::convert::diagnose;
+use crate::memory::MemoryLimit;
+
+/// Globals of the template environment; library tables are copied so expressions cannot
+/// change them for the rest of the state.
+const SAFE_GLOBALS: [&str; 10] = ["math", "string", "table", "utf8", "tostring", "tonumber", "type", "pairs", "ipairs", "select"];
+
+/// A fresh environment with the safe globals, falling back to `variables`.
+fn environment<'lua>(lua: &'lua Lua, variables: Option<rlua::Table<'lua>>) -> rlua::Result<rlua::Table<'lua>> {
+    let globals = lua.globals();
+    let env = lua.create_table()?;
+    for name in SAFE_GLOBALS {
+        match globals.raw_get::<_, rlua::Value>(name)? {
+            rlua::Value::Table(library) => {
+                let copy = lua.create_table()?;
+                for pair in library.pairs::<rlua::Value, rlua::Value>() {
+                    let (k, v) = pair?;
+                    copy.raw_set(k, v)?;
+                }
+                env.raw_set(name, copy)?;
+            },
+            value => env.raw_set(name, value)?,
+        }
+    }
+    if let Some(variables) = variables {
+        let metatable = lua.create_table()?;
+        metatable.raw_set("__index", variables)?;
+        env.set_metatable(Some(metatable));
+    }
+    Ok(env)
+}
+
+/// The text between the braces, if `text` is a single placeholder.
+fn whole_placeholder(text: &str) -> Option<&str> {
+    let inner = text.trim().strip_prefix("{{")?.strip_suffix("}}")?;
+    (!inner.contains("{{") && !inner.contains("}}")).then_some(inner)
+}
+
+struct Renderer<'lua, 'a> {
+    lua: &'lua Lua,
+    env: rlua::Table<'lua>,
+    options: &'a ConversionOptions,
+    path: Path,
+}
+
+impl<'lua> Renderer<'lua, '_> {
+    fn evaluate(&self, expression: &str) -> rlua::Result<JsonValue> {
+        let value: rlua::Value = self.lua.load(format!("return {}", expression.trim()))
+            .set_name("template")
+            .set_environment(self.env.clone())
+            .eval()
+            .map_err(|e| rlua::Error::RuntimeError(format!("template {}: {{{{{}}}}}: {}", self.path, expression, e)))?;
+        lua_to_json(self.lua, value, self.options)
+    }
+
+    fn interpolate(&self, text: &str) -> rlua::Result<String> {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some((before, after)) = rest.split_once("{{") {
+            let Some((expression, after)) = after.split_once("}}") else { break };
+            out.push_str(before);
+            match self.evaluate(expression)? {
+                JsonValue::Null => {},
+                JsonValue::String(s) => out.push_str(&s),
+                value => out.push_str(&to_string(&value, self.options)?),
+            }
+            rest = after;
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn render(&mut self, value: &JsonValue) -> rlua::Result<JsonValue> {
+        Ok(match value {
+            JsonValue::String(s) => match whole_placeholder(s) {
+                Some(expression) => self.evaluate(expression)?,
+                None if s.contains("{{") => JsonValue::String(self.interpolate(s)?),
+                None => value.clone(),
+            },
+            JsonValue::Array(a) => {
+                let mut rendered = Vec::with_capacity(a.len());
+                for (i, v) in a.iter().enumerate() {
+                    self.path.push(PathSegment::Index(i));
+                    rendered.push(self.render(v)?);
+                    self.path.pop();
+                }
+                JsonValue::Array(rendered)
+            },
+            JsonValue::Object(o) => {
+                let mut rendered = serde_json::Map::new();
+                for (k, v) in o {
+                    self.path.push(PathSegment::Key(k.clone()));
+                    rendered.insert(k.clone(), self.render(v)?);
+                    self.path.pop();
+                }
+                JsonValue::Object(rendered)
+            },
+            scalar => scalar.clone(),
+        })
+    }
+}
+
+/// Lua VM instructions the expressions of one [`render_template`] call may run when
+/// `hook_instruction_budget` is not set.
+pub const TEMPLATE_INSTRUCTION_BUDGET: u32 = 1_000_000;
+
+/// Bytes the expressions of one [`render_template`] call may add to the Lua heap when
+/// `lua_memory_limit` is not set.
+pub const TEMPLATE_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Evaluates the `{{ expression }}` placeholders in the strings of `template` as Lua, with
+/// `variables` and the `math`, `string`, `table` and `utf8` libraries and a few base functions
+/// in scope, and nothing else. A string that is a single placeholder becomes the value of the
+/// expression, of whatever type; otherwise each placeholder is replaced by its string value
+/// (`nil` by nothing, tables by their JSON). Expressions are limited by
+/// `options.hook_instruction_budget`, or [`TEMPLATE_INSTRUCTION_BUDGET`] without one, and in
+/// the memory they allocate by `options.lua_memory_limit`, or [`TEMPLATE_MEMORY_LIMIT`]; LuaJIT
+/// does not support the memory limit.
+pub fn render_template<'lua>(
+    lua: &'lua Lua, template: &JsonValue, variables: Option<rlua::Table<'lua>>, options: &ConversionOptions,
+) -> rlua::Result<JsonValue> {
+    let mut memory = MemoryLimit::new(lua, options.lua_memory_limit.or(Some(TEMPLATE_MEMORY_LIMIT)));
+    match memory.tick() {
+        Err(rlua::Error::MemoryLimitNotAvailable) => {},
+        result => result?,
+    }
+    let mut renderer = Renderer { lua, env: environment(lua, variables)?, options, path: Path::new() };
+    let budget = InstructionBudget::new(options.hook_instruction_budget.or(Some(TEMPLATE_INSTRUCTION_BUDGET)));
+    InstructionBudget::run(budget.as_ref(), lua, || renderer.render(template)).inspect_err(|_| {
+        if InstructionBudget::exhausted(budget.as_ref()) {
+            diagnose(options, &renderer.path, DiagnosticKind::BudgetExceeded);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, register, render_template};
+
+    #[test]
+    fn templates() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        let variables = lua.load("{ player = { name = 'Ann', level = 3 }, items = { 'a', 'b' } }").eval().expect("variables");
+        let template = json!({
+            "title": "{{ player.name }} (level {{ player.level }})",
+            "level": "{{ player.level + 1 }}",
+            "items": ["{{items}}", "{{ #items }} items: {{ table.concat(items, ',') }}"],
+            "missing": "[{{ nothing }}]",
+            "plain": "{ not a placeholder }}",
+            "unclosed": "{{ oops",
+        });
+        assert_eq!(render_template(&lua, &template, Some(variables), &options).expect("render"), json!({
+            "title": "Ann (level 3)",
+            "level": 4,
+            "items": [["a", "b"], "2 items: a,b"],
+            "missing": "[]",
+            "plain": "{ not a placeholder }}",
+            "unclosed": "{{ oops",
+        }));
+
+        let error = render_template(&lua, &json!({"a": ["{{ os.exit() }}"]}), None, &options).expect_err("no os");
+        assert!(error.to_string().contains("template /a/0"), "{}", error);
+        assert!(render_template(&lua, &json!("{{ string.upper('x') }}{{ (function() string.upper = nil end)() }}"), None, &options).is_ok());
+        assert!(render_template(&lua, &json!("{{ string.upper('x') }}"), None, &options).is_ok());
+
+        let bounded = ConversionOptions { hook_instruction_budget: Some(10_000), ..Default::default() };
+        assert!(render_template(&lua, &json!("{{ (function() while true do end end)() }}"), None, &bounded).is_err());
+        let error = render_template(&lua, &json!("{{ (function() while true do end end)() }}"), None, &options).expect_err("default budget");
+        assert!(error.to_string().contains("instruction budget"), "{}", error);
+        #[cfg(not(feature = "luajit"))]
+        {
+            let error = render_template(&lua, &json!("{{ #string.rep('x', 2^30) }}"), None, &options).expect_err("default memory limit");
+            assert!(error.to_string().contains("memory"), "{}", error);
+            let bounded = ConversionOptions { lua_memory_limit: Some(1024 * 1024), ..Default::default() };
+            assert!(render_template(&lua, &json!("{{ #string.rep('x', 2^21) }}"), None, &bounded).is_err());
+            assert_eq!(render_template(&lua, &json!("{{ #string.rep('x', 2^21) }}"), None, &options).expect("under the limit"), json!(2097152));
+            assert_eq!(lua.set_memory_limit(0).expect("unlimited again"), 0);
+        }
+
+        register(&lua, options).expect("register");
+        lua.load(r#"
+            local config = json.render({ port = "{{ base + 1 }}", host = "{{ name }}.local" }, { base = 8079, name = "db" })
+            assert(config.port == 8080 and config.host == "db.local")
+        "#).exec().expect("lua");
+    }
+}
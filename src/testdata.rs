@@ -0,0 +1,65 @@
+//! Deterministic documents of the shapes that stress a converter, shared by the benchmarks
+//! and usable by embedders measuring their own options.
+
+use serde_json::{Map, Value as JsonValue, json};
+
+/// An object with `keys` members of mixed scalar types.
+pub fn wide_object(keys: usize) -> JsonValue {
+    let members = (0..keys).map(|i| {
+        let value = match i % 4 {
+            0 => json!(i),
+            1 => json!(i as f64 / 8.0),
+            2 => json!(format!("value {}", i)),
+            _ => json!(i % 3 == 0),
+        };
+        (format!("key_{}", i), value)
+    });
+    JsonValue::Object(members.collect::<Map<_, _>>())
+}
+
+/// Objects nested `depth` levels deep, each with a small sibling array.
+pub fn deep_nesting(depth: usize) -> JsonValue {
+    (0..depth).fold(json!({"leaf": true}), |inner, level| json!({"level": level, "tags": ["a", "b"], "child": inner}))
+}
+
+/// An array of `len` strings of 8 to 40 bytes, some needing escapes.
+pub fn string_array(len: usize) -> JsonValue {
+    JsonValue::Array((0..len).map(|i| {
+        let text = format!("item {} \"quoted\" \\ {}", i, "x".repeat(i % 16));
+        json!(if i % 5 == 0 { format!("{}\n", text) } else { text })
+    }).collect())
+}
+
+/// A `rows` by `cols` array of arrays of floats.
+pub fn numeric_matrix(rows: usize, cols: usize) -> JsonValue {
+    JsonValue::Array((0..rows).map(|r| {
+        JsonValue::Array((0..cols).map(|c| json!((r * cols + c) as f64 * 0.25)).collect())
+    }).collect())
+}
+
+/// The benchmark fixtures by name, each a few hundred kilobytes of JSON.
+pub fn fixtures() -> Vec<(&'static str, JsonValue)> {
+    vec![
+        ("wide_object", wide_object(10_000)),
+        ("deep_nesting", deep_nesting(100)),
+        ("string_array", string_array(10_000)),
+        ("numeric_matrix", numeric_matrix(200, 200)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use crate::{ConversionOptions, json_to_lua, lua_to_json};
+    use super::fixtures;
+
+    #[test]
+    fn fixtures_round_trip() {
+        let lua = Lua::new();
+        let options = ConversionOptions::default();
+        for (name, fixture) in fixtures() {
+            let value = json_to_lua(&lua, &fixture, &options).expect(name);
+            assert_eq!(lua_to_json(&lua, value, &options).expect(name), fixture, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::{Display, Formatter};
+use rlua::Lua;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use serde_path_to_error::Segment;
+use crate::{ConversionOptions, Path, PathSegment, lua_to_json};
+use crate::convert::lua_to_json_collecting;
+use crate::defaults::merge;
+
+/// Deserializes `T` from a document, locating failures as `config.graphics.resolution[1]`.
+/// An enum variant is located like the object member holding its payload.
+fn deserialize<T: DeserializeOwned>(value: &JsonValue) -> Result<T, FieldError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let mut path = Path::new();
+        for segment in e.path().iter() {
+            match segment {
+                Segment::Seq { index } => path.push(PathSegment::Index(*index)),
+                Segment::Map { key } | Segment::Enum { variant: key } => path.push(PathSegment::Key(key.clone())),
+                Segment::Unknown => {},
+            }
+        }
+        FieldError { path, message: e.into_inner().to_string() }
+    })
+}
+
+/// Converts a Lua value into `T` through its JSON form. Errors name the offending location,
+/// e.g. `graphics.resolution[1]: invalid type: string "high", expected u32`.
+pub fn from_lua_t<'lua, T: DeserializeOwned>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> rlua::Result<T> {
+    let json = lua_to_json(lua, value, options)?;
+    deserialize::<T>(&json).map_err(|e| rlua::Error::FromLuaConversionError {
+        from: "JsonValue", to: std::any::type_name::<T>(), message: Some(e.to_string()) })
+}
+
+/// A value [`from_lua_partial`] could not convert, left at its default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub path: Path,
+    pub message: String,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.path.dotted().as_str() {
+            "" => f.write_str(&self.message),
+            path => write!(f, "{}: {}", path, self.message),
+        }
+    }
+}
+
+fn value_at<'v>(value: &'v JsonValue, path: &[PathSegment]) -> Option<&'v JsonValue> {
+    path.iter().try_fold(value, |v, segment| match segment {
+        PathSegment::Key(k) => v.get(k),
+        PathSegment::Index(i) => v.get(i),
+    })
+}
+
+fn value_at_mut<'v>(value: &'v mut JsonValue, path: &[PathSegment]) -> Option<&'v mut JsonValue> {
+    path.iter().try_fold(value, |v, segment| match segment {
+        PathSegment::Key(k) => v.get_mut(k),
+        PathSegment::Index(i) => v.get_mut(i),
+    })
+}
+
+/// Puts the default back at `path`, or at its nearest ancestor the defaults have. Arrays are
+/// reset whole, and a map entry the defaults lack is removed instead. Returns whether `value` changed.
+fn reset(value: &mut JsonValue, defaults: &JsonValue, path: &[PathSegment]) -> bool {
+    let array = path.iter().position(|segment| matches!(segment, PathSegment::Index(_)));
+    let mut path = path.get(..array.unwrap_or(path.len())).unwrap_or_default();
+    while let Some((last, parent)) = path.split_last() {
+        if let Some(default) = value_at(defaults, path) {
+            return match value_at_mut(value, path) {
+                Some(current) if current != default => {
+                    *current = default.clone();
+                    true
+                },
+                _ => false,
+            };
+        }
+        if let (PathSegment::Key(k), Some(JsonValue::Object(o))) = (last, value_at_mut(value, parent)) {
+            return o.remove(k).is_some();
+        }
+        path = parent;
+    }
+    false
+}
+
+/// Converts a Lua value into `T` field by field: values that fail to convert or deserialize
+/// keep their `T::default()` value and are reported, so one typo in a hot-reloaded config
+/// does not discard the rest of it. Missing fields are filled from the defaults as well.
+///
+/// `T` is serialized once to find the defaults of nested fields.
+pub fn from_lua_partial<'lua, T: DeserializeOwned + Serialize + Default>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions,
+) -> (T, Vec<FieldError>) {
+    let defaults = match serde_json::to_value(T::default()) {
+        Ok(defaults) => defaults,
+        Err(e) => return (T::default(), vec![FieldError { path: Path::new(), message: e.to_string() }]),
+    };
+    let (converted, errors) = lua_to_json_collecting(lua, value, options);
+    let mut errors: Vec<FieldError> = errors.into_iter()
+        .map(|e| FieldError { path: e.path, message: e.error.to_string() })
+        .collect();
+    let Some(mut converted) = converted else { return (T::default(), errors) };
+    for error in &errors {
+        reset(&mut converted, &defaults, error.path.segments());
+    }
+    let mut json = defaults.clone();
+    merge(&mut json, converted);
+
+    loop {
+        match deserialize::<T>(&json) {
+            Ok(value) => return (value, errors),
+            Err(e) => {
+                let changed = reset(&mut json, &defaults, e.path.segments());
+                errors.push(e);
+                if !changed {
+                    return (T::default(), errors);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use rlua::Lua;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use crate::{ConversionOptions, PathSegment, from_lua_partial, from_lua_t};
+    use super::deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Graphics {
+        resolution: Vec<u32>,
+        vsync: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        graphics: Graphics,
+    }
+
+    #[test]
+    fn typed_errors_have_paths() {
+        let lua = Lua::new();
+        let value = lua.load("{ graphics = { resolution = { 1920, 1080 } } }").eval().expect("table");
+        let config: Config = from_lua_t(&lua, value, &ConversionOptions::default()).expect("typed");
+        assert_eq!(config, Config { graphics: Graphics { resolution: vec![1920, 1080], vsync: None } });
+
+        let value = lua.load("{ graphics = { resolution = { 1920, 'high' } } }").eval().expect("table");
+        let error = from_lua_t::<Config>(&lua, value, &ConversionOptions::default()).expect_err("mismatch");
+        assert!(error.to_string().contains(r#"graphics.resolution[1]: invalid type: string "high", expected u32"#), "{}", error);
+
+        let value = lua.load("{ graphics = {} }").eval().expect("table");
+        let error = from_lua_t::<Config>(&lua, value, &ConversionOptions::default()).expect_err("missing");
+        assert!(error.to_string().contains("graphics: missing field `resolution`"), "{}", error);
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Server {
+        ports: Vec<Vec<u16>>,
+        limits: HashMap<String, Vec<u32>>,
+        weights: Option<BTreeMap<u8, f64>>,
+        mode: Option<Mode>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    enum Mode {
+        Fixed { rate: u32 },
+    }
+
+    fn tracked_error(value: serde_json::Value) -> (Vec<PathSegment>, String) {
+        let error = deserialize::<Server>(&value).expect_err("invalid");
+        (error.path.segments().to_vec(), error.message)
+    }
+
+    #[test]
+    fn tracked_paths() {
+        let key = |k: &str| PathSegment::Key(k.to_string());
+        let (path, message) = tracked_error(json!({"ports": [[80], [443, 70000]], "limits": {}}));
+        assert_eq!(path, [key("ports"), PathSegment::Index(1), PathSegment::Index(1)]);
+        assert!(message.starts_with("invalid value: integer `70000`"), "{}", message);
+
+        let (path, message) = tracked_error(json!({"ports": [], "limits": {"max fps": [60, -1]}}));
+        assert_eq!(path, [key("limits"), key("max fps"), PathSegment::Index(1)]);
+        assert!(message.contains("expected u32"), "{}", message);
+
+        let (path, message) = tracked_error(json!({"ports": [], "limits": {"cpu": "all"}}));
+        assert_eq!(path, [key("limits"), key("cpu")]);
+        assert!(message.contains("expected a sequence"), "{}", message);
+
+        let (path, message) = tracked_error(json!({"ports": [], "limits": {}, "weights": {"heavy": 1.5}}));
+        assert_eq!(path, [key("weights"), key("heavy")]);
+        assert!(message.contains("heavy"), "{}", message);
+
+        let (path, message) = tracked_error(json!({"ports": [], "limits": {}, "mode": {"Fixed": {"rate": "fast"}}}));
+        assert_eq!(path, [key("mode"), key("Fixed"), key("rate")]);
+        assert!(message.contains("expected u32"), "{}", message);
+
+        let (path, message) = tracked_error(json!({"ports": [[1]]}));
+        assert!(path.is_empty() && message == "missing field `limits`", "{:?} {}", path, message);
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Hud {
+        scale: f64,
+        theme: String,
+        sizes: Vec<u32>,
+    }
+
+    impl Default for Hud {
+        fn default() -> Self {
+            Hud { scale: 1.0, theme: "dark".to_string(), sizes: vec![12, 16] }
+        }
+    }
+
+    #[test]
+    fn partial_keeps_good_fields() {
+        let lua = Lua::new();
+        let value = lua.load("{ scale = 'big', theme = 'light', sizes = { 10, 'x' } }").eval().expect("table");
+        let (hud, errors) = from_lua_partial::<Hud>(&lua, value, &ConversionOptions::default());
+        assert_eq!(hud, Hud { scale: 1.0, theme: "light".to_string(), sizes: vec![12, 16] });
+        let rendered: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(rendered.len(), 2, "{:?}", rendered);
+        assert!(rendered[0].starts_with("scale: invalid type"), "{:?}", rendered);
+        assert!(rendered[1].starts_with("sizes[1]: invalid type"), "{:?}", rendered);
+
+        let value = lua.load("{ theme = 'light', extra = print }").eval().expect("table");
+        let (hud, errors) = from_lua_partial::<Hud>(&lua, value, &ConversionOptions::default());
+        assert_eq!(hud, Hud { theme: "light".to_string(), ..Hud::default() });
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path.dotted(), "extra");
+
+        let value = lua.load("42").eval().expect("number");
+        let (hud, errors) = from_lua_partial::<Hud>(&lua, value, &ConversionOptions::default());
+        assert_eq!(hud, Hud::default());
+        assert_eq!(errors.len(), 1);
+    }
+}
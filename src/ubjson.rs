@@ -0,0 +1,308 @@
+use std::sync::{Arc, Mutex};
+use rlua::Lua;
+use serde_json::{Map, Number, Value as JsonValue};
+use crate::{ConversionOptions, base64, json_to_lua, lua_to_json};
+use crate::module::format_table;
+
+/// Containers a document may nest before it is rejected.
+const MAX_DEPTH: usize = 128;
+
+fn ubjson_error(message: impl std::fmt::Display) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("ubjson: {}", message))
+}
+
+fn data(bytes: &[u8]) -> JsonValue {
+    let mut o = Map::new();
+    o.insert("$type".to_string(), JsonValue::from("data"));
+    o.insert("value".to_string(), JsonValue::String(base64::encode(bytes)));
+    JsonValue::Object(o)
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> rlua::Result<&'a [u8]> {
+        let bytes = self.input.get(self.position..self.position.saturating_add(n)).ok_or_else(|| ubjson_error("unexpected end of input"))?;
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn marker(&mut self) -> rlua::Result<u8> {
+        loop {
+            match self.bytes(1)?.first() {
+                Some(b'N') => continue,
+                Some(&marker) => return Ok(marker),
+                None => return Err(ubjson_error("unexpected end of input")),
+            }
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> rlua::Result<[u8; N]> {
+        self.bytes(N)?.try_into().map_err(|_| ubjson_error("unexpected end of input"))
+    }
+
+    fn integer(&mut self, marker: u8) -> rlua::Result<Option<i64>> {
+        Ok(Some(match marker {
+            b'i' => i64::from(i8::from_be_bytes(self.array()?)),
+            b'U' => i64::from(u8::from_be_bytes(self.array()?)),
+            b'I' => i64::from(i16::from_be_bytes(self.array()?)),
+            b'l' => i64::from(i32::from_be_bytes(self.array()?)),
+            b'L' => i64::from_be_bytes(self.array()?),
+            _ => return Ok(None),
+        }))
+    }
+
+    /// A string length or container count. One larger than the bytes left is an error before
+    /// anything is allocated for it, which also rules out counted containers of `Z`, `T` or `F`
+    /// longer than the rest of the document.
+    fn length(&mut self) -> rlua::Result<usize> {
+        let marker = self.marker()?;
+        match self.integer(marker)? {
+            Some(n) if n >= 0 && n as u64 <= self.input.len().saturating_sub(self.position) as u64 => Ok(n as usize),
+            Some(n) => Err(ubjson_error(format!("invalid length {}", n))),
+            None => Err(ubjson_error(format!("expected a length, found {:?}", char::from(marker)))),
+        }
+    }
+
+    fn string(&mut self) -> rlua::Result<String> {
+        let length = self.length()?;
+        String::from_utf8(self.bytes(length)?.to_vec()).map_err(|_| ubjson_error("string is not UTF-8"))
+    }
+
+    /// The `$type` and `#count` of an optimized container, if it has them.
+    fn optimized(&mut self) -> rlua::Result<(Option<u8>, Option<usize>)> {
+        let kind = match self.input.get(self.position) {
+            Some(b'$') => {
+                self.position += 1;
+                Some(self.bytes(1)?.first().copied().unwrap_or_default())
+            },
+            _ => None,
+        };
+        let count = match self.input.get(self.position) {
+            Some(b'#') => {
+                self.position += 1;
+                Some(self.length()?)
+            },
+            _ if kind.is_some() => return Err(ubjson_error("a container with a type needs a count")),
+            _ => None,
+        };
+        Ok((kind, count))
+    }
+
+    fn container_value(&mut self, kind: Option<u8>, depth: usize) -> rlua::Result<JsonValue> {
+        match kind {
+            Some(marker) => self.typed_value(marker, depth),
+            None => self.value(depth),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> rlua::Result<JsonValue> {
+        let marker = self.marker()?;
+        self.typed_value(marker, depth)
+    }
+
+    fn typed_value(&mut self, marker: u8, depth: usize) -> rlua::Result<JsonValue> {
+        if depth > MAX_DEPTH {
+            return Err(ubjson_error("document nested too deeply"));
+        }
+        if let Some(n) = self.integer(marker)? {
+            return Ok(JsonValue::from(n));
+        }
+        Ok(match marker {
+            b'Z' => JsonValue::Null,
+            b'T' => JsonValue::Bool(true),
+            b'F' => JsonValue::Bool(false),
+            b'd' => Number::from_f64(f64::from(f32::from_be_bytes(self.array()?))).map_or(JsonValue::Null, JsonValue::Number),
+            b'D' => Number::from_f64(f64::from_be_bytes(self.array()?)).map_or(JsonValue::Null, JsonValue::Number),
+            b'H' => {
+                let digits = self.string()?;
+                match (digits.parse::<i64>(), digits.parse::<u64>()) {
+                    (Ok(n), _) => JsonValue::from(n),
+                    (_, Ok(n)) => JsonValue::from(n),
+                    _ => JsonValue::String(digits),
+                }
+            },
+            b'C' => JsonValue::String(char::from(self.bytes(1)?.first().copied().unwrap_or_default()).to_string()),
+            b'S' => JsonValue::String(self.string()?),
+            b'[' => match self.optimized()? {
+                (Some(b'U'), Some(count)) => data(self.bytes(count)?),
+                (kind, Some(count)) => JsonValue::Array((0..count).map(|_| self.container_value(kind, depth + 1)).collect::<rlua::Result<_>>()?),
+                (_, None) => {
+                    let mut items = Vec::new();
+                    while self.input.get(self.position) != Some(&b']') {
+                        items.push(self.value(depth + 1)?);
+                    }
+                    self.position += 1;
+                    JsonValue::Array(items)
+                },
+            },
+            b'{' => {
+                let (kind, count) = self.optimized()?;
+                let mut o = Map::new();
+                let mut remaining = count;
+                loop {
+                    match remaining {
+                        Some(0) => break,
+                        Some(n) => remaining = Some(n - 1),
+                        None if self.input.get(self.position) == Some(&b'}') => {
+                            self.position += 1;
+                            break;
+                        },
+                        None => {},
+                    }
+                    let key = self.string()?;
+                    o.insert(key, self.container_value(kind, depth + 1)?);
+                }
+                JsonValue::Object(o)
+            },
+            other => return Err(ubjson_error(format!("unknown marker {:?}", char::from(other)))),
+        })
+    }
+}
+
+/// Reads a UBJSON document, including optimized (`$` typed, `#` counted) containers. An
+/// optimized array of `U` bytes is binary data, `{"$type": "data", "value": "<base64>"}`;
+/// high-precision numbers past 64 bits stay their digits.
+pub fn ubjson_to_json(bytes: &[u8]) -> rlua::Result<JsonValue> {
+    let mut reader = Reader { input: bytes, position: 0 };
+    let value = reader.value(0)?;
+    match reader.position == bytes.len() {
+        true => Ok(value),
+        false => Err(ubjson_error("trailing bytes after the document")),
+    }
+}
+
+fn write_length(n: usize, out: &mut Vec<u8>) {
+    write_integer(n as i64, out);
+}
+
+fn write_integer(n: i64, out: &mut Vec<u8>) {
+    if let Ok(n) = i8::try_from(n) {
+        out.push(b'i');
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = u8::try_from(n) {
+        out.push(b'U');
+        out.push(n);
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(b'I');
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(b'l');
+        out.extend(n.to_be_bytes());
+    } else {
+        out.push(b'L');
+        out.extend(n.to_be_bytes());
+    }
+}
+
+fn write_string(text: &str, out: &mut Vec<u8>) {
+    write_length(text.len(), out);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn write(value: &JsonValue, out: &mut Vec<u8>) -> rlua::Result<()> {
+    match value {
+        JsonValue::Null => out.push(b'Z'),
+        JsonValue::Bool(b) => out.push(if *b { b'T' } else { b'F' }),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => write_integer(n, out),
+            (None, Some(n)) => {
+                out.push(b'H');
+                write_string(&n.to_string(), out);
+            },
+            _ => {
+                out.push(b'D');
+                out.extend(n.as_f64().unwrap_or_default().to_be_bytes());
+            },
+        },
+        JsonValue::String(s) => {
+            out.push(b'S');
+            write_string(s, out);
+        },
+        JsonValue::Array(a) => {
+            out.push(b'[');
+            for item in a {
+                write(item, out)?;
+            }
+            out.push(b']');
+        },
+        JsonValue::Object(o) if o.len() == 2 && o.get("$type").and_then(JsonValue::as_str) == Some("data") => {
+            let text = o.get("value").and_then(JsonValue::as_str).unwrap_or_default();
+            let bytes = base64::decode(text).ok_or_else(|| ubjson_error(format!("invalid base64 data {:?}", text)))?;
+            out.extend_from_slice(b"[$U#");
+            write_length(bytes.len(), out);
+            out.extend(bytes);
+        },
+        JsonValue::Object(o) => {
+            out.push(b'{');
+            for (key, value) in o {
+                write_string(key, out);
+                write(value, out)?;
+            }
+            out.push(b'}');
+        },
+    }
+    Ok(())
+}
+
+/// Writes a value as UBJSON: integers in the smallest type that holds them, other numbers as
+/// `D` doubles, and tagged data as an optimized array of `U` bytes.
+pub fn json_to_ubjson(value: &JsonValue) -> rlua::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write(value, &mut out)?;
+    Ok(out)
+}
+
+/// [`ubjson_to_json`], converted into a Lua value with `options`.
+pub fn ubjson_to_lua<'lua>(lua: &'lua Lua, bytes: &[u8], options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &ubjson_to_json(bytes)?, options)
+}
+
+/// [`json_to_ubjson`] of a Lua value, converted with `options`.
+pub fn lua_to_ubjson<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<Vec<u8>> {
+    json_to_ubjson(&lua_to_json(lua, value, options)?)
+}
+
+/// The `json.ubjson` table: `decode(bytes)` and `encode(value)`.
+pub(crate) fn create_ubjson_table<'lua>(lua: &'lua Lua, options: &Arc<Mutex<ConversionOptions>>) -> rlua::Result<rlua::Table<'lua>> {
+    format_table(lua, options, ubjson_to_lua, |lua, value, (), options| lua_to_ubjson(lua, value, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_ubjson, register, ubjson_to_json};
+
+    #[test]
+    fn ubjson_documents() {
+        assert_eq!(json_to_ubjson(&json!({"a": [1, 300, -2.5, "hi", null, true]})).expect("write"),
+            b"{i\x01a[i\x01I\x01\x2cD\xc0\x04\x00\x00\x00\x00\x00\x00Si\x02hiZT]}");
+        let value = json!({
+            "n": [0, -129, 70000, 5_000_000_000i64, 18446744073709551615u64, 0.5], "s": "héllo",
+            "blob": {"$type": "data", "value": "AAH/"}, "empty": {}, "list": [[], [{}]],
+        });
+        assert_eq!(ubjson_to_json(&json_to_ubjson(&value).expect("write")).expect("reparse"), value);
+
+        // Optimized containers, no-ops, chars and high-precision numbers.
+        assert_eq!(ubjson_to_json(b"[$i#i\x03\x01\x02\x03").expect("typed"), json!([1, 2, 3]));
+        assert_eq!(ubjson_to_json(b"{#i\x02i\x01xCzi\x01yNHi\x0212").expect("counted").get("y"), Some(&json!(12)));
+        assert_eq!(ubjson_to_json(b"Hi\x1e123456789012345678901234567890").expect("big"), json!("123456789012345678901234567890"));
+        assert!(ubjson_to_json(b"[i\x01").is_err());
+        assert!(ubjson_to_json(b"Si\x05ab").is_err());
+        assert!(ubjson_to_json(b"ZZ").is_err());
+        assert!(ubjson_to_json(b"X").is_err());
+        assert!(ubjson_to_json(&b"[".repeat(200)).is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local bytes = json.ubjson.encode({ name = "x" })
+            assert(bytes == "{i\4nameSi\1x}")
+            assert(json.ubjson.decode(bytes).name == "x")
+        "#).exec().expect("lua");
+    }
+}
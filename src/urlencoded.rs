@@ -0,0 +1,203 @@
+use rlua::Lua;
+use serde_json::{Map, Value as JsonValue};
+use crate::{ConversionOptions, json_to_lua, lua_to_json};
+
+/// Brackets a decoded key may nest; the rest of a deeper key is kept as one literal segment.
+const MAX_NESTING: usize = 32;
+
+fn percent_encode(text: &str, out: &mut String) {
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => out.push(char::from(b)),
+            b' ' => out.push('+'),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+}
+
+/// `+` is a space; malformed escapes are kept as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while let Some(&b) = bytes.get(i) {
+        let escaped = (b == b'%').then(|| bytes.get(i + 1..i + 3)).flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (b, escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (b'+', None) => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            (b, None) => {
+                decoded.push(b);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn scalar_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn encode_pairs(key: &str, value: &JsonValue, pairs: &mut Vec<(String, String)>) {
+    match value {
+        JsonValue::Object(o) => o.iter().for_each(|(k, v)| encode_pairs(&format!("{}[{}]", key, k), v, pairs)),
+        JsonValue::Array(a) => for (i, v) in a.iter().enumerate() {
+            match v {
+                JsonValue::Object(_) | JsonValue::Array(_) => encode_pairs(&format!("{}[{}]", key, i), v, pairs),
+                scalar => pairs.push((format!("{}[]", key), scalar_text(scalar))),
+            }
+        },
+        scalar => pairs.push((key.to_string(), scalar_text(scalar))),
+    }
+}
+
+/// `application/x-www-form-urlencoded` text of an object, as a query string or form body:
+/// nested objects become `a[b]=...`, arrays of scalars `tags[]=x&tags[]=y` and arrays of
+/// containers `items[0][name]=...`. Booleans and numbers are written as in JSON, `null` as an
+/// empty value; empty containers are left out.
+pub fn json_to_urlencoded(value: &JsonValue) -> rlua::Result<String> {
+    let JsonValue::Object(o) = value else {
+        return Err(rlua::Error::RuntimeError("only an object can be form-encoded".to_string()));
+    };
+    let mut pairs = Vec::new();
+    o.iter().for_each(|(k, v)| encode_pairs(k, v, &mut pairs));
+    let mut out = String::new();
+    for (i, (k, v)) in pairs.iter().enumerate() {
+        if i > 0 {
+            out.push('&');
+        }
+        percent_encode(k, &mut out);
+        out.push('=');
+        percent_encode(v, &mut out);
+    }
+    Ok(out)
+}
+
+/// `name[a][]` as `["name", "a", ""]`.
+fn key_segments(key: &str) -> Vec<String> {
+    let (name, mut rest) = match key.find('[') {
+        Some(i) if i > 0 => key.split_at(i),
+        _ => return vec![key.to_string()],
+    };
+    let mut segments = vec![name.to_string()];
+    while let Some((segment, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        if segments.len() > MAX_NESTING {
+            break;
+        }
+        segments.push(segment.to_string());
+        rest = after;
+    }
+    if !rest.is_empty() {
+        if let Some(last) = segments.last_mut() {
+            last.push_str(rest);
+        }
+    }
+    segments
+}
+
+fn insert(container: &mut JsonValue, segments: &[String], value: String) {
+    let Some((segment, rest)) = segments.split_first() else {
+        *container = JsonValue::String(value);
+        return;
+    };
+    let wants_array = segment.is_empty() || segment.parse::<usize>().is_ok();
+    match (&*container, wants_array) {
+        (JsonValue::Array(_), true) | (JsonValue::Object(_), _) => {},
+        (_, true) => *container = JsonValue::Array(Vec::new()),
+        (_, false) => *container = JsonValue::Object(Map::new()),
+    }
+    match container {
+        JsonValue::Array(a) => {
+            let index = segment.parse::<usize>().ok().filter(|i| *i < a.len()).unwrap_or(a.len());
+            if index == a.len() {
+                a.push(JsonValue::Null);
+            }
+            if let Some(slot) = a.get_mut(index) {
+                insert(slot, rest, value);
+            }
+        },
+        JsonValue::Object(o) => {
+            let slot = o.entry(segment.clone()).or_insert(JsonValue::Null);
+            // A repeated plain key collects its values, as most form parsers do.
+            match (rest.is_empty(), &mut *slot) {
+                (true, JsonValue::String(first)) => *slot = JsonValue::Array(vec![JsonValue::String(std::mem::take(first)), JsonValue::String(value)]),
+                (true, JsonValue::Array(values)) => values.push(JsonValue::String(value)),
+                _ => insert(slot, rest, value),
+            }
+        },
+        _ => {},
+    }
+}
+
+/// The object `application/x-www-form-urlencoded` text describes, by the conventions
+/// [`json_to_urlencoded`] writes: values are strings, `a[b]` nests, `a[]` appends, `a[0]`
+/// indexes (an index past the end appends) and a repeated plain key collects its values into
+/// an array. A leading `?` is skipped.
+pub fn urlencoded_to_json(text: &str) -> JsonValue {
+    let mut object = JsonValue::Object(Map::new());
+    for pair in text.strip_prefix('?').unwrap_or(text).split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        insert(&mut object, &key_segments(&percent_decode(key)), percent_decode(value));
+    }
+    object
+}
+
+/// [`json_to_urlencoded`] of a Lua table, converted with `options`.
+pub fn lua_to_urlencoded<'lua>(lua: &'lua Lua, value: rlua::Value<'lua>, options: &ConversionOptions) -> rlua::Result<String> {
+    json_to_urlencoded(&lua_to_json(lua, value, options)?)
+}
+
+/// [`urlencoded_to_json`], converted into a Lua table with `options`.
+pub fn urlencoded_to_lua<'lua>(lua: &'lua Lua, text: &str, options: &ConversionOptions) -> rlua::Result<rlua::Value<'lua>> {
+    json_to_lua(lua, &urlencoded_to_json(text), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_urlencoded, register, urlencoded_to_json};
+
+    #[test]
+    fn form_encoding() {
+        let value = json!({
+            "q": "a b&c=d", "page": 2, "exact": true, "none": null, "empty": [],
+            "tags": ["x", "y"], "filter": {"min": "1", "sort": ["name"]}, "items": [{"id": "1"}, {"id": "2"}],
+        });
+        let text = json_to_urlencoded(&value).expect("encode");
+        let mut pairs: Vec<&str> = text.split('&').collect();
+        pairs.sort();
+        assert_eq!(pairs, ["exact=true", "filter%5Bmin%5D=1", "filter%5Bsort%5D%5B%5D=name", "items%5B0%5D%5Bid%5D=1",
+            "items%5B1%5D%5Bid%5D=2", "none=", "page=2", "q=a+b%26c%3Dd", "tags%5B%5D=x", "tags%5B%5D=y"]);
+        assert_eq!(urlencoded_to_json(&text), json!({
+            "q": "a b&c=d", "page": "2", "exact": "true", "none": "",
+            "tags": ["x", "y"], "filter": {"min": "1", "sort": ["name"]}, "items": [{"id": "1"}, {"id": "2"}],
+        }));
+        assert_eq!(urlencoded_to_json("?a=1&a=2&a=3&b[]=x&b[5]=y&c[d][e]=f&g&%zz=%E2%9C%93"), json!({
+            "a": ["1", "2", "3"], "b": ["x", "y"], "c": {"d": {"e": "f"}}, "g": "", "%zz": "✓",
+        }));
+        assert_eq!(urlencoded_to_json("a[b]c=1&[x]=2"), json!({"a": {"bc": "1"}, "[x]": "2"}));
+        assert!(json_to_urlencoded(&json!([1])).is_err());
+
+        let lua = Lua::new();
+        register(&lua, ConversionOptions::default()).expect("register");
+        lua.load(r#"
+            local text = json.urlencode({ user = { roles = { "admin", "dev" } } })
+            assert(text == "user%5Broles%5D%5B%5D=admin&user%5Broles%5D%5B%5D=dev", text)
+            local form = json.urldecode(json.urlencode({ user = { name = "Ann Lee", roles = { "admin", "dev" } } }))
+            assert(form.user.name == "Ann Lee" and form.user.roles[2] == "dev")
+        "#).exec().expect("lua");
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use crate::{ConversionOptions, Path, PathPattern, PathSegment, json_to_lua, lua_to_json};
+
+type ValidateFn = dyn Fn(&JsonValue) -> Result<(), String> + Send + Sync;
+
+/// Checks a value written by a script into a [`SharedDocument`](crate::SharedDocument);
+/// an `Err` message rejects the write.
+#[derive(Clone)]
+pub struct Validator(pub Arc<ValidateFn>);
+
+impl Validator {
+    pub fn new(f: impl Fn(&JsonValue) -> Result<(), String> + Send + Sync + 'static) -> Self {
+        Validator(Arc::new(f))
+    }
+
+    /// Validates against a JSON Schema fragment. Supported keywords: `type`, `enum`, `const`,
+    /// `minimum`, `maximum`, `minLength`, `maxLength`, `required`, `properties`,
+    /// `additionalProperties: false` and `items`; others are ignored.
+    pub fn schema(schema: JsonValue) -> Self {
+        Validator::new(move |value| check_schema(&schema, value, &mut Path::new()).map_err(|failure| match failure.path.is_empty() {
+            true => failure.message,
+            false => format!("{}: {}", failure.path, failure.message),
+        }))
+    }
+
+    pub fn check(&self, value: &JsonValue) -> Result<(), String> {
+        (self.0)(value)
+    }
+}
+
+impl Debug for Validator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+fn type_matches(name: &str, value: &JsonValue) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Where and why a value does not match a schema.
+struct SchemaFailure {
+    path: Path,
+    message: String,
+    expected: Option<String>,
+}
+
+fn expected_types(types: &JsonValue) -> String {
+    match types {
+        JsonValue::Array(names) => names.iter().filter_map(JsonValue::as_str).collect::<Vec<_>>().join(" or "),
+        JsonValue::String(name) => name.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn check_schema(schema: &JsonValue, value: &JsonValue, path: &mut Path) -> Result<(), SchemaFailure> {
+    let fail = |path: &Path, message: String, expected: Option<String>| {
+        Err(SchemaFailure { path: path.clone(), message, expected })
+    };
+    let Some(schema) = schema.as_object() else { return Ok(()) };
+
+    let type_ok = match schema.get("type") {
+        Some(JsonValue::String(name)) => type_matches(name, value),
+        Some(JsonValue::Array(names)) => names.iter().filter_map(JsonValue::as_str).any(|name| type_matches(name, value)),
+        _ => true,
+    };
+    if !type_ok {
+        return fail(path, format!("expected type {}, got {}", schema["type"], value), Some(expected_types(&schema["type"])));
+    }
+    if let Some(JsonValue::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return fail(path, format!("{} is not one of {}", value, schema["enum"]), Some(format!("one of {}", schema["enum"])));
+        }
+    }
+    if let Some(expected) = schema.get("const").filter(|expected| *expected != value) {
+        return fail(path, format!("expected {}, got {}", expected, value), Some(expected.to_string()));
+    }
+    if let (Some(n), Some(min)) = (value.as_f64(), schema.get("minimum").and_then(JsonValue::as_f64)) {
+        if n < min {
+            return fail(path, format!("{} is less than {}", value, min), Some(format!("at least {}", min)));
+        }
+    }
+    if let (Some(n), Some(max)) = (value.as_f64(), schema.get("maximum").and_then(JsonValue::as_f64)) {
+        if n > max {
+            return fail(path, format!("{} is greater than {}", value, max), Some(format!("at most {}", max)));
+        }
+    }
+    if let JsonValue::String(s) = value {
+        let len = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(JsonValue::as_u64).filter(|min| len < *min) {
+            return fail(path, format!("string {:?} is too short", s), Some(format!("at least {} characters", min)));
+        }
+        if let Some(max) = schema.get("maxLength").and_then(JsonValue::as_u64).filter(|max| len > *max) {
+            return fail(path, format!("string {:?} is too long", s), Some(format!("at most {} characters", max)));
+        }
+    }
+    if let JsonValue::Object(o) = value {
+        for key in schema.get("required").and_then(JsonValue::as_array).into_iter().flatten().filter_map(JsonValue::as_str) {
+            if !o.contains_key(key) {
+                return fail(path, format!("missing required key {:?}", key), Some(format!("a {:?} key", key)));
+            }
+        }
+        let properties = schema.get("properties").and_then(JsonValue::as_object);
+        for (k, v) in o {
+            match properties.and_then(|p| p.get(k)) {
+                Some(property) => {
+                    path.push(PathSegment::Key(k.clone()));
+                    check_schema(property, v, path)?;
+                    path.pop();
+                },
+                None if schema.get("additionalProperties") == Some(&JsonValue::Bool(false)) => {
+                    let known = properties.map(|p| p.keys().cloned().collect::<Vec<_>>().join(", ")).unwrap_or_default();
+                    return fail(path, format!("unexpected key {:?}", k), Some(format!("only the keys {}", known)));
+                },
+                None => {},
+            }
+        }
+    }
+    if let (JsonValue::Array(a), Some(items)) = (value, schema.get("items")) {
+        for (i, v) in a.iter().enumerate() {
+            path.push(PathSegment::Index(i));
+            check_schema(items, v, path)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+/// A conversion or schema error rendered for script authors, e.g. in a mod console:
+///
+/// ```text
+/// graphics.resolution[1]: expected type "integer", got "high"
+///   expected: integer
+///   got: "high"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFacingError {
+    pub path: Path,
+    pub message: String,
+    pub expected: Option<String>,
+    /// Compact JSON of the offending value, shortened to [`ScriptFacingError::EXCERPT_LEN`] characters.
+    pub excerpt: Option<String>,
+}
+
+impl ScriptFacingError {
+    pub const EXCERPT_LEN: usize = 60;
+
+    fn excerpt(value: &JsonValue) -> String {
+        let text = value.to_string();
+        match text.char_indices().nth(Self::EXCERPT_LEN) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text,
+        }
+    }
+
+    /// Checks `value` against a schema of the subset [`Validator::schema`] supports.
+    pub fn check(schema: &JsonValue, value: &JsonValue) -> Result<(), ScriptFacingError> {
+        check_schema(schema, value, &mut Path::new()).map_err(|failure| {
+            let offending = failure.path.segments().iter().try_fold(value, |v, segment| match segment {
+                PathSegment::Key(k) => v.get(k),
+                PathSegment::Index(i) => v.get(i),
+            });
+            ScriptFacingError {
+                excerpt: offending.map(Self::excerpt),
+                path: failure.path, message: failure.message, expected: failure.expected,
+            }
+        })
+    }
+}
+
+impl From<rlua::Error> for ScriptFacingError {
+    fn from(error: rlua::Error) -> Self {
+        ScriptFacingError { path: Path::new(), message: error.to_string(), expected: None, excerpt: None }
+    }
+}
+
+impl From<ScriptFacingError> for rlua::Error {
+    fn from(error: ScriptFacingError) -> Self {
+        rlua::Error::RuntimeError(error.to_string())
+    }
+}
+
+impl Display for ScriptFacingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.path.dotted().as_str() {
+            "" => write!(f, "{}", self.message)?,
+            location => write!(f, "{}: {}", location, self.message)?,
+        }
+        if let Some(expected) = &self.expected {
+            write!(f, "\n  expected: {}", expected)?;
+        }
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n  got: {}", excerpt)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts `value` and checks the result against `schema`, reporting either failure for scripts.
+pub fn lua_to_json_with_schema<'lua>(
+    lua: &'lua Lua, value: rlua::Value<'lua>, schema: &JsonValue, options: &ConversionOptions,
+) -> Result<JsonValue, ScriptFacingError> {
+    let json = lua_to_json(lua, value, options)?;
+    ScriptFacingError::check(schema, &json)?;
+    Ok(json)
+}
+
+/// Checks `value` against `schema` and converts it, reporting either failure for scripts.
+pub fn json_to_lua_with_schema<'lua>(
+    lua: &'lua Lua, value: &JsonValue, schema: &JsonValue, options: &ConversionOptions,
+) -> Result<rlua::Value<'lua>, ScriptFacingError> {
+    ScriptFacingError::check(schema, value)?;
+    Ok(json_to_lua(lua, value, options)?)
+}
+
+fn child<'v>(value: &'v JsonValue, key: &str) -> Option<&'v JsonValue> {
+    match value {
+        JsonValue::Object(o) => o.get(key),
+        JsonValue::Array(a) => a.get(key.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// Whether a write at `written` (unescaped pointer segments) can change what `pattern` matches.
+pub(crate) fn affects(pattern: &PathPattern, written: &[String]) -> bool {
+    pattern.segments().iter().zip(written).all(|(p, w)| p.as_ref().is_none_or(|p| p == w))
+}
+
+/// The values `pattern` matches in `value` on or along the written location.
+pub(crate) fn affected<'v>(
+    pattern: &[Option<String>], written: &[String], value: &'v JsonValue,
+    path: &mut Path, out: &mut Vec<(Path, &'v JsonValue)>,
+) {
+    let Some((first, rest)) = pattern.split_first() else {
+        out.push((path.clone(), value));
+        return;
+    };
+    let keys: Vec<String> = match (written.first(), first, value) {
+        (Some(w), _, _) => vec![w.clone()],
+        (None, Some(key), _) => vec![key.clone()],
+        (None, None, JsonValue::Object(o)) => o.keys().cloned().collect(),
+        (None, None, JsonValue::Array(a)) => (0..a.len()).map(|i| i.to_string()).collect(),
+        (None, None, _) => Vec::new(),
+    };
+    for key in keys {
+        if let Some(v) = child(value, &key) {
+            path.push(match value {
+                JsonValue::Array(_) => PathSegment::Index(key.parse().unwrap_or_default()),
+                _ => PathSegment::Key(key),
+            });
+            affected(rest, written.get(1..).unwrap_or_default(), v, path, out);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, json_to_lua_with_schema, lua_to_json_with_schema};
+
+    #[test]
+    fn script_facing_errors() {
+        let lua = Lua::new();
+        let schema = json!({"properties": {"graphics": {"properties": {"resolution": {"items": {"type": "integer"}}}}}});
+        let value = lua.load("{ graphics = { resolution = { 1920, 'high' } } }").eval().expect("table");
+        let error = lua_to_json_with_schema(&lua, value, &schema, &ConversionOptions::default()).expect_err("mismatch");
+        assert_eq!(error.to_string(), "graphics.resolution[1]: expected type \"integer\", got \"high\"\n  expected: integer\n  got: \"high\"");
+
+        let long = json!({"graphics": {"resolution": ["x".repeat(100)]}});
+        let error = json_to_lua_with_schema(&lua, &long, &schema, &ConversionOptions::default()).expect_err("mismatch");
+        assert!(error.excerpt.expect("excerpt").ends_with("xxx..."));
+        assert!(json_to_lua_with_schema(&lua, &json!({"graphics": {}}), &schema, &ConversionOptions::default()).is_ok());
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use rlua::Lua;
+use serde_json::Value as JsonValue;
+use sha2::Sha256;
+use crate::{ConversionOptions, json_to_lua};
+
+/// What a webhook signature covers and how it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// HMAC-SHA256 of the raw body, in hex, optionally prefixed `sha256=` (GitHub).
+    Body,
+    /// HMAC-SHA256 of `"{timestamp}.{body}"`, written `t={timestamp},v1={hex}` (Stripe); the
+    /// timestamp must be within the tolerance.
+    TimestampedBody,
+    /// HMAC-SHA256 of the canonical form of the document (sorted keys, no whitespace), in hex,
+    /// so payloads that were re-serialized on the way still verify.
+    Canonical,
+}
+
+/// Why a payload was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    MalformedSignature,
+    BadSignature,
+    /// The signature timestamp is this many seconds away from now, more than the tolerance.
+    Expired { age_secs: u64 },
+    InvalidJson(String),
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookError::MalformedSignature => f.write_str("webhook: malformed signature"),
+            WebhookError::BadSignature => f.write_str("webhook: signature does not match"),
+            WebhookError::Expired { age_secs } => write!(f, "webhook: signature timestamp is {} seconds off", age_secs),
+            WebhookError::InvalidJson(message) => write!(f, "webhook: invalid JSON payload: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+/// Compact JSON with object keys sorted at every level, whatever the map type.
+pub fn canonical_json(value: &JsonValue) -> String {
+    let mut text = String::new();
+    write_canonical(value, &mut text);
+    text
+}
+
+fn write_canonical(value: &JsonValue, text: &mut String) {
+    match value {
+        JsonValue::Array(a) => {
+            text.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                write_canonical(v, text);
+            }
+            text.push(']');
+        },
+        JsonValue::Object(o) => {
+            let mut entries: Vec<_> = o.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            text.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                text.push_str(&JsonValue::from(k.as_str()).to_string());
+                text.push(':');
+                write_canonical(v, text);
+            }
+            text.push('}');
+        },
+        scalar => text.push_str(&scalar.to_string()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok())).collect()
+}
+
+/// Checks signed JSON payloads, e.g. webhooks, before anything converts them.
+#[derive(Clone)]
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    scheme: SignatureScheme,
+    tolerance: Duration,
+}
+
+impl std::fmt::Debug for WebhookVerifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookVerifier").field("scheme", &self.scheme).field("tolerance", &self.tolerance).finish()
+    }
+}
+
+impl WebhookVerifier {
+    /// A verifier with a tolerance of five minutes.
+    pub fn new(secret: impl Into<Vec<u8>>, scheme: SignatureScheme) -> Self {
+        WebhookVerifier { secret: secret.into(), scheme, tolerance: Duration::from_secs(300) }
+    }
+
+    /// How far the timestamp of a [`SignatureScheme::TimestampedBody`] signature may be from now.
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// HMAC accepts keys of any length, so this does not fail in practice.
+    fn mac(&self, parts: &[&[u8]]) -> Result<Hmac<Sha256>, WebhookError> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.secret).map_err(|_| WebhookError::BadSignature)?;
+        parts.iter().for_each(|part| mac.update(part));
+        Ok(mac)
+    }
+
+    fn signed_bytes(&self, body: &[u8]) -> Result<Vec<u8>, WebhookError> {
+        match self.scheme {
+            SignatureScheme::Canonical => {
+                let value: JsonValue = serde_json::from_slice(body).map_err(|e| WebhookError::InvalidJson(e.to_string()))?;
+                Ok(canonical_json(&value).into_bytes())
+            },
+            _ => Ok(body.to_vec()),
+        }
+    }
+
+    /// The signature header value for `body`, e.g. for tests or for sending webhooks;
+    /// `timestamp` (Unix seconds) is used by [`SignatureScheme::TimestampedBody`] only.
+    pub fn sign(&self, body: &[u8], timestamp: u64) -> Result<String, WebhookError> {
+        let signed = self.signed_bytes(body)?;
+        Ok(match self.scheme {
+            SignatureScheme::TimestampedBody => {
+                let t = timestamp.to_string();
+                format!("t={},v1={}", t, hex(&self.mac(&[t.as_bytes(), b".", &signed])?.finalize().into_bytes()))
+            },
+            _ => format!("sha256={}", hex(&self.mac(&[&signed])?.finalize().into_bytes())),
+        })
+    }
+
+    /// The payload, if `signature` (the header value) matches it at `now` (Unix seconds).
+    /// Signatures are compared in constant time.
+    pub fn verify_at(&self, body: &[u8], signature: &str, now: u64) -> Result<JsonValue, WebhookError> {
+        let signed = self.signed_bytes(body)?;
+        let valid = match self.scheme {
+            SignatureScheme::TimestampedBody => {
+                let mut timestamp = None;
+                let mut candidates = Vec::new();
+                for part in signature.split(',') {
+                    match part.trim().split_once('=') {
+                        Some(("t", t)) => timestamp = t.parse::<u64>().ok(),
+                        Some(("v1", v)) => candidates.push(unhex(v).ok_or(WebhookError::MalformedSignature)?),
+                        _ => {},
+                    }
+                }
+                let timestamp = timestamp.ok_or(WebhookError::MalformedSignature)?;
+                let age_secs = now.abs_diff(timestamp);
+                if age_secs > self.tolerance.as_secs() {
+                    return Err(WebhookError::Expired { age_secs });
+                }
+                let t = timestamp.to_string();
+                let mac = self.mac(&[t.as_bytes(), b".", &signed])?;
+                candidates.iter().any(|c| mac.clone().verify_slice(c).is_ok())
+            },
+            _ => {
+                let digest = signature.trim();
+                let digest = unhex(digest.strip_prefix("sha256=").unwrap_or(digest)).ok_or(WebhookError::MalformedSignature)?;
+                self.mac(&[&signed])?.verify_slice(&digest).is_ok()
+            },
+        };
+        if !valid {
+            return Err(WebhookError::BadSignature);
+        }
+        serde_json::from_slice(body).map_err(|e| WebhookError::InvalidJson(e.to_string()))
+    }
+
+    /// [`verify_at`](Self::verify_at) the current time.
+    pub fn verify(&self, body: &[u8], signature: &str) -> Result<JsonValue, WebhookError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        self.verify_at(body, signature, now)
+    }
+
+    /// Verifies the payload, then converts it into a Lua value; a rejected payload is a
+    /// [`WebhookError`] found with `rlua::Error::downcast_ref`.
+    pub fn verify_into_lua<'lua>(
+        &self, lua: &'lua Lua, body: &[u8], signature: &str, options: &ConversionOptions,
+    ) -> rlua::Result<rlua::Value<'lua>> {
+        json_to_lua(lua, &self.verify(body, signature).map_err(rlua::Error::external)?, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rlua::Lua;
+    use serde_json::json;
+    use crate::{ConversionOptions, SignatureScheme, WebhookError, WebhookVerifier, canonical_json};
+
+    #[test]
+    fn signatures() {
+        let body = br#"{"b": 1, "a": [true, null]}"#;
+        let github = WebhookVerifier::new("secret", SignatureScheme::Body);
+        let signature = github.sign(body, 0).expect("sign");
+        assert!(signature.starts_with("sha256=") && signature.len() == 7 + 64);
+        assert_eq!(github.verify(body, &signature), Ok(json!({"a": [true, null], "b": 1})));
+        assert_eq!(github.verify(br#"{"b": 2, "a": [true, null]}"#, &signature), Err(WebhookError::BadSignature));
+        assert_eq!(github.verify(body, "sha256=zz"), Err(WebhookError::MalformedSignature));
+        assert_eq!(WebhookVerifier::new("other", SignatureScheme::Body).verify(body, &signature), Err(WebhookError::BadSignature));
+
+        let stripe = WebhookVerifier::new("secret", SignatureScheme::TimestampedBody).with_tolerance(Duration::from_secs(60));
+        let signature = stripe.sign(body, 1_000).expect("sign");
+        assert!(stripe.verify_at(body, &signature, 1_030).is_ok());
+        assert_eq!(stripe.verify_at(body, &signature, 1_100), Err(WebhookError::Expired { age_secs: 100 }));
+        assert_eq!(stripe.verify_at(body, &signature.replace("t=1000", "t=1001"), 1_000), Err(WebhookError::BadSignature));
+
+        assert_eq!(canonical_json(&json!({"b": {"d": 1, "c": "x"}, "a": []})), r#"{"a":[],"b":{"c":"x","d":1}}"#);
+        let canonical = WebhookVerifier::new("secret", SignatureScheme::Canonical);
+        let signature = canonical.sign(body, 0).expect("sign");
+        assert!(canonical.verify(br#"{ "a": [true,null], "b": 1 }"#, &signature).is_ok());
+
+        let lua = Lua::new();
+        let error = github.verify_into_lua(&lua, body, "sha256=00", &ConversionOptions::default()).expect_err("rejected");
+        assert_eq!(error.downcast_ref::<WebhookError>(), Some(&WebhookError::BadSignature));
+    }
+}